@@ -7,6 +7,14 @@ pub use color_eyre::{
     install as install_panic_handler, Help, Report,
 };
 
+/// Like [`install_panic_handler`], but error reports and panics are printed
+/// without colors
+pub fn install_panic_handler_without_colors() -> Result<()> {
+    color_eyre::config::HookBuilder::default()
+        .theme(color_eyre::config::Theme::new())
+        .install()
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct NoneError {}
 
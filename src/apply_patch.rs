@@ -8,20 +8,32 @@ use zstd::stream::read::Decoder as ZstdDecoder;
 
 pub fn apply_patch(archive: impl AsRef<Path>, patch: impl AsRef<Path>) -> Result<impl Read> {
     let archive = archive.as_ref();
-    let patch = patch.as_ref();
-
-    let patch_file =
-        File::open(patch).with_context(|| format!("open file `{}`", patch.display()))?;
-    let patch_decompressed = ZstdDecoder::new(patch_file)
-        .with_context(|| format!("read zstd compressed file `{}`", patch.display()))?;
 
     let archive_file =
         File::open(archive).with_context(|| format!("open file `{}`", archive.display()))?;
-    let archive_decompressed = zstd::stream::decode_all(BufReader::new(archive_file))
-        .with_context(|| format!("read zstd compressed file `{}`", archive.display()))?;
+    let archive_decompressed = crate::decompress_build(archive, BufReader::new(archive_file))
+        .with_context(|| format!("read build archive `{}`", archive.display()))?;
+
+    bipatch::Reader::new(
+        open_patch(patch.as_ref())?,
+        Cursor::new(archive_decompressed),
+    )
+    .context("read patch")
+}
 
-    bipatch::Reader::new(patch_decompressed, Cursor::new(archive_decompressed))
-        .context("read patch")
+/// Apply patch to an already decompressed archive
+pub fn apply_patch_to_decompressed(
+    archive: &[u8],
+    patch: impl AsRef<Path>,
+) -> Result<impl Read + '_> {
+    bipatch::Reader::new(open_patch(patch.as_ref())?, Cursor::new(archive)).context("read patch")
+}
+
+fn open_patch(patch: &Path) -> Result<impl Read> {
+    let patch_file =
+        File::open(patch).with_context(|| format!("open file `{}`", patch.display()))?;
+    ZstdDecoder::new(patch_file)
+        .with_context(|| format!("read zstd compressed file `{}`", patch.display()))
 }
 
 #[cfg(test)]
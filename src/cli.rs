@@ -1,7 +1,12 @@
-use crate::{paths, Storage, Version};
+use crate::{
+    git::{TagOrder, TagPattern},
+    output::OutputFormat,
+    packaging, paths, ChecksumAlgorithm, ErrorKind, Storage, Version,
+};
 use erreur::{ensure, Context, Result, StdResult};
 use std::{
     convert::Infallible,
+    ffi::OsString,
     fmt,
     path::{Path, PathBuf},
     str::FromStr,
@@ -17,32 +22,332 @@ pub struct Cli {
     /// Path/URL or remote storage
     #[structopt(long = "remote", env = "ARTEFACTA_REMOTE_STORE")]
     pub remote_store: Storage,
+    /// Encrypt files in remote storage with the passphrase in this file
+    ///
+    /// Everything uploaded is encrypted, and everything downloaded has to be
+    /// encrypted with the same passphrase.
+    #[structopt(
+        long = "encryption-key-file",
+        env = "ARTEFACTA_ENCRYPTION_KEY_FILE",
+        global = true
+    )]
+    pub encryption_key_file: Option<PathBuf>,
+    /// Sign new builds with the ed25519 key in this file (see
+    /// `generate-signing-key`)
+    ///
+    /// Builds are signed when adding them, and when uploading them with
+    /// `sync` if they aren't signed yet.
+    #[structopt(long = "signing-key", env = "ARTEFACTA_SIGNING_KEY", global = true)]
+    pub signing_key: Option<PathBuf>,
+    /// Check signatures of builds against the ed25519 public key in this file
+    /// before installing them
+    #[structopt(long = "verify-key", env = "ARTEFACTA_VERIFY_KEY", global = true)]
+    pub verify_key: Option<PathBuf>,
     #[structopt(subcommand)]
     pub cmd: Command,
     /// Print more debug output
     #[structopt(short = "v", long = "verbose")]
     pub verbose: bool,
+    /// Don't color logs and error reports (also when `NO_COLOR` is set)
+    // also checked before parsing the other options, see `no_color`
+    #[structopt(long = "no-color", global = true)]
+    pub no_color: bool,
+    /// Only log warnings and errors, and don't show progress bars
+    ///
+    /// Progress bars are only shown when stderr is a terminal anyway.
+    #[structopt(short = "q", long = "quiet", global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+    /// Only show what would be done, without changing any storage
+    ///
+    /// Supported by `sync`, `pull`, `clean`, `label`, `install`,
+    /// `create-patch`, `create-patches-chain`, `prune-patches`, and
+    /// `manifest --upload`.
+    #[structopt(long = "dry-run", global = true)]
+    pub dry_run: bool,
+    /// Config file with defaults for options (see `artefacta.toml` in the
+    /// README)
+    // also read before parsing the other options, see `config_path`
+    #[structopt(long = "config", env = "ARTEFACTA_CONFIG", global = true)]
+    pub config: Option<PathBuf>,
+    /// How to print results of commands (`human` or `json`)
+    ///
+    /// JSON is printed to stdout, logs always go to stderr.
+    #[structopt(long = "output", default_value = "human", global = true)]
+    pub output: OutputFormat,
+    /// How to write logs to stderr (`pretty` or `json`)
+    ///
+    /// With `json`, each log line is a JSON object with `timestamp`, `level`,
+    /// `target`, and `message`, for log aggregation in CI or containers.
+    #[structopt(
+        long = "log-format",
+        env = "ARTEFACTA_LOG_FORMAT",
+        default_value = "pretty",
+        global = true
+    )]
+    pub log_format: LogFormat,
+    /// Also write logs to this file (with the same filters as on stderr),
+    /// appending to what's there
+    #[structopt(long = "log-file", env = "ARTEFACTA_LOG_FILE", global = true)]
+    pub log_file: Option<PathBuf>,
+    /// Move an existing `--log-file` to `<path>.1` first (replacing an older
+    /// one) instead of appending to it
+    #[structopt(long = "rotate-log-file", global = true)]
+    pub rotate_log_file: bool,
+    /// Checksum algorithm for new builds and patches (`sha256` or `blake3`)
+    ///
+    /// Checksum files of existing builds are read whatever their algorithm.
+    #[structopt(
+        long = "checksum",
+        default_value = "sha256",
+        env = "ARTEFACTA_CHECKSUM",
+        global = true
+    )]
+    pub checksum: ChecksumAlgorithm,
+    /// Size of the parts (in MiB) of large builds to diff at once when
+    /// calculating patches
+    ///
+    /// Diffing takes about twelve times as much memory. Smaller values save
+    /// memory but can make patches larger.
+    #[structopt(
+        long = "diff-window",
+        default_value = "64",
+        env = "ARTEFACTA_DIFF_WINDOW",
+        global = true
+    )]
+    pub diff_window: usize,
+    /// Memory-map decompressed builds when calculating patches
+    ///
+    /// Builds are decompressed into temporary files in the local store, so
+    /// the OS can page them in and out. Needs artefacta to be built with the
+    /// `mmap` feature (unix only).
+    #[structopt(long = "diff-mmap", global = true)]
+    pub diff_mmap: bool,
+    /// Number of files to upload to remote storage at the same time
+    #[structopt(
+        long = "concurrency",
+        default_value = "3",
+        env = "ARTEFACTA_CONCURRENCY",
+        global = true
+    )]
+    pub concurrency: usize,
+    /// Transfer at most this many bytes per second to and from S3 (shared by
+    /// all uploads and downloads at the same time)
+    #[structopt(long = "max-bandwidth", env = "ARTEFACTA_MAX_BANDWIDTH", global = true)]
+    pub max_bandwidth: Option<u64>,
+    /// Reuse the listing of remote storage from an earlier run if it's
+    /// younger than this many seconds (0 to always list it)
+    ///
+    /// The listing is kept in the local store. Remotes on the file system are
+    /// listed again anyway when files were added or removed since.
+    #[structopt(
+        long = "cache-max-age",
+        default_value = "0",
+        env = "ARTEFACTA_CACHE_MAX_AGE",
+        global = true
+    )]
+    pub cache_max_age: u64,
+    /// List remote storage again even if there is a recent cached listing
+    #[structopt(long = "refresh", global = true)]
+    pub refresh: bool,
+    /// When syncing, upload builds identical to one on remote as an alias of
+    /// it instead of uploading the same content again
+    #[structopt(long = "alias-duplicates", global = true)]
+    pub alias_duplicates: bool,
+    /// Download builds again when the locally cached file differs from the
+    /// one in remote storage (instead of only warning about it)
+    #[structopt(long = "prefer-remote", global = true)]
+    pub prefer_remote: bool,
+    /// Write bytes transferred and durations of operations to this file in
+    /// Prometheus' text format (e.g. for the node exporter's textfile
+    /// collector, which reads `*.prom` files)
+    #[structopt(long = "metrics-file", env = "ARTEFACTA_METRICS_FILE", global = true)]
+    pub metrics_file: Option<PathBuf>,
+    /// Print how long each phase (listing storage, diffing, uploading, …)
+    /// took in total at the end. Each phase is also logged when it ends, see
+    /// `RUST_LOG=artefacta::timings=debug`.
+    #[structopt(long = "timings", global = true)]
+    pub timings: bool,
+    /// Suffix of build files (default `.tar.zst`)
+    ///
+    /// Builds ending in `.tzst` or `.tar` are read, too.
+    #[structopt(long = "build-suffix", env = "ARTEFACTA_BUILD_SUFFIX", global = true)]
+    pub build_suffix: Option<String>,
+    /// Suffix of patch files (default `.patch.zst`)
+    #[structopt(long = "patch-suffix", env = "ARTEFACTA_PATCH_SUFFIX", global = true)]
+    pub patch_suffix: Option<String>,
+    /// Separator of the versions in patch file names (default `-`)
+    ///
+    /// Patches between versions that contain it are named `<from>---<to>`.
+    #[structopt(
+        long = "patch-separator",
+        env = "ARTEFACTA_PATCH_SEPARATOR",
+        global = true
+    )]
+    pub patch_separator: Option<String>,
+}
+
+impl Command {
+    /// Symlink the command works on (given with `--as`), if it has one
+    pub fn link_name(&self) -> Option<&LinkName> {
+        match self {
+            Command::Install { name, .. }
+            | Command::Status { name, .. }
+            | Command::Info { name, .. }
+            | Command::Plan { name, .. }
+            | Command::Rollback { name, .. } => Some(name),
+            _ => None,
+        }
+    }
+}
+
+impl Cli {
+    /// How to name build and patch files, with the defaults for what isn't
+    /// given
+    pub fn naming(&self) -> paths::Naming {
+        let default = paths::Naming::default();
+        paths::Naming {
+            build_suffix: self.build_suffix.clone().unwrap_or(default.build_suffix),
+            patch_suffix: self.patch_suffix.clone().unwrap_or(default.patch_suffix),
+            patch_separator: self
+                .patch_separator
+                .clone()
+                .unwrap_or(default.patch_separator),
+        }
+    }
+}
+
+/// Path given with `--config` (or `ARTEFACTA_CONFIG`)
+///
+/// We need to know this before parsing arguments with structopt, as the config
+/// file provides defaults for them.
+pub fn config_path(args: impl IntoIterator<Item = OsString>) -> Option<PathBuf> {
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str().and_then(|a| a.strip_prefix("--config=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+    std::env::var_os("ARTEFACTA_CONFIG").map(PathBuf::from)
+}
+
+/// Whether `--no-color` is given or `NO_COLOR` is set (to anything but an
+/// empty string, see <https://no-color.org>)
+///
+/// We need to know this before parsing arguments with structopt, as reading the
+/// config file can already fail with an error report.
+pub fn no_color(args: impl IntoIterator<Item = OsString>) -> bool {
+    args.into_iter()
+        .take_while(|arg| arg != "--")
+        .any(|arg| arg == "--no-color")
+        || std::env::var_os("NO_COLOR").map_or(false, |value| !value.is_empty())
 }
 
 #[derive(Debug, StructOpt)]
 pub enum Command {
     /// Install new build
     Install {
-        /// Version of the build to install
-        version: Version,
+        /// Version of the build to install, `latest` for the highest version
+        /// in the store, or a glob pattern (like `app-*`) for the highest
+        /// version matching it
+        #[structopt(required_unless_one = &["tag", "label"])]
+        version: Option<Version>,
+        /// Install the build for this git tag instead, found the same way
+        /// `auto-patch` finds builds for tags
+        #[structopt(long, conflicts_with = "version")]
+        tag: Option<String>,
+        /// Install the newest build with this label instead (see `label`)
+        #[structopt(long, conflicts_with_all = &["version", "tag"])]
+        label: Option<String>,
+        /// Prefix for finding the build for `--tag`, used like "$prefix$tag"
+        #[structopt(long, default_value, env = "ARTEFACTA_TAG_PREFIX")]
+        prefix: String,
+        /// Name of the symlink in the local store pointing at the installed
+        /// build
+        #[structopt(long = "as", default_value)]
+        name: LinkName,
+        /// Download the full build instead of applying more than this many
+        /// patches in a row
+        #[structopt(long = "max-patch-hops")]
+        max_patch_hops: Option<usize>,
+        /// Afterwards, delete the oldest local builds so only this many are
+        /// left (never installed ones, and only if they are on remote)
+        #[structopt(long = "keep", env = "ARTEFACTA_KEEP_BUILDS")]
+        keep: Option<usize>,
+        /// Unpack the build into `<version>.extracted/` and point the symlink
+        /// at that directory instead of the archive
+        #[structopt(long = "extract")]
+        extract: bool,
+        /// Shell command to run after the new build was installed
+        ///
+        /// Gets `ARTEFACTA_INSTALLED_VERSION`, `ARTEFACTA_PREVIOUS_VERSION`
+        /// (if there was one), and `ARTEFACTA_CURRENT_PATH` in its
+        /// environment. Not run when the version was already installed.
+        #[structopt(long = "post-install", env = "ARTEFACTA_POST_INSTALL")]
+        post_install: Option<String>,
+        /// Refuse to install builds without a valid signature (needs
+        /// `--verify-key`)
+        #[structopt(long = "require-signature")]
+        require_signature: bool,
     },
     /// Add a new build
-    // TODO: Add option for calculating patches
-    Add(AddBuild),
+    Add {
+        /// Path to the build
+        #[structopt(required_unless_one = &["stdin", "from-url"])]
+        path: Option<PathBuf>,
+        /// Read the build archive from stdin instead of a file
+        #[structopt(long, conflicts_with = "path", requires = "version")]
+        stdin: bool,
+        /// Download the build archive from this HTTP(S) URL instead of
+        /// reading a file
+        #[structopt(long, conflicts_with_all = &["path", "stdin"], requires = "version")]
+        from_url: Option<url::Url>,
+        /// Version of the build read from stdin or downloaded
+        #[structopt(long, conflicts_with = "path")]
+        version: Option<Version>,
+        #[structopt(flatten)]
+        options: AddOptions,
+    },
     /// Package a new build and add it
     AddPackage {
         /// Version of the build
         version: Version,
         #[structopt(flatten)]
         build: AddBuild,
+        #[structopt(flatten)]
+        options: PackageOptions,
     },
     /// Create a patch from one version to another
-    CreatePatch { from: Version, to: Version },
+    CreatePatch {
+        from: Version,
+        to: Version,
+        /// Also create a patch from `to` to `from` (for downgrades)
+        #[structopt(long)]
+        reverse: bool,
+        #[structopt(flatten)]
+        diff: DiffOptions,
+    },
+    /// Create patches between consecutive versions (in natural order) that
+    /// don't have one yet
+    ///
+    /// Unlike `auto-patch`, this doesn't look at git tags.
+    CreatePatchesChain {
+        /// Versions to chain (default: all builds in local and remote storage)
+        versions: Vec<Version>,
+        #[structopt(flatten)]
+        diff: DiffOptions,
+    },
+    /// Estimate how large a patch from one version to another would be
+    ///
+    /// Much faster than creating it, but only approximate: It compares chunks
+    /// of both builds instead of diffing them, so content that only changed a
+    /// little counts as new.
+    EstimatePatch { from: Version, to: Version },
     /// Create patches by looking at the git repo
     AutoPatch {
         /// Git repository in which to look for tags
@@ -53,35 +358,222 @@ pub enum Command {
         current: Version,
         /// Prefix for finding builds, used like "$prefix$tag". When setting
         /// this, omit the prefix from the current flag.
-        #[structopt(long, default_value)]
+        #[structopt(long, default_value, env = "ARTEFACTA_TAG_PREFIX")]
         prefix: String,
+        /// Regex for reading versions from tags, with named groups `major`,
+        /// `minor`, and/or `patch` (and optionally `pre`). By default, tags
+        /// are split at `.` and `-` and each number is decremented.
+        #[structopt(long, env = "ARTEFACTA_TAG_PATTERN")]
+        tag_pattern: Option<TagPattern>,
+        /// Read tags as semantic versions (with optional `v` prefix) and
+        /// patch from the previous patch, minor, and major releases
+        #[structopt(long, conflicts_with = "tag-pattern")]
+        semver: bool,
+        /// How to decide which of several earlier tags is the newest: `name`
+        /// (numbers compared numerically) or `time` (when the tag was created)
+        #[structopt(long, default_value = "name", env = "ARTEFACTA_TAG_ORDER")]
+        tag_order: TagOrder,
+        /// Number of patches to calculate at the same time (each one needs
+        /// memory for diffing, see `--diff-window`)
+        #[structopt(long, default_value = "2", env = "ARTEFACTA_AUTO_PATCH_JOBS")]
+        jobs: usize,
+    },
+    /// Create patches to a version from the builds right before it (in
+    /// natural version order, without looking at git tags)
+    AutoPatchLocal {
+        /// Version to create patches to
+        to: Version,
+        /// Number of earlier builds to create patches from
+        #[structopt(long, default_value = "3")]
+        count: usize,
     },
     /// Sync all new local files to remote store
-    Sync,
+    Sync(SyncOptions),
+    /// Download builds that are only in remote storage, e.g. to pre-seed a
+    /// new machine
+    ///
+    /// With `--dry-run`, only lists the files that would be downloaded.
+    Pull {
+        /// Only pull this build, `latest`, or a glob pattern (see `install`)
+        version: Option<Version>,
+        /// Also pull patches (only the ones to the build, with a version)
+        #[structopt(long)]
+        patches: bool,
+    },
+    /// Delete the local file of a build (and local patches from or to it) to
+    /// free disk space
+    ///
+    /// Copies in remote storage are not touched.
+    RemoveLocal {
+        /// Version of the build, `latest`, or a glob pattern (see `install`)
+        version: Version,
+        /// Also remove builds that are installed or not in remote storage
+        #[structopt(long)]
+        force: bool,
+    },
+    /// Delete patches that are never part of a cheapest upgrade path
+    ///
+    /// With `--dry-run`, only lists the patches that would be deleted.
+    PrunePatches,
+    /// Delete local patches from or to builds that exist neither locally nor
+    /// remotely
+    ///
+    /// With `--dry-run`, only lists the patches that would be deleted.
+    Clean,
+    /// Attach a label (like `stable` or `beta`) to a build
+    ///
+    /// Labels are stored in remote storage. `install --label <label>` installs
+    /// the newest build with a label.
+    Label {
+        version: Version,
+        label: String,
+        /// Take the label off the build instead
+        #[structopt(long)]
+        remove: bool,
+    },
+    /// Show the installed version and whether there is a newer one
+    Status {
+        /// Look at this symlink instead of `current`
+        #[structopt(long = "as", default_value)]
+        name: LinkName,
+    },
+    /// Show everything known about one build, and what installing it would
+    /// download
+    Info {
+        /// Version of the build, `latest`, or a glob pattern (see `install`)
+        version: Version,
+        /// Compare with the build this symlink points at instead of `current`
+        #[structopt(long = "as", default_value)]
+        name: LinkName,
+    },
+    /// Show what `install` would do: which patches it would apply or whether
+    /// it would download the full build, and how much it would transfer
+    Plan {
+        /// Version to install, `latest`, or a glob pattern (see `install`)
+        version: Version,
+        /// Plan from the build this symlink points at instead of `current`
+        #[structopt(long = "as", default_value)]
+        name: LinkName,
+    },
+    /// Switch back to the build that was installed before the last `install`
+    Rollback {
+        /// Roll back this symlink instead of `current`
+        #[structopt(long = "as", default_value)]
+        name: LinkName,
+    },
+    /// Check that stored builds (and patches) are not corrupted
+    Verify {
+        /// Only check this build (default: all builds)
+        version: Option<Version>,
+        /// Also check patches (by applying them to their source build if it
+        /// is available locally)
+        #[structopt(long)]
+        patches: bool,
+        /// Also check files in remote storage (this downloads them!)
+        #[structopt(long)]
+        remote: bool,
+    },
+    /// Fully check all local builds and patches, e.g. periodically on cache
+    /// volumes
+    ///
+    /// Decompresses every file and compares it with its checksum (if known),
+    /// reading it even if the checksum is cached. Exits with 65 if any file is
+    /// corrupt.
+    VerifyLocal {
+        /// Number of files to check at the same time
+        #[structopt(long, default_value = "2", env = "ARTEFACTA_VERIFY_JOBS")]
+        jobs: usize,
+    },
+    /// List builds (and patches) in local and remote storage
+    List(ListOptions),
     /// Build index (from local and remote data) and print it
     Debug,
+    /// Check the index for problems
+    ///
+    /// Also lists local builds that look corrupt (empty or not zstd
+    /// compressed, e.g. after a download was killed).
+    Doctor {
+        /// Delete local builds that look corrupt, so they're downloaded again
+        #[structopt(long)]
+        repair: bool,
+    },
+    /// Create a key pair for signing builds
+    ///
+    /// Writes the private key to `path` and the public key to `<path>.pub`.
+    GenerateSigningKey {
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+    },
+    /// Print a JSON manifest of the builds and patches in remote storage
+    Manifest {
+        /// Also store it in remote storage as `manifest.json`
+        #[structopt(long)]
+        upload: bool,
+    },
+}
+
+impl Command {
+    /// Whether the command changes the local store, and so needs to lock it
+    /// (see [`crate::lock`])
+    pub fn changes_local_store(&self) -> bool {
+        !matches!(
+            self,
+            Command::EstimatePatch { .. }
+                | Command::Label { .. }
+                | Command::Status { .. }
+                | Command::Info { .. }
+                | Command::Plan { .. }
+                | Command::Verify { .. }
+                | Command::VerifyLocal { .. }
+                | Command::List(_)
+                | Command::Debug
+                | Command::Doctor { repair: false }
+                | Command::GenerateSigningKey { .. }
+                | Command::Manifest { .. }
+        )
+    }
 }
 
 #[derive(Debug, StructOpt)]
 pub struct AddBuild {
     /// Path to the build
     pub path: PathBuf,
+    #[structopt(flatten)]
+    pub options: AddOptions,
+}
+
+// What to do after adding a build (no doc comment, see `DiffOptions`)
+#[derive(Debug, StructOpt)]
+pub struct AddOptions {
     /// Upload to remote storage
     #[structopt(long = "upload")]
     pub upload: bool,
     /// Calculate path from this build version
     #[structopt(long = "calc-patch-from")]
     pub calculate_patch_from: Option<Version>,
+    /// Make sure the build is a complete `.tar.zst` archive before adding it
+    /// (reads all of it)
+    #[structopt(long)]
+    pub verify: bool,
+    /// Store `key=value` in the build's metadata, e.g. the git commit it
+    /// was built from (can be used multiple times)
+    #[structopt(long = "meta", number_of_values = 1)]
+    pub meta: Vec<MetaValue>,
+    #[structopt(flatten)]
+    pub diff: DiffOptions,
 }
 
 impl AddBuild {
     pub async fn add_to(&self, index: &mut crate::ArtefactIndex) -> Result<()> {
-        // TODO: Also set exitcode::NOINPUT in this case
-        ensure!(
-            self.path.exists(),
-            "Tried to add `{}` as new build, but file does not exist",
-            self.path.display()
-        );
+        if !self.path.exists() {
+            return Err(ErrorKind::NoInput.error(format!(
+                "Tried to add `{}` as new build, but file does not exist",
+                self.path.display()
+            )));
+        }
+        if self.options.verify {
+            verify_build_archive(&self.path)?;
+        }
 
         let entry = index
             .add_local_build(&self.path)
@@ -93,8 +585,44 @@ impl AddBuild {
             entry
         );
 
+        let new_build: Version = paths::file_name(&entry.path)?.parse()?;
+        self.options.finish(index, new_build).await
+    }
+}
+
+/// Make sure the file at `path` is a complete build archive, for `--verify`
+pub(crate) fn verify_build_archive(path: &Path) -> Result<()> {
+    let file = std::fs::File::open(path).with_context(|| format!("open `{}`", path.display()))?;
+    let entries = packaging::verify_archive(path, std::io::BufReader::new(file)).map_err(|e| {
+        ErrorKind::Data.wrap(
+            e,
+            format!("`{}` is not a valid build archive", path.display()),
+        )
+    })?;
+    log::debug!("`{}` is an archive of {} entries", path.display(), entries);
+    Ok(())
+}
+
+impl AddOptions {
+    /// Calculate patch to and upload the newly added build, if asked to
+    pub(crate) async fn finish(
+        &self,
+        index: &mut crate::ArtefactIndex,
+        new_build: Version,
+    ) -> Result<()> {
+        if !self.meta.is_empty() {
+            let values = self
+                .meta
+                .iter()
+                .map(|meta| (meta.key.clone(), meta.value.clone()))
+                .collect();
+            index
+                .add_custom_meta(&new_build, values)
+                .context("store metadata of new build")?;
+        }
+
         if let Some(old_build) = self.calculate_patch_from.as_ref() {
-            let new_build: Version = paths::file_name(&entry.path)?.parse()?;
+            self.diff.apply_to(index)?;
             index
                 .calculate_patch(old_build.clone(), new_build)
                 .await
@@ -104,7 +632,7 @@ impl AddBuild {
         if self.upload {
             log::debug!("uploading new local artefacts to remote");
             index
-                .push()
+                .push(false)
                 .await
                 .context("could not sync local changes to remote")?;
         }
@@ -113,6 +641,188 @@ impl AddBuild {
     }
 }
 
+/// `key=value` pair for `--meta`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetaValue {
+    pub key: String,
+    pub value: String,
+}
+
+impl FromStr for MetaValue {
+    type Err = String;
+
+    fn from_str(s: &str) -> StdResult<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => Ok(MetaValue {
+                key: key.trim().to_string(),
+                value: value.to_string(),
+            }),
+            _ => Err(format!("`{}` is not a `key=value` pair", s)),
+        }
+    }
+}
+
+#[derive(Debug, Default, StructOpt)]
+pub struct SyncOptions {
+    /// POST a JSON summary of the uploaded builds and patches to this URL
+    /// (only when something was uploaded)
+    #[structopt(long, env = "ARTEFACTA_WEBHOOK")]
+    pub webhook: Option<url::Url>,
+    /// Fail when the webhook can't be notified (default: only warn)
+    #[structopt(long)]
+    pub webhook_required: bool,
+}
+
+#[derive(Debug, Default, StructOpt)]
+pub struct ListOptions {
+    /// Also list patches
+    #[structopt(long)]
+    pub patches: bool,
+    /// Only list what exists locally but not in remote storage
+    #[structopt(long, conflicts_with = "remote-only")]
+    pub local_only: bool,
+    /// Only list what exists in remote storage but not locally
+    #[structopt(long)]
+    pub remote_only: bool,
+    /// Only list builds with this label
+    #[structopt(long)]
+    pub label: Option<String>,
+}
+
+impl ListOptions {
+    pub(crate) fn includes(&self, local: bool, remote: bool) -> bool {
+        if self.local_only {
+            local && !remote
+        } else if self.remote_only {
+            remote && !local
+        } else {
+            true
+        }
+    }
+}
+
+// Trade-off between speed and size of patches (see `bidiff::DiffParams`).
+// No doc comment, as structopt would use it as the about text of commands.
+#[derive(Debug, Clone, StructOpt)]
+pub struct DiffOptions {
+    /// Number of threads for sorting the old build (default: 4 for builds
+    /// larger than `--diff-parallel-threshold`, 1 otherwise). More threads
+    /// make slightly larger patches.
+    #[structopt(long = "diff-threads")]
+    pub threads: Option<usize>,
+    /// Use several threads for sorting builds larger than this many MB
+    /// (compressed), unless `--diff-threads` is given
+    #[structopt(
+        long = "diff-parallel-threshold",
+        default_value = "100",
+        env = "ARTEFACTA_DIFF_PARALLEL_THRESHOLD"
+    )]
+    pub parallel_threshold: u64,
+    /// Scan the new build in chunks of this many bytes, in parallel. Smaller
+    /// chunks make slightly larger patches.
+    #[structopt(long = "diff-chunk-size", default_value = "100000000")]
+    pub chunk_size: usize,
+}
+
+impl DiffOptions {
+    pub fn apply_to(&self, index: &mut crate::ArtefactIndex) -> Result<()> {
+        index
+            .set_diff_params(
+                self.threads,
+                self.chunk_size,
+                self.parallel_threshold * 1_000_000,
+            )
+            .context("invalid `--diff-threads` or `--diff-chunk-size`")
+    }
+}
+
+#[derive(Debug, Default, StructOpt)]
+pub struct PackageOptions {
+    /// More files or directories to add to the build
+    pub more_paths: Vec<PathBuf>,
+    /// Put the content of the n-th path in a directory of this name (when
+    /// used, this needs to be given once for every path)
+    #[structopt(long = "prefix-with", number_of_values = 1)]
+    pub prefix_with: Vec<PathBuf>,
+    /// Number of threads to use for compression (default: all CPUs for builds
+    /// larger than 100MB, single threaded otherwise)
+    #[structopt(long = "compression-threads", env = "ARTEFACTA_COMPRESSION_THREADS")]
+    pub compression_threads: Option<u32>,
+    /// Add the files symlinks point to instead of the symlinks themselves
+    #[structopt(long = "follow-symlinks")]
+    pub follow_symlinks: bool,
+    /// Leave out paths matching this pattern (gitignore syntax, can be used
+    /// multiple times). Patterns listed in a `.artefactaignore` file in the
+    /// build directory are always left out.
+    #[structopt(long = "exclude", number_of_values = 1)]
+    pub exclude: Vec<String>,
+    /// Add files with names that are not valid UTF-8 instead of failing
+    #[structopt(long = "allow-non-utf8-paths")]
+    pub allow_non_utf8_paths: bool,
+    /// Write the archive to a temporary directory in this directory (default:
+    /// the system's, e.g. `$TMPDIR`), to package large builds when `/tmp`
+    /// is small
+    #[structopt(long = "temp-dir", env = "ARTEFACTA_TEMP_DIR")]
+    pub temp_dir: Option<PathBuf>,
+    /// Keep the temporary directory (with the partial archive) when packaging
+    /// fails, to look into what went wrong. Also enabled by setting
+    /// `ARTEFACTA_KEEP_TEMP` (to anything but `0`).
+    #[structopt(long = "keep-temp")]
+    pub keep_temp: bool,
+}
+
+impl PackageOptions {
+    /// All paths to package, starting with `path`, with their prefixes
+    pub(crate) fn sources(&self, path: &Path) -> Result<Vec<packaging::Source>> {
+        let paths = std::iter::once(path).chain(self.more_paths.iter().map(PathBuf::as_path));
+        let paths = paths
+            .map(|path| {
+                if !path.exists() {
+                    return Err(ErrorKind::NoInput.error(format!(
+                        "cannot package `{}`, it does not exist",
+                        path.display()
+                    )));
+                }
+                path.canonicalize()
+                    .with_context(|| format!("cannot canonicalize path `{}`", path.display()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if self.prefix_with.is_empty() {
+            return Ok(paths.into_iter().map(packaging::Source::new).collect());
+        }
+
+        ensure!(
+            self.prefix_with.len() == paths.len(),
+            "got {} paths but {} prefixes, need one `--prefix-with` per path",
+            paths.len(),
+            self.prefix_with.len()
+        );
+        Ok(paths
+            .into_iter()
+            .zip(&self.prefix_with)
+            .map(|(path, prefix)| packaging::Source::with_prefix(path, prefix))
+            .collect())
+    }
+
+    pub(crate) fn keep_temp_on_failure(&self) -> bool {
+        self.keep_temp
+            || std::env::var_os("ARTEFACTA_KEEP_TEMP").map_or(false, |value| value != "0")
+    }
+
+    pub(crate) fn packaging_settings(&self) -> packaging::Settings {
+        packaging::Settings {
+            symlinks: if self.follow_symlinks {
+                packaging::Symlinks::Follow
+            } else {
+                packaging::Symlinks::Preserve
+            },
+            exclude: self.exclude.clone(),
+            allow_non_utf8_paths: self.allow_non_utf8_paths,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct WorkingDir(PathBuf);
 
@@ -141,3 +851,123 @@ impl AsRef<Path> for WorkingDir {
         &self.0
     }
 }
+
+/// Name of a symlink in the local store pointing at an installed build
+///
+/// Defaults to `current`, but a store can have several, e.g. `staging` and
+/// `prod`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkName(String);
+
+impl LinkName {
+    /// Location of the link in the local store
+    pub fn path_in(&self, local_store: &Path) -> PathBuf {
+        local_store.join(&self.0)
+    }
+
+    /// Make sure the link doesn't clash with files artefacta manages
+    ///
+    /// Already done when parsing, but needs to be repeated once the naming
+    /// of build and patch files is set (see [`paths::naming`]).
+    pub fn check(&self) -> StdResult<(), String> {
+        let s = self.0.as_str();
+        if s == "previous"
+            || paths::is_build_path(s)
+            || paths::is_patch_path(s)
+            || [
+                ".previous",
+                paths::META_EXTENSION,
+                paths::EXTRACTED_EXTENSION,
+            ]
+            .iter()
+            .any(|suffix| s.ends_with(suffix))
+        {
+            return Err(format!(
+                "`{}` is not a valid name, it would clash with files artefacta manages",
+                s
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for LinkName {
+    fn default() -> Self {
+        LinkName("current".into())
+    }
+}
+
+impl FromStr for LinkName {
+    type Err = String;
+
+    fn from_str(s: &str) -> StdResult<Self, Self::Err> {
+        if s.is_empty() || s.contains(['/', '\\']) || s.starts_with('.') {
+            return Err(format!(
+                "`{}` is not a valid name, it needs to be a plain file name in the local store",
+                s
+            ));
+        }
+        let name = LinkName(s.into());
+        name.check()?;
+        Ok(name)
+    }
+}
+
+impl fmt::Display for LinkName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[test]
+fn meta_values() {
+    assert_eq!(
+        "git_sha=abc=123".parse(),
+        Ok(MetaValue {
+            key: "git_sha".into(),
+            value: "abc=123".into(),
+        })
+    );
+    assert_eq!(
+        "empty=".parse::<MetaValue>().map(|meta| meta.value),
+        Ok(String::new())
+    );
+    assert!("no-value".parse::<MetaValue>().is_err());
+    assert!("=value".parse::<MetaValue>().is_err());
+}
+
+#[test]
+fn link_names() {
+    assert_eq!("prod".parse(), Ok(LinkName("prod".into())));
+    assert_eq!(LinkName::default().to_string(), "current");
+    for invalid in [
+        "",
+        "../current",
+        "a/b",
+        ".hidden",
+        "previous",
+        "prod.previous",
+        "build1.tar.zst",
+    ] {
+        assert!(invalid.parse::<LinkName>().is_err(), "{}", invalid);
+    }
+}
+
+/// How to write logs, see [`Cli::log_format`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Pretty,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> StdResult<Self, Self::Err> {
+        match s {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format `{}`", s)),
+        }
+    }
+}
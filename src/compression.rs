@@ -1,18 +1,86 @@
 use erreur::{Context, Result};
 use std::{
     env,
-    io::{Read, Write},
+    io::{self, BufRead, Read, Write},
+    path::Path,
 };
-use zstd::stream::{decode_all, write::Encoder as ZstdEncoder};
+use zstd::stream::{decode_all, read::Decoder, write::Encoder as ZstdEncoder};
 
 pub fn compress<W: Write>(w: W) -> Result<ZstdEncoder<'static, W>> {
     ZstdEncoder::new(w, compression_level()).context("Can't instantiate ZSTD encoder")
 }
 
+/// Like [`compress`] but spread the work over `threads` worker threads
+///
+/// Output is still a regular zstd stream that can be read by [`decompress`]
+/// (or `zstd -d`). Using 0 threads is the same as calling [`compress`].
+pub fn compress_with_threads<W: Write>(w: W, threads: u32) -> Result<ZstdEncoder<'static, W>> {
+    let mut encoder = compress(w)?;
+    if threads > 0 {
+        encoder
+            .multithread(threads)
+            .with_context(|| format!("Can't use {} threads for ZSTD encoder", threads))?;
+    }
+    Ok(encoder)
+}
+
 pub fn decompress<R: Read>(r: R) -> Result<Vec<u8>> {
     decode_all(r).context("Can't read zstd compressed file")
 }
 
+/// Decompress all of `r` without keeping the result, returning its size
+///
+/// Like [`decompress`], but for checking that a file is intact without needing
+/// memory for all of its content.
+pub(crate) fn decompressed_size<R: Read>(r: R) -> Result<u64> {
+    let mut decoder = Decoder::new(r).context("Can't instantiate ZSTD decoder")?;
+    io::copy(&mut decoder, &mut io::sink()).context("Can't read zstd compressed file")
+}
+
+/// Whether `start` (the beginning of a file) is the beginning of zstd
+/// compressed data: a zstd frame or a skippable frame (`0x184D2A5?`)
+pub(crate) fn is_zstd(start: &[u8]) -> bool {
+    match start {
+        [a, b, c, d, ..] => {
+            let magic = u32::from_le_bytes([*a, *b, *c, *d]);
+            magic == 0xFD2F_B528 || magic & 0xFFFF_FFF0 == 0x184D_2A50
+        }
+        _ => false,
+    }
+}
+
+/// Content of a build archive, decompressing it unless it's a plain tar
+/// archive
+///
+/// Builds are zstd compressed tar archives, but some people store them
+/// uncompressed (as `.tar`). A build `name`d like that which doesn't start
+/// with zstd's magic number is read as it is. Everything else has to be zstd
+/// compressed, so e.g. an encrypted `.tar.zst` isn't mistaken for a plain one.
+pub fn build_reader<'a, R: BufRead + 'a>(
+    name: impl AsRef<Path>,
+    mut r: R,
+) -> Result<Box<dyn Read + 'a>> {
+    let plain = name
+        .as_ref()
+        .to_str()
+        .map_or(false, crate::paths::is_plain_build_path);
+    if plain && !is_zstd(r.fill_buf().context("Can't read build archive")?) {
+        log::trace!("build archive is not zstd compressed, reading it as is");
+        return Ok(Box::new(r));
+    }
+    let decoder = Decoder::with_buffer(r).context("Can't instantiate ZSTD decoder")?;
+    Ok(Box::new(decoder))
+}
+
+/// Like [`decompress`], but for build archives, see [`build_reader`]
+pub fn decompress_build<R: BufRead>(name: impl AsRef<Path>, r: R) -> Result<Vec<u8>> {
+    let mut content = Vec::new();
+    build_reader(name, r)?
+        .read_to_end(&mut content)
+        .context("Can't read build archive")?;
+    Ok(content)
+}
+
 const LEVEL_VAR: &str = "ARTEFACTA_COMPRESSION_LEVEL";
 
 #[cfg(test)]
@@ -21,10 +89,24 @@ const DEFAULT_LEVEL: i32 = 1;
 #[cfg(not(test))]
 const DEFAULT_LEVEL: i32 = 14;
 
+/// Inputs larger than this get compressed using all available CPUs by default
+const MULTITHREAD_THRESHOLD: u64 = 100 * 1_000_000;
+
+/// Number of threads to use for compressing input of the given size
+pub fn default_threads(input_size: u64) -> u32 {
+    if input_size > MULTITHREAD_THRESHOLD {
+        std::thread::available_parallelism()
+            .map(|n| n.get() as u32)
+            .unwrap_or(1)
+    } else {
+        0
+    }
+}
+
 fn compression_level() -> i32 {
     if let Ok(x) = env::var(LEVEL_VAR) {
         match x.parse::<i32>() {
-            Ok(x) => x,
+            Ok(x) => clamp_level(x),
             Err(e) => {
                 log::warn!("Can't parse `{}` as integer: {}", LEVEL_VAR, e);
                 DEFAULT_LEVEL
@@ -34,3 +116,83 @@ fn compression_level() -> i32 {
         DEFAULT_LEVEL
     }
 }
+
+/// Make sure the given level is one zstd will accept
+///
+/// zstd also knows about negative ("fast") levels but we only care about the
+/// regular ones, starting at 1.
+pub fn clamp_level(level: i32) -> i32 {
+    let max = *zstd::compression_level_range().end();
+    let clamped = level.clamp(1, max);
+    if clamped != level {
+        log::warn!(
+            "Compression level {} is outside of the supported range 1..={}, using {} instead",
+            level,
+            max,
+            clamped
+        );
+    }
+    clamped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_in_range_are_kept() {
+        let max = *zstd::compression_level_range().end();
+        assert_eq!(clamp_level(1), 1);
+        assert_eq!(clamp_level(DEFAULT_LEVEL), DEFAULT_LEVEL);
+        assert_eq!(clamp_level(max), max);
+    }
+
+    #[test]
+    fn levels_out_of_range_are_clamped() {
+        let max = *zstd::compression_level_range().end();
+        assert_eq!(clamp_level(0), 1);
+        assert_eq!(clamp_level(-10), 1);
+        assert_eq!(clamp_level(max + 1), max);
+        assert_eq!(clamp_level(50), max);
+    }
+
+    #[test]
+    fn clamped_levels_can_be_used_by_encoder() {
+        let mut encoder = ZstdEncoder::new(Vec::new(), clamp_level(50)).unwrap();
+        encoder.write_all(b"lorem ipsum").unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(decompress(&compressed[..]).unwrap(), b"lorem ipsum");
+    }
+
+    #[test]
+    fn multithreaded_output_can_be_decompressed() {
+        let content = crate::test_helpers::random_bytes(4 << 20).unwrap();
+        let mut encoder = compress_with_threads(Vec::new(), 4).unwrap();
+        encoder.write_all(&content).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(decompress(&compressed[..]).unwrap(), content);
+    }
+
+    #[test]
+    fn plain_builds_are_read_as_they_are() {
+        let tar = b"not really a tar archive, but not zstd either".to_vec();
+        let compressed = zstd::stream::encode_all(&tar[..], 1).unwrap();
+        assert!(is_zstd(&compressed));
+        assert!(!is_zstd(&tar));
+        assert!(!is_zstd(&compressed[..3]));
+
+        for name in ["build.tar", "build.tar.zst"] {
+            assert_eq!(decompress_build(name, &compressed[..]).unwrap(), tar);
+        }
+        assert_eq!(decompress_build("build.tar", &tar[..]).unwrap(), tar);
+        assert_eq!(decompress_build("build.tar", &b""[..]).unwrap(), b"");
+        assert!(decompress_build("build.tar.zst", &tar[..]).is_err());
+    }
+
+    #[test]
+    fn small_inputs_are_compressed_single_threaded() {
+        assert_eq!(default_threads(0), 0);
+        assert_eq!(default_threads(MULTITHREAD_THRESHOLD), 0);
+        assert!(default_threads(MULTITHREAD_THRESHOLD + 1) >= 1);
+    }
+}
@@ -0,0 +1,338 @@
+//! Defaults for command line options, read from `artefacta.toml`
+//!
+//! The config file is looked up in the working directory first, then in
+//! `$XDG_CONFIG_HOME/artefacta/` (usually `~/.config/artefacta/`). A specific
+//! file can be given with `--config <path>`.
+//!
+//! Values from the file are only used as defaults: We export them as the
+//! environment variables the CLI reads anyway, unless they are already set. So
+//! flags override environment variables, which override the config file.
+//!
+//! ```toml
+//! local_store = "/var/lib/artefacta"
+//! remote_store = "s3://my-bucket.ams3.digitaloceanspaces.com/builds"
+//! compression_level = 19
+//! prefix = "my-app-"
+//!
+//! [s3]
+//! access_key_id = "..."
+//! secret_access_key = "..."
+//! ```
+
+use erreur::{Context, Result};
+use serde::Deserialize;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+pub const FILE_NAME: &str = "artefacta.toml";
+
+/// Values from a config file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub local_store: Option<PathBuf>,
+    pub remote_store: Option<String>,
+    /// File with the passphrase to encrypt remote storage with
+    pub encryption_key_file: Option<PathBuf>,
+    /// File with the key to sign new builds with
+    pub signing_key: Option<PathBuf>,
+    /// File with the public key to check signatures of builds with
+    pub verify_key: Option<PathBuf>,
+    pub compression_level: Option<i64>,
+    /// Prefix for finding builds from git tags (see `auto-patch`)
+    pub prefix: Option<String>,
+    /// Regex for reading versions from git tags (see `auto-patch`)
+    pub tag_pattern: Option<String>,
+    /// `name` or `time`, for picking the newest of several git tags
+    pub tag_order: Option<String>,
+    /// Number of local builds to keep after `install`
+    pub keep_builds: Option<i64>,
+    /// Shell command to run after `install`
+    pub post_install: Option<String>,
+    /// `sha256` or `blake3`, for checksums of new builds and patches
+    pub checksum: Option<String>,
+    /// MiB of large builds to diff at once when calculating patches
+    pub diff_window: Option<i64>,
+    /// Number of files to upload at the same time
+    pub concurrency: Option<i64>,
+    /// Bytes per second to transfer to and from S3 at most
+    pub max_bandwidth: Option<i64>,
+    /// Seconds to reuse the listing of remote storage for
+    pub cache_max_age: Option<i64>,
+    /// MB above which builds are diffed with several threads
+    pub diff_parallel_threshold: Option<i64>,
+    /// URL to notify after `sync` uploaded something
+    pub webhook: Option<String>,
+    /// File to write metrics of each run to
+    pub metrics_file: Option<PathBuf>,
+    /// Number of patches `auto-patch` calculates at the same time
+    pub auto_patch_jobs: Option<i64>,
+    /// Suffix of build files, e.g. `.tar.zst`
+    pub build_suffix: Option<String>,
+    /// Suffix of patch files, e.g. `.patch.zst`
+    pub patch_suffix: Option<String>,
+    /// Separator of the versions in patch file names, e.g. `-`
+    pub patch_separator: Option<String>,
+    #[serde(default)]
+    pub s3: S3Config,
+}
+
+/// Credentials for S3 remotes
+///
+/// Used for the `AWS_*` variables rusoto reads.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct S3Config {
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    pub profile: Option<String>,
+}
+
+impl Config {
+    /// Find and read the config file
+    ///
+    /// When `path` is given, that file has to exist. Otherwise it's fine to
+    /// not have a config file at all.
+    pub fn load(path: Option<&Path>) -> Result<Option<Config>> {
+        if let Some(path) = path {
+            return Config::read(path).map(Some);
+        }
+
+        let candidates = [
+            Some(PathBuf::from(FILE_NAME)),
+            user_config_dir().map(|dir| dir.join("artefacta").join(FILE_NAME)),
+        ];
+        for path in candidates.iter().flatten() {
+            if path.exists() {
+                return Config::read(path).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("read config file `{}`", path.display()))?;
+        let mut config = Config::parse(&content)
+            .with_context(|| format!("parse config file `{}`", path.display()))?;
+
+        // relative paths are relative to the config file, not the working dir
+        if let (Some(local), Some(dir)) = (&mut config.local_store, path.parent()) {
+            if local.is_relative() {
+                *local = dir.join(&local);
+            }
+        }
+        Ok(config)
+    }
+
+    pub fn parse(content: &str) -> Result<Self> {
+        toml::from_str(content).context("invalid config")
+    }
+
+    /// Use values as defaults for the CLI
+    ///
+    /// Sets the environment variables the CLI (and rusoto) read, unless they
+    /// are already set.
+    pub fn apply_as_defaults(&self) {
+        fn set_default(var: &str, value: Option<String>) {
+            if let Some(value) = value {
+                if env::var_os(var).is_none() {
+                    env::set_var(var, value);
+                }
+            }
+        }
+
+        set_default(
+            "ARTEFACTA_LOCAL_STORE",
+            self.local_store
+                .as_ref()
+                .map(|path| path.display().to_string()),
+        );
+        set_default("ARTEFACTA_REMOTE_STORE", self.remote_store.clone());
+        set_default(
+            "ARTEFACTA_ENCRYPTION_KEY_FILE",
+            self.encryption_key_file
+                .as_ref()
+                .map(|path| path.display().to_string()),
+        );
+        set_default(
+            "ARTEFACTA_SIGNING_KEY",
+            self.signing_key
+                .as_ref()
+                .map(|path| path.display().to_string()),
+        );
+        set_default(
+            "ARTEFACTA_VERIFY_KEY",
+            self.verify_key
+                .as_ref()
+                .map(|path| path.display().to_string()),
+        );
+        set_default(
+            "ARTEFACTA_COMPRESSION_LEVEL",
+            self.compression_level.map(|level| level.to_string()),
+        );
+        set_default("ARTEFACTA_TAG_PREFIX", self.prefix.clone());
+        set_default("ARTEFACTA_TAG_PATTERN", self.tag_pattern.clone());
+        set_default("ARTEFACTA_TAG_ORDER", self.tag_order.clone());
+        set_default(
+            "ARTEFACTA_KEEP_BUILDS",
+            self.keep_builds.map(|keep| keep.to_string()),
+        );
+        set_default("ARTEFACTA_POST_INSTALL", self.post_install.clone());
+        set_default("ARTEFACTA_CHECKSUM", self.checksum.clone());
+        set_default(
+            "ARTEFACTA_DIFF_WINDOW",
+            self.diff_window.map(|size| size.to_string()),
+        );
+        set_default(
+            "ARTEFACTA_CONCURRENCY",
+            self.concurrency.map(|n| n.to_string()),
+        );
+        set_default(
+            "ARTEFACTA_MAX_BANDWIDTH",
+            self.max_bandwidth.map(|rate| rate.to_string()),
+        );
+        set_default(
+            "ARTEFACTA_CACHE_MAX_AGE",
+            self.cache_max_age.map(|secs| secs.to_string()),
+        );
+        set_default(
+            "ARTEFACTA_DIFF_PARALLEL_THRESHOLD",
+            self.diff_parallel_threshold.map(|size| size.to_string()),
+        );
+        set_default("ARTEFACTA_WEBHOOK", self.webhook.clone());
+        set_default(
+            "ARTEFACTA_METRICS_FILE",
+            self.metrics_file
+                .as_ref()
+                .map(|path| path.display().to_string()),
+        );
+        set_default(
+            "ARTEFACTA_AUTO_PATCH_JOBS",
+            self.auto_patch_jobs.map(|n| n.to_string()),
+        );
+        set_default("ARTEFACTA_BUILD_SUFFIX", self.build_suffix.clone());
+        set_default("ARTEFACTA_PATCH_SUFFIX", self.patch_suffix.clone());
+        set_default("ARTEFACTA_PATCH_SEPARATOR", self.patch_separator.clone());
+        set_default("AWS_ACCESS_KEY_ID", self.s3.access_key_id.clone());
+        set_default("AWS_SECRET_ACCESS_KEY", self.s3.secret_access_key.clone());
+        set_default("AWS_PROFILE", self.s3.profile.clone());
+    }
+}
+
+fn user_config_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_full_config() {
+        let config = Config::parse(
+            r#"
+            # where builds live
+            local_store = "/var/lib/artefacta"
+            remote_store = 's3://bucket.example.com/builds' # trailing comment
+            encryption_key_file = "/etc/artefacta/key"
+            signing_key = "/etc/artefacta/signing-key"
+            verify_key = "/etc/artefacta/signing-key.pub"
+            compression_level = 1_9
+            prefix = "app-#1-"
+            tag_pattern = '^v(?P<major>\d+)\.(?P<minor>\d+)$'
+            tag_order = "time"
+            keep_builds = 3
+            post_install = "systemctl restart app" # restart with new build
+            checksum = "blake3"
+            diff_window = 16
+            concurrency = 8
+            max_bandwidth = 1_000_000
+            cache_max_age = 300
+            diff_parallel_threshold = 50
+            webhook = "https://ci.example.com/hooks/artefacta"
+            metrics_file = "/var/lib/node_exporter/artefacta.prom"
+            auto_patch_jobs = 4
+            build_suffix = ".tar.zstd"
+            patch_suffix = ".bsdiff.zstd"
+            patch_separator = "_to_"
+
+            [s3]
+            access_key_id = "key"
+            secret_access_key = "se\"cret"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config,
+            Config {
+                local_store: Some("/var/lib/artefacta".into()),
+                remote_store: Some("s3://bucket.example.com/builds".into()),
+                encryption_key_file: Some("/etc/artefacta/key".into()),
+                signing_key: Some("/etc/artefacta/signing-key".into()),
+                verify_key: Some("/etc/artefacta/signing-key.pub".into()),
+                compression_level: Some(19),
+                prefix: Some("app-#1-".into()),
+                tag_pattern: Some(r"^v(?P<major>\d+)\.(?P<minor>\d+)$".into()),
+                tag_order: Some("time".into()),
+                keep_builds: Some(3),
+                post_install: Some("systemctl restart app".into()),
+                checksum: Some("blake3".into()),
+                diff_window: Some(16),
+                concurrency: Some(8),
+                max_bandwidth: Some(1_000_000),
+                cache_max_age: Some(300),
+                diff_parallel_threshold: Some(50),
+                webhook: Some("https://ci.example.com/hooks/artefacta".into()),
+                metrics_file: Some("/var/lib/node_exporter/artefacta.prom".into()),
+                auto_patch_jobs: Some(4),
+                build_suffix: Some(".tar.zstd".into()),
+                patch_suffix: Some(".bsdiff.zstd".into()),
+                patch_separator: Some("_to_".into()),
+                s3: S3Config {
+                    access_key_id: Some("key".into()),
+                    secret_access_key: Some("se\"cret".into()),
+                    profile: None,
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn empty_config_is_fine() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+        assert_eq!(Config::parse("# nothing\n\n").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn unknown_options_are_rejected() {
+        assert!(Config::parse("local-store = \"/tmp\"").is_err());
+        assert!(Config::parse("[s3]\nregion = \"eu\"").is_err());
+    }
+
+    #[test]
+    fn wrong_types_are_rejected() {
+        assert!(Config::parse("compression_level = \"high\"").is_err());
+        assert!(Config::parse("local_store = 42").is_err());
+        assert!(Config::parse("local_store = \"unclosed").is_err());
+        assert!(Config::parse("[s3").is_err());
+        assert!(Config::parse("just some words").is_err());
+    }
+
+    #[test]
+    fn relative_local_store_is_relative_to_config_file() {
+        let dir = crate::test_helpers::tempdir().unwrap();
+        let path = dir.path().join(FILE_NAME);
+        fs::write(&path, "local_store = \"store\"").unwrap();
+
+        let config = Config::read(&path).unwrap();
+        assert_eq!(config.local_store, Some(dir.path().join("store")));
+    }
+}
@@ -0,0 +1,389 @@
+//! Binary diffs of builds in bounded memory
+//!
+//! bidiff needs both inputs as slices and builds a suffix array of the old
+//! one, which takes four times its size. For large builds, we instead diff
+//! them window by window: Each window of the new build is compared to the part
+//! of the old build around the same offset. Content that moved further than
+//! that isn't found, so patches get a bit larger, but only a few windows need
+//! to be in memory at any time.
+//!
+//! The result is a regular bipatch file, as the controls of all windows are
+//! written to the same stream with seeks between them.
+//!
+//! The inputs are either read as streams, keeping only the current windows in
+//! memory, or given as slices, e.g. of memory-mapped files (with the `mmap`
+//! feature), which the OS can page in and out as needed.
+
+use crate::progress::Progress;
+use std::{
+    io::{self, Read, Write},
+    ops::Range,
+};
+
+/// Size of the windows of the new build to diff at once, by default
+///
+/// Diffing needs about twelve times this much memory. Builds that fit into a
+/// single window are diffed as a whole. (Keep in sync with the
+/// default of `--diff-window`.)
+pub const DEFAULT_WINDOW_SIZE: usize = 64 * 1024 * 1024;
+
+/// Smallest window size that makes sense
+pub const MIN_WINDOW_SIZE: usize = 4 * 1024;
+
+/// Size of the chunks of each window that are scanned in parallel, by default
+///
+/// (Keep in sync with the default of `--diff-chunk-size`.)
+pub const DEFAULT_SCAN_CHUNK_SIZE: usize = 100_000_000;
+
+/// Builds larger than this are sorted with [`LARGE_BUILD_THREADS`] by default
+///
+/// (Keep in sync with the default of `--diff-parallel-threshold`.)
+pub const DEFAULT_PARALLEL_THRESHOLD: u64 = 100_000_000;
+
+/// Threads for sorting large builds, by default
+pub const LARGE_BUILD_THREADS: usize = 4;
+
+/// Parameters for diffing a build of `new_build_size` (compressed) bytes
+///
+/// Without `threads`, builds larger than `parallel_threshold` are sorted in
+/// [`LARGE_BUILD_THREADS`] partitions and others in one.
+pub(crate) fn params(
+    threads: Option<usize>,
+    chunk_size: usize,
+    parallel_threshold: u64,
+    new_build_size: u64,
+) -> Result<bidiff::DiffParams, String> {
+    let threads = sort_threads(threads, parallel_threshold, new_build_size);
+    bidiff::DiffParams::new(threads, Some(chunk_size)).map_err(|e| e.to_string())
+}
+
+fn sort_threads(threads: Option<usize>, parallel_threshold: u64, new_build_size: u64) -> usize {
+    match threads {
+        Some(threads) => threads,
+        None if new_build_size > parallel_threshold => LARGE_BUILD_THREADS,
+        None => 1,
+    }
+}
+
+/// Diff `older` and `newer` window by window, writing a bipatch to `out`
+///
+/// The part of `older` searched for matches extends a quarter window beyond
+/// the current window of `newer` on each side. Returns the size of `newer`.
+///
+/// Reports progress as matches come in. Note that when scanning in chunks (see
+/// `params`), bidiff only hands out matches once all chunks of a window are
+/// scanned.
+pub(crate) fn diff_windowed(
+    older: impl Read,
+    newer: impl Read,
+    out: &mut dyn Write,
+    progress: &Progress,
+    window_size: usize,
+    params: &bidiff::DiffParams,
+) -> io::Result<u64> {
+    diff_sources(
+        Window::new(older),
+        Window::new(newer),
+        out,
+        progress,
+        window_size,
+        params,
+    )
+}
+
+/// Same as [`diff_windowed`] but for inputs that are already in (virtual)
+/// memory
+///
+/// Windows are slices of the inputs, so they are not copied.
+#[cfg_attr(not(all(feature = "mmap", unix)), allow(dead_code))]
+pub(crate) fn diff_slices_windowed(
+    older: &[u8],
+    newer: &[u8],
+    out: &mut dyn Write,
+    progress: &Progress,
+    window_size: usize,
+    params: &bidiff::DiffParams,
+) -> io::Result<u64> {
+    diff_sources(
+        SliceWindow::new(older),
+        SliceWindow::new(newer),
+        out,
+        progress,
+        window_size,
+        params,
+    )
+}
+
+fn diff_sources(
+    mut older: impl Source,
+    mut newer: impl Source,
+    out: &mut dyn Write,
+    progress: &Progress,
+    window_size: usize,
+    params: &bidiff::DiffParams,
+) -> io::Result<u64> {
+    let window_size = window_size.max(MIN_WINDOW_SIZE);
+    let margin = window_size / 4;
+    let mut controls = Controls::new(out)?;
+
+    let mut start = 0;
+    loop {
+        newer.slide(start, start + window_size)?;
+        if newer.data().is_empty() {
+            break;
+        }
+        older.slide(start.saturating_sub(margin), start + window_size + margin)?;
+
+        let (old, new) = (older.data(), newer.data());
+        if old.is_empty() {
+            // nothing to match against, so it's all new
+            controls.push(
+                old,
+                older.offset(),
+                new,
+                bidiff::Match {
+                    add_old_start: 0,
+                    add_new_start: 0,
+                    add_length: 0,
+                    copy_end: new.len(),
+                },
+            )?;
+        } else {
+            bidiff::diff(old, new, params, |m| {
+                progress.advance_to((start + m.copy_end) as u64);
+                controls.push(old, older.offset(), new, m)
+            })?;
+        }
+        start += new.len();
+    }
+
+    controls.finish()?;
+    Ok(start as u64)
+}
+
+/// Part of an input that is currently diffed
+trait Source {
+    /// Move to `start..end`, or as much of it as the input has
+    ///
+    /// Windows only ever move forward.
+    fn slide(&mut self, start: usize, end: usize) -> io::Result<()>;
+
+    fn data(&self) -> &[u8];
+
+    /// Position of [`Source::data`] in the input
+    fn offset(&self) -> usize;
+}
+
+/// Part of a stream kept in memory, starting at `offset`
+struct Window<R> {
+    reader: R,
+    offset: usize,
+    data: Vec<u8>,
+}
+
+impl<R: Read> Window<R> {
+    fn new(reader: R) -> Self {
+        Window {
+            reader,
+            offset: 0,
+            data: Vec::new(),
+        }
+    }
+}
+
+impl<R: Read> Source for Window<R> {
+    fn slide(&mut self, start: usize, end: usize) -> io::Result<()> {
+        debug_assert!(start >= self.offset, "can't slide window backwards");
+
+        let keep_from = (start - self.offset).min(self.data.len());
+        self.data.drain(..keep_from);
+        self.offset += keep_from;
+
+        if start > self.offset {
+            let skipped = io::copy(
+                &mut (&mut self.reader).take((start - self.offset) as u64),
+                &mut io::sink(),
+            )?;
+            self.offset += skipped as usize;
+        }
+
+        let missing = end.saturating_sub(self.offset + self.data.len());
+        (&mut self.reader)
+            .take(missing as u64)
+            .read_to_end(&mut self.data)?;
+        Ok(())
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+/// Part of an input that is already in memory
+struct SliceWindow<'a> {
+    input: &'a [u8],
+    range: Range<usize>,
+}
+
+impl<'a> SliceWindow<'a> {
+    fn new(input: &'a [u8]) -> Self {
+        SliceWindow { input, range: 0..0 }
+    }
+}
+
+impl<'a> Source for SliceWindow<'a> {
+    fn slide(&mut self, start: usize, end: usize) -> io::Result<()> {
+        let len = self.input.len();
+        self.range = start.min(len)..end.min(len);
+        Ok(())
+    }
+
+    fn data(&self) -> &[u8] {
+        &self.input[self.range.clone()]
+    }
+
+    fn offset(&self) -> usize {
+        self.range.start
+    }
+}
+
+/// Turns matches of all windows into controls of one patch
+///
+/// Like [`bidiff::Translator`], a control is only written once the next match
+/// is known, as its seek depends on where that one starts in the old build.
+/// As the windows are gone by then, we keep a copy of the control's data.
+struct Controls<W: Write> {
+    writer: bidiff::enc::Writer<W>,
+    pending: Option<PendingControl>,
+}
+
+struct PendingControl {
+    add: Vec<u8>,
+    copy: Vec<u8>,
+    /// Position in the old build after the add
+    old_end: usize,
+}
+
+impl<W: Write> Controls<W> {
+    fn new(out: W) -> io::Result<Self> {
+        Ok(Controls {
+            writer: bidiff::enc::Writer::new(out)?,
+            pending: None,
+        })
+    }
+
+    /// Add match with positions relative to the windows, where the old window
+    /// starts at `old_offset`
+    fn push(
+        &mut self,
+        old: &[u8],
+        old_offset: usize,
+        new: &[u8],
+        m: bidiff::Match,
+    ) -> io::Result<()> {
+        let old_start = old_offset + m.add_old_start;
+        self.write_pending(old_start)?;
+
+        let add = (0..m.add_length)
+            .map(|i| new[m.add_new_start + i].wrapping_sub(old[m.add_old_start + i]))
+            .collect();
+        self.pending = Some(PendingControl {
+            add,
+            copy: new[m.copy_start()..m.copy_end].to_vec(),
+            old_end: old_start + m.add_length,
+        });
+        Ok(())
+    }
+
+    /// Write pending control, seeking to `next_old_start` afterwards
+    fn write_pending(&mut self, next_old_start: usize) -> io::Result<()> {
+        if let Some(pending) = self.pending.take() {
+            self.writer.write(&bidiff::Control {
+                add: &pending.add,
+                copy: &pending.copy,
+                seek: next_old_start as i64 - pending.old_end as i64,
+            })?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<()> {
+        if let Some(pending) = self.pending.take() {
+            self.writer.write(&bidiff::Control {
+                add: &pending.add,
+                copy: &pending.copy,
+                seek: 0,
+            })?;
+        }
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::random_bytes;
+    use std::io::Cursor;
+
+    fn roundtrip(older: &[u8], newer: &[u8], window_size: usize) {
+        let progress = Progress::new("test", newer.len() as u64);
+        let params = bidiff::DiffParams::default();
+        let mut streamed = Vec::new();
+        let size =
+            diff_windowed(older, newer, &mut streamed, &progress, window_size, &params).unwrap();
+        assert_eq!(size, newer.len() as u64);
+
+        let mut sliced = Vec::new();
+        diff_slices_windowed(older, newer, &mut sliced, &progress, window_size, &params).unwrap();
+        assert!(streamed == sliced, "slices are diffed differently");
+
+        let mut patched = Vec::new();
+        bipatch::Reader::new(Cursor::new(streamed), Cursor::new(older))
+            .unwrap()
+            .read_to_end(&mut patched)
+            .unwrap();
+        assert_eq!(patched, newer, "patch doesn't produce new build");
+    }
+
+    #[test]
+    fn windows_make_up_a_valid_patch() {
+        let older = random_bytes(50_000).unwrap();
+        let mut newer = older.clone();
+        newer[123] ^= 0xff;
+        newer.splice(20_000..20_000, random_bytes(3_000).unwrap());
+        newer.truncate(45_000);
+
+        for window_size in [MIN_WINDOW_SIZE, 10_000, 1_000_000] {
+            roundtrip(&older, &newer, window_size);
+        }
+    }
+
+    #[test]
+    fn large_builds_are_sorted_in_parallel() {
+        let threshold = DEFAULT_PARALLEL_THRESHOLD;
+        assert_eq!(sort_threads(None, threshold, 50_000_000), 1);
+        assert_eq!(
+            sort_threads(None, threshold, 150_000_000),
+            LARGE_BUILD_THREADS
+        );
+        assert_eq!(
+            sort_threads(None, 40_000_000, 50_000_000),
+            LARGE_BUILD_THREADS
+        );
+        assert_eq!(sort_threads(Some(2), 40_000_000, 50_000_000), 2);
+        assert!(params(Some(0), DEFAULT_SCAN_CHUNK_SIZE, threshold, 0).is_err());
+    }
+
+    #[test]
+    fn old_and_new_of_different_sizes() {
+        let older = random_bytes(20_000).unwrap();
+        roundtrip(&older, &[], MIN_WINDOW_SIZE);
+        roundtrip(&[], &older, MIN_WINDOW_SIZE);
+        roundtrip(&older[..5_000], &older, MIN_WINDOW_SIZE);
+        roundtrip(&older, &older[15_000..], MIN_WINDOW_SIZE);
+    }
+}
@@ -0,0 +1,108 @@
+//! Kinds of failures, so `main` can exit with a matching code
+//!
+//! Where we know what went wrong, the error gets an [`ErrorKind`] (see
+//! [`ErrorKind::error`] and [`ErrorKind::wrap`]). Failed network requests are
+//! recognized by their errors. Everything else exits with 1 as before.
+
+use erreur::Report;
+use std::{error::Error as StdError, fmt};
+
+/// What kind of failure an error is, see [`ErrorKind::of`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// An input file doesn't exist or can't be read
+    NoInput,
+    /// The command was used wrongly
+    Usage,
+    /// Remote storage (or another server) can't be reached
+    Unavailable,
+    /// Builds, patches, or signatures are corrupt
+    Data,
+    /// Another process is using the local store, trying again later may work
+    Busy,
+}
+
+impl ErrorKind {
+    /// Exit code for this kind of failure, from `sysexits.h`
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::NoInput => exitcode::NOINPUT,
+            ErrorKind::Usage => exitcode::USAGE,
+            ErrorKind::Unavailable => exitcode::UNAVAILABLE,
+            ErrorKind::Data => exitcode::DATAERR,
+            ErrorKind::Busy => exitcode::TEMPFAIL,
+        }
+    }
+
+    /// Error of this kind
+    pub fn error(self, message: impl fmt::Display) -> Report {
+        Report::new(KindError {
+            kind: self,
+            message: message.to_string(),
+            source: None,
+        })
+    }
+
+    /// Add `message` to `error`, marking it as this kind of failure
+    pub fn wrap(self, error: Report, message: impl fmt::Display) -> Report {
+        Report::new(KindError {
+            kind: self,
+            message: message.to_string(),
+            source: Some(error.into()),
+        })
+    }
+
+    /// Kind of the failure `error` describes, if we know it
+    ///
+    /// The outermost error with a kind wins.
+    pub fn of(error: &Report) -> Option<Self> {
+        error.chain().find_map(|cause| {
+            if let Some(kinded) = cause.downcast_ref::<KindError>() {
+                Some(kinded.kind)
+            } else if cause.is::<rusoto_core::HttpDispatchError>() || cause.is::<hyper::Error>() {
+                Some(ErrorKind::Unavailable)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+#[derive(Debug)]
+struct KindError {
+    kind: ErrorKind,
+    message: String,
+    source: Option<Box<dyn StdError + Send + Sync + 'static>>,
+}
+
+impl fmt::Display for KindError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl StdError for KindError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source
+            .as_deref()
+            .map(|source| source as &(dyn StdError + 'static))
+    }
+}
+
+#[test]
+fn kinds_survive_context() {
+    use erreur::Context;
+
+    let missing: erreur::Result<()> = Err(ErrorKind::NoInput.error("no such file"));
+    let error = missing.context("add build").unwrap_err();
+    assert_eq!(ErrorKind::of(&error), Some(ErrorKind::NoInput));
+
+    let corrupt = ErrorKind::Data.wrap(error, "build is corrupt");
+    assert_eq!(ErrorKind::of(&corrupt), Some(ErrorKind::Data));
+    assert_eq!(
+        corrupt.chain().map(|e| e.to_string()).collect::<Vec<_>>(),
+        ["build is corrupt", "add build", "no such file"]
+    );
+
+    assert_eq!(ErrorKind::of(&Report::msg("something else")), None);
+}
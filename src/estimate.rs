@@ -0,0 +1,170 @@
+//! Quick estimates of how large a patch would be
+//!
+//! Calculating a patch sorts the old build and searches it for every part of
+//! the new one. To guess whether that's worth it, we instead split both builds
+//! into chunks and look up which chunks of the new build also appear in the
+//! old one. Chunk boundaries depend on the content around them (using a gear
+//! hash, like content-defined chunking in backup tools), so data inserted
+//! into the new build only changes the chunks around it, not all that follow.
+//!
+//! Content that didn't change is cheap in a patch, everything else ends up in
+//! it about as well compressed as in the build. As this misses content that
+//! changed only a little (which bidiff still finds), the estimate is on the
+//! pessimistic side.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet},
+    hash::Hasher,
+    io::{self, Read},
+};
+
+/// Chunks are at least this large, ...
+const MIN_CHUNK_SIZE: usize = 2 * 1024;
+/// ... at most this large, ...
+const MAX_CHUNK_SIZE: usize = 64 * 1024;
+/// ... and 8 KiB on average (a boundary is where the low 13 bits of the hash
+/// are zero)
+const BOUNDARY_MASK: u64 = (1 << 13) - 1;
+
+/// Random values for each byte, for the gear hash
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    // splitmix64, so the table is the same in every build
+    let mut table = [0; 256];
+    let mut state: u64 = 0x5eed_a47e_fac7_a000;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// How much of a new build's content is also in the old one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Comparison {
+    /// Size of the (decompressed) new build
+    pub new_size: u64,
+    /// Bytes of the new build in chunks that the old one doesn't have
+    pub unmatched: u64,
+}
+
+/// Compare decompressed builds chunk by chunk
+///
+/// Only keeps hashes of the old build's chunks in memory.
+pub fn compare(old: impl Read, new: impl Read) -> io::Result<Comparison> {
+    let mut old_chunks = HashSet::new();
+    for_each_chunk(old, |chunk| {
+        old_chunks.insert(chunk_hash(chunk));
+    })?;
+
+    let mut unmatched = 0;
+    let new_size = for_each_chunk(new, |chunk| {
+        if !old_chunks.contains(&chunk_hash(chunk)) {
+            unmatched += chunk.len() as u64;
+        }
+    })?;
+    Ok(Comparison {
+        new_size,
+        unmatched,
+    })
+}
+
+impl Comparison {
+    /// Likely size of a patch, for a new build of `build_size` bytes
+    /// (compressed)
+    pub fn patch_size(&self, build_size: u64) -> u64 {
+        if self.new_size == 0 {
+            return 0;
+        }
+        let ratio = build_size as f64 / self.new_size as f64;
+        (self.unmatched as f64 * ratio).ceil() as u64
+    }
+}
+
+fn chunk_hash(chunk: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(chunk);
+    hasher.finish()
+}
+
+/// Call `f` with each content-defined chunk of `reader`, returning its size
+fn for_each_chunk(mut reader: impl Read, mut f: impl FnMut(&[u8])) -> io::Result<u64> {
+    let mut buf = vec![0; 64 * 1024];
+    let mut chunk = Vec::with_capacity(MAX_CHUNK_SIZE);
+    let mut hash: u64 = 0;
+    let mut total = 0;
+
+    loop {
+        let read = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        total += read as u64;
+
+        let mut start = 0;
+        for (i, &byte) in buf[..read].iter().enumerate() {
+            hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+            let len = chunk.len() + i + 1 - start;
+            if len >= MIN_CHUNK_SIZE && (hash & BOUNDARY_MASK == 0 || len >= MAX_CHUNK_SIZE) {
+                chunk.extend_from_slice(&buf[start..=i]);
+                f(&chunk);
+                chunk.clear();
+                start = i + 1;
+                hash = 0;
+            }
+        }
+        chunk.extend_from_slice(&buf[start..read]);
+    }
+    if !chunk.is_empty() {
+        f(&chunk);
+    }
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::random_bytes;
+
+    #[test]
+    fn chunks_cover_everything() {
+        let data = random_bytes(500_000).unwrap();
+        let mut chunks = Vec::new();
+        let size = for_each_chunk(&data[..], |chunk| chunks.push(chunk.to_vec())).unwrap();
+        assert_eq!(size, data.len() as u64);
+        assert_eq!(chunks.concat(), data);
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.len() <= MAX_CHUNK_SIZE && !chunk.is_empty()));
+        assert!(chunks.len() > 10, "only {} chunks", chunks.len());
+    }
+
+    #[test]
+    fn insertions_only_change_nearby_chunks() {
+        let old = random_bytes(1_000_000).unwrap();
+        let mut new = old.clone();
+        new.splice(300_000..300_000, random_bytes(1_000).unwrap());
+        new[700_000] ^= 0xff;
+
+        let comparison = compare(&old[..], &new[..]).unwrap();
+        assert_eq!(comparison.new_size, new.len() as u64);
+        assert!(
+            comparison.unmatched >= 2_000 && comparison.unmatched < 4 * MAX_CHUNK_SIZE as u64,
+            "{:?}",
+            comparison
+        );
+
+        let unrelated = random_bytes(100_000).unwrap();
+        let comparison = compare(&old[..], &unrelated[..]).unwrap();
+        assert_eq!(comparison.unmatched, unrelated.len() as u64);
+        assert_eq!(comparison.patch_size(50_000), 50_000);
+    }
+}
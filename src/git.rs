@@ -1,10 +1,24 @@
-use erreur::{Context, Result};
+use crate::index::natural_cmp;
+use erreur::{ensure, Context, Result};
+use regex::Regex;
 use smol_str::SmolStr;
+use std::{cmp::Ordering, fmt, str::FromStr};
 
 #[derive(Debug, Clone)]
 pub struct Tag {
     pub name: String,
+    /// When the tag was created
+    ///
+    /// For annotated tags, this is the tagger's date. Lightweight tags don't
+    /// have one, so we use the commit time for them.
     pub time: chrono::NaiveDateTime,
+    /// Time of the tagged commit
+    pub commit_time: chrono::NaiveDateTime,
+    /// Who created the tag (only known for annotated tags)
+    pub tagger: Option<String>,
+    /// Message of annotated tags
+    pub message: Option<String>,
+    /// ID of the tagged commit
     pub id: git2::Oid,
 }
 
@@ -17,27 +31,93 @@ pub fn get_tags(repo: &git2::Repository) -> Result<Vec<Tag>> {
             let commit = reference.peel_to_commit().with_context(|| {
                 format!("cannot get commit for reference {:?}", reference.name())
             })?;
+            let commit_time = to_datetime(commit.time()).context("cannot read commit time")?;
+            // lightweight tags point to the commit directly
+            let annotated = reference.peel_to_tag().ok();
+            let signature = annotated.as_ref().and_then(|tag| tag.tagger());
+            let time = match &signature {
+                Some(signature) => to_datetime(signature.when()).context("cannot read tag time")?,
+                None => commit_time,
+            };
+
             Ok(Tag {
                 name: reference.shorthand().map(String::from).unwrap_or_else(|| {
                     String::from_utf8_lossy(reference.shorthand_bytes()).to_string()
                 }),
-                time: chrono::NaiveDateTime::from_timestamp_opt(commit.time().seconds(), 0)
-                    .context("cannot read commit time")?,
+                time,
+                commit_time,
+                tagger: signature
+                    .as_ref()
+                    .map(|signature| String::from_utf8_lossy(signature.name_bytes()).to_string()),
+                message: annotated
+                    .as_ref()
+                    .and_then(|tag| tag.message_bytes())
+                    .map(|message| String::from_utf8_lossy(message).trim_end().to_string()),
                 id: commit.id(),
             })
         })
         .collect()
 }
 
+fn to_datetime(time: git2::Time) -> Option<chrono::NaiveDateTime> {
+    chrono::NaiveDateTime::from_timestamp_opt(time.seconds(), 0)
+}
+
 pub fn tag_to_slice(tag: &str) -> Vec<SmolStr> {
     tag.to_lowercase()
-        .split(|c| c == '.' || c == '-')
+        .split(['.', '-'])
         .map(SmolStr::from)
         .collect()
 }
 
+/// Which tag counts as the newest when several could be patched from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagOrder {
+    /// Compare names, with numbers in them compared numerically
+    Name,
+    /// Compare when tags were created (see [`Tag::time`])
+    Time,
+}
+
+impl Default for TagOrder {
+    fn default() -> Self {
+        TagOrder::Name
+    }
+}
+
+impl FromStr for TagOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(TagOrder::Name),
+            "time" => Ok(TagOrder::Time),
+            _ => Err(format!("unknown tag order `{}`", s)),
+        }
+    }
+}
+
+impl fmt::Display for TagOrder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TagOrder::Name => write!(f, "name"),
+            TagOrder::Time => write!(f, "time"),
+        }
+    }
+}
+
+impl TagOrder {
+    fn compare(self, a: &Tag, b: &Tag) -> Ordering {
+        let by_name = || natural_cmp(&a.name, &b.name);
+        match self {
+            TagOrder::Name => by_name(),
+            TagOrder::Time => a.time.cmp(&b.time).then_with(by_name),
+        }
+    }
+}
+
 /// assume versions are in format `….c.b.a` (or `…-c-b-a`)
-pub fn find_tags_to_patch(current: &str, tags: &[String]) -> Result<Vec<String>> {
+pub fn find_tags_to_patch(current: &str, tags: &[Tag], order: TagOrder) -> Result<Vec<String>> {
     fn dec(x: &SmolStr) -> Option<SmolStr> {
         let num = x.parse::<u32>().ok()?;
         let prev = num.checked_sub(1)?;
@@ -45,11 +125,14 @@ pub fn find_tags_to_patch(current: &str, tags: &[String]) -> Result<Vec<String>>
     }
 
     let tags = {
-        let mut tags = tags.to_vec();
-        tags.sort_by(|a, b| human_sort::compare(a, b));
+        let mut tags = tags.iter().collect::<Vec<_>>();
+        tags.sort_by(|a, b| order.compare(a, b));
         tags
     };
-    let parsed_tags = tags.iter().map(|tag| tag_to_slice(tag)).collect::<Vec<_>>();
+    let parsed_tags = tags
+        .iter()
+        .map(|tag| tag_to_slice(&tag.name))
+        .collect::<Vec<_>>();
     let current = tag_to_slice(current);
     let to_patch: Vec<String> = (0..current.len())
         .filter_map(|pos_from_end| {
@@ -64,10 +147,9 @@ pub fn find_tags_to_patch(current: &str, tags: &[String]) -> Result<Vec<String>>
                 if let Some((idx, _)) = parsed_tags
                     .iter()
                     .enumerate()
-                    .filter(|(_idx, tag)| tag.starts_with(&prev[..=pos]))
-                    .last()
+                    .rfind(|(_idx, tag)| tag.starts_with(&prev[..=pos]))
                 {
-                    return Some(tags[idx].clone());
+                    return Some(tags[idx].name.clone());
                 } else {
                     log::debug!("no matching tag for {:?}", prev);
                 }
@@ -79,18 +161,250 @@ pub fn find_tags_to_patch(current: &str, tags: &[String]) -> Result<Vec<String>>
     Ok(to_patch)
 }
 
+/// Version components to look for in tag names, most significant first
+const COMPONENTS: &[&str] = &["major", "minor", "patch"];
+
+/// Regular expression describing the version format used in tags
+///
+/// Needs at least one of the named groups `major`, `minor`, and `patch`,
+/// each matching a number. An optional `pre` group marks pre-releases (like
+/// `rc1`), which sort before the release with the same numbers.
+#[derive(Debug, Clone)]
+pub struct TagPattern(Regex);
+
+impl TagPattern {
+    /// Numeric components of `tag`, or `None` if it doesn't match
+    ///
+    /// Components whose group didn't participate in the match count as 0.
+    fn components(&self, tag: &str) -> Option<Vec<u64>> {
+        let captures = self.0.captures(tag)?;
+        COMPONENTS
+            .iter()
+            .filter(|name| self.has_group(name))
+            .map(|name| match captures.name(name) {
+                Some(x) => x.as_str().parse().ok(),
+                None => Some(0),
+            })
+            .collect()
+    }
+
+    fn is_pre_release(&self, tag: &str) -> bool {
+        self.0
+            .captures(tag)
+            .map_or(false, |captures| captures.name("pre").is_some())
+    }
+
+    fn has_group(&self, name: &str) -> bool {
+        self.0.capture_names().flatten().any(|group| group == name)
+    }
+}
+
+impl FromStr for TagPattern {
+    type Err = erreur::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let regex = Regex::new(s).with_context(|| format!("invalid tag pattern `{}`", s))?;
+        let pattern = TagPattern(regex);
+        ensure!(
+            COMPONENTS.iter().any(|name| pattern.has_group(name)),
+            "tag pattern `{}` needs at least one of the groups `(?P<major>…)`, \
+            `(?P<minor>…)`, or `(?P<patch>…)`",
+            s
+        );
+        Ok(pattern)
+    }
+}
+
+impl fmt::Display for TagPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// How versions are read from tag names
+#[derive(Debug, Clone)]
+pub enum TagFormat {
+    /// See [`find_tags_to_patch`]
+    Heuristic,
+    /// See [`find_tags_to_patch_with_pattern`]
+    Pattern(TagPattern),
+    /// See [`find_tags_to_patch_semver`]
+    Semver,
+}
+
+impl Default for TagFormat {
+    fn default() -> Self {
+        TagFormat::Heuristic
+    }
+}
+
+/// Find tags to create patches from, using the given tag format
+pub fn find_tags_to_patch_with_format(
+    current: &str,
+    tags: &[Tag],
+    format: &TagFormat,
+    order: TagOrder,
+) -> Result<Vec<String>> {
+    match format {
+        TagFormat::Heuristic => find_tags_to_patch(current, tags, order),
+        TagFormat::Pattern(pattern) => {
+            find_tags_to_patch_with_pattern(current, tags, pattern, order)
+        }
+        TagFormat::Semver => find_tags_to_patch_semver(current, tags, order),
+    }
+}
+
+/// Like [`find_tags_to_patch`], but read versions using `pattern`
+///
+/// For each component (starting with the least significant one), picks the
+/// newest tag that has the same more significant components as `current` and
+/// a smaller value for this one. Tags not matching `pattern` are ignored.
+///
+/// With [`TagOrder::Name`], "newest" means the highest version.
+pub fn find_tags_to_patch_with_pattern(
+    current: &str,
+    tags: &[Tag],
+    pattern: &TagPattern,
+    order: TagOrder,
+) -> Result<Vec<String>> {
+    let current_parts = pattern.components(current).with_context(|| {
+        format!(
+            "current version `{}` does not match tag pattern `{}`",
+            current, pattern
+        )
+    })?;
+
+    let candidates = tags
+        .iter()
+        .filter_map(|tag| {
+            Some(Candidate {
+                parts: pattern.components(&tag.name)?,
+                version: pattern.is_pre_release(&tag.name),
+                tag,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    Ok(predecessors(&current_parts, &candidates, order, |a, b| {
+        a.parts
+            .cmp(&b.parts)
+            .then_with(|| match (a.version, b.version) {
+                (true, false) => Ordering::Less,
+                (false, true) => Ordering::Greater,
+                _ => natural_cmp(&a.tag.name, &b.tag.name),
+            })
+    }))
+}
+
+/// Like [`find_tags_to_patch`], but read tags as semantic versions
+///
+/// Picks the highest earlier patch release of the current minor version, the
+/// highest release of an earlier minor version, and the highest release of
+/// an earlier major version. A leading `v` is fine, other tags that aren't
+/// valid semver are ignored.
+pub fn find_tags_to_patch_semver(
+    current: &str,
+    tags: &[Tag],
+    order: TagOrder,
+) -> Result<Vec<String>> {
+    let current = parse_semver(current)
+        .with_context(|| format!("current version `{}` is not valid semver", current))?;
+
+    let candidates = tags
+        .iter()
+        .filter_map(|tag| match parse_semver(&tag.name) {
+            Some(version) => Some(Candidate {
+                parts: vec![version.major, version.minor, version.patch],
+                version,
+                tag,
+            }),
+            None => {
+                log::trace!("ignoring tag `{}`, it's not semver", tag.name);
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    Ok(predecessors(
+        &[current.major, current.minor, current.patch],
+        &candidates,
+        order,
+        |a, b| {
+            a.version
+                .cmp(&b.version)
+                .then_with(|| natural_cmp(&a.tag.name, &b.tag.name))
+        },
+    ))
+}
+
+fn parse_semver(tag: &str) -> Option<semver::Version> {
+    semver::Version::parse(tag.strip_prefix('v').unwrap_or(tag)).ok()
+}
+
+/// Tag with its version split into numeric components
+struct Candidate<'t, V> {
+    parts: Vec<u64>,
+    version: V,
+    tag: &'t Tag,
+}
+
+/// Newest candidate for each component of `current`, least significant first
+///
+/// Candidates need to have the same more significant components as `current`
+/// and a smaller value for the component itself.
+fn predecessors<V>(
+    current: &[u64],
+    candidates: &[Candidate<V>],
+    order: TagOrder,
+    by_version: impl Fn(&Candidate<V>, &Candidate<V>) -> Ordering,
+) -> Vec<String> {
+    let newer = |a: &&Candidate<V>, b: &&Candidate<V>| match order {
+        TagOrder::Name => by_version(a, b),
+        TagOrder::Time => a.tag.time.cmp(&b.tag.time).then_with(|| by_version(a, b)),
+    };
+
+    let mut to_patch: Vec<String> = Vec::new();
+    for pos in (0..current.len()).rev() {
+        let candidate = candidates
+            .iter()
+            .filter(|c| c.parts[..pos] == current[..pos] && c.parts[pos] < current[pos])
+            .max_by(newer);
+        match candidate {
+            Some(c) if !to_patch.contains(&c.tag.name) => to_patch.push(c.tag.name.clone()),
+            Some(_) => {}
+            None => log::debug!("no matching tag below {:?} at {}", current, pos),
+        }
+    }
+    to_patch
+}
+
+/// Tags with the given names, tagged in that order
+#[cfg(test)]
+fn tagged(names: &[&str]) -> Vec<Tag> {
+    names
+        .iter()
+        .enumerate()
+        .map(|(idx, name)| {
+            let time =
+                chrono::NaiveDateTime::from_timestamp_opt(1_600_000_000 + idx as i64, 0).unwrap();
+            Tag {
+                name: name.to_string(),
+                time,
+                commit_time: time,
+                tagger: None,
+                message: None,
+                id: git2::Oid::zero(),
+            }
+        })
+        .collect()
+}
+
 #[test]
 fn tags_to_patch_from_works() {
     crate::test_helpers::logger();
-    let tags = vec![
-        "IL40.0.0".to_string(),
-        "IL40.0.1".to_string(),
-        "IL40.1.0".to_string(),
-        "IL40.2.17".to_string(),
-        "IL40.2.18".to_string(),
-    ];
+    let tags = tagged(&["IL40.0.0", "IL40.0.1", "IL40.1.0", "IL40.2.17", "IL40.2.18"]);
     let current_tag = "IL40.2.19";
-    let patch_these = find_tags_to_patch(current_tag, &tags).unwrap();
+    let patch_these = find_tags_to_patch(current_tag, &tags, TagOrder::Name).unwrap();
     assert_eq!(
         patch_these,
         vec!["IL40.2.18".to_string(), "IL40.1.0".to_string()]
@@ -100,33 +414,27 @@ fn tags_to_patch_from_works() {
 #[test]
 fn tags_to_patch_from_1() {
     crate::test_helpers::logger();
-    let tags = vec![];
+    let tags = tagged(&[]);
     let current_tag = "IL40.2.19";
-    let patch_these = find_tags_to_patch(current_tag, &tags).unwrap();
+    let patch_these = find_tags_to_patch(current_tag, &tags, TagOrder::Name).unwrap();
     assert!(patch_these.is_empty());
 }
 
 #[test]
 fn tags_to_patch_from_2() {
     crate::test_helpers::logger();
-    let tags = vec!["garbage".to_string(), "v1.5-1.beta.1".to_string()];
+    let tags = tagged(&["garbage", "v1.5-1.beta.1"]);
     let current_tag = "v2.0.0";
-    let patch_these = find_tags_to_patch(current_tag, &tags).unwrap();
+    let patch_these = find_tags_to_patch(current_tag, &tags, TagOrder::Name).unwrap();
     assert!(patch_these.is_empty());
 }
 
 #[test]
 fn tags_to_patch_from_3() {
     crate::test_helpers::logger();
-    let tags = vec![
-        "IL40.0.0".to_string(),
-        "IL40.0.1".to_string(),
-        "IL40.1.x".to_string(),
-        "IL40.2.17".to_string(),
-        "IL40.2.18".to_string(),
-    ];
+    let tags = tagged(&["IL40.0.0", "IL40.0.1", "IL40.1.x", "IL40.2.17", "IL40.2.18"]);
     let current_tag = "IL40.2.19";
-    let patch_these = find_tags_to_patch(current_tag, &tags).unwrap();
+    let patch_these = find_tags_to_patch(current_tag, &tags, TagOrder::Name).unwrap();
     assert_eq!(
         patch_these,
         vec!["IL40.2.18".to_string(), "IL40.1.x".to_string()]
@@ -135,15 +443,9 @@ fn tags_to_patch_from_3() {
 
 #[test]
 fn tags_to_patch_from_4() {
-    let tags = vec![
-        "IL40.0.1".to_string(),
-        "IL40.1.0".to_string(),
-        "IL40.2.17".to_string(),
-        "IL40.2.18".to_string(),
-        "IL40.x.0".to_string(),
-    ];
+    let tags = tagged(&["IL40.0.1", "IL40.1.0", "IL40.2.17", "IL40.2.18", "IL40.x.0"]);
     let current_tag = "IL40.2.19";
-    let patch_these = find_tags_to_patch(current_tag, &tags).unwrap();
+    let patch_these = find_tags_to_patch(current_tag, &tags, TagOrder::Name).unwrap();
     assert_eq!(
         patch_these,
         vec!["IL40.2.18".to_string(), "IL40.1.0".to_string()]
@@ -152,15 +454,9 @@ fn tags_to_patch_from_4() {
 
 #[test]
 fn tags_to_patch_from_fuzzy() {
-    let tags = vec![
-        "IL40.0.1".to_string(),
-        "IL40.1.0".to_string(),
-        "IL40.2.17".to_string(),
-        "IL40.2.18".to_string(),
-        "IL40.x.0".to_string(),
-    ];
+    let tags = tagged(&["IL40.0.1", "IL40.1.0", "IL40.2.17", "IL40.2.18", "IL40.x.0"]);
     let current_tag = "il40-2-19";
-    let patch_these = find_tags_to_patch(current_tag, &tags).unwrap();
+    let patch_these = find_tags_to_patch(current_tag, &tags, TagOrder::Name).unwrap();
     assert_eq!(
         patch_these,
         vec!["IL40.2.18".to_string(), "IL40.1.0".to_string()]
@@ -169,26 +465,190 @@ fn tags_to_patch_from_fuzzy() {
 
 #[test]
 fn tags_to_patch_from_5() {
-    let tags = vec![
-        "il60-0-8".to_string(),
-        "il60-0-9".to_string(),
-        "il60-0-10".to_string(),
-        "il60-0-11".to_string(),
-    ];
+    let tags = tagged(&["il60-0-8", "il60-0-9", "il60-0-10", "il60-0-11"]);
     let current_tag = "il60-1-0";
-    let patch_these = find_tags_to_patch(current_tag, &tags).unwrap();
+    let patch_these = find_tags_to_patch(current_tag, &tags, TagOrder::Name).unwrap();
     assert_eq!(patch_these, vec!["il60-0-11".to_string()]);
 }
 
 #[test]
 fn tags_to_patch_from_5_sorted() {
-    let tags = vec![
-        "il60-0-10".to_string(),
-        "il60-0-11".to_string(),
-        "il60-0-8".to_string(),
-        "il60-0-9".to_string(),
-    ];
+    let tags = tagged(&["il60-0-10", "il60-0-11", "il60-0-8", "il60-0-9"]);
     let current_tag = "il60-1-0";
-    let patch_these = find_tags_to_patch(current_tag, &tags).unwrap();
+    let patch_these = find_tags_to_patch(current_tag, &tags, TagOrder::Name).unwrap();
     assert_eq!(patch_these, vec!["il60-0-11".to_string()]);
 }
+
+#[test]
+fn tags_to_patch_with_calendar_versions() {
+    let pattern: TagPattern = r"^(?P<major>\d{4})\.(?P<minor>\d{2})$".parse().unwrap();
+    let tags = tagged(&["2023.11", "2023.12", "2024.01", "2024.03", "nightly"]);
+    let patch_these =
+        find_tags_to_patch_with_pattern("2024.04", &tags, &pattern, TagOrder::Name).unwrap();
+    assert_eq!(
+        patch_these,
+        vec!["2024.03".to_string(), "2023.12".to_string()]
+    );
+
+    // skipped months are fine, too
+    let patch_these =
+        find_tags_to_patch_with_pattern("2024.03", &tags, &pattern, TagOrder::Name).unwrap();
+    assert_eq!(
+        patch_these,
+        vec!["2024.01".to_string(), "2023.12".to_string()]
+    );
+}
+
+#[test]
+fn tags_to_patch_with_release_candidates() {
+    let pattern: TagPattern =
+        r"^v(?P<major>\d+)\.(?P<minor>\d+)\.(?P<patch>\d+)(-(?P<pre>rc\d+))?$"
+            .parse()
+            .unwrap();
+    let tags = tagged(&[
+        "v1.1.0",
+        "v1.1.1-rc1",
+        "v1.2.0-rc1",
+        "v1.2.0",
+        "v1.2.3-rc1",
+        "v1.2.3-rc2",
+    ]);
+    let patch_these =
+        find_tags_to_patch_with_pattern("v1.2.4", &tags, &pattern, TagOrder::Name).unwrap();
+    assert_eq!(
+        patch_these,
+        vec!["v1.2.3-rc2".to_string(), "v1.1.1-rc1".to_string()]
+    );
+
+    let patch_these =
+        find_tags_to_patch_with_pattern("v1.3.0-rc1", &tags, &pattern, TagOrder::Name).unwrap();
+    assert_eq!(patch_these, vec!["v1.2.3-rc2".to_string()]);
+
+    // without a `pre` group, release candidates are just ignored
+    let pattern: TagPattern = r"^v(?P<major>\d+)\.(?P<minor>\d+)\.(?P<patch>\d+)$"
+        .parse()
+        .unwrap();
+    let patch_these =
+        find_tags_to_patch_with_pattern("v1.2.4", &tags, &pattern, TagOrder::Name).unwrap();
+    assert_eq!(
+        patch_these,
+        vec!["v1.2.0".to_string(), "v1.1.0".to_string()]
+    );
+}
+
+#[test]
+fn tag_pattern_needs_version_groups() {
+    assert!("^v(\\d+)$".parse::<TagPattern>().is_err());
+    assert!("^v(?P<major>\\d+".parse::<TagPattern>().is_err());
+    let pattern: TagPattern = "^v(?P<major>\\d+)$".parse().unwrap();
+    assert!(find_tags_to_patch_with_pattern("latest", &[], &pattern, TagOrder::Name).is_err());
+}
+
+#[test]
+fn tags_to_patch_ordered_by_time() {
+    // the hotfix was tagged after the release candidate, but sorts before it
+    let tags = tagged(&["IL40.2.0", "IL40.2.1-rc", "IL40.2.1-hotfix"]);
+    let current_tag = "IL40.3.0";
+    let by_name = find_tags_to_patch(current_tag, &tags, TagOrder::Name).unwrap();
+    assert_eq!(by_name, vec!["IL40.2.1-rc".to_string()]);
+    let by_time = find_tags_to_patch(current_tag, &tags, TagOrder::Time).unwrap();
+    assert_eq!(by_time, vec!["IL40.2.1-hotfix".to_string()]);
+
+    let pattern: TagPattern = r"^IL(?P<major>\d+)\.(?P<minor>\d+)\.(?P<patch>\d+)"
+        .parse()
+        .unwrap();
+    // a hotfix for an older release tagged after newer ones
+    let tags = tagged(&["IL40.2.0", "IL40.2.1-rc", "IL40.2.0-hotfix"]);
+    let by_name =
+        find_tags_to_patch_with_pattern(current_tag, &tags, &pattern, TagOrder::Name).unwrap();
+    assert_eq!(by_name, vec!["IL40.2.1-rc".to_string()]);
+    let by_time =
+        find_tags_to_patch_with_pattern(current_tag, &tags, &pattern, TagOrder::Time).unwrap();
+    assert_eq!(by_time, vec!["IL40.2.0-hotfix".to_string()]);
+}
+
+#[test]
+fn annotated_tags_have_their_own_time() {
+    let dir = crate::test_helpers::tempdir().unwrap();
+    let repo = git2::Repository::init(dir.path()).unwrap();
+    let committer =
+        git2::Signature::new("Committer", "c@example.com", &git2::Time::new(1_000, 0)).unwrap();
+    let tagger =
+        git2::Signature::new("Tagger", "t@example.com", &git2::Time::new(5_000, 0)).unwrap();
+    let tree = {
+        let mut index = repo.index().unwrap();
+        repo.find_tree(index.write_tree().unwrap()).unwrap()
+    };
+    let commit_id = repo
+        .commit(Some("HEAD"), &committer, &committer, "init", &tree, &[])
+        .unwrap();
+    let commit = repo.find_object(commit_id, None).unwrap();
+    repo.tag_lightweight("v1.0.0", &commit, false).unwrap();
+    repo.tag("v1.0.1", &commit, &tagger, "hotfix release\n", false)
+        .unwrap();
+
+    let mut tags = get_tags(&repo).unwrap();
+    tags.sort_by(|a, b| a.name.cmp(&b.name));
+    let commit_time = chrono::NaiveDateTime::from_timestamp_opt(1_000, 0).unwrap();
+
+    let lightweight = &tags[0];
+    assert_eq!(lightweight.name, "v1.0.0");
+    assert_eq!(lightweight.time, commit_time);
+    assert_eq!(lightweight.commit_time, commit_time);
+    assert_eq!(lightweight.tagger, None);
+    assert_eq!(lightweight.message, None);
+    assert_eq!(lightweight.id, commit_id);
+
+    let annotated = &tags[1];
+    assert_eq!(annotated.name, "v1.0.1");
+    assert_eq!(
+        annotated.time,
+        chrono::NaiveDateTime::from_timestamp_opt(5_000, 0).unwrap()
+    );
+    assert_eq!(annotated.commit_time, commit_time);
+    assert_eq!(annotated.tagger.as_deref(), Some("Tagger"));
+    assert_eq!(annotated.message.as_deref(), Some("hotfix release"));
+    assert_eq!(annotated.id, commit_id);
+}
+
+#[test]
+fn tags_to_patch_semver() {
+    let tags = tagged(&[
+        "v1.0.0",
+        "v1.4.2",
+        "v1.4.10",
+        "v1.5.0-beta.1",
+        "2.0.0",
+        "v2.0.1",
+        "v2.1.0-rc.1",
+        "v2.1.0-rc.2",
+        "v2.1.0",
+        "v2.1.1",
+        "v2.1.2-rc.1",
+        "v2.1.x",
+        "latest",
+    ]);
+
+    // patch, minor, and major predecessors
+    let patch_these = find_tags_to_patch_semver("v2.1.2", &tags, TagOrder::Name).unwrap();
+    assert_eq!(
+        patch_these,
+        vec![
+            "v2.1.1".to_string(),
+            "v2.0.1".to_string(),
+            "v1.5.0-beta.1".to_string()
+        ]
+    );
+
+    // the first release of a new major version is patched from the latest tag
+    let patch_these = find_tags_to_patch_semver("3.0.0", &tags, TagOrder::Name).unwrap();
+    assert_eq!(patch_these, vec!["v2.1.2-rc.1".to_string()]);
+
+    let patch_these = find_tags_to_patch_semver("v1.4.11", &tags, TagOrder::Name).unwrap();
+    assert_eq!(
+        patch_these,
+        vec!["v1.4.10".to_string(), "v1.0.0".to_string()]
+    );
+
+    assert!(find_tags_to_patch_semver("v1.4", &tags, TagOrder::Name).is_err());
+}
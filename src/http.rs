@@ -0,0 +1,101 @@
+//! Plain HTTP(S) requests, for webhooks and builds from other build systems
+
+use erreur::{ensure, Context, Result};
+use hyper::{body::HttpBody, client::HttpConnector, Body, Client, StatusCode};
+use hyper_rustls::HttpsConnector;
+use std::{
+    io::Write,
+    time::{Duration, Instant},
+};
+
+/// How long to wait for a response, or for more data of a download
+pub(crate) const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often to log progress of downloads
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Client for `http://` and `https://` URLs, trusting the system's CAs
+pub(crate) fn client() -> Client<HttpsConnector<HttpConnector>, Body> {
+    let connector = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_native_roots()
+        .https_or_http()
+        .enable_http1()
+        .build();
+    Client::builder().build(connector)
+}
+
+/// Parse `url` the way hyper wants it
+pub(crate) fn uri(url: &url::Url) -> Result<hyper::Uri> {
+    url.as_str()
+        .parse()
+        .with_context(|| format!("invalid URL `{}`", url))
+}
+
+/// GET `url` and write the response body to `out`, returning its size
+///
+/// Logs progress like downloads from S3 do.
+pub(crate) async fn download(url: &url::Url, out: &mut impl Write) -> Result<u64> {
+    use humansize::{file_size_opts as options, FileSize};
+
+    let client = client();
+    let response = tokio::time::timeout(TIMEOUT, client.get(uri(url)?))
+        .await
+        .with_context(|| format!("no response from `{}` within {}s", url, TIMEOUT.as_secs()))?
+        .with_context(|| format!("request `{}`", url))?;
+
+    let status = response.status();
+    ensure!(status != StatusCode::NOT_FOUND, "`{}` not found (404)", url);
+    ensure!(status.is_success(), "`{}` responded with `{}`", url, status);
+    let total = response
+        .headers()
+        .get(hyper::header::CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok()?.parse::<u64>().ok());
+    let file_size = |bytes: u64| bytes.file_size(options::BINARY).expect("never negative");
+
+    let mut body = response.into_body();
+    let mut size = 0;
+    let mut last_report = Instant::now();
+    loop {
+        let chunk = tokio::time::timeout(TIMEOUT, body.data())
+            .await
+            .with_context(|| {
+                format!(
+                    "no data from `{}` for {}s after {}",
+                    url,
+                    TIMEOUT.as_secs(),
+                    file_size(size)
+                )
+            })?;
+        let chunk = match chunk {
+            Some(chunk) => chunk.with_context(|| format!("read response of `{}`", url))?,
+            None => break,
+        };
+        out.write_all(&chunk).context("write downloaded data")?;
+        size += chunk.len() as u64;
+
+        if last_report.elapsed() >= PROGRESS_INTERVAL {
+            last_report = Instant::now();
+            match total {
+                Some(total) => log::info!(
+                    "reading `{}`… {}/{}",
+                    url,
+                    file_size(size),
+                    file_size(total)
+                ),
+                None => log::info!("reading `{}`… {}", url, file_size(size)),
+            }
+        }
+    }
+    if let Some(total) = total {
+        ensure!(
+            size == total,
+            "got {} bytes from `{}` but expected {}",
+            size,
+            url,
+            total
+        );
+    }
+
+    log::info!("downloaded `{}` ({})", url, file_size(size));
+    Ok(size)
+}
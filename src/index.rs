@@ -1,14 +1,21 @@
 use crate::{
-    apply_patch, paths,
+    apply_patch_to_decompressed,
+    metrics::{Metrics, Operation},
+    paths,
+    progress::Progress,
     storage::{Entry, File as FileEntry, Storage},
-    PartialFile,
+    timings::{self, Phase},
+    ErrorKind, PartialFile,
 };
 use erreur::{bail, ensure, Context, Help, LogAndDiscardResult, Report, Result};
 use std::{
+    collections::BTreeMap,
     convert::TryFrom,
-    fs::File,
-    io::{self, BufReader, Cursor, Read},
-    path::Path,
+    fs::{self, File},
+    io::{self, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
 };
 
 mod build;
@@ -17,7 +24,20 @@ mod patch;
 pub use patch::Patch;
 mod graph;
 pub use graph::{Location, PatchGraph, UpgradePath};
+mod meta;
+pub use meta::BuildMeta;
+mod checksum;
+pub use checksum::{Checksum, ChecksumAlgorithm};
+mod cache;
+mod checksum_cache;
+use cache::RemoteCache;
+use checksum_cache::ChecksumCache;
+mod labels;
+pub use labels::Labels;
+mod signature;
+pub use signature::{Signature, SigningKey, VerifyingKey};
 mod version;
+pub(crate) use version::natural_cmp;
 pub use version::Version;
 
 /// Artefact index
@@ -31,67 +51,581 @@ pub struct Index {
     local: Storage,
     remote: Storage,
     patch_graph: PatchGraph,
+    /// Number of bytes fetched from remote storage so far
+    downloaded: u64,
+    /// Algorithm for checksums of new builds and patches
+    checksum_algorithm: ChecksumAlgorithm,
+    /// Bytes of the new build to diff at once when calculating patches
+    diff_window_size: usize,
+    /// Threads for sorting builds when diffing (depends on size if not set)
+    diff_threads: Option<usize>,
+    /// Bytes of a window to scan at once when diffing
+    diff_chunk_size: usize,
+    /// Builds larger than this are diffed with several threads by default
+    diff_parallel_threshold: u64,
+    /// Number of files to upload at the same time in `push`
+    upload_concurrency: usize,
+    /// Diff memory-mapped copies of decompressed builds
+    diff_mmap: bool,
+    /// Reuse listings of remote storage younger than this (if set)
+    cache_max_age: Option<Duration>,
+    /// Whether the remote part of the graph came from the cache
+    from_cache: bool,
+    /// Upload aliases instead of builds identical to ones on remote
+    alias_duplicates: bool,
+    /// Download builds again when the local file differs from the remote one
+    prefer_remote: bool,
+    /// Where to record transfers and timings (if anywhere)
+    metrics: Option<Arc<Metrics>>,
+    /// Key to sign new builds with
+    signing_key: Option<SigningKey>,
+    /// Key to check signatures of builds with
+    verifying_key: Option<VerifyingKey>,
+    /// Whether remote storage has a labels file (see [`Labels`])
+    remote_labels: bool,
+    /// Checksums of local files we calculated before
+    checksums: ChecksumCache,
+}
+
+/// How [`Index::upgrade_to_build`] would get from one build to another, as
+/// returned by [`Index::plan_upgrade`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UpgradePlan {
+    pub path: UpgradePath,
+    /// Bytes to download from remote storage (files we have locally are free)
+    pub download_size: u64,
+    /// Names of the files to download from remote storage
+    pub files_to_fetch: Vec<String>,
 }
 
 impl Index {
     /// Build index from directory content
     pub async fn new(local: impl AsRef<Path>, remote: Storage) -> Result<Self> {
-        let local = Storage::try_from(local.as_ref())
+        Index::open(local.as_ref(), remote, None).await
+    }
+
+    /// Build index, reusing the listing of remote storage from an earlier run
+    /// if it's younger than `max_age`
+    ///
+    /// The listing is kept in the local store (see [`cache::FILE_NAME`]) and
+    /// updated whenever remote storage is listed. Local storage is always
+    /// listed, as that's cheap. With a `max_age` of zero, remote storage is
+    /// listed again, too.
+    pub async fn new_cached(
+        local: impl AsRef<Path>,
+        remote: Storage,
+        max_age: Duration,
+    ) -> Result<Self> {
+        Index::open(local.as_ref(), remote, Some(max_age)).await
+    }
+
+    async fn open(local: &Path, remote: Storage, cache_max_age: Option<Duration>) -> Result<Self> {
+        let local = Storage::try_from(local)
             .context("invalid local storage path")
             .note("`mkdir -pv` is your friend")?;
-        let mut patch_graph = PatchGraph::empty();
-        patch_graph
-            .update_from_file_list(
-                &remote.list_files().await.context("list files")?,
-                Location::Remote,
-            )
-            .with_context(|| format!("build patch graph from `{:?}`", remote))?;
-        patch_graph
-            .update_from_file_list(
-                &local.list_files().await.context("list files")?,
-                Location::Local,
-            )
-            .with_context(|| format!("build patch graph from `{:?}`", local))?;
 
-        Ok(Index {
+        let mut index = Index {
             local,
             remote,
-            patch_graph,
-        })
+            patch_graph: PatchGraph::empty(),
+            downloaded: 0,
+            checksum_algorithm: ChecksumAlgorithm::default(),
+            diff_window_size: crate::diff::DEFAULT_WINDOW_SIZE,
+            diff_threads: None,
+            diff_chunk_size: crate::diff::DEFAULT_SCAN_CHUNK_SIZE,
+            diff_parallel_threshold: crate::diff::DEFAULT_PARALLEL_THRESHOLD,
+            upload_concurrency: DEFAULT_UPLOAD_CONCURRENCY,
+            diff_mmap: false,
+            cache_max_age,
+            from_cache: false,
+            alias_duplicates: false,
+            prefer_remote: false,
+            metrics: None,
+            signing_key: None,
+            verifying_key: None,
+            remote_labels: false,
+            checksums: ChecksumCache::default(),
+        };
+        index.checksums = ChecksumCache::load(index.local.local_path().as_deref());
+        index.load(cache_max_age).await?;
+
+        Ok(index)
     }
 
-    /// Generate patches from leaf nodes to disconnected nodes
-    pub fn generate_missing_patches(&mut self) -> Result<Vec<String>> {
-        todo!()
+    /// List local and remote storage again and rebuild the graph
+    ///
+    /// Ignores the cached remote listing, but updates it when caching is used.
+    pub async fn refresh(&mut self) -> Result<()> {
+        self.load(self.cache_max_age.map(|_| Duration::ZERO)).await
     }
 
-    pub async fn calculate_patch(&mut self, from: Version, to: Version) -> Result<()> {
-        fn read_file(entry: Entry) -> Result<Vec<u8>> {
-            ensure!(
-                entry.storage.is_local(),
-                "only reading from local storage supported"
+    /// Whether the remote part of the graph came from a cached listing
+    pub fn is_from_cache(&self) -> bool {
+        self.from_cache
+    }
+
+    async fn load(&mut self, cache_max_age: Option<Duration>) -> Result<()> {
+        let cached_remote_files =
+            cache_max_age.and_then(|max_age| self.cached_remote_files(max_age));
+        self.from_cache = cached_remote_files.is_some();
+
+        // Listing remote storage may take a while, so list local storage in
+        // the meantime. Remote files are added to the graph as they come in,
+        // and only kept when they go into the cache.
+        let mut patch_graph = PatchGraph::empty();
+        let mut remote_labels = false;
+        let is_labels = |file: &Entry| file.path.rsplit('/').next() == Some(Labels::FILE_NAME);
+        let list_remote = async {
+            use futures::TryStreamExt;
+
+            let mut update = patch_graph.update_from_files(Location::Remote);
+            let mut listed = Vec::new();
+            let listed_at = match cached_remote_files {
+                Some(files) => {
+                    log::debug!("using cached listing of `{:?}`", self.remote);
+                    for file in files {
+                        remote_labels |= is_labels(&file);
+                        update.add(file)?;
+                    }
+                    None
+                }
+                None => {
+                    let listed_at = SystemTime::now();
+                    let mut files = self.remote.list_files_stream();
+                    while let Some(file) = files.try_next().await.context("list files")? {
+                        if cache_max_age.is_some() {
+                            listed.push(file.clone());
+                        }
+                        remote_labels |= is_labels(&file);
+                        update.add(file)?;
+                    }
+                    Some(listed_at)
+                }
+            };
+            update.finish()?;
+            Ok::<_, Report>((listed, listed_at))
+        };
+        let listing = timings::start(Phase::ListStorage);
+        let (remote_files, local_files) = futures::join!(list_remote, self.local.list_files());
+        drop(listing);
+        let (remote_files, listed_at) =
+            remote_files.with_context(|| format!("build patch graph from `{:?}`", self.remote))?;
+        let local_files = local_files.context("list files")?;
+        if let (Some(listed_at), Some(_)) = (listed_at, cache_max_age) {
+            self.store_remote_cache(listed_at, &remote_files)
+                .log_and_discard();
+        }
+
+        let _timing = timings::start(Phase::BuildGraph);
+        patch_graph
+            .update_from_file_list(&local_files, Location::Local)
+            .with_context(|| format!("build patch graph from `{:?}`", self.local))?;
+        self.patch_graph = patch_graph;
+        self.remote_labels = remote_labels;
+
+        self.load_local_checksums(&local_files);
+        self.load_local_meta(&local_files);
+        for (version, problem) in suspect_builds(&local_files) {
+            log::warn!(
+                "local build `{}` looks corrupt ({}), `artefacta doctor --repair` deletes it \
+                so it's downloaded again",
+                version,
+                problem
             );
-            let path = entry.path;
-            let file =
-                File::open(&path).with_context(|| format!("could not open file {}", path))?;
-            let mut file = BufReader::new(file);
-            let mut bytes = Vec::with_capacity(2 << 20);
-            file.read_to_end(&mut bytes).context("read file")?;
-            Ok(bytes)
         }
+        Ok(())
+    }
 
-        fn file_size(size: u64) -> String {
-            use humansize::{file_size_opts as options, FileSize};
-            size.file_size(options::BINARY).expect("never negative")
+    fn remote_cache_path(&self) -> Option<PathBuf> {
+        self.local
+            .local_path()
+            .map(|dir| dir.join(cache::FILE_NAME))
+    }
+
+    /// Files in remote storage from the cache, if it's fresh enough
+    fn cached_remote_files(&self, max_age: Duration) -> Option<Vec<Entry>> {
+        let path = self.remote_cache_path()?;
+        if !path.exists() {
+            return None;
+        }
+        let cache = match RemoteCache::read(&path) {
+            Ok(cache) => cache,
+            Err(e) => {
+                log::debug!("ignoring cache: {}", e);
+                return None;
+            }
+        };
+        if cache.is_fresh(&self.remote, max_age, SystemTime::now()) {
+            Some(cache.files(&self.remote))
+        } else {
+            None
+        }
+    }
+
+    fn store_remote_cache(&self, listed_at: SystemTime, files: &[Entry]) -> Result<()> {
+        let path = self
+            .remote_cache_path()
+            .context("local storage is not a directory")?;
+        RemoteCache::new(&self.remote, listed_at, files)
+            .write(&path)
+            .context("write listing of remote storage to cache")
+    }
+
+    /// Forget the cached remote listing, as we just changed remote storage
+    fn invalidate_remote_cache(&self) {
+        if let Some(path) = self.remote_cache_path().filter(|path| path.exists()) {
+            if let Err(e) = fs::remove_file(&path) {
+                log::warn!("could not remove cache `{}`: {}", path.display(), e);
+            }
+        }
+    }
+
+    /// Read checksum files of local builds into the graph
+    ///
+    /// Checksums from metadata files take precedence, so call this first.
+    fn load_local_checksums(&mut self, local_files: &[Entry]) {
+        for entry in local_files {
+            let (build_path, algorithm) = match ChecksumAlgorithm::ALL.iter().find_map(|&algo| {
+                let build_path = entry.path.strip_suffix(algo.extension())?;
+                build_path
+                    .strip_suffix('.')
+                    .filter(|path| paths::is_build_path(path))
+                    .map(|path| (path, algo))
+            }) {
+                Some(found) => found,
+                None => continue,
+            };
+            let res = paths::build_version_from_path(build_path).and_then(|version| {
+                let content = fs::read(&entry.path).context("read checksum file")?;
+                let checksum = Checksum::from_sidecar(algorithm, &content)?;
+                self.patch_graph.set_checksum(&version, checksum)
+            });
+            if let Err(e) = res {
+                log::debug!("ignoring checksum file `{}`: {}", entry.path, e);
+            }
+        }
+    }
+
+    /// Read `.meta.json` files of local builds into the graph
+    fn load_local_meta(&mut self, local_files: &[Entry]) {
+        for entry in local_files
+            .iter()
+            .filter(|entry| entry.path.ends_with(paths::META_EXTENSION))
+        {
+            let res = paths::build_version_from_meta_path(&entry.path).and_then(|version| {
+                let meta = BuildMeta::read(&entry.path)?;
+                self.apply_meta(&version, &meta)
+            });
+            if let Err(e) = res {
+                log::warn!("ignoring metadata file `{}`: {}", entry.path, e);
+            }
+        }
+    }
+
+    /// Local builds that look corrupt (e.g. after a download was killed), with
+    /// what's wrong with them
+    ///
+    /// Only a quick check that doesn't read whole files: builds must not be
+    /// empty and must start with a zstd frame (or a tar header, for plain
+    /// archives). `verify` checks their content.
+    /// Empty builds aren't in the index at all, but their files are still in
+    /// the way.
+    pub async fn suspect_local_builds(&self) -> Result<Vec<(Version, &'static str)>> {
+        let files = self.local.list_files().await.context("list files")?;
+        Ok(suspect_builds(&files))
+    }
+
+    /// Delete a local build found by [`Index::suspect_local_builds`], so it's
+    /// downloaded again when needed
+    ///
+    /// Unlike [`Index::remove_local_build`], this keeps local patches.
+    pub async fn remove_suspect_local_build(&mut self, version: &Version) -> Result<()> {
+        if self.patch_graph.has_local_build(version.clone()) {
+            return self.delete_local_build(version).await;
+        }
+        // not in the index (as it's empty), but it might be `.tar` or `.tzst`
+        let files = self.local.list_files().await.context("list files")?;
+        for entry in files
+            .iter()
+            .filter(|entry| paths::is_build_path(&entry.path))
+        {
+            if paths::build_version_from_path(&entry.path).ok().as_ref() != Some(version) {
+                continue;
+            }
+            let file_name = Path::new(&entry.path)
+                .file_name()
+                .context("build without file name")?;
+            self.local
+                .remove_file(&paths::path_as_string(file_name)?)
+                .await
+                .with_context(|| format!("remove local build `{}`", version))?;
+        }
+        Ok(())
+    }
+
+    fn apply_meta(&mut self, version: &Version, meta: &BuildMeta) -> Result<()> {
+        if let Some(size) = meta.uncompressed_size {
+            self.patch_graph.set_uncompressed_size(version, size)?;
+        }
+        if let Some(checksum) = meta.checksum {
+            self.patch_graph.set_checksum(version, checksum)?;
+        }
+        if !meta.custom.is_empty() {
+            self.patch_graph
+                .set_custom_meta(version, meta.custom.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Write metadata of build to local storage and add it to the graph
+    pub(crate) fn store_meta(&mut self, version: &Version, meta: &BuildMeta) -> Result<()> {
+        let local = self
+            .local
+            .local_path()
+            .context("store_meta can only write to local storage right now")?;
+        let path = local.join(paths::build_meta_path_from_version(version.clone())?);
+        meta.write(&path)
+            .with_context(|| format!("write metadata of `{}`", version))?;
+        self.apply_meta(version, meta)
+    }
+
+    /// Add key-value pairs to the metadata of a local build, replacing
+    /// values of keys it already has
+    pub fn add_custom_meta(
+        &mut self,
+        version: &Version,
+        values: BTreeMap<String, String>,
+    ) -> Result<()> {
+        let local = self
+            .local
+            .local_path()
+            .context("add_custom_meta can only write to local storage right now")?;
+        let path = local.join(paths::build_meta_path_from_version(version.clone())?);
+        let mut meta = if path.exists() {
+            BuildMeta::read(&path)?
+        } else {
+            BuildMeta::default()
+        };
+        meta.custom.extend(values);
+        self.store_meta(version, &meta)
+    }
+
+    /// Copy metadata of build from remote, if there is any
+    async fn fetch_remote_meta(&mut self, version: &Version) -> Result<()> {
+        let meta_path = paths::build_meta_path_from_version(version.clone())?;
+        let meta = match self.remote.get_file(&meta_path).await? {
+            FileEntry::InFilesystem(entry) => BuildMeta::read(&entry.path)?,
+            FileEntry::Temporary(_, temp) => BuildMeta::read(temp.path())?,
+            FileEntry::Inline(_, content) => BuildMeta::parse(&content)?,
+        };
+        self.store_meta(version, &meta)
+    }
+
+    /// Use `algorithm` for checksums of builds and patches added from now on
+    ///
+    /// Existing checksum files are still read, whatever their algorithm.
+    pub fn set_checksum_algorithm(&mut self, algorithm: ChecksumAlgorithm) {
+        self.checksum_algorithm = algorithm;
+    }
+
+    /// Diff at most `size` bytes of a build at once when calculating patches
+    ///
+    /// Smaller windows need less memory but can produce larger patches. See
+    /// [`crate::diff`] for details.
+    pub fn set_diff_window_size(&mut self, size: usize) {
+        self.diff_window_size = size;
+    }
+
+    /// Sort the old build with `threads` and scan the new one in chunks of
+    /// `chunk_size` bytes in parallel when calculating patches
+    ///
+    /// Both trade patch size for speed. Without `threads`, builds larger than
+    /// `parallel_threshold` bytes (compressed) are sorted with several threads.
+    pub fn set_diff_params(
+        &mut self,
+        threads: Option<usize>,
+        chunk_size: usize,
+        parallel_threshold: u64,
+    ) -> Result<()> {
+        crate::diff::params(threads, chunk_size, parallel_threshold, 0)
+            .map_err(Report::msg)
+            .context("invalid diff parameters")?;
+        self.diff_threads = threads;
+        self.diff_chunk_size = chunk_size;
+        self.diff_parallel_threshold = parallel_threshold;
+        Ok(())
+    }
+
+    /// Decompress builds into temporary files and memory-map them when
+    /// calculating patches
+    ///
+    /// Lets the OS page builds in and out instead of reading windows of them
+    /// into memory. Needs the `mmap` feature, which is only supported on unix.
+    pub fn set_diff_mmap(&mut self, enabled: bool) -> Result<()> {
+        ensure!(
+            !enabled || cfg!(all(feature = "mmap", unix)),
+            "memory-mapping builds is not supported by this build of artefacta"
+        );
+        self.diff_mmap = enabled;
+        Ok(())
+    }
+
+    /// Upload up to `concurrency` files at the same time in [`Index::push`]
+    pub fn set_upload_concurrency(&mut self, concurrency: usize) -> Result<()> {
+        ensure!(
+            concurrency >= 1,
+            "need to upload at least 1 file at a time, got {}",
+            concurrency
+        );
+        self.upload_concurrency = concurrency;
+        Ok(())
+    }
+
+    /// Don't upload builds with the same content as another build in
+    /// [`Index::push`]
+    ///
+    /// Instead, an `<alias>---<target>.alias` file tells others to use the
+    /// target's file (see [`paths::alias_path`]). Only builds with checksums
+    /// of the same algorithm can be recognized as identical.
+    pub fn set_alias_duplicates(&mut self, enabled: bool) {
+        self.alias_duplicates = enabled;
+    }
+
+    /// Replace local builds that differ from the ones in remote storage in
+    /// [`Index::get_build`], instead of only warning about it
+    ///
+    /// The local file is only discarded once: the download is checked against
+    /// the checksum on remote, but not compared again.
+    pub fn set_prefer_remote(&mut self, enabled: bool) {
+        self.prefer_remote = enabled;
+    }
+
+    /// Record transferred bytes and durations of operations in `metrics`
+    pub fn set_metrics(&mut self, metrics: Option<Arc<Metrics>>) {
+        self.metrics = metrics;
+    }
+
+    /// Sign builds when adding them, and before uploading them if they
+    /// aren't signed yet
+    pub fn set_signing_key(&mut self, key: Option<SigningKey>) {
+        self.signing_key = key;
+    }
+
+    /// Check signatures of builds with `key` (see [`Index::verify_signature`])
+    pub fn set_verifying_key(&mut self, key: Option<VerifyingKey>) {
+        self.verifying_key = key;
+    }
+
+    pub fn has_verifying_key(&self) -> bool {
+        self.verifying_key.is_some()
+    }
+
+    fn record(&self, f: impl FnOnce(&Metrics)) {
+        if let Some(metrics) = &self.metrics {
+            f(metrics);
+        }
+    }
+
+    fn add_downloaded(&mut self, bytes: u64, started: Instant) {
+        self.downloaded += bytes;
+        timings::record(Phase::Download, started.elapsed());
+        self.record(|metrics| {
+            metrics.add_downloaded(bytes);
+            metrics.add_operation(Operation::GetFile, started.elapsed());
+        });
+    }
+
+    /// Checksum from the checksum file next to a build or patch file
+    ///
+    /// Looks for `<file>.sha256` and `<file>.b3`, in that order. Returns
+    /// `None` when there is no checksum file (e.g. for files uploaded by older
+    /// versions).
+    async fn read_checksum(&self, location: Location, file_name: &str) -> Result<Option<Checksum>> {
+        for &algorithm in ChecksumAlgorithm::ALL.iter() {
+            let checksum_path = paths::checksum_path(file_name, algorithm);
+            let file = match self.storage(location).get_file(&checksum_path).await {
+                Ok(file) => file,
+                Err(e) => {
+                    log::debug!("no `{}` file for `{}`: {}", algorithm, file_name, e);
+                    continue;
+                }
+            };
+            let mut content = Vec::new();
+            file.reader()?
+                .read_to_end(&mut content)
+                .with_context(|| format!("read `{}`", checksum_path))?;
+            return Checksum::from_sidecar(algorithm, &content)
+                .with_context(|| format!("invalid checksum file `{}`", checksum_path))
+                .map(Some);
+        }
+        Ok(None)
+    }
+
+    /// Make sure a file copied from remote storage matches the checksum
+    /// uploaded along with it, and keep that checksum locally
+    ///
+    /// The local copy of `remote_name` is `local_name`, which differs for
+    /// builds stored with another extension (see
+    /// [`paths::is_build_path`]).
+    async fn verify_download(&self, remote_name: &str, local_name: &str) -> Result<()> {
+        let checksum = match self.read_checksum(Location::Remote, remote_name).await? {
+            Some(checksum) => checksum,
+            None => {
+                log::debug!(
+                    "no checksum for `{}` on remote, skipping verification",
+                    remote_name
+                );
+                return Ok(());
+            }
+        };
+
+        let local = self.get_local_file(local_name).await?;
+        self.checksums
+            .validate(Path::new(&local.path), &checksum)
+            .with_context(|| format!("verify downloaded `{}`", remote_name))?;
+        write_checksum(Path::new(&local.path), &checksum)
+    }
+
+    /// Delete checksum files of a build or patch file, if there are any
+    async fn remove_checksum(&self, location: Location, file_name: &str) {
+        for &algorithm in ChecksumAlgorithm::ALL.iter() {
+            let checksum_path = paths::checksum_path(file_name, algorithm);
+            if let Err(e) = self.storage(location).remove_file(&checksum_path).await {
+                log::debug!("no checksum file `{}` to remove: {}", checksum_path, e);
+            }
         }
+    }
+
+    /// Generate patches from leaf nodes to disconnected nodes
+    pub fn generate_missing_patches(&mut self) -> Result<Vec<String>> {
+        todo!()
+    }
+
+    pub async fn calculate_patch(&mut self, from: Version, to: Version) -> Result<()> {
+        let job = match self.prepare_patch(from, to).await? {
+            Some(job) => job,
+            None => return Ok(()),
+        };
+        let patch = job.run()?;
+        self.add_calculated_patch(patch)
+    }
 
+    /// Get both builds of a patch, so [`PatchJob::run`] can calculate it
+    /// without the index
+    ///
+    /// Returns `None` when the patch exists already.
+    pub(crate) async fn prepare_patch(
+        &mut self,
+        from: Version,
+        to: Version,
+    ) -> Result<Option<PatchJob>> {
         if self.get_patch(from.clone(), to.clone()).await.is_ok() {
             log::warn!(
                 "asked to calculate patch from `{:?}` to `{:?}` but it's already present",
                 from,
                 to
             );
-            return Ok(());
+            return Ok(None);
         }
 
         log::debug!("calculate path from `{}` to `{}`", from, to);
@@ -105,72 +639,46 @@ impl Index {
             .get_build(from.clone())
             .await
             .context("get old build")?;
-        let old_build = read_file(old_build).context("read old build")?;
-        let old_build = crate::decompress(Cursor::new(old_build))?;
-
         let new_build = self.get_build(to.clone()).await.context("get new build")?;
-        let new_build_size = new_build.size;
-        let new_build = read_file(new_build).context("read new build")?;
-        let new_build = crate::decompress(Cursor::new(new_build))?;
-
-        let path_name = Patch::new(from.clone(), to.clone());
-        // TODO: Fix that arbitrary "+ zst" here and everywhere else
-        let patch_path = local.join(path_name.to_string() + ".zst");
-        log::debug!("write patch {:?} to `{:?}`", path_name, patch_path);
-
-        let mut patch_file =
-            PartialFile::create(&patch_path).context("creating file to write patch to")?;
-        let mut patch = crate::compress(&mut patch_file)?;
-        bidiff::simple_diff_with_params(&old_build, &new_build, &mut patch, &{
-            const MB: u64 = 1_000_000;
-            bidiff::DiffParams::new(
-                {
-                    if new_build_size > (100 * MB) {
-                        4
-                    } else {
-                        1
-                    }
-                },
-                Some(100 * MB as usize),
-            )
-            .map_err(|e| Report::msg(e.to_string()))
-            .context("valid diff params")
-            .note("this is a programming error, please open an issue")?
-        })
-        .context("calculating binary diff between builds")?;
-        patch.finish().context("finishing zstd file")?;
-        patch_file
-            .finish()
-            .context("finishing writing patch file")?;
 
-        let patch_size = patch_path
-            .metadata()
-            .with_context(|| {
-                format!(
-                    "can't read metadata for new patch file `{}`",
-                    patch_path.display()
-                )
-            })?
-            .len();
+        Ok(Some(PatchJob {
+            new_build_uncompressed_size: self.patch_graph.uncompressed_size(to.clone()),
+            from,
+            to,
+            old_build,
+            new_build,
+            local,
+            checksum_algorithm: self.checksum_algorithm,
+            checksums: self.checksums.clone(),
+            window_size: self.diff_window_size,
+            threads: self.diff_threads,
+            chunk_size: self.diff_chunk_size,
+            parallel_threshold: self.diff_parallel_threshold,
+            mmap: self.diff_mmap,
+        }))
+    }
 
+    /// Add a patch written by [`PatchJob::run`] to the index
+    pub(crate) fn add_calculated_patch(&mut self, patch: CalculatedPatch) -> Result<()> {
+        let CalculatedPatch {
+            from,
+            to,
+            path,
+            size,
+            duration,
+        } = patch;
         let entry = Entry {
             storage: self.local.clone(),
-            path: paths::path_as_string(patch_path)?,
-            size: patch_size,
+            path,
+            size,
+            modified: None,
         };
-
-        log::info!(
-            "Calculated new patch from {} to {} of size {} -- that's {:.1}% of the new build's {}",
-            from,
-            to,
-            file_size(patch_size),
-            (patch_size as f64) / (new_build_size as f64) * 100_f64,
-            file_size(new_build_size),
-        );
-
         self.patch_graph
             .add_patch(&from, &to, entry, Location::Local)?;
-
+        self.record(|metrics| {
+            metrics.add_created_patch(size);
+            metrics.add_operation(Operation::CalculatePatch, duration);
+        });
         Ok(())
     }
 
@@ -198,6 +706,7 @@ impl Index {
             Err(e) => log::debug!("could not get patch {:?} locally: {}", patch, e),
         }
 
+        let started = Instant::now();
         let remote_entry = self
             .remote
             .get_file(&patch_name)
@@ -207,6 +716,12 @@ impl Index {
         self.add_patch(&remote_entry)
             .await
             .context("copy remote entry to local storage")?;
+        self.add_downloaded(remote_entry.size(), started);
+        if let Err(e) = self.verify_download(&patch_name, &patch_name).await {
+            self.local.remove_file(&patch_name).await.log_and_discard();
+            self.patch_graph.remove_local_patch(&patch.from, &patch.to);
+            return Err(e).with_context(|| format!("patch `{}` is corrupt", patch));
+        }
         log::debug!("fetched patch `{}` from remote ({:?})", patch, remote_entry);
 
         self.get_local_file(&patch_name)
@@ -214,9 +729,26 @@ impl Index {
             .context("fetch newly added local path")
     }
 
+    /// Number of bytes fetched from remote storage since the index was opened
+    pub fn downloaded_bytes(&self) -> u64 {
+        self.downloaded
+    }
+
     /// Upgrade from one version to the next
-    pub async fn upgrade_to_build(&mut self, from: Version, to: Version) -> Result<Entry> {
+    ///
+    /// Downloads the full build instead of applying patches if that is
+    /// cheaper or if it would take more than `max_patch_hops` patches.
+    /// Returns the new build and the way we actually got it. See
+    /// [`Index::plan_upgrade`] to find out what this would do without doing
+    /// it.
+    pub async fn upgrade_to_build(
+        &mut self,
+        from: Version,
+        to: Version,
+        max_patch_hops: Option<usize>,
+    ) -> Result<(Entry, UpgradePath)> {
         log::debug!("searching for upgrade path from `{}` to `{}`", from, to);
+        let started = Instant::now();
         ensure!(
             self.patch_graph.has_build(from.clone()),
             "build `{:?}` unknown",
@@ -228,15 +760,16 @@ impl Index {
             to
         );
 
-        match self
+        let (local_build, path) = match self
             .patch_graph
-            .find_upgrade_path(from.clone(), to.clone())
+            .find_upgrade_path(from.clone(), to.clone(), max_patch_hops)
             .with_context(|| format!("can't find upgrade path from `{:?}` to `{:?}", from, to))?
         {
             UpgradePath::ApplyPatches(patches) => {
                 log::debug!("found upgrade path via patches: {:?}", patches);
                 let needed_patches = patches
-                    .into_iter()
+                    .iter()
+                    .cloned()
                     .skip_while(|patch| self.patch_graph.has_local_build(patch.to.clone()))
                     .collect::<Vec<Patch>>();
                 log::debug!(
@@ -245,61 +778,134 @@ impl Index {
                 );
 
                 async fn apply_patches(index: &mut Index, needed_patches: &[Patch]) -> Result<()> {
+                    let mut previous_build = None;
                     for patch in needed_patches {
                         index
-                            .add_build_from_patch(patch)
+                            .add_build_from_patch(patch, &mut previous_build)
                             .await
                             .with_context(|| format!("add build from patch `{:?}`", patch))?;
                     }
                     Ok(())
                 }
 
-                match apply_patches(self, &needed_patches).await {
-                    Ok(_) => log::debug!("successfully applied all patches to get to final build."),
+                let path = match apply_patches(self, &needed_patches).await {
+                    Ok(_) => {
+                        log::debug!("successfully applied all patches to get to final build.");
+                        self.record(|metrics| {
+                            metrics.add_applied_patches(needed_patches.len() as u64)
+                        });
+                        UpgradePath::ApplyPatches(patches)
+                    }
                     e => {
                         log::warn!("failed to get build using patches, will use direct build.");
                         e.note("one of the patches might be corrupt.")
                             .log_and_discard();
+                        UpgradePath::InstallBuild(Box::new(Build::new(to.clone())))
                     }
-                }
+                };
 
                 let local_build = self.get_build(to).await.context("fetch target build")?;
                 log::debug!("arrived at final build: {:?}", local_build);
 
-                Ok(local_build)
+                (local_build, path)
             }
             UpgradePath::InstallBuild(build) => {
                 log::debug!("found upgrade path installing build `{:?}`", build);
                 let local_build = self.get_build(to).await.context("install fresh build")?;
-                Ok(local_build)
+                (local_build, UpgradePath::InstallBuild(build))
             }
-        }
+        };
+        self.record(|metrics| metrics.add_operation(Operation::UpgradeToBuild, started.elapsed()));
+        Ok((local_build, path))
     }
 
-    async fn add_build_from_patch(&mut self, patch: &Patch) -> Result<Entry> {
+    /// Create build by applying patch to its source build
+    ///
+    /// When `previous_build` contains the decompressed source build it is used
+    /// instead of reading it from disk again. Afterwards, it contains the
+    /// decompressed new build (unless it is too large to keep in memory), so
+    /// applying a chain of patches decompresses each build at most once.
+    async fn add_build_from_patch(
+        &mut self,
+        patch: &Patch,
+        previous_build: &mut Option<DecompressedBuild>,
+    ) -> Result<Entry> {
         let patch_file = self
             .get_patch(patch.from.clone(), patch.to.clone())
             .await
             .context("fetch patch")?;
-        let source_build = self
-            .get_build(patch.from.clone())
-            .await
-            .context("fetch source build")?;
+        let source_build = match previous_build.take() {
+            Some(build) if build.version == patch.from => {
+                log::debug!("using decompressed build `{}` from memory", build.version);
+                build.content
+            }
+            _ => {
+                let source_build = self
+                    .get_build(patch.from.clone())
+                    .await
+                    .context("fetch source build")?;
+                let file = File::open(&source_build.path)
+                    .with_context(|| format!("open file `{}`", source_build.path))?;
+                let _timing = timings::start(Phase::Decompress);
+                crate::decompress_build(&source_build.path, BufReader::new(file))
+                    .with_context(|| format!("decompress `{}`", source_build.path))?
+            }
+        };
 
-        let build_name = format!("{}.tar.zst", patch.to);
+        // builds stored uncompressed stay that way, so they match their checksum
+        let plain = self
+            .build_file_name(&patch.to, Location::Remote)
+            .map_or(false, |name| paths::is_plain_build_path(&name));
+        let build_name = if plain {
+            paths::plain_build_path_from_version(patch.to.clone())?
+        } else {
+            paths::build_path_from_version(patch.to.clone())?
+        };
         let build_root = self.local.local_path().context("local storage not local")?;
         let build_path = build_root.join(&build_name);
 
+        let applying = timings::start(Phase::ApplyPatch);
         let mut build_file = PartialFile::create(&build_path)
             .with_context(|| format!("create new build file `{}`", build_path.display()))?;
-        let mut build_writer =
-            crate::compress(&mut build_file).context("zstd writer for new build")?;
         let mut patch_data =
-            apply_patch(&source_build.path, &patch_file.path).context("apply patch")?;
-
-        io::copy(&mut patch_data, &mut build_writer).context("write patch")?;
-        build_writer.finish().context("finish zstd writer")?;
+            apply_patch_to_decompressed(&source_build, &patch_file.path).context("apply patch")?;
+        let mut write_build = |out: &mut dyn Write| {
+            let mut output = KeepInMemory::new(out, MAX_DECOMPRESSED_BUILD_IN_MEMORY);
+            let size = io::copy(&mut patch_data, &mut output).context("write patch")?;
+            Ok::<_, Report>((size, output.into_content()))
+        };
+        let (uncompressed_size, new_build) = if plain {
+            write_build(&mut build_file)?
+        } else {
+            let mut build_writer =
+                crate::compress(&mut build_file).context("zstd writer for new build")?;
+            let written = write_build(&mut build_writer)?;
+            build_writer.finish().context("finish zstd writer")?;
+            written
+        };
         build_file.finish().context("finish build file")?;
+        drop(applying);
+
+        // a broken patch might still produce something that decompresses
+        let checksum = self
+            .checksums
+            .checksum(&build_path, self.checksum_algorithm)
+            .context("calculate checksum of new build")?;
+        if let Err(e) = self
+            .check_patched_build(&patch.to, &checksum, &build_path)
+            .await
+        {
+            fs::remove_file(&build_path).with_context(|| {
+                format!("remove wrongly patched build `{}`", build_path.display())
+            })?;
+            return Err(e);
+        }
+        write_checksum(&build_path, &checksum).context("write checksum of new build")?;
+
+        *previous_build = new_build.map(|content| DecompressedBuild {
+            version: patch.to.clone(),
+            content,
+        });
 
         let entry = Entry::from_path(&build_path, self.local.clone())
             .context("create entry for new build file")?;
@@ -317,9 +923,58 @@ impl Index {
                     build_path.display()
                 )
             })?;
+        self.patch_graph.set_checksum(&patch.to, checksum)?;
+        self.store_meta(
+            &patch.to,
+            &BuildMeta {
+                uncompressed_size: Some(uncompressed_size),
+                ..BuildMeta::default()
+            },
+        )
+        .context("store metadata of new build")?;
         Ok(entry)
     }
 
+    /// Make sure a build created from a patch is exactly the one we expected
+    ///
+    /// Compares with the checksum from the build's metadata or the checksum
+    /// file on remote. If neither exists, there is nothing to compare with.
+    /// When the expected checksum uses another algorithm than `checksum`, the
+    /// file at `path` is hashed again.
+    async fn check_patched_build(
+        &self,
+        version: &Version,
+        checksum: &Checksum,
+        path: &Path,
+    ) -> Result<()> {
+        let build_name = self.build_file_name(version, Location::Remote)?;
+        let expected = match self.patch_graph.checksum(version) {
+            Some(expected) => expected,
+            None => match self.read_checksum(Location::Remote, &build_name).await? {
+                Some(expected) => expected,
+                None => {
+                    log::debug!(
+                        "no checksum known for `{}`, can't verify patched build",
+                        version
+                    );
+                    return Ok(());
+                }
+            },
+        };
+        let checksum = if expected.algorithm() == checksum.algorithm() {
+            *checksum
+        } else {
+            self.checksums.checksum(path, expected.algorithm())?
+        };
+        if expected != checksum {
+            return Err(ErrorKind::Data.error(format!(
+                "patched build `{}` is not the expected one: checksum mismatch: expected `{}` but got `{}`",
+                version, expected, checksum
+            )));
+        }
+        Ok(())
+    }
+
     /// Get build (adds to local cache if not present)
     pub async fn get_build(&mut self, version: Version) -> Result<Entry> {
         ensure!(
@@ -328,22 +983,33 @@ impl Index {
             version
         );
 
-        let build_path = paths::build_path_from_version(version.clone())?;
+        let build_path = self.build_file_name(&version, Location::Local)?;
         match self.get_local_file(&build_path).await {
             Ok(local) => {
                 log::debug!("using local file for build `{:?}`", local);
 
                 // quick sanity check
-                if let Some(remote) = self.patch_graph.remote_build(version.clone()) {
-                    if local.size != remote.size {
+                match self.local_build_difference(&version, &local).await {
+                    Some(difference) if self.prefer_remote => {
+                        log::warn!(
+                            "Locally cached file for `{}` is not what's on remote ({}), fetching it again",
+                            version,
+                            difference
+                        );
+                        self.discard_local_build(&version, &build_path)
+                            .await
+                            .with_context(|| format!("discard local `{}`", version))?;
+                    }
+                    Some(difference) => {
                         log::warn!(
-                            "Using locally cached file for `{}` but size on remote differs",
-                            version
+                            "Using locally cached file for `{}` but {}",
+                            version,
+                            difference
                         );
+                        return Ok(local);
                     }
+                    None => return Ok(local),
                 }
-
-                return Ok(local);
             }
             Err(e) => log::debug!(
                 "could not get local patch {:?} ({}), trying remote next",
@@ -352,7 +1018,60 @@ impl Index {
             ),
         }
 
-        let remote_entry = self.remote.get_file(&build_path).await.with_context(|| {
+        if let Some(target) = self.patch_graph.alias_of(&version).cloned() {
+            return self.get_aliased_build(version, target).await;
+        }
+        self.download_build(version).await
+    }
+
+    /// How the local file of a build differs from the one in remote storage,
+    /// going by checksum or (if checksums can't be compared) size
+    async fn local_build_difference(&self, version: &Version, local: &Entry) -> Option<String> {
+        let remote_size = self.patch_graph.remote_build(version.clone())?.size;
+        let build_path = self.build_file_name(version, Location::Local).ok()?;
+        let local_checksum = self
+            .read_checksum(Location::Local, &build_path)
+            .await
+            .unwrap_or_else(|e| {
+                log::debug!("can't read checksum of local `{}`: {}", version, e);
+                None
+            });
+        let expected = self.patch_graph.checksum(version);
+        // checksums with different algorithms can't be compared
+        let comparable = matches!(
+            (&local_checksum, &expected),
+            (Some(a), Some(b)) if a.algorithm() == b.algorithm()
+        );
+        match (local_checksum, expected) {
+            (Some(local_checksum), Some(expected)) if comparable && local_checksum != expected => {
+                Some(format!(
+                    "its checksum `{}` differs from the expected `{}`",
+                    local_checksum, expected
+                ))
+            }
+            (Some(_), Some(_)) if comparable => None,
+            _ if local.size != remote_size => Some("size on remote differs".to_string()),
+            _ => None,
+        }
+    }
+
+    /// Delete local copy of a build (and its checksum), e.g. to download it
+    /// again
+    async fn discard_local_build(&mut self, version: &Version, build_path: &str) -> Result<()> {
+        self.local
+            .remove_file(build_path)
+            .await
+            .with_context(|| format!("remove `{}`", build_path))?;
+        self.remove_checksum(Location::Local, build_path).await;
+        self.patch_graph.remove_local_build(version);
+        Ok(())
+    }
+
+    /// Copy build from remote storage and make sure it's intact
+    async fn download_build(&mut self, version: Version) -> Result<Entry> {
+        let remote_name = self.build_file_name(&version, Location::Remote)?;
+        let started = Instant::now();
+        let remote_entry = self.remote.get_file(&remote_name).await.with_context(|| {
             format!(
                 "can't find `{}` either locally or remotely",
                 version.as_str()
@@ -362,13 +1081,62 @@ impl Index {
         self.add_build(&remote_entry)
             .await
             .context("copy remote entry to local storage")?;
+        let build_path = self.build_file_name(&version, Location::Local)?;
+        self.add_downloaded(remote_entry.size(), started);
+        if let Err(e) = self.verify_download(&remote_name, &build_path).await {
+            self.local.remove_file(&build_path).await.log_and_discard();
+            self.patch_graph.remove_local_build(&version);
+            return Err(e).with_context(|| format!("build `{}` is corrupt", version));
+        }
+        if let Err(e) = self.fetch_remote_meta(&version).await {
+            log::debug!("no metadata for `{}` on remote: {}", version, e);
+        }
         self.get_local_file(&build_path)
             .await
             .context("fetch newly added local build")
     }
 
-    pub fn get_build_for_tag(&self, tag: &str) -> Result<Version> {
-        let parsed_tag = crate::git::tag_to_slice(tag);
+    /// Get a build that's stored as `target` in remote storage
+    ///
+    /// Locally, it's a hard link to the target build.
+    async fn get_aliased_build(&mut self, version: Version, target: Version) -> Result<Entry> {
+        log::debug!("`{}` is stored as `{}` on remote", version, target);
+        let target_path = self.build_file_name(&target, Location::Local)?;
+        let target_entry = match self.get_local_file(&target_path).await {
+            Ok(entry) => entry,
+            Err(_) => self
+                .download_build(target.clone())
+                .await
+                .with_context(|| format!("get `{}`, which `{}` is an alias of", target, version))?,
+        };
+
+        let local = self
+            .local
+            .local_path()
+            .context("can only link builds in local storage")?;
+        let new_path = if paths::is_plain_build_path(&target_entry.path) {
+            local.join(paths::plain_build_path_from_version(version.clone())?)
+        } else {
+            local.join(paths::build_path_from_version(version.clone())?)
+        };
+        link_identical(Path::new(&target_entry.path), &new_path)?;
+        let entry = Entry::from_path(&new_path, self.local.clone())
+            .context("create entry for linked build file")?;
+        self.patch_graph
+            .add_build(&version, entry.clone(), Location::Local)
+            .with_context(|| format!("add build `{}`", new_path.display()))?;
+        if let Some(checksum) = self.patch_graph.checksum(&target) {
+            self.patch_graph.set_checksum(&version, checksum)?;
+            write_checksum(&new_path, &checksum)?;
+        }
+        if let Err(e) = self.fetch_remote_meta(&version).await {
+            log::debug!("no metadata for `{}` on remote: {}", version, e);
+        }
+        Ok(entry)
+    }
+
+    pub fn get_build_for_tag(&self, tag: &str) -> Result<Version> {
+        let parsed_tag = crate::git::tag_to_slice(tag);
         self.patch_graph
             .builds
             .keys()
@@ -380,9 +1148,18 @@ impl Index {
     pub async fn add_local_build(&mut self, path: impl AsRef<Path>) -> Result<Entry> {
         let entry = Entry::from_path(path.as_ref(), self.local.clone())
             .context("local build file as entry")?;
-        self.add_build(&FileEntry::InFilesystem(entry))
+        let entry = self
+            .add_build(&FileEntry::InFilesystem(entry))
             .await
-            .context("add local build file")
+            .context("add local build file")?;
+        let version = paths::build_version_from_path(&entry.path)?;
+        let checksum = self
+            .patch_graph
+            .checksum(&version)
+            .context("new build has no checksum")?;
+        write_checksum(Path::new(&entry.path), &checksum).context("write checksum of new build")?;
+        self.sign_local_build(&version, Path::new(&entry.path), checksum)?;
+        Ok(entry)
     }
 
     /// Add build to graph and copy it into index's root directory
@@ -402,19 +1179,93 @@ impl Index {
                 path.canonicalize()
                     .with_context(|| format!("canonicalize {}", path.display()))?
             }
-            FileEntry::Inline(entry, ..) => Path::new(&entry.path).to_path_buf(),
+            FileEntry::Inline(entry, ..) | FileEntry::Temporary(entry, _) => {
+                Path::new(&entry.path).to_path_buf()
+            }
         };
 
         let file_name = paths::file_name(&path)?;
         let version: Version = file_name.parse()?;
-        let new_path = local.join(format!("{}.tar.zst", version.as_str()));
+        ensure_not_reserved(&version)?;
+        let new_path = if paths::is_plain_build_path(&path.to_string_lossy()) {
+            local.join(paths::plain_build_path_from_version(version.clone())?)
+        } else {
+            local.join(paths::build_path_from_version(version.clone())?)
+        };
 
         self.local
             .add_file(file, &new_path)
             .await
             .context("write build file to local storage")?;
+        let entry = self.register_local_build(&version, &new_path)?;
+
+        if let FileEntry::InFilesystem(_) = file {
+            let meta_path =
+                path.with_file_name(paths::build_meta_path_from_version(version.clone())?);
+            if meta_path.exists() {
+                let meta = BuildMeta::read(&meta_path)?;
+                if let Some(expected) = &meta.checksum {
+                    self.checksums
+                        .validate(&new_path, expected)
+                        .with_context(|| {
+                            format!(
+                                "build `{}` does not match checksum in `{}`",
+                                version,
+                                meta_path.display()
+                            )
+                        })?;
+                }
+                self.store_meta(&version, &meta)
+                    .with_context(|| format!("add metadata `{}`", meta_path.display()))?;
+            }
+        }
 
-        let entry = Entry::from_path(&new_path, self.local.clone())
+        Ok(entry)
+    }
+
+    /// Add build archive read from `reader` to local storage and the index,
+    /// writing its checksum file as well
+    ///
+    /// Unlike [`Index::add_local_build`], the archive is written to the local
+    /// store directly, without needing a file somewhere else first.
+    pub fn add_local_build_from_reader(
+        &mut self,
+        version: &Version,
+        mut reader: impl Read,
+    ) -> Result<Entry> {
+        ensure_not_reserved(version)?;
+        let local = self
+            .local
+            .local_path()
+            .context("builds can only be written to local storage right now")?;
+        let new_path = local.join(paths::build_path_from_version(version.clone())?);
+
+        let mut file = PartialFile::create(&new_path)
+            .with_context(|| format!("create `{}`", new_path.display()))?;
+        let size = io::copy(&mut reader, &mut file).context("read build archive")?;
+        // an empty archive is dropped, not finished, so it's never in the store
+        ensure!(
+            size > 0,
+            "Read build `{}` but it's empty (size 0). That's not gonna be useful.",
+            version
+        );
+        file.finish()
+            .with_context(|| format!("finish writing `{}`", new_path.display()))?;
+
+        let entry = self.register_local_build(version, &new_path)?;
+        let checksum = self
+            .patch_graph
+            .checksum(version)
+            .context("new build has no checksum")?;
+        write_checksum(&new_path, &checksum).context("write checksum of new build")?;
+        self.sign_local_build(version, &new_path, checksum)?;
+        Ok(entry)
+    }
+
+    /// Add build file at `path` in local storage to the graph, with its
+    /// checksum
+    fn register_local_build(&mut self, version: &Version, path: &Path) -> Result<Entry> {
+        let entry = Entry::from_path(path, self.local.clone())
             .context("create entry for new build file")?;
 
         ensure!(
@@ -423,12 +1274,108 @@ impl Index {
             entry.path
         );
 
+        let checksum = self
+            .checksums
+            .checksum(path, self.checksum_algorithm)
+            .context("calculate checksum of new build")?;
+
+        // Re-tagged releases often have the exact same content
+        if let Some(identical) = self.identical_local_build(version, &checksum) {
+            match link_identical(Path::new(&identical.path), path) {
+                Ok(()) => log::info!(
+                    "`{}` is identical to `{}`, storing it only once",
+                    version,
+                    identical.path
+                ),
+                Err(e) => log::warn!("could not link identical builds: {}", e),
+            }
+        }
+
         self.patch_graph
-            .add_build(&version, entry.clone(), Location::Local)
+            .add_build(version, entry.clone(), Location::Local)
             .with_context(|| format!("add build `{}`", path.display()))?;
+        self.patch_graph.set_checksum(version, checksum)?;
         Ok(entry)
     }
 
+    /// Write the signature file of the local build at `path`, if we have a
+    /// key to sign with
+    fn sign_local_build(&self, version: &Version, path: &Path, checksum: Checksum) -> Result<()> {
+        let key = match &self.signing_key {
+            Some(key) => key,
+            None => return Ok(()),
+        };
+        let file_name = paths::path_as_string(path.file_name().context("no file name")?)?;
+        let signature_path = path.with_file_name(paths::signature_path(&file_name));
+        let mut file = PartialFile::create(&signature_path)
+            .with_context(|| format!("create `{}`", signature_path.display()))?;
+        file.write_all(key.sign(version, checksum).sidecar().as_bytes())
+            .with_context(|| format!("write `{}`", signature_path.display()))?;
+        file.finish()
+            .with_context(|| format!("finish writing `{}`", signature_path.display()))?;
+        Ok(())
+    }
+
+    /// Make sure the local file of a build is what the owner of our
+    /// verifying key signed
+    ///
+    /// The signature is read from the local store, or else from remote
+    /// storage. Returns `false` when there is no signature at all.
+    pub async fn verify_signature(&self, version: &Version) -> Result<bool> {
+        let key = self
+            .verifying_key
+            .as_ref()
+            .context("no public key to verify signatures with")
+            .suggestion("pass one with `--verify-key`")?;
+        let mut signature = None;
+        for &location in [Location::Local, Location::Remote].iter() {
+            let file_name = match self.build_file_name(version, location) {
+                Ok(file_name) => file_name,
+                Err(_) => continue,
+            };
+            let signature_path = paths::signature_path(&file_name);
+            let file = match self.storage(location).get_file(&signature_path).await {
+                Ok(file) => file,
+                Err(e) => {
+                    log::debug!("no signature file `{}`: {}", signature_path, e);
+                    continue;
+                }
+            };
+            let mut content = Vec::new();
+            file.reader()?
+                .read_to_end(&mut content)
+                .with_context(|| format!("read `{}`", signature_path))?;
+            let read = Signature::from_sidecar(&content)
+                .with_context(|| format!("invalid signature file `{}`", signature_path))?;
+            signature = Some((self.stored_version(version, location).clone(), read));
+            break;
+        }
+        let (signed_version, signature) = match signature {
+            Some(found) => found,
+            None => return Ok(false),
+        };
+        key.verify(&signed_version, &signature)?;
+
+        let local = self
+            .patch_graph
+            .local_build(version.clone())
+            .with_context(|| format!("build `{}` is not stored locally", version))?;
+        self.checksums
+            .validate(Path::new(&local.path), &signature.checksum)
+            .with_context(|| format!("build `{}` is not the signed one", version))?;
+        Ok(true)
+    }
+
+    /// Another local build with the same content, going by checksums
+    fn identical_local_build(&self, version: &Version, checksum: &Checksum) -> Option<Entry> {
+        self.patch_graph
+            .builds()
+            .into_iter()
+            .filter(|build| &build.version != version)
+            .filter(|build| build.checksum.as_ref() == Some(checksum))
+            .find_map(|build| build.local.clone())
+    }
+
     /// Add build to graph and copy it into index's root directory
     ///
     /// TODO: Refactor this and add_build to be the same generic method
@@ -447,7 +1394,9 @@ impl Index {
                 path.canonicalize()
                     .with_context(|| format!("canonicalize {}", path.display()))?
             }
-            FileEntry::Inline(entry, ..) => Path::new(&entry.path).to_path_buf(),
+            FileEntry::Inline(entry, ..) | FileEntry::Temporary(entry, _) => {
+                Path::new(&entry.path).to_path_buf()
+            }
         };
 
         let patch = Patch::from_path(&path)?;
@@ -469,14 +1418,616 @@ impl Index {
         Ok(())
     }
 
+    /// All known builds, ordered by version
+    pub fn builds(&self) -> Vec<&Build> {
+        self.patch_graph.builds()
+    }
+
+    /// Version of the build users mean by `version`
+    ///
+    /// [`Version::LATEST`] is the highest version of all builds (in natural
+    /// order). A glob pattern like `wtf-*` that isn't the version of a build
+    /// is the highest version matching it. Any other version stays as it is.
+    pub fn resolve_version(&self, version: Version) -> Result<Version> {
+        let versions = self
+            .builds()
+            .into_iter()
+            .map(|build| build.version.clone())
+            .collect::<Vec<_>>();
+
+        if version.as_str() == Version::LATEST {
+            let latest = versions
+                .into_iter()
+                .max()
+                .context("no builds in the store, so there is no latest one")
+                .suggestion("add a build first, or check the configured stores")?;
+            log::info!("`{}` is `{}`", Version::LATEST, latest);
+            return Ok(latest);
+        }
+
+        let pattern = version.as_str();
+        if !pattern.contains(['*', '?', '[', '{']) || versions.contains(&version) {
+            return Ok(version);
+        }
+        ensure!(
+            !pattern.contains(['{', ',']),
+            "`{}` looks like several patterns, but only one is supported",
+            pattern
+        );
+        let glob = globset::Glob::new(pattern)
+            .with_context(|| format!("invalid version pattern `{}`", pattern))?
+            .compile_matcher();
+        let latest = versions
+            .iter()
+            .filter(|version| glob.is_match(version.as_str()))
+            .max()
+            .cloned();
+        match latest {
+            Some(latest) => {
+                log::info!("`{}` matches `{}` and earlier builds", pattern, latest);
+                Ok(latest)
+            }
+            None => {
+                let mut newest = versions.iter().rev().take(10).collect::<Vec<_>>();
+                newest.sort();
+                let candidates = newest
+                    .iter()
+                    .map(|version| format!("`{}`", version))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let error = None.with_context(|| format!("no build matches `{}`", pattern));
+                if candidates.is_empty() {
+                    error
+                } else {
+                    error.with_note(|| format!("newest builds are {}", candidates))
+                }
+            }
+        }
+    }
+
+    /// Labels of builds, as stored in remote storage
+    pub async fn labels(&self) -> Result<Labels> {
+        if !self.remote_labels {
+            return Ok(Labels::default());
+        }
+        let file = self
+            .remote
+            .get_file(Labels::FILE_NAME)
+            .await
+            .with_context(|| format!("get `{}`", Labels::FILE_NAME))?;
+        let mut content = Vec::new();
+        file.reader()?
+            .read_to_end(&mut content)
+            .with_context(|| format!("read `{}`", Labels::FILE_NAME))?;
+        Labels::parse(&content)
+    }
+
+    /// Attach `label` to a build (or take it off with `remove`) and store the
+    /// labels in remote storage
+    ///
+    /// Returns whether anything changed.
+    pub async fn set_label(
+        &mut self,
+        version: &Version,
+        label: &str,
+        remove: bool,
+    ) -> Result<bool> {
+        let mut labels = self.labels().await?;
+        let changed = if remove {
+            labels.remove(label, version)
+        } else {
+            ensure!(
+                self.patch_graph.has_build(version.clone()),
+                "build `{}` unknown",
+                version
+            );
+            labels.add(label, version.clone())?
+        };
+        if changed {
+            self.upload_file(Labels::FILE_NAME, labels.to_json()?)
+                .await
+                .context("store labels")?;
+            self.remote_labels = true;
+        }
+        Ok(changed)
+    }
+
+    /// Newest build with `label`
+    pub async fn resolve_label(&self, label: &str) -> Result<Version> {
+        let labels = self.labels().await?;
+        let latest = labels
+            .versions(label)
+            .filter(|version| self.patch_graph.has_build((*version).clone()))
+            .max()
+            .cloned()
+            .with_context(|| format!("no build is labeled `{}`", label))
+            .suggestion("attach the label to a build with `artefacta label <version> <label>`")?;
+        log::info!("`{}` is labeled `{}`", latest, label);
+        Ok(latest)
+    }
+
+    /// All known patches, ordered by the versions they go from and to
+    pub fn patches(&self) -> Vec<&Patch> {
+        self.patch_graph.patches()
+    }
+
+    /// Build whose file holds `version` in remote storage, if it's an alias
+    pub fn remote_alias_of(&self, version: &Version) -> Option<&Version> {
+        self.patch_graph.alias_of(version)
+    }
+
+    /// How we would upgrade from one build to another, and how many bytes
+    /// that would download
+    pub fn upgrade_plan(
+        &self,
+        from: Version,
+        to: Version,
+        max_patch_hops: Option<usize>,
+    ) -> Result<(UpgradePath, u64)> {
+        let path = self
+            .patch_graph
+            .find_upgrade_path(from.clone(), to.clone(), max_patch_hops)
+            .with_context(|| format!("can't find upgrade path from `{:?}` to `{:?}", from, to))?;
+        let size = self.patch_graph.download_size(&path);
+        Ok((path, size))
+    }
+
+    /// Plan upgrading from one build to another, without downloading or
+    /// changing anything
+    ///
+    /// This is the side effect free counterpart to
+    /// [`Index::upgrade_to_build`]: it finds the same path (without a limit
+    /// on patch hops), and tells which files that needs and how large they
+    /// are. Sizes of the patches along the path are available from
+    /// [`UpgradePath::patches`].
+    pub fn plan_upgrade(&self, from: Version, to: Version) -> Result<UpgradePlan> {
+        ensure!(
+            self.patch_graph.has_build(from.clone()),
+            "build `{:?}` unknown",
+            from
+        );
+        ensure!(
+            self.patch_graph.has_build(to.clone()),
+            "build `{:?}` unknown",
+            to
+        );
+        let (path, download_size) = self.upgrade_plan(from, to, None)?;
+        let files_to_fetch = self.files_to_fetch(&path)?;
+        Ok(UpgradePlan {
+            path,
+            download_size,
+            files_to_fetch,
+        })
+    }
+
+    /// Names of the files [`Index::upgrade_to_build`] would download from
+    /// remote storage when following `path`
+    pub fn files_to_fetch(&self, path: &UpgradePath) -> Result<Vec<String>> {
+        self.patch_graph.files_to_fetch(path)
+    }
+
+    fn storage(&self, location: Location) -> &Storage {
+        match location {
+            Location::Local => &self.local,
+            Location::Remote => &self.remote,
+        }
+    }
+
+    /// Make sure `file` has the checksum `expected`, reusing what we know of
+    /// local files
+    fn validate_file(
+        &self,
+        file: &FileEntry,
+        location: Location,
+        expected: &Checksum,
+    ) -> Result<()> {
+        match (file, location) {
+            (FileEntry::InFilesystem(entry), Location::Local) => {
+                self.checksums.validate(Path::new(&entry.path), expected)
+            }
+            _ => expected.validate(file.reader()?),
+        }
+    }
+
+    /// Make sure a stored build can be decompressed and matches its checksum
+    /// (if we know it)
+    pub async fn verify_build(&self, version: Version, location: Location) -> Result<()> {
+        let path = self.build_file_name(&version, location)?;
+        let file = self
+            .storage(location)
+            .get_file(&path)
+            .await
+            .with_context(|| format!("get build `{}`", version))?;
+
+        if let Some(checksum) = self.build_checksum(&version, location).await? {
+            self.validate_file(&file, location, &checksum)?;
+        } else {
+            log::debug!(
+                "no checksum for `{}`, only checking it decompresses",
+                version
+            );
+        }
+
+        io::copy(
+            &mut crate::build_reader(&path, BufReader::new(file.reader()?))?,
+            &mut io::sink(),
+        )
+        .context("decompress build")?;
+        Ok(())
+    }
+
+    /// Checksum of a build, as known from the index or read from the checksum
+    /// file stored next to it in `location`
+    pub async fn build_checksum(
+        &self,
+        version: &Version,
+        location: Location,
+    ) -> Result<Option<Checksum>> {
+        if let Some(checksum) = self.patch_graph.checksum(version) {
+            return Ok(Some(checksum));
+        }
+        let path = self.build_file_name(version, location)?;
+        self.read_checksum(location, &path).await
+    }
+
+    /// Name of the file holding `version` in `location`
+    ///
+    /// That's the name it was listed with, which may have another extension
+    /// than the `.tar.zst` we write (see [`paths::is_build_path`]).
+    fn build_file_name(&self, version: &Version, location: Location) -> Result<String> {
+        let stored = self.stored_version(version, location);
+        let entry = match location {
+            Location::Local => self.patch_graph.local_build(stored.clone()),
+            Location::Remote => self.patch_graph.remote_build(stored.clone()),
+        };
+        match entry.and_then(|entry| Path::new(&entry.path).file_name()) {
+            Some(name) => paths::path_as_string(name),
+            None => paths::build_path_from_version(stored.clone()),
+        }
+    }
+
+    /// Version whose file holds `version` in `location`, which is another one
+    /// for builds aliased on remote
+    fn stored_version<'a>(&'a self, version: &'a Version, location: Location) -> &'a Version {
+        match location {
+            Location::Remote => self.patch_graph.alias_of(version).unwrap_or(version),
+            Location::Local => version,
+        }
+    }
+
+    /// Make sure a stored patch can be decompressed
+    ///
+    /// If the patch is stored locally and its source build is available
+    /// locally as well, also make sure applying it works.
+    pub async fn verify_patch(&self, patch: &Patch, location: Location) -> Result<()> {
+        let file = self
+            .storage(location)
+            .get_file(&patch.file_name())
+            .await
+            .with_context(|| format!("get patch `{}`", patch))?;
+
+        if let Some(checksum) = self.read_checksum(location, &patch.file_name()).await? {
+            self.validate_file(&file, location, &checksum)?;
+        }
+
+        crate::compression::decompressed_size(file.reader()?).context("decompress patch")?;
+
+        let patch_file = match (&file, location) {
+            (FileEntry::InFilesystem(entry), Location::Local) => entry,
+            _ => return Ok(()),
+        };
+        let source_path = self.build_file_name(&patch.from, Location::Local)?;
+        let source = match self.get_local_file(&source_path).await {
+            Ok(source) => source,
+            Err(_) => {
+                log::debug!(
+                    "source build `{}` not available locally, not applying `{}`",
+                    patch.from,
+                    patch
+                );
+                return Ok(());
+            }
+        };
+
+        let source = crate::decompress_build(
+            &source.path,
+            BufReader::new(
+                File::open(&source.path).with_context(|| format!("open file `{}`", source.path))?,
+            ),
+        )
+        .with_context(|| format!("decompress source build `{}`", patch.from))?;
+        let mut patched =
+            apply_patch_to_decompressed(&source, &patch_file.path).context("apply patch")?;
+        io::copy(&mut patched, &mut io::sink()).context("apply patch")?;
+        Ok(())
+    }
+
+    /// Local builds and patches to check with [`LocalCheck::run`]
+    pub(crate) async fn local_checks(&self) -> Vec<LocalCheck> {
+        let mut checks = Vec::new();
+        for build in self.builds() {
+            if let Some(entry) = &build.local {
+                let checksum = self.build_checksum(&build.version, Location::Local).await;
+                checks.push(LocalCheck::new(entry, true, checksum));
+            }
+        }
+        for patch in self.patches() {
+            if let Some(entry) = &patch.local {
+                let checksum = self
+                    .read_checksum(Location::Local, &patch.file_name())
+                    .await;
+                checks.push(LocalCheck::new(entry, false, checksum));
+            }
+        }
+        checks
+    }
+
+    /// Groups of builds with no patches between them
+    pub fn build_islands(&self) -> Vec<Vec<Version>> {
+        self.patch_graph.connected_components()
+    }
+
+    /// Patches that are never part of a cheapest upgrade path
+    pub fn redundant_patches(&self) -> Vec<Patch> {
+        self.patch_graph.redundant_patches()
+    }
+
+    /// Local patches from or to builds that exist neither locally nor
+    /// remotely, e.g. after builds were deleted
+    ///
+    /// The graph can't hold those, so this lists local storage again.
+    pub async fn orphaned_local_patches(&self) -> Result<Vec<Patch>> {
+        let files = self.local.list_files().await.context("list local files")?;
+        let mut orphans = Vec::new();
+        for entry in files {
+            if !paths::is_patch_path(&entry.path) {
+                continue;
+            }
+            let patch = match Patch::from_path(&entry.path) {
+                Ok(patch) => patch,
+                Err(e) => {
+                    log::debug!("not a patch `{}`: {}", entry.path, e);
+                    continue;
+                }
+            };
+            if !self.patch_graph.has_build(patch.from.clone())
+                || !self.patch_graph.has_build(patch.to.clone())
+            {
+                orphans.push(patch);
+            }
+        }
+        orphans.sort_by(|a, b| (&a.from, &a.to).cmp(&(&b.from, &b.to)));
+        Ok(orphans)
+    }
+
+    /// Delete a patch found by [`ArtefactIndex::orphaned_local_patches`]
+    pub async fn remove_orphaned_patch(&self, patch: &Patch) -> Result<()> {
+        let patch_name = patch.file_name();
+        self.local
+            .remove_file(&patch_name)
+            .await
+            .with_context(|| format!("remove local patch `{}`", patch))?;
+        self.remove_checksum(Location::Local, &patch_name).await;
+        Ok(())
+    }
+
+    /// Delete patch from local and remote storage
+    pub async fn remove_patch(&mut self, from: Version, to: Version) -> Result<()> {
+        let patch = self
+            .patch_graph
+            .patch(from.clone(), to.clone())
+            .with_context(|| format!("patch `{:?}` unknown", (&from, &to)))?
+            .clone();
+        let patch_name = patch.file_name();
+
+        if patch.local.is_some() {
+            self.local
+                .remove_file(&patch_name)
+                .await
+                .context("remove local patch")?;
+            self.remove_checksum(Location::Local, &patch_name).await;
+        }
+        if patch.remote.is_some() {
+            self.remote
+                .remove_file(&patch_name)
+                .await
+                .context("remove remote patch")?;
+            self.remove_checksum(Location::Remote, &patch_name).await;
+            self.invalidate_remote_cache();
+        }
+
+        self.patch_graph.remove_patch(&from, &to);
+        Ok(())
+    }
+
+    /// Delete old local builds so that only the newest `keep` are left
+    ///
+    /// Never deletes `protected` builds (e.g. installed ones), and only
+    /// deletes builds (and patches from or to them) that are also in remote
+    /// storage, so nothing gets lost. Returns the versions of the deleted
+    /// builds.
+    pub async fn prune_local_builds(
+        &mut self,
+        keep: usize,
+        protected: &[Version],
+    ) -> Result<Vec<Version>> {
+        let local_builds = self
+            .patch_graph
+            .builds()
+            .into_iter()
+            .filter(|build| build.local.is_some())
+            .cloned()
+            .collect::<Vec<Build>>();
+        let cutoff = local_builds.len().saturating_sub(keep);
+
+        let mut removed = Vec::new();
+        for build in &local_builds[..cutoff] {
+            if protected.contains(&build.version) {
+                continue;
+            }
+            if build.remote.is_none() {
+                log::warn!(
+                    "keeping old local build `{}` as it is not in remote storage",
+                    build.version
+                );
+                continue;
+            }
+
+            self.delete_local_build(&build.version).await?;
+            log::info!("removed old local build `{}`", build.version);
+            removed.push(build.version.clone());
+        }
+
+        let orphaned_patches = self
+            .patch_graph
+            .patches()
+            .into_iter()
+            .filter(|patch| patch.local.is_some())
+            .filter(|patch| removed.contains(&patch.from) || removed.contains(&patch.to))
+            .cloned()
+            .collect::<Vec<Patch>>();
+        for patch in orphaned_patches {
+            if patch.remote.is_none() {
+                log::warn!(
+                    "keeping local patch `{}` as it is not in remote storage",
+                    patch
+                );
+                continue;
+            }
+            self.delete_local_patch(&patch).await?;
+            log::debug!("removed local patch `{}`", patch);
+        }
+
+        Ok(removed)
+    }
+
+    /// Delete the local file of a build, and of all patches from or to it
+    ///
+    /// Copies in remote storage stay, so this is only safe for builds (and
+    /// patches) that are in remote storage or not needed anymore. Returns the
+    /// deleted patches.
+    pub async fn remove_local_build(&mut self, version: &Version) -> Result<Vec<Patch>> {
+        ensure!(
+            self.patch_graph.has_build(version.clone()),
+            "build `{}` unknown",
+            version
+        );
+        ensure!(
+            self.patch_graph.has_local_build(version.clone()),
+            "build `{}` is not stored locally",
+            version
+        );
+
+        self.delete_local_build(version).await?;
+        let patches = self
+            .patch_graph
+            .patches()
+            .into_iter()
+            .filter(|patch| patch.local.is_some())
+            .filter(|patch| &patch.from == version || &patch.to == version)
+            .cloned()
+            .collect::<Vec<Patch>>();
+        for patch in &patches {
+            self.delete_local_patch(patch).await?;
+        }
+        Ok(patches)
+    }
+
+    /// Delete a local build with its checksum, metadata, and extracted
+    /// directory
+    async fn delete_local_build(&mut self, version: &Version) -> Result<()> {
+        let build_path = self.build_file_name(version, Location::Local)?;
+        self.local
+            .remove_file(&build_path)
+            .await
+            .with_context(|| format!("remove local build `{}`", version))?;
+        self.remove_checksum(Location::Local, &build_path).await;
+        if let Err(e) = self
+            .local
+            .remove_file(&paths::signature_path(&build_path))
+            .await
+        {
+            log::debug!("no signature to remove for `{}`: {}", version, e);
+        }
+        let meta_path = paths::build_meta_path_from_version(version.clone())?;
+        if let Err(e) = self.local.remove_file(&meta_path).await {
+            log::debug!("no metadata to remove for `{}`: {}", version, e);
+        }
+        let extracted = self
+            .local
+            .local_path()
+            .context("local storage is not in the file system")?
+            .join(paths::extracted_path_from_version(version.clone())?);
+        if extracted.is_dir() {
+            fs::remove_dir_all(&extracted)
+                .with_context(|| format!("remove extracted build `{}`", extracted.display()))?;
+        }
+        self.patch_graph.remove_local_build(version);
+        Ok(())
+    }
+
+    async fn delete_local_patch(&mut self, patch: &Patch) -> Result<()> {
+        self.local
+            .remove_file(&patch.file_name())
+            .await
+            .with_context(|| format!("remove local patch `{}`", patch))?;
+        self.remove_checksum(Location::Local, &patch.file_name())
+            .await;
+        self.patch_graph.remove_local_patch(&patch.from, &patch.to);
+        Ok(())
+    }
+
+    /// Split `builds` into those to upload and `(alias, target)` pairs of
+    /// builds with the same content as one on remote or uploaded before them
+    fn find_duplicates(&self, builds: Vec<Build>) -> (Vec<Build>, Vec<(Version, Version)>) {
+        let mut stored: Vec<(Checksum, Version)> = self
+            .patch_graph
+            .builds()
+            .into_iter()
+            .filter(|build| build.remote.is_some())
+            .filter_map(|build| {
+                let target = self
+                    .patch_graph
+                    .alias_of(&build.version)
+                    .unwrap_or(&build.version);
+                Some((build.checksum?, target.clone()))
+            })
+            .collect();
+
+        let mut upload = Vec::new();
+        let mut aliases = Vec::new();
+        for build in builds {
+            let checksum = match build.checksum {
+                Some(checksum) => checksum,
+                None => {
+                    upload.push(build);
+                    continue;
+                }
+            };
+            match stored.iter().find(|(other, _)| *other == checksum) {
+                Some((_, target)) => aliases.push((build.version, target.clone())),
+                None => {
+                    stored.push((checksum, build.version.clone()));
+                    upload.push(build);
+                }
+            }
+        }
+        (upload, aliases)
+    }
+
     // Fetch current state from S3 and upload all missing files (i.e. new builds
-    // and patches)
-    pub async fn push(&self) -> Result<()> {
+    // and patches). Returns the names and sizes of the uploaded files -- or
+    // with `dry_run`, the files that would be uploaded, without uploading
+    // anything.
+    pub async fn push(&self, dry_run: bool) -> Result<Vec<(String, u64)>> {
         use futures::stream::{self, StreamExt, TryStreamExt};
 
-        let builds = self
-            .patch_graph
-            .local_only_builds()
+        let (builds, aliases) = if self.alias_duplicates {
+            self.find_duplicates(self.patch_graph.local_only_builds())
+        } else {
+            (self.patch_graph.local_only_builds(), Vec::new())
+        };
+        let builds = builds
             .into_iter()
             .map(|b| {
                 if let Some(local) = b.local {
@@ -491,7 +2042,25 @@ impl Index {
             "found {} builds locally that are not on remote",
             builds.len()
         );
-        let builds = stream::iter(builds);
+        let local_dir = self
+            .local
+            .local_path()
+            .context("can only push from local storage")?;
+        let metas = builds
+            .iter()
+            .map(|build| paths::build_version_from_path(&build.path))
+            .chain(aliases.iter().map(|(alias, _)| Ok(alias.clone())))
+            .map(|version| -> Result<Option<Entry>> {
+                let meta_path = local_dir.join(paths::build_meta_path_from_version(version?)?);
+                if meta_path.exists() {
+                    Ok(Some(Entry::from_path(&meta_path, self.local.clone())?))
+                } else {
+                    Ok(None)
+                }
+            })
+            .filter_map(|x| x.transpose())
+            .collect::<Result<Vec<Entry>>>()
+            .context("collecting build metadata to upload")?;
 
         let patches = self
             .patch_graph
@@ -510,20 +2079,103 @@ impl Index {
             "found {} patches locally that are not on remote",
             patches.len()
         );
-        let patches = stream::iter(patches);
 
-        builds
+        let checksums = builds
+            .iter()
+            .chain(&patches)
+            .map(|entry| -> Result<Vec<Entry>> {
+                let path = Path::new(&entry.path);
+                let file_name = paths::path_as_string(path.file_name().context("no file name")?)?;
+                ChecksumAlgorithm::ALL
+                    .iter()
+                    .map(|&algorithm| {
+                        path.with_file_name(paths::checksum_path(&file_name, algorithm))
+                    })
+                    .filter(|checksum_path| checksum_path.exists())
+                    .map(|checksum_path| Entry::from_path(&checksum_path, self.local.clone()))
+                    .collect::<Result<Vec<Entry>>>()
+            })
+            .collect::<Result<Vec<Vec<Entry>>>>()
+            .map(|checksums| checksums.into_iter().flatten().collect::<Vec<Entry>>())
+            .context("collecting checksums to upload")?;
+
+        let signatures = builds
+            .iter()
+            .map(|entry| -> Result<Option<Entry>> {
+                let path = Path::new(&entry.path);
+                let file_name = paths::path_as_string(path.file_name().context("no file name")?)?;
+                let signature_path = path.with_file_name(paths::signature_path(&file_name));
+                if !signature_path.exists() && self.signing_key.is_some() && !dry_run {
+                    let version = paths::build_version_from_path(path)?;
+                    let checksum = match self.patch_graph.checksum(&version) {
+                        Some(checksum) => checksum,
+                        None => self.checksums.checksum(path, self.checksum_algorithm)?,
+                    };
+                    self.sign_local_build(&version, path, checksum)
+                        .with_context(|| format!("sign `{}`", version))?;
+                }
+                if signature_path.exists() {
+                    Ok(Some(Entry::from_path(&signature_path, self.local.clone())?))
+                } else {
+                    Ok(None)
+                }
+            })
+            .filter_map(|x| x.transpose())
+            .collect::<Result<Vec<Entry>>>()
+            .context("collecting signatures to upload")?;
+
+        let alias_files = aliases.iter().map(|(alias, target)| {
+            let name = paths::alias_path(alias, target);
+            let content = format!("{}\n", target.as_str()).into_bytes();
+            let entry = Entry {
+                storage: self.local.clone(),
+                path: name.clone(),
+                size: content.len() as u64,
+                modified: None,
+            };
+            (name, FileEntry::Inline(entry, content.into()))
+        });
+
+        let files = builds
+            .into_iter()
+            .chain(metas)
             .chain(patches)
-            .map(|x| -> Result<Entry> { Ok(x) }) // necessary for fallible method and type inference
-            .try_for_each_concurrent(3, |entry| async {
+            .chain(checksums)
+            .chain(signatures)
+            .map(|entry| {
                 let s3_key = entry
                     .path
                     .rsplit('/')
                     .next()
                     .expect("always one item in split")
                     .to_owned();
+                (s3_key, FileEntry::InFilesystem(entry))
+            })
+            .chain(alias_files)
+            .collect::<Vec<_>>();
+        let names: Vec<(String, u64)> = files
+            .iter()
+            .map(|(key, file)| (key.clone(), file.size()))
+            .collect();
+        if dry_run || files.is_empty() {
+            return Ok(names);
+        }
+
+        let started = Instant::now();
+        let uploading = timings::start(Phase::Upload);
+        self.invalidate_remote_cache();
+        stream::iter(files)
+            .map(|x| -> Result<(String, FileEntry)> { Ok(x) }) // necessary for fallible method and type inference
+            .try_for_each_concurrent(self.upload_concurrency, |(s3_key, file)| async move {
+                // builds and patches have checksums, by which an interrupted
+                // upload of them is found again (even when encrypted anew)
+                let checksum = if paths::is_build_path(&s3_key) || paths::is_patch_path(&s3_key) {
+                    self.read_checksum(Location::Local, &s3_key).await?
+                } else {
+                    None
+                };
                 self.remote
-                    .add_file(&FileEntry::InFilesystem(entry), &s3_key)
+                    .add_file_with_checksum(&file, &s3_key, checksum.as_ref())
                     .await
                     .with_context(|| format!("adding `{}`", s3_key))?;
                 log::info!("uploaded `{}`", s3_key);
@@ -531,11 +2183,429 @@ impl Index {
             })
             .await
             .context("uploading missing files to remote")?;
+        drop(uploading);
+        self.record(|metrics| {
+            metrics.add_uploaded(names.iter().map(|(_, size)| size).sum());
+            metrics.add_operation(Operation::Push, started.elapsed());
+        });
+
+        Ok(names)
+    }
 
+    /// Store `content` as `name` in remote storage, replacing what's there
+    pub async fn upload_file(&self, name: &str, content: Vec<u8>) -> Result<()> {
+        let entry_size = content.len() as u64;
+        let entry = Entry {
+            storage: self.local.clone(),
+            path: name.to_owned(),
+            size: content.len() as u64,
+            modified: None,
+        };
+        self.invalidate_remote_cache();
+        self.remote
+            .add_file(&FileEntry::Inline(entry, content.into()), name)
+            .await
+            .with_context(|| format!("adding `{}`", name))?;
+        log::info!("uploaded `{}`", name);
+        self.record(|metrics| metrics.add_uploaded(entry_size));
         Ok(())
     }
 }
 
+/// Replace the file at `path` with a hard link to `original`, which has the
+/// same content
+fn link_identical(original: &Path, path: &Path) -> Result<()> {
+    let link = PathBuf::from(format!("{}.link", path.display()));
+    fs::hard_link(original, &link)
+        .with_context(|| format!("link `{}` to `{}`", link.display(), original.display()))?;
+    fs::rename(&link, path).with_context(|| {
+        fs::remove_file(&link).log_and_discard();
+        format!("move `{}` to `{}`", link.display(), path.display())
+    })
+}
+
+/// Make sure `version` can be the version of a build
+fn ensure_not_reserved(version: &Version) -> Result<()> {
+    ensure!(
+        version.as_str() != Version::LATEST,
+        "`{}` is reserved for the newest build and can't be the version of a build",
+        Version::LATEST
+    );
+    Ok(())
+}
+
+/// Calculate checksum of a local build or patch file and write it to
+/// `<file>.<algorithm>` next to it
+fn store_checksum(
+    checksums: &ChecksumCache,
+    path: &Path,
+    algorithm: ChecksumAlgorithm,
+) -> Result<Checksum> {
+    let checksum = checksums.checksum(path, algorithm)?;
+    write_checksum(path, &checksum)?;
+    Ok(checksum)
+}
+
+/// Write `checksum` to `<file>.sha256` (or `<file>.b3`) next to the file at `path`
+fn write_checksum(path: &Path, checksum: &Checksum) -> Result<()> {
+    let file_name = paths::path_as_string(
+        path.file_name()
+            .with_context(|| format!("no file name in `{}`", path.display()))?,
+    )?;
+    let checksum_path = path.with_file_name(paths::checksum_path(&file_name, checksum.algorithm()));
+    let mut file = PartialFile::create(&checksum_path)
+        .with_context(|| format!("create `{}`", checksum_path.display()))?;
+    file.write_all(checksum.sidecar(&file_name).as_bytes())
+        .with_context(|| format!("write `{}`", checksum_path.display()))?;
+    file.finish()
+        .with_context(|| format!("finish writing `{}`", checksum_path.display()))?;
+    Ok(())
+}
+
+/// Open a local build for reading its decompressed content (or its content,
+/// for plain tar archives)
+pub(crate) fn open_build(entry: &Entry) -> Result<impl Read> {
+    ensure!(
+        entry.storage.is_local(),
+        "only reading from local storage supported"
+    );
+    let path = &entry.path;
+    let file = File::open(path).with_context(|| format!("could not open file {}", path))?;
+    crate::build_reader(path, BufReader::new(file)).with_context(|| format!("read build {}", path))
+}
+
+/// Diff builds after decompressing them into temporary files in `dir` and
+/// memory-mapping those
+///
+/// The temp files are next to the builds, as the system's temp dir might be in
+/// memory.
+#[cfg(all(feature = "mmap", unix))]
+fn diff_mapped(
+    old_build: &Entry,
+    new_build: &Entry,
+    dir: &Path,
+    out: &mut dyn Write,
+    progress: &Progress,
+    window_size: usize,
+    params: &bidiff::DiffParams,
+) -> Result<u64> {
+    let map_build = |entry: &Entry| -> Result<crate::mmap::Mmap> {
+        let mut file = tempfile::tempfile_in(dir).context("create temp file")?;
+        io::copy(&mut open_build(entry)?, &mut file).context("decompress build")?;
+        crate::mmap::Mmap::map(&file).context("map decompressed build")
+    };
+    let old_build = map_build(old_build).context("read old build")?;
+    let new_build = map_build(new_build).context("read new build")?;
+    crate::diff::diff_slices_windowed(&old_build, &new_build, out, progress, window_size, params)
+        .context("diff mapped builds")
+}
+
+#[cfg(not(all(feature = "mmap", unix)))]
+fn diff_mapped(
+    _old_build: &Entry,
+    _new_build: &Entry,
+    _dir: &Path,
+    _out: &mut dyn Write,
+    _progress: &Progress,
+    _window_size: usize,
+    _params: &bidiff::DiffParams,
+) -> Result<u64> {
+    bail!("memory-mapping builds is not supported by this build of artefacta")
+}
+
+/// Everything needed to calculate a patch between two local builds
+///
+/// Created by [`Index::prepare_patch`]. As running it doesn't need the index,
+/// several patches can be calculated at the same time.
+#[derive(Debug)]
+pub(crate) struct PatchJob {
+    from: Version,
+    to: Version,
+    old_build: Entry,
+    new_build: Entry,
+    new_build_uncompressed_size: Option<u64>,
+    /// Local storage, where the patch is written to
+    local: PathBuf,
+    checksum_algorithm: ChecksumAlgorithm,
+    checksums: ChecksumCache,
+    window_size: usize,
+    threads: Option<usize>,
+    chunk_size: usize,
+    parallel_threshold: u64,
+    mmap: bool,
+}
+
+/// Checking a local build or patch, prepared by [`Index::local_checks`] to run
+/// without the index (e.g. on another thread)
+#[derive(Debug)]
+pub(crate) struct LocalCheck {
+    path: PathBuf,
+    size: u64,
+    /// Whether it's a build (which might not be compressed) or a patch
+    build: bool,
+    /// Checksum to compare with (if we know it), or why reading it failed
+    checksum: Result<Option<Checksum>>,
+}
+
+impl LocalCheck {
+    fn new(entry: &Entry, build: bool, checksum: Result<Option<Checksum>>) -> Self {
+        LocalCheck {
+            path: PathBuf::from(&entry.path),
+            size: entry.size,
+            build,
+            checksum,
+        }
+    }
+
+    /// Name of the file in the local store
+    pub(crate) fn file_name(&self) -> String {
+        self.path.file_name().map_or_else(
+            || self.path.display().to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        )
+    }
+
+    pub(crate) fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Decompress all of the file and compare its checksum, returning its
+    /// decompressed size and whether there was a checksum to compare
+    ///
+    /// Reads the whole file even if its checksum is cached, as this is for
+    /// finding files that went bad without being modified.
+    pub(crate) fn run(self) -> Result<(u64, bool)> {
+        let name = self.file_name();
+        let path = self.path;
+        let open = || {
+            File::open(&path)
+                .map(BufReader::new)
+                .with_context(|| format!("open `{}`", path.display()))
+        };
+        let checksum = self.checksum.context("read checksum file")?;
+        if let Some(checksum) = &checksum {
+            checksum.validate(open()?)?;
+        }
+        let size = if self.build {
+            crate::build_reader(&path, open()?)
+                .and_then(|mut content| io::copy(&mut content, &mut io::sink()).map_err(Into::into))
+        } else {
+            crate::compression::decompressed_size(open()?)
+        }
+        .map_err(|e| ErrorKind::Data.wrap(e, format!("`{}` can't be decompressed", name)))?;
+        Ok((size, checksum.is_some()))
+    }
+}
+
+/// Patch file written by [`PatchJob::run`], to add to the index with
+/// [`Index::add_calculated_patch`]
+#[derive(Debug)]
+pub(crate) struct CalculatedPatch {
+    pub(crate) from: Version,
+    pub(crate) to: Version,
+    path: String,
+    size: u64,
+    duration: Duration,
+}
+
+impl PatchJob {
+    /// Diff the builds and write the patch (with its checksum) to local
+    /// storage
+    pub(crate) fn run(self) -> Result<CalculatedPatch> {
+        fn file_size(size: u64) -> String {
+            use humansize::{file_size_opts as options, FileSize};
+            size.file_size(options::BINARY).expect("never negative")
+        }
+
+        let started = Instant::now();
+        let PatchJob {
+            from,
+            to,
+            old_build,
+            new_build,
+            local,
+            ..
+        } = &self;
+        let new_build_size = new_build.size;
+        let new_build_uncompressed_size = match self.new_build_uncompressed_size {
+            Some(size) => size,
+            None => {
+                let _timing = timings::start(Phase::Decompress);
+                io::copy(&mut open_build(new_build)?, &mut io::sink()).context("read new build")?
+            }
+        };
+
+        let patch_path = local.join(Patch::new(from.clone(), to.clone()).file_name());
+        log::debug!("write patch `{} -> {}` to `{:?}`", from, to, patch_path);
+
+        let mut patch_file =
+            PartialFile::create(&patch_path).context("creating file to write patch to")?;
+        let mut patch = crate::compress(&mut patch_file)?;
+        let params = crate::diff::params(
+            self.threads,
+            self.chunk_size,
+            self.parallel_threshold,
+            new_build_size,
+        )
+        .map_err(Report::msg)
+        .context("invalid diff parameters")?;
+        let progress = Progress::new(
+            format!("diffing {} -> {}", from, to),
+            new_build_uncompressed_size,
+        );
+
+        let diffing = timings::start(Phase::Diff);
+        let diffed = if self.mmap {
+            diff_mapped(
+                old_build,
+                new_build,
+                local,
+                &mut patch,
+                &progress,
+                self.window_size,
+                &params,
+            )
+        } else {
+            crate::diff::diff_windowed(
+                open_build(old_build).context("read old build")?,
+                open_build(new_build).context("read new build")?,
+                &mut patch,
+                &progress,
+                self.window_size,
+                &params,
+            )
+            .map_err(Report::from)
+        };
+        diffed.context("calculating binary diff between builds")?;
+        drop(progress);
+        patch.finish().context("finishing zstd file")?;
+        drop(diffing);
+        patch_file
+            .finish()
+            .context("finishing writing patch file")?;
+        store_checksum(&self.checksums, &patch_path, self.checksum_algorithm)
+            .context("write checksum of new patch")?;
+
+        let patch_size = patch_path
+            .metadata()
+            .with_context(|| {
+                format!(
+                    "can't read metadata for new patch file `{}`",
+                    patch_path.display()
+                )
+            })?
+            .len();
+
+        log::info!(
+            "Calculated new patch from {} to {} of size {} -- that's {:.1}% of the new build's {} ({} uncompressed)",
+            from,
+            to,
+            file_size(patch_size),
+            (patch_size as f64) / (new_build_size as f64) * 100_f64,
+            file_size(new_build_size),
+            file_size(new_build_uncompressed_size),
+        );
+
+        Ok(CalculatedPatch {
+            path: paths::path_as_string(patch_path)?,
+            size: patch_size,
+            duration: started.elapsed(),
+            from: self.from,
+            to: self.to,
+        })
+    }
+}
+
+/// Number of files [`Index::push`] uploads at the same time, by default
+pub const DEFAULT_UPLOAD_CONCURRENCY: usize = 3;
+
+/// Builds among local `files` that look corrupt, with what's wrong with them
+fn suspect_builds(files: &[Entry]) -> Vec<(Version, &'static str)> {
+    files
+        .iter()
+        .filter(|entry| paths::is_build_path(&entry.path))
+        .filter_map(|entry| {
+            let problem = suspect_build_file(entry)?;
+            let version = paths::build_version_from_path(&entry.path).ok()?;
+            Some((version, problem))
+        })
+        .collect()
+}
+
+/// What's wrong with the local build file `entry`, if it looks corrupt
+fn suspect_build_file(entry: &Entry) -> Option<&'static str> {
+    if entry.size == 0 {
+        return Some("it's empty");
+    }
+    let plain = paths::is_plain_build_path(&entry.path);
+    // enough for the header of the first file in a tar archive
+    let mut start = Vec::new();
+    match File::open(&entry.path).and_then(|file| file.take(512).read_to_end(&mut start)) {
+        Ok(_) if crate::compression::is_zstd(&start) => None,
+        // plain tar archives (see `build_reader`) say so in that header
+        Ok(_) if plain && start.get(257..262) == Some(&b"ustar"[..]) => None,
+        Ok(_) if start.len() < 4 => Some("it's truncated"),
+        Ok(_) if plain => Some("it's neither zstd compressed nor a tar archive"),
+        Ok(_) => Some("it isn't zstd compressed"),
+        Err(e) => {
+            log::debug!("could not check local build `{}`: {}", entry.path, e);
+            None
+        }
+    }
+}
+
+/// Decompressed builds larger than this are not kept in memory while applying
+/// a chain of patches
+const MAX_DECOMPRESSED_BUILD_IN_MEMORY: usize = 512 * 1024 * 1024;
+
+/// Decompressed content of a build
+struct DecompressedBuild {
+    version: Version,
+    content: Vec<u8>,
+}
+
+/// Writer that passes everything through but also keeps a copy of it in
+/// memory, as long as that copy stays below the given limit
+struct KeepInMemory<W> {
+    inner: W,
+    content: Option<Vec<u8>>,
+    limit: usize,
+}
+
+impl<W: Write> KeepInMemory<W> {
+    fn new(inner: W, limit: usize) -> Self {
+        KeepInMemory {
+            inner,
+            content: Some(Vec::new()),
+            limit,
+        }
+    }
+
+    /// Everything written, unless it was too much
+    fn into_content(self) -> Option<Vec<u8>> {
+        self.content
+    }
+}
+
+impl<W: Write> Write for KeepInMemory<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if let Some(content) = self.content.as_mut() {
+            if content.len() + written > self.limit {
+                log::debug!("output larger than {} bytes, not keeping it", self.limit);
+                self.content = None;
+            } else {
+                content.extend_from_slice(&buf[..written]);
+            }
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -566,6 +2636,75 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn planning_upgrades_downloads_nothing() -> Result<()> {
+        let build_dir = tempdir()?;
+        let local_dir = tempdir()?;
+        let remote_dir = tempdir()?;
+
+        let mut content = random_bytes(4096)?;
+        zstd_file(build_dir.path().join("build1.tar.zst"), &content)?;
+        content.extend(random_bytes(32)?);
+        zstd_file(build_dir.path().join("build2.tar.zst"), &content)?;
+        let mut builds = Index::new(build_dir.path(), remote_dir.path().try_into()?).await?;
+        builds
+            .calculate_patch("build1".parse()?, "build2".parse()?)
+            .await?;
+        for file in [
+            "build1.tar.zst",
+            "build2.tar.zst",
+            "build1-build2.patch.zst",
+        ] {
+            fs::copy(build_dir.path().join(file), remote_dir.path().join(file))?;
+        }
+        fs::copy(
+            build_dir.path().join("build1.tar.zst"),
+            local_dir.path().join("build1.tar.zst"),
+        )?;
+
+        let index = Index::new(local_dir.path(), remote_dir.path().try_into()?).await?;
+        let plan = index.plan_upgrade("build1".parse()?, "build2".parse()?)?;
+        let patch_size = fs::metadata(remote_dir.path().join("build1-build2.patch.zst"))?.len();
+        assert!(matches!(plan.path, UpgradePath::ApplyPatches(_)));
+        assert_eq!(plan.path.patches().len(), 1);
+        assert_eq!(plan.path.patches()[0].to().as_str(), "build2");
+        assert_eq!(plan.path.size(), patch_size);
+        assert_eq!(plan.download_size, patch_size);
+        assert_eq!(plan.files_to_fetch, ["build1-build2.patch.zst"]);
+
+        assert!(index
+            .plan_upgrade("build1".parse()?, "build3".parse()?)
+            .is_err());
+        assert_eq!(index.downloaded_bytes(), 0);
+        assert_eq!(fs::read_dir(local_dir.path())?.count(), 1, "nothing new");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn created_patches_are_found_by_their_file_name() -> Result<()> {
+        let local_dir = tempdir()?;
+        let remote_dir = tempdir()?;
+        for (from, to, file_name) in [
+            ("build1", "build2", "build1-build2.patch.zst"),
+            ("v1.0-rc1", "v1.0", "v1.0-rc1---v1.0.patch.zst"),
+        ] {
+            random_zstd_file(local_dir.path().join(format!("{}.tar.zst", from)))?;
+            random_zstd_file(local_dir.path().join(format!("{}.tar.zst", to)))?;
+            let mut index = Index::new(local_dir.path(), remote_dir.path().try_into()?).await?;
+            index.calculate_patch(from.parse()?, to.parse()?).await?;
+            assert!(local_dir.path().join(file_name).exists(), "{}", file_name);
+
+            let entry = index.get_patch(from.parse()?, to.parse()?).await?;
+            assert!(entry.path.ends_with(file_name), "{}", entry.path);
+
+            // and when listing the store again
+            let index = Index::new(local_dir.path(), remote_dir.path().try_into()?).await?;
+            assert!(index.patch_graph.has_patch(from.parse()?, to.parse()?));
+        }
+        Ok(())
+    }
+
     #[tokio::test]
     async fn generate_patches() -> Result<()> {
         let dir = test_dir(&["1.tar.zst", "2.tar.zst", "1-2.patch.zst"])?;
@@ -596,6 +2735,120 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn either_storage_may_be_empty() -> Result<()> {
+        let empty = tempdir()?;
+        let builds = test_dir(&["1.tar.zst", "2.tar.zst", "1-2.patch.zst"])?;
+
+        let index = Index::new(empty.path(), builds.path().try_into()?).await?;
+        assert!(index.patch_graph.remote_build("2".parse()?).is_some());
+        assert!(index.patch_graph.local_build("2".parse()?).is_none());
+        assert!(index.patch_graph.has_patch("1".parse()?, "2".parse()?));
+
+        let index = Index::new(builds.path(), empty.path().try_into()?).await?;
+        assert!(index.patch_graph.local_build("2".parse()?).is_some());
+        assert!(index.patch_graph.remote_build("2".parse()?).is_none());
+        assert!(index.patch_graph.has_patch("1".parse()?, "2".parse()?));
+
+        let both = Index::new(builds.path(), builds.path().try_into()?).await?;
+        assert!(both.patch_graph.local_build("1".parse()?).is_some());
+        assert!(both.patch_graph.remote_build("1".parse()?).is_some());
+
+        Ok(())
+    }
+
+    #[cfg(all(feature = "mmap", unix))]
+    #[tokio::test]
+    async fn calculate_patch_from_mapped_builds() -> Result<()> {
+        let local_dir = tempdir()?;
+        let remote_dir = tempdir()?;
+
+        let content = random_bytes(100_000)?;
+        zstd_file(local_dir.path().join("1.tar.zst"), &content[..90_000])?;
+        zstd_file(local_dir.path().join("2.tar.zst"), &content[5_000..])?;
+
+        let mut index = Index::new(local_dir.path(), remote_dir.path().try_into()?).await?;
+        index.set_diff_mmap(true)?;
+        index.set_diff_window_size(16 * 1024);
+        index.calculate_patch("1".parse()?, "2".parse()?).await?;
+
+        let mut patched = Vec::new();
+        crate::apply_patch(
+            local_dir.path().join("1.tar.zst"),
+            local_dir.path().join("1-2.patch.zst"),
+        )?
+        .read_to_end(&mut patched)?;
+        assert!(
+            patched == content[5_000..],
+            "patch doesn't produce new build"
+        );
+        assert_eq!(
+            fs::read_dir(local_dir.path())?.count(),
+            4,
+            "temp files are gone, only builds and patch remain: {:?}",
+            fs::read_dir(local_dir.path())?.collect::<Vec<_>>()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chain_of_patches_reuses_decompressed_builds() -> Result<()> {
+        logger();
+        let local_dir = tempdir()?;
+        let remote_dir = tempdir()?;
+
+        let mut content = random_bytes(1024)?;
+        zstd_file(local_dir.path().join("1.tar.zst"), &content)?;
+        for build in 2..=4 {
+            content.extend(random_bytes(32)?);
+            zstd_file(
+                remote_dir.path().join(format!("{}.tar.zst", build)),
+                &content,
+            )?;
+        }
+
+        let mut index = Index::new(local_dir.path(), remote_dir.path().try_into()?).await?;
+        for (from, to) in [("1", "2"), ("2", "3"), ("3", "4")] {
+            index.calculate_patch(from.parse()?, to.parse()?).await?;
+        }
+        for build in 2..=4 {
+            fs::remove_file(local_dir.path().join(format!("{}.tar.zst", build)))?;
+        }
+
+        let mut index = Index::new(local_dir.path(), remote_dir.path().try_into()?).await?;
+        let mut previous_build = None;
+        index
+            .add_build_from_patch(&Patch::new("1".parse()?, "2".parse()?), &mut previous_build)
+            .await?;
+
+        // if build 2 were read from disk again, this would fail now
+        fs::write(local_dir.path().join("2.tar.zst"), b"garbage")?;
+        index
+            .add_build_from_patch(&Patch::new("2".parse()?, "3".parse()?), &mut previous_build)
+            .await?;
+        fs::write(local_dir.path().join("3.tar.zst"), b"garbage")?;
+        index
+            .add_build_from_patch(&Patch::new("3".parse()?, "4".parse()?), &mut previous_build)
+            .await?;
+
+        let build4 = zstd::stream::decode_all(fs::File::open(local_dir.path().join("4.tar.zst"))?)?;
+        assert_eq!(build4, content);
+
+        Ok(())
+    }
+
+    #[test]
+    fn large_outputs_are_not_kept_in_memory() {
+        let mut output = KeepInMemory::new(Vec::new(), 4);
+        output.write_all(b"lore").unwrap();
+        assert_eq!(output.into_content(), Some(b"lore".to_vec()));
+
+        let mut output = KeepInMemory::new(Vec::new(), 4);
+        output.write_all(b"lorem").unwrap();
+        assert_eq!(output.into_content(), None);
+    }
+
     fn test_dir(files: &[&str]) -> Result<TempDir> {
         let dir = tempdir()?;
         let mut rng = rand::thread_rng();
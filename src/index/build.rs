@@ -1,4 +1,8 @@
-use crate::{index::Version, storage::Entry};
+use crate::{
+    index::{Checksum, Version},
+    storage::Entry,
+};
+use std::collections::BTreeMap;
 
 /// Artefact with version
 #[derive(Debug, Clone, Eq, PartialOrd, Ord)]
@@ -6,6 +10,11 @@ pub struct Build {
     pub(crate) version: Version,
     pub(crate) local: Option<Entry>,
     pub(crate) remote: Option<Entry>,
+    pub(crate) uncompressed_size: Option<u64>,
+    /// Checksum of the (compressed) build file, if known
+    pub(crate) checksum: Option<Checksum>,
+    /// Key-value pairs from the build's metadata (empty if there are none)
+    pub(crate) custom_meta: BTreeMap<String, String>,
 }
 
 /// Builder
@@ -18,6 +27,9 @@ impl Build {
             version,
             local: None,
             remote: None,
+            uncompressed_size: None,
+            checksum: None,
+            custom_meta: BTreeMap::new(),
         }
     }
 
@@ -28,12 +40,32 @@ impl Build {
     pub fn set_remote(&mut self, remote: Entry) {
         self.remote = Some(remote);
     }
+
+    pub fn set_uncompressed_size(&mut self, size: u64) {
+        self.uncompressed_size = Some(size);
+    }
+
+    pub fn set_checksum(&mut self, checksum: Checksum) {
+        self.checksum = Some(checksum);
+    }
+
+    pub fn set_custom_meta(&mut self, meta: BTreeMap<String, String>) {
+        self.custom_meta = meta;
+    }
 }
 
 impl Build {
-    #[allow(unused)]
+    pub fn version(&self) -> &Version {
+        &self.version
+    }
+
+    /// Size of the build once decompressed, if known
+    pub fn uncompressed_size(&self) -> Option<u64> {
+        self.uncompressed_size
+    }
+
     pub fn size(&self) -> u64 {
-        if let Some(entry) = self.local.as_ref().or_else(|| self.remote.as_ref()) {
+        if let Some(entry) = self.local.as_ref().or(self.remote.as_ref()) {
             entry.size
         } else {
             panic!(
@@ -0,0 +1,171 @@
+use crate::{storage::Entry, PartialFile, Storage};
+use erreur::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::Write,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Name of the cache file in the local store
+pub const FILE_NAME: &str = ".artefacta-remote-cache.json";
+
+/// Listing of remote storage from an earlier run
+///
+/// Listing S3 buckets is slow, so we keep the remote builds and patches (with
+/// their sizes) next to the local builds. Local storage is always listed
+/// again, so local files in the graph always exist.
+///
+/// This is only the listing, not the graph built from it (which is quick to
+/// build) or checksums of remote files: Those are read when downloading a
+/// file, so it's checked against the checksum that's on the remote now rather
+/// than one from an earlier run.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct RemoteCache {
+    /// Which remote storage this is a listing of (its `Debug` representation)
+    remote: String,
+    /// When we started listing, in milliseconds since the epoch
+    listed_at: u64,
+    files: Vec<CachedFile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CachedFile {
+    path: String,
+    size: u64,
+    /// In milliseconds since the epoch (missing in caches of older versions)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    modified: Option<u64>,
+}
+
+impl RemoteCache {
+    pub(crate) fn new(remote: &Storage, listed_at: SystemTime, files: &[Entry]) -> Self {
+        RemoteCache {
+            remote: format!("{:?}", remote),
+            listed_at: millis_since_epoch(listed_at),
+            files: files
+                .iter()
+                .map(|entry| CachedFile {
+                    path: entry.path.clone(),
+                    size: entry.size,
+                    modified: entry.modified.map(millis_since_epoch),
+                })
+                .collect(),
+        }
+    }
+
+    pub(crate) fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content =
+            fs::read(path).with_context(|| format!("read cache file `{}`", path.display()))?;
+        serde_json::from_slice(&content)
+            .with_context(|| format!("parse cache file `{}`", path.display()))
+    }
+
+    pub(crate) fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut file = PartialFile::create(path)
+            .with_context(|| format!("create cache file `{}`", path.display()))?;
+        serde_json::to_writer(&mut file, self).context("serialize cache")?;
+        file.flush().context("write cache")?;
+        file.finish().context("finish writing cache file")?;
+        Ok(())
+    }
+
+    /// Whether this listing of `remote` can be used instead of listing it
+    /// again
+    ///
+    /// That's the case when it's younger than `max_age` and, for remotes on
+    /// the file system, nothing was added or removed since.
+    pub(crate) fn is_fresh(&self, remote: &Storage, max_age: Duration, now: SystemTime) -> bool {
+        if self.remote != format!("{:?}", remote) {
+            log::debug!("cached listing is of another remote: {}", self.remote);
+            return false;
+        }
+        let age = millis_since_epoch(now).saturating_sub(self.listed_at);
+        if Duration::from_millis(age) >= max_age {
+            log::debug!("cached listing is {}ms old, too old", age);
+            return false;
+        }
+        match remote.modified() {
+            Some(modified) if millis_since_epoch(modified) >= self.listed_at => {
+                log::debug!("remote was changed after it was listed");
+                false
+            }
+            _ => true,
+        }
+    }
+
+    pub(crate) fn files(&self, remote: &Storage) -> Vec<Entry> {
+        self.files
+            .iter()
+            .map(|file| Entry {
+                storage: remote.clone(),
+                path: file.path.clone(),
+                size: file.size,
+                modified: file
+                    .modified
+                    .map(|millis| UNIX_EPOCH + Duration::from_millis(millis)),
+            })
+            .collect()
+    }
+}
+
+fn millis_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+    use std::convert::TryInto;
+
+    #[test]
+    fn cache_roundtrip() {
+        let dir = tempdir().unwrap();
+        let remote: Storage = "s3://bucket.example.com/builds".parse().unwrap();
+        let files = vec![Entry {
+            storage: remote.clone(),
+            path: "builds/1.tar.zst".into(),
+            size: 42,
+            modified: Some(UNIX_EPOCH + Duration::from_millis(1_653_912_000_123)),
+        }];
+
+        let cache = RemoteCache::new(&remote, SystemTime::now(), &files);
+        cache.write(dir.path().join(FILE_NAME)).unwrap();
+        let cache = RemoteCache::read(dir.path().join(FILE_NAME)).unwrap();
+        assert_eq!(cache.files(&remote), files);
+    }
+
+    #[test]
+    fn cache_gets_stale() {
+        let remote: Storage = "s3://bucket.example.com/builds".parse().unwrap();
+        let other: Storage = "s3://bucket.example.com/other".parse().unwrap();
+        let listed_at = SystemTime::now();
+        let cache = RemoteCache::new(&remote, listed_at, &[]);
+        let minute = Duration::from_secs(60);
+
+        assert!(cache.is_fresh(&remote, minute, listed_at + minute / 2));
+        assert!(!cache.is_fresh(&remote, minute, listed_at + minute * 2));
+        assert!(!cache.is_fresh(&remote, Duration::ZERO, listed_at));
+        assert!(!cache.is_fresh(&other, minute, listed_at));
+    }
+
+    #[test]
+    fn cache_of_changed_directory_is_stale() {
+        let dir = tempdir().unwrap();
+        let remote: Storage = dir.path().try_into().unwrap();
+        let listed_at = SystemTime::now();
+        let cache = RemoteCache::new(&remote, listed_at, &[]);
+        let minute = Duration::from_secs(60);
+        assert!(cache.is_fresh(&remote, minute, listed_at));
+
+        std::thread::sleep(Duration::from_millis(10));
+        fs::write(dir.path().join("1.tar.zst"), b"build").unwrap();
+        assert!(!cache.is_fresh(&remote, minute, listed_at));
+    }
+}
@@ -0,0 +1,246 @@
+use crate::ErrorKind;
+use erreur::{bail, Context, Result};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::{fmt, io::Read, str::FromStr};
+
+/// Checksum of a file's content
+///
+/// Written as `<algorithm>:<hex digest>`, e.g. `sha256:b94d27b9…`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum Checksum {
+    Sha256([u8; 32]),
+    Blake3([u8; 32]),
+}
+
+/// Algorithm to use for new checksums
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    Sha256,
+    /// Much faster than SHA-256 for large builds
+    Blake3,
+}
+
+impl Checksum {
+    /// Calculate SHA-256 checksum of everything `content` yields
+    pub fn sha256(content: impl Read) -> Result<Self> {
+        let mut hasher = Sha256::new();
+        read_chunks(content, |chunk| hasher.update(chunk))?;
+        Ok(Checksum::Sha256(hasher.finalize().into()))
+    }
+
+    /// Calculate Blake3 checksum of everything `content` yields
+    pub fn blake3(content: impl Read) -> Result<Self> {
+        let mut hasher = blake3::Hasher::new();
+        read_chunks(content, |chunk| {
+            hasher.update(chunk);
+        })?;
+        Ok(Checksum::Blake3(hasher.finalize().into()))
+    }
+
+    /// Calculate checksum of everything `content` yields using `algorithm`
+    pub fn calculate(algorithm: ChecksumAlgorithm, content: impl Read) -> Result<Self> {
+        match algorithm {
+            ChecksumAlgorithm::Sha256 => Checksum::sha256(content),
+            ChecksumAlgorithm::Blake3 => Checksum::blake3(content),
+        }
+    }
+
+    pub fn algorithm(&self) -> ChecksumAlgorithm {
+        match self {
+            Checksum::Sha256(_) => ChecksumAlgorithm::Sha256,
+            Checksum::Blake3(_) => ChecksumAlgorithm::Blake3,
+        }
+    }
+
+    fn digest(&self) -> &[u8; 32] {
+        match self {
+            Checksum::Sha256(digest) | Checksum::Blake3(digest) => digest,
+        }
+    }
+
+    /// Make sure `content` has this checksum
+    pub fn validate(&self, content: impl Read) -> Result<()> {
+        let actual = Checksum::calculate(self.algorithm(), content)?;
+        if *self != actual {
+            return Err(ErrorKind::Data.error(format!(
+                "checksum mismatch: expected `{}` but got `{}`",
+                self, actual
+            )));
+        }
+        Ok(())
+    }
+
+    /// Content of a checksum file (see [`ChecksumAlgorithm::extension`])
+    ///
+    /// Uses the format of `sha256sum` (and `b3sum`), so downloaded files can
+    /// also be checked with `sha256sum -c <file>.sha256`.
+    pub fn sidecar(&self, file_name: &str) -> String {
+        format!("{}  {}\n", hex::encode(self.digest()), file_name)
+    }
+
+    /// Read checksum from content of a checksum file
+    pub fn from_sidecar(algorithm: ChecksumAlgorithm, content: &[u8]) -> Result<Self> {
+        let content = std::str::from_utf8(content).context("checksum file is not UTF-8")?;
+        let digest = content
+            .split_whitespace()
+            .next()
+            .context("checksum file is empty")?;
+        format!("{}:{}", algorithm, digest).parse()
+    }
+}
+
+impl ChecksumAlgorithm {
+    pub const ALL: [ChecksumAlgorithm; 2] = [ChecksumAlgorithm::Sha256, ChecksumAlgorithm::Blake3];
+
+    /// Extension of checksum files next to builds and patches, e.g.
+    /// `<file>.sha256`
+    pub fn extension(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Sha256 => "sha256",
+            ChecksumAlgorithm::Blake3 => "b3",
+        }
+    }
+}
+
+impl Default for ChecksumAlgorithm {
+    fn default() -> Self {
+        ChecksumAlgorithm::Sha256
+    }
+}
+
+impl fmt::Display for ChecksumAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChecksumAlgorithm::Sha256 => write!(f, "sha256"),
+            ChecksumAlgorithm::Blake3 => write!(f, "blake3"),
+        }
+    }
+}
+
+impl FromStr for ChecksumAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sha256" => Ok(ChecksumAlgorithm::Sha256),
+            "blake3" => Ok(ChecksumAlgorithm::Blake3),
+            _ => Err(format!("unknown checksum algorithm `{}`", s)),
+        }
+    }
+}
+
+/// Feed everything `content` yields to `update` in chunks
+fn read_chunks(mut content: impl Read, mut update: impl FnMut(&[u8])) -> Result<()> {
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let read = content.read(&mut buf).context("read content to hash")?;
+        if read == 0 {
+            return Ok(());
+        }
+        update(&buf[..read]);
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.algorithm(), hex::encode(self.digest()))
+    }
+}
+
+impl fmt::Debug for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Checksum({})", self)
+    }
+}
+
+impl FromStr for Checksum {
+    type Err = erreur::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (algorithm, digest) = s
+            .split_once(':')
+            .with_context(|| format!("checksum `{}` has no `<algorithm>:` prefix", s))?;
+        let algorithm = match algorithm.parse() {
+            Ok(algorithm) => algorithm,
+            Err(_) => bail!("unsupported checksum algorithm `{}`", algorithm),
+        };
+        let mut bytes = [0; 32];
+        hex::decode_to_slice(digest, &mut bytes)
+            .with_context(|| format!("invalid {} digest `{}`", algorithm, digest))?;
+        Ok(match algorithm {
+            ChecksumAlgorithm::Sha256 => Checksum::Sha256(bytes),
+            ChecksumAlgorithm::Blake3 => Checksum::Blake3(bytes),
+        })
+    }
+}
+
+impl Serialize for Checksum {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Checksum {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+#[test]
+fn basic_sha256() {
+    let checksum = Checksum::sha256(&b"hello world"[..]).unwrap();
+    assert_eq!(
+        checksum.to_string(),
+        "sha256:b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9"
+    );
+    checksum.validate(&b"hello world"[..]).unwrap();
+    assert!(checksum.validate(&b"hello world!"[..]).is_err());
+}
+
+#[test]
+fn basic_blake3() {
+    let checksum = Checksum::blake3(&b"hello world"[..]).unwrap();
+    assert_eq!(
+        checksum.to_string(),
+        "blake3:d74981efa70a0c880b8d8c1985d075dbcbf679b99a5f9914e5aaf96b831a9e24"
+    );
+    checksum.validate(&b"hello world"[..]).unwrap();
+    assert!(checksum.validate(&b"hello world!"[..]).is_err());
+    assert_eq!(checksum.to_string().parse::<Checksum>().unwrap(), checksum);
+}
+
+#[test]
+fn checksum_roundtrip() {
+    let checksum = Checksum::sha256(&b"lorem ipsum"[..]).unwrap();
+    assert_eq!(checksum.to_string().parse::<Checksum>().unwrap(), checksum);
+
+    let json = serde_json::to_string(&checksum).unwrap();
+    assert_eq!(serde_json::from_str::<Checksum>(&json).unwrap(), checksum);
+
+    assert!("md5:abc".parse::<Checksum>().is_err());
+    assert!("sha256:abc".parse::<Checksum>().is_err());
+}
+
+#[test]
+fn sidecar_roundtrip() {
+    let checksum = Checksum::sha256(&b"build1"[..]).unwrap();
+    let sidecar = checksum.sidecar("build1.tar.zst");
+    assert!(sidecar.ends_with("  build1.tar.zst\n"));
+    assert_eq!(
+        Checksum::from_sidecar(ChecksumAlgorithm::Sha256, sidecar.as_bytes()).unwrap(),
+        checksum
+    );
+
+    let checksum = Checksum::blake3(&b"build1"[..]).unwrap();
+    let sidecar = checksum.sidecar("build1.tar.zst");
+    assert_eq!(
+        Checksum::from_sidecar(ChecksumAlgorithm::Blake3, sidecar.as_bytes()).unwrap(),
+        checksum
+    );
+
+    assert!(Checksum::from_sidecar(ChecksumAlgorithm::Sha256, b"").is_err());
+    assert!(Checksum::from_sidecar(ChecksumAlgorithm::Sha256, b"abc  build1.tar.zst\n").is_err());
+}
@@ -0,0 +1,211 @@
+use super::{Checksum, ChecksumAlgorithm};
+use crate::{ErrorKind, PartialFile};
+use erreur::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+/// Name of the cache file in the local store
+pub const FILE_NAME: &str = ".artefacta-checksums.json";
+
+/// Checksums of local files, so unchanged files aren't hashed again
+///
+/// Kept next to the local builds, by path. A cached checksum is only used
+/// while the file has the same size and modification time (and on unix, the
+/// same inode and change time, which also changes when the modification time
+/// is set back), so a modified file is always hashed again.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ChecksumCache {
+    /// Where the cache is stored (nowhere if the local store isn't a directory)
+    path: Option<PathBuf>,
+    entries: Arc<Mutex<BTreeMap<String, Cached>>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Cached {
+    stamp: Stamp,
+    checksum: Checksum,
+}
+
+/// What the file system tells about a file's content without reading it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Stamp {
+    size: u64,
+    modified: SystemTime,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    inode: Option<u64>,
+    /// Seconds and nanoseconds of the last change (unix only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    changed: Option<(i64, i64)>,
+}
+
+impl Stamp {
+    fn of(path: &Path) -> Result<Self> {
+        let metadata =
+            fs::metadata(path).with_context(|| format!("read metadata of `{}`", path.display()))?;
+        #[cfg(unix)]
+        let (inode, changed) = {
+            use std::os::unix::fs::MetadataExt;
+            (
+                Some(metadata.ino()),
+                Some((metadata.ctime(), metadata.ctime_nsec())),
+            )
+        };
+        #[cfg(not(unix))]
+        let (inode, changed) = (None, None);
+        Ok(Stamp {
+            size: metadata.len(),
+            modified: metadata
+                .modified()
+                .with_context(|| format!("read modification time of `{}`", path.display()))?,
+            inode,
+            changed,
+        })
+    }
+}
+
+impl ChecksumCache {
+    /// Read the cache of the local store at `dir`, starting empty if there
+    /// is none (or it can't be read)
+    pub(crate) fn load(dir: Option<&Path>) -> Self {
+        let path = dir.map(|dir| dir.join(FILE_NAME));
+        let entries = match path.as_deref().filter(|path| path.exists()) {
+            Some(path) => fs::read(path)
+                .context("read checksum cache")
+                .and_then(|content| {
+                    serde_json::from_slice(&content).context("parse checksum cache")
+                })
+                .unwrap_or_else(|e| {
+                    log::debug!("ignoring checksum cache `{}`: {}", path.display(), e);
+                    BTreeMap::new()
+                }),
+            None => BTreeMap::new(),
+        };
+        ChecksumCache {
+            path,
+            entries: Arc::new(Mutex::new(entries)),
+        }
+    }
+
+    /// Checksum of the file at `path`, calculated with `algorithm` unless it's
+    /// cached
+    pub(crate) fn checksum(&self, path: &Path, algorithm: ChecksumAlgorithm) -> Result<Checksum> {
+        let key = path.to_string_lossy().into_owned();
+        let stamp = Stamp::of(path)?;
+        let cached = self.entries.lock().expect("poisoned").get(&key).cloned();
+        if let Some(cached) = cached {
+            if cached.stamp == stamp && cached.checksum.algorithm() == algorithm {
+                log::trace!("using cached checksum of `{}`", path.display());
+                return Ok(cached.checksum);
+            }
+        }
+
+        let file = File::open(path).with_context(|| format!("open `{}`", path.display()))?;
+        let checksum = Checksum::calculate(algorithm, BufReader::new(file))
+            .with_context(|| format!("calculate checksum of `{}`", path.display()))?;
+        // don't remember what we read if the file changed in the meantime
+        if Stamp::of(path)? == stamp {
+            self.entries
+                .lock()
+                .expect("poisoned")
+                .insert(key, Cached { stamp, checksum });
+            if let Err(e) = self.store() {
+                log::debug!("could not store checksum cache: {}", e);
+            }
+        }
+        Ok(checksum)
+    }
+
+    /// Make sure the file at `path` has the checksum `expected`
+    pub(crate) fn validate(&self, path: &Path, expected: &Checksum) -> Result<()> {
+        let actual = self.checksum(path, expected.algorithm())?;
+        if *expected != actual {
+            return Err(ErrorKind::Data.error(format!(
+                "checksum mismatch: expected `{}` but got `{}`",
+                expected, actual
+            )));
+        }
+        Ok(())
+    }
+
+    /// Write the cache, without the files that are gone
+    fn store(&self) -> Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let mut entries = self.entries.lock().expect("poisoned");
+        entries.retain(|file, _| Path::new(file).exists());
+
+        let mut file = PartialFile::create(path)
+            .with_context(|| format!("create cache file `{}`", path.display()))?;
+        serde_json::to_writer(&mut file, &*entries).context("serialize checksum cache")?;
+        file.flush().context("write checksum cache")?;
+        file.finish().context("finish writing checksum cache")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::*;
+
+    #[test]
+    fn checksums_of_changed_files_are_not_reused() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("build1.tar.zst");
+        fs::write(&path, b"first").unwrap();
+
+        let cache = ChecksumCache::load(Some(dir.path()));
+        let first = cache.checksum(&path, ChecksumAlgorithm::Sha256).unwrap();
+        assert_eq!(first, Checksum::sha256(&b"first"[..]).unwrap());
+        assert!(dir.path().join(FILE_NAME).exists());
+
+        let reloaded = ChecksumCache::load(Some(dir.path()));
+        assert_eq!(reloaded.entries.lock().unwrap().len(), 1);
+        assert_eq!(
+            reloaded.checksum(&path, ChecksumAlgorithm::Sha256).unwrap(),
+            first
+        );
+        assert_eq!(
+            reloaded.checksum(&path, ChecksumAlgorithm::Blake3).unwrap(),
+            Checksum::blake3(&b"first"[..]).unwrap()
+        );
+
+        // same size, and maybe even the same modification time
+        fs::write(&path, b"other").unwrap();
+        let second = reloaded.checksum(&path, ChecksumAlgorithm::Sha256).unwrap();
+        assert_eq!(second, Checksum::sha256(&b"other"[..]).unwrap());
+        assert!(reloaded.validate(&path, &first).is_err());
+
+        fs::remove_file(&path).unwrap();
+        fs::write(dir.path().join("build2.tar.zst"), b"second").unwrap();
+        reloaded
+            .checksum(
+                &dir.path().join("build2.tar.zst"),
+                ChecksumAlgorithm::Sha256,
+            )
+            .unwrap();
+        let reloaded = ChecksumCache::load(Some(dir.path()));
+        assert_eq!(
+            reloaded
+                .entries
+                .lock()
+                .unwrap()
+                .keys()
+                .cloned()
+                .collect::<Vec<_>>(),
+            [dir.path()
+                .join("build2.tar.zst")
+                .to_string_lossy()
+                .into_owned()]
+        );
+    }
+}
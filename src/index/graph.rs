@@ -1,9 +1,16 @@
-use super::{Build, Patch, Version};
-use crate::{paths, storage::Entry};
-use erreur::{Context, Help, LogAndDiscardResult, Result, StdResult};
+use super::{Build, Checksum, Patch, Version};
+use crate::{paths, storage::Entry, Storage};
+use erreur::{Context, Help, LogAndDiscardResult, Report, Result};
 
-use petgraph::graph::{DefaultIx, EdgeIndex, Graph, NodeIndex};
-use std::{collections::HashMap, convert::TryFrom, fs::ReadDir, io::Error as IoError};
+use petgraph::{
+    graph::{DefaultIx, EdgeIndex, EdgeReference, Graph, NodeIndex},
+    visit::EdgeRef,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryFrom,
+    fs::{self, ReadDir},
+};
 
 /// Graph of builds and upgrade paths using patches
 ///
@@ -17,48 +24,72 @@ pub struct PatchGraph {
     pub(crate) builds: HashMap<Version, NodeIndex<DefaultIx>>,
     /// helper for looking up edges in the graph
     patches: HashMap<(Version, Version), EdgeIndex<DefaultIx>>,
+    /// builds stored in remote storage as the file of another, identical build
+    aliases: HashMap<Version, Version>,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Location {
-    Local,
-    Remote,
+/// Adds listed files to a [`PatchGraph`]
+///
+/// Builds are added right away. Aliases and patches are kept until
+/// [`FileListUpdate::finish`], as they refer to builds that may be listed
+/// after them. Other files are ignored.
+pub(crate) struct FileListUpdate<'g> {
+    graph: &'g mut PatchGraph,
+    location: Location,
+    aliases: Vec<Entry>,
+    patches: Vec<Entry>,
 }
 
-impl PatchGraph {
-    pub fn empty() -> Self {
-        Self::default()
+impl<'g> FileListUpdate<'g> {
+    pub(crate) fn add(&mut self, entry: Entry) -> Result<()> {
+        if paths::is_build_path(&entry.path) && entry.size > 0 {
+            log::trace!("Build: {:?}", entry);
+            let version = paths::build_version_from_path(&entry.path)?;
+            let path = entry.path.clone();
+            self.graph
+                .add_build(&version, entry, self.location)
+                .with_context(|| format!("add build `{}`", path))?;
+        } else if paths::is_patch_path(&entry.path) && entry.size > 0 {
+            self.patches.push(entry);
+        } else if entry.path.ends_with(paths::ALIAS_EXTENSION) {
+            self.aliases.push(entry);
+        }
+        Ok(())
     }
 
-    pub fn update_from_file_list(&mut self, list: &[Entry], location: Location) -> Result<()> {
-        let builds: Vec<_> = list
-            .iter()
-            .filter(|entry| entry.path.ends_with(".tar.zst"))
-            .filter(|entry| entry.size > 0)
-            .collect();
-        let patches: Vec<_> = list
-            .iter()
-            .filter(|entry| entry.path.ends_with(".patch.zst"))
-            .filter(|entry| entry.size > 0)
-            .collect();
+    /// Add the aliases and patches
+    pub(crate) fn finish(self) -> Result<()> {
+        let FileListUpdate {
+            graph,
+            location,
+            aliases,
+            patches,
+        } = self;
 
-        log::trace!("Builds: {:?}", builds);
-        for entry in builds {
-            if entry.path.ends_with('/') {
+        log::trace!("Aliases: {:?}", aliases);
+        for entry in aliases {
+            let (alias, target) = paths::alias_versions_from_path(&entry.path)?;
+            if graph.build_entry(&alias, location).is_some() {
+                log::debug!("`{}` exists, ignoring alias `{}`", alias, entry.path);
                 continue;
             }
-            let version = paths::build_version_from_path(&entry.path)?;
-            self.add_build(&version, entry.clone(), location)
-                .with_context(|| format!("add build `{}`", entry.path))?;
+            let target_entry = match graph.build_entry(&target, location) {
+                Some(target_entry) => target_entry.clone(),
+                None => {
+                    log::warn!("target of alias `{}` does not exist", entry.path);
+                    continue;
+                }
+            };
+            graph
+                .add_build(&alias, target_entry, location)
+                .with_context(|| format!("add alias `{}`", entry.path))?;
+            graph.aliases.insert(alias, target);
         }
 
         log::trace!("Patches: {:?}", patches);
         for entry in patches {
-            if entry.path.ends_with('/') {
-                continue;
-            }
             let Patch { from, to, .. } = Patch::from_path(&entry.path)?;
-            match self.add_patch(&from, &to, entry.clone(), location) {
+            match graph.add_patch(&from, &to, entry.clone(), location) {
                 Ok(_) => log::debug!("added patch `{}`", entry.path),
                 e => {
                     log::error!("failed to add patch `{}`. continuing.", entry.path);
@@ -69,6 +100,36 @@ impl PatchGraph {
 
         Ok(())
     }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    Local,
+    Remote,
+}
+
+impl PatchGraph {
+    pub fn empty() -> Self {
+        Self::default()
+    }
+
+    pub fn update_from_file_list(&mut self, list: &[Entry], location: Location) -> Result<()> {
+        let mut update = self.update_from_files(location);
+        for entry in list {
+            update.add(entry.clone())?;
+        }
+        update.finish()
+    }
+
+    /// Add files to the graph one by one, as they are listed
+    pub(crate) fn update_from_files(&mut self, location: Location) -> FileListUpdate<'_> {
+        FileListUpdate {
+            graph: self,
+            location,
+            aliases: Vec::new(),
+            patches: Vec::new(),
+        }
+    }
 
     pub(crate) fn add_build(
         &mut self,
@@ -158,6 +219,61 @@ impl PatchGraph {
         Ok(())
     }
 
+    pub(crate) fn set_uncompressed_size(&mut self, v: &Version, size: u64) -> Result<()> {
+        let build_idx = self
+            .builds
+            .get(v)
+            .with_context(|| format!("unknown build `{}`", v))?;
+        let build = self
+            .graph
+            .node_weight_mut(*build_idx)
+            .context("`builds` points to non-existing NodeIndex")?;
+        build.set_uncompressed_size(size);
+        Ok(())
+    }
+
+    pub(crate) fn set_checksum(&mut self, v: &Version, checksum: Checksum) -> Result<()> {
+        let build_idx = self
+            .builds
+            .get(v)
+            .with_context(|| format!("unknown build `{}`", v))?;
+        let build = self
+            .graph
+            .node_weight_mut(*build_idx)
+            .context("`builds` points to non-existing NodeIndex")?;
+        build.set_checksum(checksum);
+        Ok(())
+    }
+
+    pub(crate) fn set_custom_meta(
+        &mut self,
+        v: &Version,
+        meta: BTreeMap<String, String>,
+    ) -> Result<()> {
+        let build_idx = self
+            .builds
+            .get(v)
+            .with_context(|| format!("unknown build `{}`", v))?;
+        let build = self
+            .graph
+            .node_weight_mut(*build_idx)
+            .context("`builds` points to non-existing NodeIndex")?;
+        build.set_custom_meta(meta);
+        Ok(())
+    }
+
+    pub(crate) fn checksum(&self, v: &Version) -> Option<Checksum> {
+        let build_idx = self.builds.get(v)?;
+        let build = self.graph.node_weight(*build_idx)?;
+        build.checksum
+    }
+
+    pub(crate) fn uncompressed_size(&self, v: Version) -> Option<u64> {
+        let build_idx = self.builds.get(&v)?;
+        let build = self.graph.node_weight(*build_idx)?;
+        build.uncompressed_size
+    }
+
     pub(crate) fn has_build(&self, v: Version) -> bool {
         self.builds.contains_key(&v)
     }
@@ -174,6 +290,18 @@ impl PatchGraph {
         build.remote.as_ref()
     }
 
+    fn build_entry(&self, v: &Version, location: Location) -> Option<&Entry> {
+        match location {
+            Location::Local => self.local_build(v.clone()),
+            Location::Remote => self.remote_build(v.clone()),
+        }
+    }
+
+    /// Build whose file is used for `v` in remote storage, if `v` is an alias
+    pub(crate) fn alias_of(&self, v: &Version) -> Option<&Version> {
+        self.aliases.get(v)
+    }
+
     pub(crate) fn has_local_build(&self, v: Version) -> bool {
         self.local_build(v).is_some()
     }
@@ -182,50 +310,325 @@ impl PatchGraph {
         self.patches.contains_key(&(from, to))
     }
 
-    fn patches_needed(&self, from: Version, to: Version) -> Result<(u64, Vec<Patch>)> {
+    /// Cheapest chain of patches from one build to another
+    ///
+    /// With `max_hops` set, chains of more patches than that are not
+    /// considered at all.
+    fn patches_needed(
+        &self,
+        from: Version,
+        to: Version,
+        max_hops: Option<usize>,
+    ) -> Result<(u64, Vec<Patch>)> {
+        use std::{cmp::Reverse, collections::BinaryHeap};
+
         let from_idx = *self.builds.get(&from).context("unknown `from` version")?;
         let to_idx = *self.builds.get(&to).context("unknown `to` version")?;
 
-        let (cost, steps) = petgraph::algo::astar(
-            &self.graph,
-            from_idx,
-            |f| f == to_idx,
-            |edge| edge.weight().size(),
-            |_| 0,
-        )
-        .with_context(|| format!("no A& solution for patch from `{:?}` to `{:?}`", from, to))?;
-        let mut path: Vec<_> = steps
+        // We optimize for download size: Patches we have locally as well as
+        // patches leading to builds we have locally cost nothing.
+        let cost_of = |patch: &Patch, target: NodeIndex| {
+            if patch.local.is_some() || self.graph[target].local.is_some() {
+                0
+            } else {
+                patch.size()
+            }
+        };
+
+        // Dijkstra over (build, number of patches applied to get there). A
+        // build reached again only needs to be expanded if we got there using
+        // fewer patches than before, otherwise the earlier (and not more
+        // expensive) visit is at least as good.
+        let mut costs: HashMap<(NodeIndex, usize), u64> = HashMap::new();
+        let mut previous: HashMap<(NodeIndex, usize), NodeIndex> = HashMap::new();
+        let mut fewest_hops: HashMap<NodeIndex, usize> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        costs.insert((from_idx, 0), 0);
+        queue.push(Reverse((0, 0, from_idx)));
+
+        let (cost, hops) = loop {
+            let Reverse((cost, hops, node)) = queue.pop().with_context(|| {
+                let limit = max_hops
+                    .map(|max| format!(" using at most {} patches", max))
+                    .unwrap_or_default();
+                format!("no patch path from `{:?}` to `{:?}`{}", from, to, limit)
+            })?;
+            if node == to_idx {
+                break (cost, hops);
+            }
+            if fewest_hops
+                .get(&node)
+                .map_or(false, |&fewest| fewest <= hops)
+            {
+                continue;
+            }
+            fewest_hops.insert(node, hops);
+            if max_hops.map_or(false, |max| hops >= max) {
+                continue;
+            }
+
+            for edge in self.graph.edges(node) {
+                let next = (edge.target(), hops + 1);
+                let next_cost = cost + cost_of(edge.weight(), edge.target());
+                if costs.get(&next).map_or(true, |&known| next_cost < known) {
+                    costs.insert(next, next_cost);
+                    previous.insert(next, node);
+                    queue.push(Reverse((next_cost, hops + 1, edge.target())));
+                }
+            }
+        };
+
+        let mut steps = vec![to_idx];
+        for hop in (1..=hops).rev() {
+            let step = previous[&(steps[steps.len() - 1], hop)];
+            steps.push(step);
+        }
+        steps.reverse();
+
+        // Patches are directed edges, so the steps always form a chain in the
+        // direction we want to go (which for downgrades means reverse patches).
+        // Keep them in this order, it is the order they need to be applied in.
+        let path: Vec<_> = steps
             .windows(2)
             .map(|x| {
                 let from = self.graph[x[0]].version.clone();
                 let to = self.graph[x[1]].version.clone();
-                Patch::new(from, to)
+                // keep the file entries around, so callers can see sizes
+                match self.patch(from.clone(), to.clone()) {
+                    Some(patch) => patch.clone(),
+                    None => Patch::new(from, to),
+                }
             })
             .collect();
-        path.sort();
 
         Ok((cost, path))
     }
 
-    pub fn find_upgrade_path(&self, from: Version, to: Version) -> Result<UpgradePath> {
+    /// Decide how to get from one build to another
+    ///
+    /// Patches are only used if they are smaller than the target build, and if
+    /// `max_patch_hops` is set, only if there are at most that many of them.
+    pub fn find_upgrade_path(
+        &self,
+        from: Version,
+        to: Version,
+        max_patch_hops: Option<usize>,
+    ) -> Result<UpgradePath> {
         let next_build_idx = *self
             .builds
             .get(&to)
             .with_context(|| format!("unknown build size for `{:?}`", to))?;
         let next_build = self.graph[next_build_idx].clone();
-        let build_size = next_build.size();
+        let build_size = if next_build.local.is_some() {
+            0
+        } else {
+            next_build.size()
+        };
 
-        let res = self.patches_needed(from, to).map_err(|e| {
+        let res = self.patches_needed(from, to, max_patch_hops).map_err(|e| {
             log::debug!("{}", e);
             e
         });
 
         match res {
             Ok((size, path)) if build_size > size => Ok(UpgradePath::ApplyPatches(path)),
-            _ => Ok(UpgradePath::InstallBuild(next_build)),
+            _ => Ok(UpgradePath::InstallBuild(Box::new(next_build))),
+        }
+    }
+
+    /// Number of bytes we need to download to follow this upgrade path
+    pub(crate) fn download_size(&self, path: &UpgradePath) -> u64 {
+        let is_local = |v: &Version| self.has_local_build(v.clone());
+        match path {
+            UpgradePath::InstallBuild(build) if is_local(&build.version) => 0,
+            UpgradePath::InstallBuild(build) => build.size(),
+            UpgradePath::ApplyPatches(patches) => patches
+                .iter()
+                .filter(|patch| !is_local(&patch.to))
+                .filter_map(|patch| self.patch(patch.from.clone(), patch.to.clone()))
+                .filter(|patch| patch.local.is_none())
+                .map(|patch| patch.size())
+                .sum(),
+        }
+    }
+
+    /// Names of the files we need to download to follow this upgrade path
+    pub(crate) fn files_to_fetch(&self, path: &UpgradePath) -> Result<Vec<String>> {
+        let is_local = |v: &Version| self.has_local_build(v.clone());
+        match path {
+            UpgradePath::InstallBuild(build) if is_local(&build.version) => Ok(Vec::new()),
+            UpgradePath::InstallBuild(build) => {
+                Ok(vec![paths::build_path_from_version(build.version.clone())?])
+            }
+            UpgradePath::ApplyPatches(patches) => Ok(patches
+                .iter()
+                .filter(|patch| !is_local(&patch.to))
+                .filter_map(|patch| self.patch(patch.from.clone(), patch.to.clone()))
+                .filter(|patch| patch.local.is_none())
+                .map(|patch| patch.file_name())
+                .collect()),
+        }
+    }
+
+    pub(crate) fn patch(&self, from: Version, to: Version) -> Option<&Patch> {
+        let patch_idx = self.patches.get(&(from, to))?;
+        self.graph.edge_weight(*patch_idx)
+    }
+
+    pub(crate) fn remove_patch(&mut self, from: &Version, to: &Version) -> Option<Patch> {
+        let idx = self.patches.remove(&(from.clone(), to.clone()))?;
+        let patch = self.graph.remove_edge(idx)?;
+        // `remove_edge` moves the last edge into the now free index
+        if let Some(moved) = self.graph.edge_weight(idx) {
+            self.patches
+                .insert((moved.from.clone(), moved.to.clone()), idx);
+        }
+        Some(patch)
+    }
+
+    /// Forget about the local copy of a build (after deleting it)
+    pub(crate) fn remove_local_build(&mut self, v: &Version) {
+        let graph = &mut self.graph;
+        if let Some(build) = self
+            .builds
+            .get(v)
+            .and_then(|idx| graph.node_weight_mut(*idx))
+        {
+            build.local = None;
+        }
+    }
+
+    /// Forget about the local copy of a patch (after deleting it)
+    pub(crate) fn remove_local_patch(&mut self, from: &Version, to: &Version) {
+        let graph = &mut self.graph;
+        if let Some(patch) = self
+            .patches
+            .get(&(from.clone(), to.clone()))
+            .and_then(|idx| graph.edge_weight_mut(*idx))
+        {
+            patch.local = None;
         }
     }
 
+    /// Patches that are not part of any cheapest upgrade path
+    ///
+    /// A patch is useful when it lies on a cheapest path (by patch size) from
+    /// one build to another, and that path is smaller than the target build
+    /// itself. To not leave builds without any patch leading to them, the
+    /// smallest incoming patches of each build (all of them, if several are
+    /// equally small) are always kept.
+    pub fn redundant_patches(&self) -> Vec<Patch> {
+        use petgraph::{algo::dijkstra, Direction};
+        use std::collections::HashSet;
+
+        let mut useful: HashSet<EdgeIndex> = HashSet::new();
+        for start in self.graph.node_indices() {
+            let distances = dijkstra(&self.graph, start, None, |edge| edge.weight().size());
+            let on_cheapest_path = |edge: &EdgeReference<Patch>| match (
+                distances.get(&edge.source()),
+                distances.get(&edge.target()),
+            ) {
+                (Some(to_source), Some(to_target)) => {
+                    to_source + edge.weight().size() == *to_target
+                }
+                _ => false,
+            };
+
+            // walk the cheapest paths back from the builds it's worth
+            // patching to from `start`
+            let mut todo: Vec<NodeIndex> = distances
+                .iter()
+                .filter(|(end, cost)| **cost < self.graph[**end].size())
+                .map(|(end, _)| *end)
+                .collect();
+            let mut seen: HashSet<NodeIndex> = todo.iter().copied().collect();
+            while let Some(build) = todo.pop() {
+                let incoming = self
+                    .graph
+                    .edges_directed(build, Direction::Incoming)
+                    .filter(|edge| on_cheapest_path(edge));
+                for edge in incoming {
+                    useful.insert(edge.id());
+                    if seen.insert(edge.source()) {
+                        todo.push(edge.source());
+                    }
+                }
+            }
+        }
+
+        for build in self.graph.node_indices() {
+            let incoming: Vec<_> = self
+                .graph
+                .edges_directed(build, Direction::Incoming)
+                .collect();
+            if incoming.iter().any(|edge| useful.contains(&edge.id())) {
+                continue;
+            }
+            let smallest = incoming.iter().map(|edge| edge.weight().size()).min();
+            for keep in incoming
+                .iter()
+                .filter(|edge| Some(edge.weight().size()) == smallest)
+            {
+                log::debug!(
+                    "keeping patch `{}` as it's the only way to patch to `{}`",
+                    keep.weight(),
+                    self.graph[build].version
+                );
+                useful.insert(keep.id());
+            }
+        }
+
+        let mut patches: Vec<Patch> = self
+            .graph
+            .edge_references()
+            .filter(|edge| !useful.contains(&edge.id()))
+            .map(|edge| edge.weight().clone())
+            .collect();
+        patches.sort();
+        patches
+    }
+
+    /// Groups of builds that are connected by patches (in any direction)
+    ///
+    /// There is no way to upgrade from a build in one group to a build in
+    /// another one without downloading the full build. Versions in each group
+    /// as well as the groups themselves are sorted.
+    pub fn connected_components(&self) -> Vec<Vec<Version>> {
+        use petgraph::unionfind::UnionFind;
+
+        let mut components = UnionFind::new(self.graph.node_count());
+        for edge in self.graph.edge_references() {
+            components.union(edge.source().index(), edge.target().index());
+        }
+
+        let mut groups: HashMap<usize, Vec<Version>> = HashMap::new();
+        for idx in self.graph.node_indices() {
+            groups
+                .entry(components.find(idx.index()))
+                .or_default()
+                .push(self.graph[idx].version.clone());
+        }
+
+        let mut groups: Vec<Vec<Version>> = groups.into_values().collect();
+        groups.iter_mut().for_each(|group| group.sort());
+        groups.sort();
+        groups
+    }
+
+    /// All builds, ordered by version
+    pub(crate) fn builds(&self) -> Vec<&Build> {
+        let mut builds: Vec<&Build> = self.graph.node_weights().collect();
+        builds.sort();
+        builds
+    }
+
+    /// All patches, ordered by the versions they go from and to
+    pub(crate) fn patches(&self) -> Vec<&Patch> {
+        let mut patches: Vec<&Patch> = self.graph.edge_weights().collect();
+        patches.sort();
+        patches
+    }
+
     pub(crate) fn local_only_builds(&self) -> Vec<Build> {
         self.graph
             .raw_nodes()
@@ -250,21 +653,86 @@ impl PatchGraph {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum UpgradePath {
     ApplyPatches(Vec<Patch>),
-    InstallBuild(Build),
+    InstallBuild(Box<Build>),
 }
 
+impl UpgradePath {
+    /// Patches to apply, in order (none when installing a full build)
+    pub fn patches(&self) -> &[Patch] {
+        match self {
+            UpgradePath::ApplyPatches(patches) => patches,
+            UpgradePath::InstallBuild(_) => &[],
+        }
+    }
+
+    /// Size of all files along the path, whether we have them locally or not
+    pub fn size(&self) -> u64 {
+        match self {
+            UpgradePath::ApplyPatches(patches) => patches.iter().map(Patch::size).sum(),
+            UpgradePath::InstallBuild(build) => build.size(),
+        }
+    }
+}
+
+/// Build graph from the content of a local directory
+///
+/// Symlinks and files with names that are not valid UTF-8 are skipped.
 impl TryFrom<ReadDir> for PatchGraph {
-    type Error = IoError;
+    type Error = Report;
+
+    fn try_from(dir: ReadDir) -> Result<Self> {
+        let mut storage: Option<Storage> = None;
+        let mut entries = Vec::new();
 
-    fn try_from(_x: ReadDir) -> StdResult<Self, IoError> {
-        todo!()
+        for entry in dir {
+            let entry = entry.context("could not read file entry")?;
+            let path = entry.path();
+            let metadata = fs::symlink_metadata(&path)
+                .with_context(|| format!("could not read metadata of `{}`", path.display()))?;
+            if metadata.file_type().is_symlink() {
+                log::debug!("skipping symlink `{}`", path.display());
+                continue;
+            }
+            let path_str = match paths::path_as_string(&path) {
+                Ok(p) => p,
+                Err(e) => {
+                    log::warn!("skipping `{}`: {}", path.display(), e);
+                    continue;
+                }
+            };
+
+            let storage = match storage.as_ref() {
+                Some(storage) => storage.clone(),
+                None => {
+                    let parent = path
+                        .parent()
+                        .with_context(|| format!("can't find parent of `{}`", path.display()))?;
+                    let new = Storage::try_from(parent)?;
+                    storage = Some(new.clone());
+                    new
+                }
+            };
+            entries.push(Entry {
+                storage,
+                path: path_str,
+                size: metadata.len(),
+                modified: metadata.modified().ok(),
+            });
+        }
+        entries.sort();
+
+        let mut graph = PatchGraph::empty();
+        graph
+            .update_from_file_list(&entries, Location::Local)
+            .context("build patch graph from directory")?;
+        Ok(graph)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{test_helpers::*, Storage};
+    use crate::test_helpers::*;
 
     #[test]
     fn this_is_fine() -> Result<()> {
@@ -277,35 +745,40 @@ mod tests {
                     storage: Storage::try_from(Path::new("/tmp"))?,
                     path: "1.tar.zst".into(),
                     size: 42,
+                    modified: None,
                 },
                 Entry {
                     storage: Storage::try_from(Path::new("/tmp"))?,
                     path: "1-2.patch.zst".into(),
                     size: 2,
+                    modified: None,
                 },
                 Entry {
                     storage: Storage::try_from(Path::new("/tmp"))?,
                     path: "2-3.patch.zst".into(),
                     size: 20,
+                    modified: None,
                 },
                 Entry {
                     storage: Storage::try_from(Path::new("/tmp"))?,
                     path: "2.tar.zst".into(),
                     size: 64,
+                    modified: None,
                 },
                 Entry {
                     storage: Storage::try_from(Path::new("/tmp"))?,
                     path: "3.tar.zst".into(),
                     size: 72,
+                    modified: None,
                 },
             ],
-            Location::Local,
+            Location::Remote,
         )?;
         dbg!(&graph);
         let installed_version = Version::try_from("1")?;
         let target_version = Version::try_from("3")?;
 
-        let res = graph.find_upgrade_path(installed_version, target_version)?;
+        let res = graph.find_upgrade_path(installed_version, target_version, None)?;
 
         assert_eq!(
             res,
@@ -329,36 +802,448 @@ mod tests {
                     storage: Storage::try_from(Path::new("/tmp"))?,
                     path: "1.tar.zst".into(),
                     size: 42,
+                    modified: None,
                 },
                 Entry {
                     storage: Storage::try_from(Path::new("/tmp"))?,
                     path: "1-2.patch.zst".into(),
                     size: 2,
+                    modified: None,
                 },
                 Entry {
                     storage: Storage::try_from(Path::new("/tmp"))?,
                     path: "2-3.patch.zst".into(),
                     size: 70, // <- large now!
+                    modified: None,
                 },
                 Entry {
                     storage: Storage::try_from(Path::new("/tmp"))?,
                     path: "2.tar.zst".into(),
                     size: 64,
+                    modified: None,
                 },
                 Entry {
                     storage: Storage::try_from(Path::new("/tmp"))?,
                     path: "3.tar.zst".into(),
                     size: 72,
+                    modified: None,
                 },
             ],
-            Location::Local,
+            Location::Remote,
         )?;
         let installed_version = Version::try_from("1")?;
         let target_version = Version::try_from("3")?;
 
-        let res = graph.find_upgrade_path(installed_version, target_version)?;
+        let res = graph.find_upgrade_path(installed_version, target_version, None)?;
+
+        assert_eq!(
+            res,
+            UpgradePath::InstallBuild(Box::new(Build::new("3".parse()?)))
+        );
+
+        Ok(())
+    }
+
+    fn entry(path: &str, size: u64) -> Result<Entry> {
+        Ok(Entry {
+            storage: Storage::try_from(Path::new("/tmp"))?,
+            path: path.into(),
+            size,
+            modified: None,
+        })
+    }
+
+    /// Builds `1`, `2`, `3` on remote with a cheap patch `1-2` and an expensive
+    /// patch `2-3` so that installing `3` directly is cheapest
+    fn remote_graph() -> Result<PatchGraph> {
+        let mut graph = PatchGraph::empty();
+        graph.update_from_file_list(
+            &[
+                entry("1.tar.zst", 42)?,
+                entry("2.tar.zst", 64)?,
+                entry("3.tar.zst", 72)?,
+                entry("1-2.patch.zst", 2)?,
+                entry("2-3.patch.zst", 70)?,
+            ],
+            Location::Remote,
+        )?;
+        Ok(graph)
+    }
+
+    #[test]
+    fn cached_intermediate_build_is_free() -> Result<()> {
+        logger();
+
+        let mut graph = remote_graph()?;
+        let res = graph.find_upgrade_path("1".parse()?, "3".parse()?, None)?;
+        assert_eq!(
+            res,
+            UpgradePath::InstallBuild(Box::new(Build::new("3".parse()?)))
+        );
+
+        // with build 2 cached we only need to download the 2-3 patch
+        graph.update_from_file_list(&[entry("2.tar.zst", 64)?], Location::Local)?;
+        let res = graph.find_upgrade_path("1".parse()?, "3".parse()?, None)?;
+        assert_eq!(
+            res,
+            UpgradePath::ApplyPatches(vec![
+                Patch::new("1".parse()?, "2".parse()?),
+                Patch::new("2".parse()?, "3".parse()?),
+            ])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn cached_patch_is_free() -> Result<()> {
+        logger();
+
+        let mut graph = remote_graph()?;
+        graph.update_from_file_list(
+            &[entry("1.tar.zst", 42)?, entry("2-3.patch.zst", 70)?],
+            Location::Local,
+        )?;
+        let res = graph.find_upgrade_path("1".parse()?, "3".parse()?, None)?;
+        assert_eq!(
+            res,
+            UpgradePath::ApplyPatches(vec![
+                Patch::new("1".parse()?, "2".parse()?),
+                Patch::new("2".parse()?, "3".parse()?),
+            ])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn cached_target_build_is_installed_directly() -> Result<()> {
+        logger();
+
+        let mut graph = remote_graph()?;
+        graph.update_from_file_list(
+            &[entry("1-2.patch.zst", 2)?, entry("2-3.patch.zst", 70)?],
+            Location::Local,
+        )?;
+        graph.update_from_file_list(&[entry("3.tar.zst", 72)?], Location::Local)?;
+        let res = graph.find_upgrade_path("1".parse()?, "3".parse()?, None)?;
+        assert_eq!(
+            res,
+            UpgradePath::InstallBuild(Box::new(Build::new("3".parse()?)))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn graph_from_read_dir() -> Result<()> {
+        logger();
+
+        let dir = tempdir()?;
+        random_zstd_file(dir.path().join("1.tar.zst"))?;
+        random_zstd_file(dir.path().join("2.tar.zst"))?;
+        random_zstd_file(dir.path().join("1-2.patch.zst"))?;
+        // patch between unknown builds gets skipped
+        random_zstd_file(dir.path().join("3-4.patch.zst"))?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.path().join("2.tar.zst"), dir.path().join("5.tar.zst"))?;
+
+        let graph = PatchGraph::try_from(fs::read_dir(dir.path())?)?;
+
+        assert!(graph.has_local_build("1".parse()?));
+        assert!(graph.has_local_build("2".parse()?));
+        assert!(!graph.has_build("5".parse()?));
+        assert!(graph.has_patch("1".parse()?, "2".parse()?));
+        assert!(!graph.has_patch("3".parse()?, "4".parse()?));
+
+        Ok(())
+    }
+
+    #[test]
+    fn patches_on_cheapest_paths_are_not_redundant() -> Result<()> {
+        let mut graph = PatchGraph::empty();
+        graph.update_from_file_list(
+            &[
+                entry("1.tar.zst", 100)?,
+                entry("2.tar.zst", 100)?,
+                entry("3.tar.zst", 100)?,
+                entry("1-2.patch.zst", 10)?,
+                entry("2-3.patch.zst", 10)?,
+                // chain via 2 is cheaper
+                entry("1-3.patch.zst", 50)?,
+            ],
+            Location::Remote,
+        )?;
+
+        assert_eq!(
+            graph.redundant_patches(),
+            vec![Patch::new("1".parse()?, "3".parse()?)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn patches_larger_than_build_are_redundant() -> Result<()> {
+        let mut graph = PatchGraph::empty();
+        graph.update_from_file_list(
+            &[
+                entry("1.tar.zst", 100)?,
+                entry("2.tar.zst", 100)?,
+                entry("3.tar.zst", 100)?,
+                entry("1-3.patch.zst", 50)?,
+                entry("2-3.patch.zst", 200)?,
+            ],
+            Location::Remote,
+        )?;
+
+        assert_eq!(
+            graph.redundant_patches(),
+            vec![Patch::new("2".parse()?, "3".parse()?)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn only_patch_to_build_is_kept() -> Result<()> {
+        let mut graph = PatchGraph::empty();
+        graph.update_from_file_list(
+            &[
+                entry("1.tar.zst", 100)?,
+                entry("2.tar.zst", 100)?,
+                entry("1-2.patch.zst", 200)?,
+            ],
+            Location::Remote,
+        )?;
+
+        assert!(graph.redundant_patches().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn equally_cheap_paths_are_both_kept() -> Result<()> {
+        let mut graph = PatchGraph::empty();
+        graph.update_from_file_list(
+            &[
+                entry("1.tar.zst", 100)?,
+                entry("2.tar.zst", 100)?,
+                entry("3.tar.zst", 100)?,
+                entry("1-2.patch.zst", 10)?,
+                entry("2-3.patch.zst", 10)?,
+                // as cheap as the chain via 2
+                entry("1-3.patch.zst", 20)?,
+            ],
+            Location::Remote,
+        )?;
+
+        assert!(graph.redundant_patches().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn equally_small_patches_to_build_are_kept() -> Result<()> {
+        let mut graph = PatchGraph::empty();
+        graph.update_from_file_list(
+            &[
+                entry("1.tar.zst", 100)?,
+                entry("2.tar.zst", 100)?,
+                entry("3.tar.zst", 100)?,
+                entry("2-3.patch.zst", 200)?,
+                entry("1-3.patch.zst", 200)?,
+                entry("1-2.patch.zst", 10)?,
+                entry("3-2.patch.zst", 150)?,
+            ],
+            Location::Remote,
+        )?;
+
+        // neither patch to 3 is worth it, but they are the smallest ones to it
+        assert_eq!(
+            graph.redundant_patches(),
+            vec![Patch::new("3".parse()?, "2".parse()?)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn removing_patches_keeps_lookup_intact() -> Result<()> {
+        let mut graph = remote_graph()?;
+
+        let removed = graph.remove_patch(&"1".parse()?, &"2".parse()?);
+        assert_eq!(removed, Some(Patch::new("1".parse()?, "2".parse()?)));
+        assert!(!graph.has_patch("1".parse()?, "2".parse()?));
+        assert_eq!(
+            graph.patch("2".parse()?, "3".parse()?),
+            Some(&Patch::new("2".parse()?, "3".parse()?))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn builds_without_patches_between_them_are_islands() -> Result<()> {
+        let mut graph = PatchGraph::empty();
+        graph.update_from_file_list(
+            &[
+                entry("1.tar.zst", 100)?,
+                entry("2.tar.zst", 100)?,
+                entry("3.tar.zst", 100)?,
+                entry("a.tar.zst", 100)?,
+                entry("b.tar.zst", 100)?,
+                entry("x.tar.zst", 100)?,
+                entry("1-2.patch.zst", 10)?,
+                entry("3-2.patch.zst", 10)?,
+                entry("a-b.patch.zst", 10)?,
+            ],
+            Location::Remote,
+        )?;
+
+        assert_eq!(
+            graph.connected_components(),
+            vec![
+                vec!["1".parse()?, "2".parse()?, "3".parse()?],
+                vec!["a".parse()?, "b".parse()?],
+                vec!["x".parse()?],
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn downgrade_using_reverse_patches() -> Result<()> {
+        let mut graph = PatchGraph::empty();
+        graph.update_from_file_list(
+            &[
+                entry("1.tar.zst", 100)?,
+                entry("2.tar.zst", 100)?,
+                entry("3.tar.zst", 100)?,
+                entry("1-2.patch.zst", 10)?,
+                entry("2-3.patch.zst", 10)?,
+                entry("3-2.patch.zst", 10)?,
+                entry("2-1.patch.zst", 10)?,
+            ],
+            Location::Remote,
+        )?;
+
+        assert_eq!(
+            graph.find_upgrade_path("3".parse()?, "1".parse()?, None)?,
+            UpgradePath::ApplyPatches(vec![
+                Patch::new("3".parse()?, "2".parse()?),
+                Patch::new("2".parse()?, "1".parse()?),
+            ])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn forward_patches_are_not_used_for_downgrades() -> Result<()> {
+        let mut graph = PatchGraph::empty();
+        graph.update_from_file_list(
+            &[
+                entry("1.tar.zst", 100)?,
+                entry("2.tar.zst", 100)?,
+                entry("3.tar.zst", 100)?,
+                entry("1-2.patch.zst", 10)?,
+                entry("2-3.patch.zst", 10)?,
+                entry("2-1.patch.zst", 10)?,
+            ],
+            Location::Remote,
+        )?;
+
+        assert_eq!(
+            graph.find_upgrade_path("3".parse()?, "1".parse()?, None)?,
+            UpgradePath::InstallBuild(Box::new(Build::new("1".parse()?)))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn long_patch_chains_are_limited() -> Result<()> {
+        let mut graph = PatchGraph::empty();
+        graph.update_from_file_list(
+            &[
+                entry("1.tar.zst", 100)?,
+                entry("2.tar.zst", 100)?,
+                entry("3.tar.zst", 100)?,
+                entry("4.tar.zst", 100)?,
+                entry("1-2.patch.zst", 1)?,
+                entry("2-3.patch.zst", 1)?,
+                entry("3-4.patch.zst", 1)?,
+                entry("2-4.patch.zst", 20)?,
+            ],
+            Location::Remote,
+        )?;
+
+        assert_eq!(
+            graph.find_upgrade_path("1".parse()?, "4".parse()?, None)?,
+            UpgradePath::ApplyPatches(vec![
+                Patch::new("1".parse()?, "2".parse()?),
+                Patch::new("2".parse()?, "3".parse()?),
+                Patch::new("3".parse()?, "4".parse()?),
+            ])
+        );
+        assert_eq!(
+            graph.find_upgrade_path("1".parse()?, "4".parse()?, Some(2))?,
+            UpgradePath::ApplyPatches(vec![
+                Patch::new("1".parse()?, "2".parse()?),
+                Patch::new("2".parse()?, "4".parse()?),
+            ])
+        );
+        assert_eq!(
+            graph.find_upgrade_path("1".parse()?, "4".parse()?, Some(1))?,
+            UpgradePath::InstallBuild(Box::new(Build::new("4".parse()?)))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn aliases_use_file_of_their_target() -> Result<()> {
+        let mut graph = PatchGraph::empty();
+        graph.update_from_file_list(
+            &[
+                entry("1.tar.zst", 42)?,
+                entry("2---1.alias", 1)?,
+                entry("3---4.alias", 1)?,
+            ],
+            Location::Remote,
+        )?;
+
+        assert_eq!(
+            graph.remote_build("2".parse()?),
+            graph.remote_build("1".parse()?)
+        );
+        assert_eq!(graph.alias_of(&"2".parse()?), Some(&"1".parse()?));
+        assert!(!graph.has_build("3".parse()?), "alias of unknown build");
+
+        Ok(())
+    }
+
+    #[test]
+    fn files_are_added_as_they_are_listed() -> Result<()> {
+        let mut graph = PatchGraph::empty();
+        let mut update = graph.update_from_files(Location::Remote);
+        // patches and aliases can come before their builds
+        for file in [
+            entry("1-2.patch.zst", 5)?,
+            entry("3---2.alias", 1)?,
+            entry("1.tar.zst", 42)?,
+            entry("1.tar.zst.sha256", 80)?,
+            entry("2.tar.zst", 64)?,
+        ] {
+            update.add(file)?;
+        }
+        update.finish()?;
 
-        assert_eq!(res, UpgradePath::InstallBuild(Build::new("3".parse()?)));
+        assert!(graph.has_build("1".parse()?));
+        assert_eq!(graph.alias_of(&"3".parse()?), Some(&"2".parse()?));
+        assert_eq!(graph.patches().len(), 1);
 
         Ok(())
     }
@@ -0,0 +1,93 @@
+use super::Version;
+use erreur::{ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Labels like `stable` or `beta`, and the builds they are attached to
+///
+/// Gives release channels on top of versions: the newest build with a label
+/// is the one to install for it. Stored as [`Labels::FILE_NAME`] in remote
+/// storage, as a JSON object of labels and their builds.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Labels(BTreeMap<String, BTreeSet<Version>>);
+
+impl Labels {
+    pub const FILE_NAME: &'static str = "labels.json";
+
+    pub fn parse(content: &[u8]) -> Result<Self> {
+        serde_json::from_slice(content).context("invalid labels file")
+    }
+
+    pub fn to_json(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec_pretty(self).context("serialize labels")
+    }
+
+    /// Attach `label` to `version`, returning whether it wasn't already
+    pub fn add(&mut self, label: &str, version: Version) -> Result<bool> {
+        check_label(label)?;
+        Ok(self.0.entry(label.to_string()).or_default().insert(version))
+    }
+
+    /// Take `label` off `version`, returning whether it was attached
+    pub fn remove(&mut self, label: &str, version: &Version) -> bool {
+        let removed = match self.0.get_mut(label) {
+            Some(versions) => versions.remove(version),
+            None => false,
+        };
+        if self.0.get(label).map_or(false, BTreeSet::is_empty) {
+            self.0.remove(label);
+        }
+        removed
+    }
+
+    /// Builds with `label`, oldest first
+    pub fn versions(&self, label: &str) -> impl Iterator<Item = &Version> {
+        self.0.get(label).into_iter().flatten()
+    }
+
+    /// Labels attached to `version`
+    pub fn of(&self, version: &Version) -> Vec<&str> {
+        self.0
+            .iter()
+            .filter(|(_, versions)| versions.contains(version))
+            .map(|(label, _)| label.as_str())
+            .collect()
+    }
+}
+
+fn check_label(label: &str) -> Result<()> {
+    ensure!(
+        !label.is_empty()
+            && label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')),
+        "`{}` is not a valid label, use only letters, digits, `-`, `_`, and `.`",
+        label
+    );
+    Ok(())
+}
+
+#[test]
+fn labels_roundtrip() {
+    let mut labels = Labels::default();
+    assert!(labels.add("stable", "1.0.0".parse().unwrap()).unwrap());
+    assert!(labels.add("stable", "1.1.0".parse().unwrap()).unwrap());
+    assert!(!labels.add("stable", "1.0.0".parse().unwrap()).unwrap());
+    assert!(labels.add("beta", "1.1.0".parse().unwrap()).unwrap());
+    assert!(labels.add("no spaces", "1.1.0".parse().unwrap()).is_err());
+
+    let read = Labels::parse(&labels.to_json().unwrap()).unwrap();
+    assert_eq!(read, labels);
+    assert_eq!(read.of(&"1.1.0".parse().unwrap()), ["beta", "stable"]);
+
+    assert!(labels.remove("beta", &"1.1.0".parse().unwrap()));
+    assert!(!labels.remove("beta", &"1.1.0".parse().unwrap()));
+    assert_eq!(labels.versions("beta").count(), 0);
+    assert_eq!(labels.to_json().unwrap(), {
+        let mut only_stable = Labels::default();
+        only_stable.add("stable", "1.0.0".parse().unwrap()).unwrap();
+        only_stable.add("stable", "1.1.0".parse().unwrap()).unwrap();
+        only_stable.to_json().unwrap()
+    });
+}
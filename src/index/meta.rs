@@ -0,0 +1,68 @@
+use super::Checksum;
+use crate::PartialFile;
+use erreur::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, io::Write, path::Path};
+
+/// Additional information about a build
+///
+/// Stored next to the build file as `<version>.meta.json` (see
+/// [`crate::paths::build_meta_path_from_version`]) and copied/uploaded along
+/// with it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildMeta {
+    /// Size of the tar archive before compression
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uncompressed_size: Option<u64>,
+    /// Checksum of the compressed build file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<Checksum>,
+    /// Key-value pairs given when adding the build (see `add --meta`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub custom: BTreeMap<String, String>,
+}
+
+impl BuildMeta {
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content =
+            fs::read(path).with_context(|| format!("read metadata `{}`", path.display()))?;
+        Self::parse(&content).with_context(|| format!("parse metadata `{}`", path.display()))
+    }
+
+    pub fn parse(content: &[u8]) -> Result<Self> {
+        serde_json::from_slice(content).context("invalid metadata")
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut file = PartialFile::create(path)
+            .with_context(|| format!("create metadata file `{}`", path.display()))?;
+        serde_json::to_writer_pretty(&mut file, self).context("serialize metadata")?;
+        file.flush().context("write metadata")?;
+        file.finish().context("finish writing metadata file")?;
+        Ok(())
+    }
+}
+
+#[test]
+fn meta_roundtrip() {
+    let dir = crate::test_helpers::tempdir().unwrap();
+    let path = dir.path().join("build1.meta.json");
+
+    let meta = BuildMeta {
+        uncompressed_size: Some(1337),
+        checksum: Some(Checksum::sha256(&b"build1"[..]).unwrap()),
+        custom: vec![("git_sha".to_string(), "abc123".to_string())]
+            .into_iter()
+            .collect(),
+    };
+    meta.write(&path).unwrap();
+    assert_eq!(BuildMeta::read(&path).unwrap(), meta);
+}
+
+#[test]
+fn meta_fields_are_optional() {
+    let meta: BuildMeta = serde_json::from_str("{}").unwrap();
+    assert_eq!(meta, BuildMeta::default());
+}
@@ -1,6 +1,10 @@
-use crate::{index::Version, paths::file_name, storage::Entry};
-use erreur::{bail, Context, Result};
-use std::{convert::TryFrom, fmt, path::Path};
+use crate::{
+    index::Version,
+    paths::{self, patch_versions_from_path},
+    storage::Entry,
+};
+use erreur::{Context, Result};
+use std::{fmt, path::Path};
 
 /// Patch from old to new build
 #[derive(Debug, Clone, Eq, PartialOrd, Ord)]
@@ -37,26 +41,33 @@ impl Patch {
 }
 
 impl Patch {
+    /// Build the patch applies to
+    pub fn from(&self) -> &Version {
+        &self.from
+    }
+
+    /// Build the patch results in
+    pub fn to(&self) -> &Version {
+        &self.to
+    }
+
     pub fn size(&self) -> u64 {
-        if let Some(entry) = self.local.as_ref().or_else(|| self.remote.as_ref()) {
+        if let Some(entry) = self.local.as_ref().or(self.remote.as_ref()) {
             entry.size
         } else {
             panic!("patch `{}` has neither local not remote information!", self)
         }
     }
 
+    /// Name of the file the patch is stored in
     pub fn file_name(&self) -> String {
-        self.to_string() + ".zst"
+        paths::patch_path(&self.from, &self.to)
     }
 }
 
 impl fmt::Display for Patch {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        if self.from.as_str().contains('-') || self.to.as_str().contains('-') {
-            write!(f, "{}---{}.patch", self.from.as_str(), self.to.as_str())
-        } else {
-            write!(f, "{}-{}.patch", self.from.as_str(), self.to.as_str())
-        }
+        write!(f, "{}", paths::patch_name(&self.from, &self.to))
     }
 }
 
@@ -66,38 +77,10 @@ impl PartialEq for Patch {
     }
 }
 
-fn patch_versions_from_path(path: impl AsRef<Path>) -> Result<(Version, Version)> {
-    let path = path.as_ref();
-    let name = file_name(path).with_context(|| format!("get name of `{:?}`", path))?;
-
-    let parts: Vec<&str> = name.split('-').collect();
-    if parts.len() == 2 {
-        // patch file name pattern is assumed to be `<hash>-<hash>`
-        return Version::try_from(parts[0])
-            .into_iter()
-            .zip(Version::try_from(parts[1]))
-            .next()
-            .with_context(|| format!("parse name `{}` from path `{:?}` as version", name, path));
-    }
-
-    let parts: Vec<&str> = name.split("---").collect();
-    if parts.len() == 2 {
-        // patch file name pattern is assumed to be `<complex-name>---<complex-name>`
-        return Version::try_from(parts[0])
-            .into_iter()
-            .zip(Version::try_from(parts[1]))
-            .next()
-            .with_context(|| format!("parse name `{}` from path `{:?}` as version", name, path));
-    }
-
-    bail!(
-        "path `{}` cannot be parsed as patch file name pattern with 2 version",
-        path.display()
-    );
-}
-
 #[test]
 fn parsing_weird_patch_names() {
+    use std::convert::TryFrom;
+
     assert_patch_names("foo/bar/build1-build2.tar.zst", "build1", "build2");
     assert_patch_names(
         "foo/bar/module-v1.2.3---module-v1.2.4.tar.zst",
@@ -0,0 +1,173 @@
+use super::{Checksum, Version};
+use crate::ErrorKind;
+use erreur::{ensure, Context, Result};
+use ring::{
+    rand::SystemRandom,
+    signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519},
+};
+use std::{fmt, fs, path::Path, sync::Arc};
+
+/// Private ed25519 key for signing builds
+///
+/// Key files contain the base64 encoded PKCS#8 document of the key, as
+/// written by [`SigningKey::generate`].
+#[derive(Clone)]
+pub struct SigningKey(Arc<Ed25519KeyPair>);
+
+/// Public ed25519 key for verifying signatures of builds
+///
+/// Key files contain the base64 encoded 32 bytes of the key.
+#[derive(Clone, PartialEq, Eq)]
+pub struct VerifyingKey(Vec<u8>);
+
+/// Signature of a build's checksum, as stored in `<build file>.sig`
+///
+/// The file contains the checksum and the signature, like
+/// `sha256:b94d27b9… <base64 signature>`. What is signed is the version
+/// together with the checksum, so a signed build can't be passed off as
+/// another version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub checksum: Checksum,
+    signature: Vec<u8>,
+}
+
+impl SigningKey {
+    /// Create a new key pair, returning the contents of the private and the
+    /// public key file
+    pub fn generate() -> Result<(String, String)> {
+        let document = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+            .ok()
+            .context("generate ed25519 key")?;
+        let key = SigningKey::from_pkcs8(document.as_ref())?;
+        Ok((
+            format!("{}\n", base64::encode(document.as_ref())),
+            format!("{}\n", base64::encode(key.verifying_key().0)),
+        ))
+    }
+
+    fn from_pkcs8(document: &[u8]) -> Result<Self> {
+        let pair = Ed25519KeyPair::from_pkcs8(document)
+            .ok()
+            .context("not an ed25519 private key")?;
+        Ok(SigningKey(Arc::new(pair)))
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let document = read_key_file(path)?;
+        SigningKey::from_pkcs8(&document)
+            .with_context(|| format!("invalid signing key `{}`", path.display()))
+    }
+
+    pub fn verifying_key(&self) -> VerifyingKey {
+        VerifyingKey(self.0.public_key().as_ref().to_vec())
+    }
+
+    pub fn sign(&self, version: &Version, checksum: Checksum) -> Signature {
+        let signature = self.0.sign(signed_message(version, &checksum).as_bytes());
+        Signature {
+            checksum,
+            signature: signature.as_ref().to_vec(),
+        }
+    }
+}
+
+impl VerifyingKey {
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let key = read_key_file(path)?;
+        ensure!(
+            key.len() == 32,
+            "invalid public key `{}`: expected 32 bytes but got {}",
+            path.display(),
+            key.len()
+        );
+        Ok(VerifyingKey(key))
+    }
+
+    /// Make sure `signature` was made for `version` by our key's private key
+    pub fn verify(&self, version: &Version, signature: &Signature) -> Result<()> {
+        UnparsedPublicKey::new(&ED25519, &self.0)
+            .verify(
+                signed_message(version, &signature.checksum).as_bytes(),
+                &signature.signature,
+            )
+            .map_err(|_| ErrorKind::Data.error(format!("invalid signature for `{}`", version)))
+    }
+}
+
+impl Signature {
+    /// Content of a signature file
+    pub fn sidecar(&self) -> String {
+        format!("{} {}\n", self.checksum, base64::encode(&self.signature))
+    }
+
+    /// Read signature from content of a signature file
+    pub fn from_sidecar(content: &[u8]) -> Result<Self> {
+        let content = std::str::from_utf8(content).context("signature file is not UTF-8")?;
+        let (checksum, signature) = content
+            .trim()
+            .split_once(' ')
+            .context("signature file has no checksum")?;
+        Ok(Signature {
+            checksum: checksum.parse()?,
+            signature: base64::decode(signature.trim()).context("invalid signature")?,
+        })
+    }
+}
+
+fn signed_message(version: &Version, checksum: &Checksum) -> String {
+    format!("artefacta build {} {}", version.as_str(), checksum)
+}
+
+fn read_key_file(path: &Path) -> Result<Vec<u8>> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("read key file `{}`", path.display()))?;
+    base64::decode(content.trim())
+        .with_context(|| format!("key file `{}` is not base64", path.display()))
+}
+
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("SigningKey")
+            .field(&self.verifying_key())
+            .finish()
+    }
+}
+
+impl fmt::Debug for VerifyingKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "VerifyingKey({})", base64::encode(&self.0))
+    }
+}
+
+#[test]
+fn signature_roundtrip() {
+    let (private, public) = SigningKey::generate().unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("key"), private).unwrap();
+    fs::write(dir.path().join("key.pub"), public).unwrap();
+    let key = SigningKey::read(dir.path().join("key")).unwrap();
+    let public = VerifyingKey::read(dir.path().join("key.pub")).unwrap();
+    assert_eq!(key.verifying_key(), public);
+
+    let version: Version = "build1".parse().unwrap();
+    let checksum = Checksum::sha256(&b"build1"[..]).unwrap();
+    let signature = key.sign(&version, checksum);
+    let read = Signature::from_sidecar(signature.sidecar().as_bytes()).unwrap();
+    assert_eq!(read, signature);
+    public.verify(&version, &read).unwrap();
+
+    let other_version: Version = "build2".parse().unwrap();
+    assert!(public.verify(&other_version, &read).is_err());
+    let other_key =
+        SigningKey::from_pkcs8(&base64::decode(SigningKey::generate().unwrap().0.trim()).unwrap())
+            .unwrap();
+    assert!(other_key.verifying_key().verify(&version, &read).is_err());
+    let forged = Signature {
+        checksum: Checksum::sha256(&b"build2"[..]).unwrap(),
+        ..read
+    };
+    assert!(public.verify(&version, &forged).is_err());
+}
@@ -1,16 +1,91 @@
 use erreur::StdError;
-use std::{convert::TryFrom, fmt, str::FromStr};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{cmp::Ordering, convert::TryFrom, fmt, str::FromStr};
 
 /// Short string in specific format. Cheap to clone.
-#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+///
+/// Versions are ordered "naturally", see [`Version::natural_cmp`].
+#[derive(Clone, PartialEq, Eq, Hash)]
 pub struct Version {
     data: smol_str::SmolStr,
 }
 
 impl Version {
+    /// Reserved for the newest build, see [`crate::ArtefactIndex::resolve_version`]
+    pub const LATEST: &'static str = "latest";
+
     pub fn as_str(&self) -> &str {
         self.data.as_str()
     }
+
+    /// Compare versions the way humans would, see [`natural_cmp`]
+    pub fn natural_cmp(&self, other: &Self) -> Ordering {
+        natural_cmp(self.as_str(), other.as_str())
+    }
+}
+
+/// Compare strings the way humans would
+///
+/// Runs of digits are compared by their numeric value, so `build2` comes
+/// before `build10` and `v1.9.0` before `v1.10.0`. Everything else is
+/// compared as-is. Numbers can be arbitrarily long, and strings that only
+/// differ in leading zeros are ordered by their string representation to stay
+/// consistent with `Eq`.
+///
+/// Git tags are ordered this way as well, so they sort like the versions of
+/// their builds.
+pub(crate) fn natural_cmp(lhs: &str, rhs: &str) -> Ordering {
+    let mut a = segments(lhs);
+    let mut b = segments(rhs);
+    loop {
+        let ord = match (a.next(), b.next()) {
+            (Some(a), Some(b)) => compare_segments(a, b),
+            (Some(_), None) => Ordering::Greater,
+            (None, Some(_)) => Ordering::Less,
+            (None, None) => return lhs.cmp(rhs),
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.natural_cmp(other)
+    }
+}
+
+/// Split string into alternating runs of ASCII digits and other characters
+fn segments(s: &str) -> impl Iterator<Item = &str> {
+    let mut rest = s;
+    std::iter::from_fn(move || {
+        let first = rest.chars().next()?;
+        let is_digit = first.is_ascii_digit();
+        let end = rest
+            .find(|c: char| c.is_ascii_digit() != is_digit)
+            .unwrap_or(rest.len());
+        let (segment, tail) = rest.split_at(end);
+        rest = tail;
+        Some(segment)
+    })
+}
+
+fn compare_segments(a: &str, b: &str) -> Ordering {
+    let is_number = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if is_number(a) && is_number(b) {
+        let a = a.trim_start_matches('0');
+        let b = b.trim_start_matches('0');
+        a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+    } else {
+        a.cmp(b)
+    }
 }
 
 impl fmt::Debug for Version {
@@ -28,6 +103,9 @@ impl fmt::Display for Version {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum InvalidVersion {
     ThreeDashes,
+    PathSeparator,
+    ParentDirectory,
+    LeadingDash,
 }
 
 impl StdError for InvalidVersion {}
@@ -39,6 +117,18 @@ impl fmt::Display for InvalidVersion {
                 f,
                 "Invalid version format: `---` must not appear in version"
             ),
+            InvalidVersion::PathSeparator => write!(
+                f,
+                "Invalid version format: version is used as file name and must not contain `/` or `\\`"
+            ),
+            InvalidVersion::ParentDirectory => write!(
+                f,
+                "Invalid version format: version is used as file name and must not contain `..`"
+            ),
+            InvalidVersion::LeadingDash => write!(
+                f,
+                "Invalid version format: version must not start with `-`"
+            ),
         }
     }
 }
@@ -50,11 +140,20 @@ impl FromStr for Version {
         if s.contains("---") {
             return Err(InvalidVersion::ThreeDashes);
         }
+        if s.contains(['/', '\\']) {
+            return Err(InvalidVersion::PathSeparator);
+        }
+        if s.contains("..") {
+            return Err(InvalidVersion::ParentDirectory);
+        }
+        if s.starts_with('-') {
+            return Err(InvalidVersion::LeadingDash);
+        }
         Ok(Version { data: s.into() })
     }
 }
 
-impl<'a> TryFrom<&'a str> for Version {
+impl TryFrom<&str> for Version {
     type Error = InvalidVersion;
 
     fn try_from(s: &str) -> Result<Self, Self::Error> {
@@ -62,7 +161,7 @@ impl<'a> TryFrom<&'a str> for Version {
     }
 }
 
-impl<'a> TryFrom<&'a String> for Version {
+impl TryFrom<&String> for Version {
     type Error = InvalidVersion;
 
     fn try_from(s: &String) -> Result<Self, Self::Error> {
@@ -70,6 +169,19 @@ impl<'a> TryFrom<&'a String> for Version {
     }
 }
 
+impl Serialize for Version {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Version {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
 #[test]
 fn versions_can_be_parsed() {
     let _: Version = "v1.2.3".parse().unwrap();
@@ -82,3 +194,102 @@ fn versions_can_be_parsed() {
         Err(InvalidVersion::ThreeDashes)
     );
 }
+
+#[test]
+fn versions_cannot_escape_store() {
+    assert_eq!(
+        Version::try_from("../../etc/passwd"),
+        Err(InvalidVersion::PathSeparator)
+    );
+    assert_eq!(
+        Version::try_from("foo/bar"),
+        Err(InvalidVersion::PathSeparator)
+    );
+    assert_eq!(
+        Version::try_from("foo\\bar"),
+        Err(InvalidVersion::PathSeparator)
+    );
+    assert_eq!(
+        Version::try_from(".."),
+        Err(InvalidVersion::ParentDirectory)
+    );
+    assert_eq!(
+        Version::try_from("v1..2"),
+        Err(InvalidVersion::ParentDirectory)
+    );
+    assert_eq!(Version::try_from("-rf"), Err(InvalidVersion::LeadingDash));
+
+    let _ = Version::try_from("v1.2.3-rc1").unwrap();
+}
+
+#[test]
+fn versions_are_ordered_naturally() {
+    fn sorted(versions: &[&str]) -> Vec<String> {
+        let mut versions: Vec<Version> = versions.iter().map(|v| v.parse().unwrap()).collect();
+        versions.sort();
+        versions.iter().map(|v| v.to_string()).collect()
+    }
+
+    assert_eq!(
+        sorted(&["build10", "build2", "build1"]),
+        ["build1", "build2", "build10"]
+    );
+    assert_eq!(
+        sorted(&["v1.10.0", "v1.9.0", "v1.9.1", "v0.20.0"]),
+        ["v0.20.0", "v1.9.0", "v1.9.1", "v1.10.0"]
+    );
+    assert_eq!(
+        sorted(&["il60-1-0", "il60-0-11", "il60-0-9", "il59-3-0"]),
+        ["il59-3-0", "il60-0-9", "il60-0-11", "il60-1-0"]
+    );
+    assert_eq!(
+        sorted(&["module-20200629", "module-20200701", "build-20200630"]),
+        ["build-20200630", "module-20200629", "module-20200701"]
+    );
+}
+
+#[test]
+fn natural_ordering_is_consistent_with_eq() {
+    let a: Version = "v1.02".parse().unwrap();
+    let b: Version = "v1.2".parse().unwrap();
+    assert_ne!(a, b);
+    assert_ne!(a.cmp(&b), Ordering::Equal);
+    assert_eq!(a.cmp(&b), b.cmp(&a).reverse());
+    assert_eq!(a.cmp(&a.clone()), Ordering::Equal);
+}
+
+#[test]
+fn long_numbers_are_compared_by_value() {
+    let a: Version = "build-99999999999999999999".parse().unwrap();
+    let b: Version = "build-100000000000000000000".parse().unwrap();
+    assert!(a < b);
+}
+
+#[test]
+fn naturally_ordered_versions_work_as_map_keys() {
+    let mut builds = std::collections::HashMap::new();
+    builds.insert(Version::try_from("v1.02").unwrap(), 1);
+    builds.insert(Version::try_from("v1.2").unwrap(), 2);
+    assert_eq!(builds.len(), 2);
+    assert_eq!(builds[&Version::try_from("v1.2").unwrap()], 2);
+}
+
+#[test]
+fn long_versions_can_be_parsed() {
+    let version: Version = "StandaloneLinux64-2024.01.15-rc3".parse().unwrap();
+    assert_eq!(version.as_str(), "StandaloneLinux64-2024.01.15-rc3");
+}
+
+#[cfg(test)]
+proptest::proptest! {
+    #[test]
+    fn long_versions_roundtrip(
+        name in r"[A-Za-z][A-Za-z0-9_]{8,40}(-[0-9]{4}\.[0-9]{2}\.[0-9]{2})?(-rc[0-9]{1,2})?"
+    ) {
+        let version: Version = name.parse().unwrap();
+        proptest::prop_assert_eq!(version.to_string(), name.clone());
+
+        let path = crate::paths::build_path_from_version(version.clone()).unwrap();
+        proptest::prop_assert_eq!(crate::paths::build_version_from_path(&path).unwrap(), version);
+    }
+}
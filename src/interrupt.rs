@@ -0,0 +1,114 @@
+//! Cleaning up when interrupted
+//!
+//! Ctrl-C (SIGINT) and SIGTERM end the process without running destructors,
+//! which would leave partial files and half-done multipart uploads behind.
+//! Whatever would need cleaning up registers a future with [`on_interrupt`]
+//! for as long as it's in progress. With [`handle_interrupts`], a signal runs
+//! them all before exiting. Another signal while they run exits right away.
+//!
+//! On Windows, only Ctrl-C is handled, and files that are still open can't be
+//! deleted, so a partial file that is being written to might be left behind.
+
+use futures::future::{join_all, select, BoxFuture, Either, FutureExt};
+use once_cell::sync::Lazy;
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+static CLEANUPS: Lazy<Mutex<BTreeMap<u64, BoxFuture<'static, ()>>>> =
+    Lazy::new(|| Mutex::new(BTreeMap::new()));
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Run `cleanup` if the process is interrupted before the returned
+/// registration is dropped
+pub(crate) fn on_interrupt(cleanup: impl Future<Output = ()> + Send + 'static) -> Registration {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    cleanups().insert(id, cleanup.boxed());
+    Registration(id)
+}
+
+/// Cleanup registered with [`on_interrupt`], which isn't needed anymore once
+/// this is dropped
+#[derive(Debug)]
+#[must_use = "the cleanup is unregistered when this is dropped"]
+pub(crate) struct Registration(u64);
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        cleanups().remove(&self.0);
+    }
+}
+
+fn cleanups() -> std::sync::MutexGuard<'static, BTreeMap<u64, BoxFuture<'static, ()>>> {
+    CLEANUPS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Clean up and exit when the process gets SIGINT or SIGTERM (only Ctrl-C on
+/// Windows) from now on
+///
+/// Needs to be called within a Tokio runtime, and the cleanup only runs while
+/// one of its threads is free.
+pub fn handle_interrupts() {
+    tokio::spawn(async {
+        let code = interrupted().await;
+        log::warn!("interrupted, cleaning up");
+        let pending = std::mem::take(&mut *cleanups());
+        if let Either::Right(_) =
+            select(join_all(pending.into_values()), interrupted().boxed()).await
+        {
+            log::warn!("interrupted again, exiting without cleaning up");
+        }
+        std::process::exit(code);
+    });
+}
+
+/// Wait for a signal, returning the exit code for it
+#[cfg(unix)]
+async fn interrupted() -> i32 {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(e) => {
+            log::debug!("can't handle SIGTERM: {}", e);
+            let _ = tokio::signal::ctrl_c().await;
+            return 130;
+        }
+    };
+    let signal = select(tokio::signal::ctrl_c().boxed(), terminate.recv().boxed()).await;
+    match signal {
+        Either::Left(_) => 130,
+        Either::Right(_) => 143,
+    }
+}
+
+/// Wait for Ctrl-C, returning the exit code for it
+#[cfg(not(unix))]
+async fn interrupted() -> i32 {
+    let _ = tokio::signal::ctrl_c().await;
+    130
+}
+
+#[test]
+fn dropped_registrations_are_forgotten() {
+    use std::sync::{atomic::AtomicBool, Arc};
+
+    let cleaned = Arc::new(AtomicBool::new(false));
+    let registration = on_interrupt({
+        let cleaned = cleaned.clone();
+        async move { cleaned.store(true, Ordering::SeqCst) }
+    });
+    let id = registration.0;
+    assert!(cleanups().contains_key(&id));
+
+    drop(registration);
+    assert!(!cleanups().contains_key(&id));
+    assert!(!cleaned.load(Ordering::SeqCst));
+}
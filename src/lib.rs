@@ -1,24 +1,40 @@
-use std::{convert::TryFrom, fs, path::Path};
+use std::{
+    convert::TryFrom,
+    fs, io,
+    path::{Path, PathBuf},
+};
 
-use cli::AddBuild;
-use erreur::{ensure, Context, Help, Result};
+use cli::{AddBuild, PackageOptions};
+use erreur::{ensure, Context, Help, LogAndDiscardResult, Report, Result};
 
 pub mod paths;
 
 mod apply_patch;
-pub use apply_patch::apply_patch;
+pub use apply_patch::{apply_patch, apply_patch_to_decompressed};
+
+pub mod diff;
+
+#[cfg(all(feature = "mmap", unix))]
+mod mmap;
 
 mod index;
-pub use index::{Index as ArtefactIndex, Version};
+pub use index::{
+    Build, BuildMeta, Checksum, ChecksumAlgorithm, Index as ArtefactIndex, Patch, SigningKey,
+    UpgradePath, UpgradePlan, VerifyingKey, Version,
+};
 
 mod packaging;
 pub use packaging::package;
 
-mod storage;
+pub mod storage;
 pub use storage::Storage;
 
+mod estimate;
+
 mod compression;
-pub use compression::{compress, decompress};
+pub use compression::{
+    build_reader, compress, compress_with_threads, decompress, decompress_build,
+};
 
 mod partial_file;
 pub use partial_file::PartialFile;
@@ -27,123 +43,1404 @@ pub mod git;
 
 pub mod cli;
 
+pub mod config;
+
+pub mod output;
+use output::{
+    BuildInfo, CreatePatchOutput, InfoOutput, InstallOutput, ListOutput, Manifest, ManifestBuild,
+    ManifestPatch, PatchEstimate, PatchInfo, PatchPlan, PlanOutput, StatusOutput, SyncOutput,
+    UpgradeInfo, UpgradeMethod, MANIFEST_FILE_NAME, MANIFEST_SCHEMA_VERSION,
+};
+
+pub mod error;
+pub use error::ErrorKind;
+
+pub mod metrics;
+
+pub mod progress;
+
+pub mod interrupt;
+
+pub mod lock;
+
+pub mod timings;
+
+pub mod webhook;
+
+mod http;
+
 #[cfg(test)]
 pub(crate) mod test_helpers;
 
-pub async fn sync(index: &ArtefactIndex) -> Result<()> {
-    index.push().await.context("sync new local files to remote")
+/// Upload local builds and patches that are not in remote storage yet
+///
+/// With `dry_run`, only collects what would be uploaded.
+pub async fn sync(
+    index: &ArtefactIndex,
+    dry_run: bool,
+    options: &cli::SyncOptions,
+) -> Result<SyncOutput> {
+    let files = index
+        .push(dry_run)
+        .await
+        .context("sync new local files to remote")?;
+    if dry_run {
+        return Ok(SyncOutput::new(files, dry_run));
+    }
+
+    if let (Some(url), false) = (&options.webhook, files.is_empty()) {
+        let notified = webhook::notify(url, &webhook::SyncSummary::new(&files))
+            .await
+            .with_context(|| format!("notify webhook at `{}`", url));
+        if options.webhook_required {
+            notified?;
+        } else if let Err(e) = notified {
+            log::warn!("{:#}", e);
+        }
+    }
+    Ok(SyncOutput::new(files, dry_run))
 }
 
-pub async fn install(
+/// Download builds (and patches) that are only in remote storage
+///
+/// With a version, only that build and (with `patches`) the patches to it are
+/// fetched. With `dry_run`, only prints the names of the files.
+pub async fn pull(
     index: &mut ArtefactIndex,
-    target_version: Version,
-    current: &Path,
+    version: Option<Version>,
+    patches: bool,
+    dry_run: bool,
 ) -> Result<()> {
-    let target_build = match fs::read_link(&current) {
-        Ok(curent_path) => {
-            let current_version = paths::build_version_from_path(&curent_path)?;
-            log::debug!(
-                "identified version `{}` from path `{}`",
-                current_version,
-                curent_path.display()
+    let version = match version {
+        Some(version) => {
+            let version = index.resolve_version(version)?;
+            ensure!(
+                index.builds().iter().any(|b| b.version == version),
+                "build `{}` unknown",
+                version
             );
+            Some(version)
+        }
+        None => None,
+    };
+    let wanted = |v: &Version| version.as_ref().map_or(true, |version| version == v);
+
+    let builds = index
+        .builds()
+        .into_iter()
+        .filter(|build| build.local.is_none() && build.remote.is_some())
+        .filter(|build| wanted(&build.version))
+        .map(|build| build.version.clone())
+        .collect::<Vec<_>>();
+    let patches = if patches {
+        index
+            .patches()
+            .into_iter()
+            .filter(|patch| patch.local.is_none() && patch.remote.is_some())
+            .filter(|patch| wanted(&patch.to))
+            .map(|patch| (patch.from.clone(), patch.to.clone()))
+            .collect()
+    } else {
+        Vec::new()
+    };
+    if builds.is_empty() && patches.is_empty() {
+        log::info!("nothing to pull, local storage has everything");
+        return Ok(());
+    }
+
+    for version in builds {
+        if dry_run {
+            println!("{}", paths::build_path_from_version(version)?);
+            continue;
+        }
+        index
+            .get_build(version.clone())
+            .await
+            .with_context(|| format!("pull build `{}`", version))?;
+        log::info!("pulled build `{}`", version);
+    }
+    for (from, to) in patches {
+        let patch = index::Patch::new(from.clone(), to.clone());
+        if dry_run {
+            println!("{}", patch.file_name());
+            continue;
+        }
+        index
+            .get_patch(from, to)
+            .await
+            .with_context(|| format!("pull patch `{}`", patch))?;
+        log::info!("pulled patch `{}`", patch);
+    }
+    Ok(())
+}
+
+pub async fn list(index: &ArtefactIndex, options: &cli::ListOptions) -> Result<ListOutput> {
+    let labeled = match &options.label {
+        Some(label) => Some(
+            index
+                .labels()
+                .await?
+                .versions(label)
+                .cloned()
+                .collect::<Vec<_>>(),
+        ),
+        None => None,
+    };
+    let builds = index
+        .builds()
+        .into_iter()
+        .filter(|build| options.includes(build.local.is_some(), build.remote.is_some()))
+        .filter(|build| {
+            labeled
+                .as_ref()
+                .map_or(true, |labeled| labeled.contains(&build.version))
+        })
+        .map(|build| BuildInfo {
+            version: build.version.clone(),
+            local: build.local.is_some(),
+            remote: build.remote.is_some(),
+            size: build.size(),
+            checksum: build.checksum,
+            modified: build
+                .remote
+                .as_ref()
+                .or(build.local.as_ref())
+                .and_then(|entry| entry.modified),
+            meta: build.custom_meta.clone(),
+        })
+        .collect();
+
+    let patches = options.patches.then(|| {
+        index
+            .patches()
+            .into_iter()
+            .filter(|patch| options.includes(patch.local.is_some(), patch.remote.is_some()))
+            .map(patch_info)
+            .collect()
+    });
+
+    Ok(ListOutput { builds, patches })
+}
+
+/// Attach `label` to a build, or take it off with `remove`
+pub async fn label(
+    index: &mut ArtefactIndex,
+    version: Version,
+    label: &str,
+    remove: bool,
+    dry_run: bool,
+) -> Result<()> {
+    let version = index.resolve_version(version)?;
+    let labels = index.labels().await?;
+    let labeled = labels.of(&version).contains(&label);
+    if dry_run {
+        match (remove, labeled) {
+            (false, false) => println!("would label `{}` as `{}`", version, label),
+            (true, true) => println!("would remove label `{}` from `{}`", label, version),
+            _ => {}
+        }
+        return Ok(());
+    }
+
+    let changed = index
+        .set_label(&version, label, remove)
+        .await
+        .with_context(|| format!("label `{}` as `{}`", version, label))?;
+    match (remove, changed) {
+        (false, true) => log::info!("labeled `{}` as `{}`", version, label),
+        (false, false) => log::info!("`{}` is already labeled `{}`", version, label),
+        (true, true) => log::info!("removed label `{}` from `{}`", label, version),
+        (true, false) => log::info!("`{}` is not labeled `{}`", version, label),
+    }
+    Ok(())
+}
 
-            if current_version == target_version {
-                log::info!("version `{}` already installed", target_version);
-                return Ok(());
+fn patch_info(patch: &index::Patch) -> PatchInfo {
+    PatchInfo {
+        from: patch.from.clone(),
+        to: patch.to.clone(),
+        local: patch.local.is_some(),
+        remote: patch.remote.is_some(),
+        size: patch.size(),
+    }
+}
+
+/// Describe all builds and patches in remote storage, and with `upload`, store
+/// that description there as well
+pub async fn manifest(index: &ArtefactIndex, upload: bool) -> Result<Manifest> {
+    let mut builds = Vec::new();
+    for build in index.builds() {
+        let entry = match &build.remote {
+            Some(entry) => entry,
+            None => continue,
+        };
+        builds.push(ManifestBuild {
+            version: build.version.clone(),
+            file: remote_file_name(entry),
+            size: entry.size,
+            uncompressed_size: build.uncompressed_size,
+            checksum: index
+                .build_checksum(&build.version, index::Location::Remote)
+                .await
+                .with_context(|| format!("get checksum of `{}`", build.version))?,
+        });
+    }
+
+    let patches = index
+        .patches()
+        .into_iter()
+        .filter_map(|patch| {
+            let entry = patch.remote.as_ref()?;
+            Some(ManifestPatch {
+                from: patch.from.clone(),
+                to: patch.to.clone(),
+                file: remote_file_name(entry),
+                size: entry.size,
+            })
+        })
+        .collect();
+
+    let manifest = Manifest {
+        schema_version: MANIFEST_SCHEMA_VERSION,
+        builds,
+        patches,
+    };
+    if upload {
+        let json = serde_json::to_vec_pretty(&manifest).context("serialize manifest")?;
+        index
+            .upload_file(MANIFEST_FILE_NAME, json)
+            .await
+            .context("upload manifest")?;
+    }
+    Ok(manifest)
+}
+
+fn remote_file_name(entry: &storage::Entry) -> String {
+    entry
+        .path
+        .rsplit('/')
+        .next()
+        .unwrap_or(&entry.path)
+        .to_owned()
+}
+
+pub async fn verify(
+    index: &ArtefactIndex,
+    version: Option<Version>,
+    patches: bool,
+    remote: bool,
+) -> Result<()> {
+    use index::Location;
+
+    if let Some(version) = &version {
+        ensure!(
+            index.builds().iter().any(|b| &b.version == version),
+            "build `{}` unknown",
+            version
+        );
+    }
+    let locations = |local: bool, on_remote: bool| {
+        let mut locations = Vec::new();
+        if local {
+            locations.push(Location::Local);
+        }
+        if on_remote && remote {
+            locations.push(Location::Remote);
+        }
+        locations
+    };
+    fn report(name: String, location: Location, res: Result<()>, corrupt: &mut usize) {
+        let location = match location {
+            Location::Local => "local",
+            Location::Remote => "remote",
+        };
+        match res {
+            Ok(()) => println!("ok       {} ({})", name, location),
+            Err(e) => {
+                log::error!("`{}` ({}) is corrupt: {:?}", name, location, e);
+                println!("CORRUPT  {} ({})", name, location);
+                *corrupt += 1;
+            }
+        }
+    }
+
+    let mut checked = 0;
+    let mut corrupt = 0;
+    for build in index.builds() {
+        if version.as_ref().map_or(false, |v| v != &build.version) {
+            continue;
+        }
+        for location in locations(build.local.is_some(), build.remote.is_some()) {
+            let res = index.verify_build(build.version.clone(), location).await;
+            report(build.version.to_string(), location, res, &mut corrupt);
+            checked += 1;
+        }
+    }
+    if patches {
+        for patch in index.patches() {
+            if version.as_ref().map_or(false, |v| v != &patch.to) {
+                continue;
             }
+            for location in locations(patch.local.is_some(), patch.remote.is_some()) {
+                let res = index.verify_patch(patch, location).await;
+                report(patch.file_name(), location, res, &mut corrupt);
+                checked += 1;
+            }
+        }
+    }
 
+    println!("{} ok, {} corrupt", checked - corrupt, corrupt);
+    ensure!(corrupt == 0, "{} of {} files are corrupt", corrupt, checked);
+    Ok(())
+}
+
+/// Fully check all local builds and patches, `jobs` files at a time
+///
+/// Unlike [`verify`], this reads every file even if its checksum is cached,
+/// and doesn't apply patches. A corrupt file doesn't stop the other checks.
+pub async fn verify_local(index: &ArtefactIndex, jobs: usize) -> Result<output::VerifyLocalOutput> {
+    use futures::stream::{self, StreamExt};
+
+    ensure!(
+        jobs > 0,
+        "need to check at least 1 file at a time, got {}",
+        jobs
+    );
+
+    let checks = index.local_checks().await;
+    let files = stream::iter(checks)
+        .map(|check| async move {
+            let (name, size) = (check.file_name(), check.size());
+            let checked = tokio::task::spawn_blocking(move || check.run())
+                .await
+                .context("check panicked")
+                .and_then(|checked| checked);
+            if let Err(e) = &checked {
+                log::error!("`{}` is corrupt: {:?}", name, e);
+            }
+            output::VerifiedFile {
+                uncompressed_size: checked.as_ref().ok().map(|(size, _)| *size),
+                checksum_checked: checked.as_ref().map_or(false, |(_, checked)| *checked),
+                error: checked.err().map(|e| format!("{:#}", e)),
+                name,
+                size,
+            }
+        })
+        .buffer_unordered(jobs)
+        .collect()
+        .await;
+    Ok(output::VerifyLocalOutput::new(files))
+}
+
+/// Report problems with the index, and delete local builds that look corrupt
+/// if `repair` is set
+pub async fn doctor(index: &mut ArtefactIndex, repair: bool) -> Result<()> {
+    let islands = index.build_islands();
+    if islands.len() > 1 {
+        log::warn!(
+            "found {} groups of builds without patches between them, \
+            upgrading from one group to another requires a full download",
+            islands.len()
+        );
+        for (idx, island) in islands.iter().enumerate() {
+            let versions = island
+                .iter()
+                .map(|v| v.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            println!("island {}: {}", idx + 1, versions);
+        }
+    } else {
+        log::info!("all builds are connected by patches");
+    }
+
+    for (version, problem) in index.suspect_local_builds().await? {
+        println!("suspect local build {}: {}", version, problem);
+        if repair {
             index
-                .upgrade_to_build(current_version, target_version.clone())
+                .remove_suspect_local_build(&version)
                 .await
-                .context("get build")?
+                .with_context(|| format!("remove suspect local build `{}`", version))?;
+            log::info!("removed local build `{}`", version);
+        }
+    }
+    Ok(())
+}
+
+pub async fn prune_patches(index: &mut ArtefactIndex, dry_run: bool) -> Result<()> {
+    let patches = index.redundant_patches();
+    if patches.is_empty() {
+        log::info!("no patches to prune");
+        return Ok(());
+    }
+
+    for patch in patches {
+        if dry_run {
+            println!("{}", patch.file_name());
+            continue;
+        }
+
+        index
+            .remove_patch(patch.from.clone(), patch.to.clone())
+            .await
+            .with_context(|| format!("remove patch `{}`", patch))?;
+        log::info!("removed patch `{}`", patch);
+    }
+    Ok(())
+}
+
+/// Delete the local file of a build (and its local patches) to free disk
+/// space
+///
+/// Refuses to delete builds that a symlink like `current` points at, or that
+/// are not in remote storage, unless `force` is set.
+pub async fn remove_local(
+    index: &mut ArtefactIndex,
+    version: Version,
+    current: &Path,
+    force: bool,
+) -> Result<()> {
+    let version = index.resolve_version(version)?;
+    let build = index
+        .builds()
+        .into_iter()
+        .find(|build| build.version == version)
+        .with_context(|| format!("build `{}` unknown", version))?;
+    ensure!(
+        build.local.is_some(),
+        "build `{}` is not stored locally",
+        version
+    );
+    if !force && linked_versions(current).contains(&version) {
+        return Err(Report::msg(format!("build `{}` is installed", version)))
+            .suggestion("install another build first, or use `--force`");
+    }
+    if !force && build.remote.is_none() {
+        return Err(Report::msg(format!(
+            "build `{}` is not in remote storage, removing it would lose it",
+            version
+        )))
+        .suggestion("run `artefacta sync` first, or use `--force`");
+    }
+
+    let patches = index
+        .remove_local_build(&version)
+        .await
+        .with_context(|| format!("remove local build `{}`", version))?;
+    log::info!("removed local build `{}`", version);
+    for patch in patches {
+        log::info!("removed local patch `{}`", patch);
+    }
+    Ok(())
+}
+
+/// Delete local patches whose builds are gone, locally and remotely
+pub async fn clean(index: &ArtefactIndex, dry_run: bool) -> Result<()> {
+    let patches = index
+        .orphaned_local_patches()
+        .await
+        .context("find orphaned patches")?;
+    if patches.is_empty() {
+        log::info!("no orphaned patches to clean up");
+        return Ok(());
+    }
+
+    for patch in patches {
+        if dry_run {
+            println!("{}", patch.file_name());
+            continue;
+        }
+
+        index
+            .remove_orphaned_patch(&patch)
+            .await
+            .with_context(|| format!("remove orphaned patch `{}`", patch))?;
+        log::info!("removed orphaned patch `{}`", patch);
+    }
+    Ok(())
+}
+
+/// Write a new key pair for signing builds to `path` and `<path>.pub`
+///
+/// Existing key files are never overwritten.
+pub fn generate_signing_key(path: &Path) -> Result<()> {
+    use std::io::Write;
+
+    let mut public_path = path.as_os_str().to_owned();
+    public_path.push(".pub");
+    let public_path = PathBuf::from(public_path);
+    let (private, public) = SigningKey::generate()?;
+    for (path, content, mode) in [
+        (path, private, 0o600),
+        (public_path.as_path(), public, 0o644),
+    ] {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(mode);
+        }
+        // no permission bits to set elsewhere
+        #[cfg(not(unix))]
+        let _ = mode;
+        let mut file = options
+            .open(path)
+            .with_context(|| format!("create key file `{}`", path.display()))?;
+        file.write_all(content.as_bytes())
+            .with_context(|| format!("write key file `{}`", path.display()))?;
+    }
+    log::info!(
+        "wrote signing key to `{}` and its public key to `{}`",
+        path.display(),
+        public_path.display()
+    );
+    Ok(())
+}
+
+/// Version the `current` symlink points at, if there is one
+fn installed_version(current: &Path) -> Result<Option<Version>> {
+    match fs::read_link(current) {
+        Ok(current_path) => {
+            let version = paths::build_version_from_path(&current_path)?;
+            log::debug!(
+                "identified version `{}` from path `{}`",
+                version,
+                current_path.display()
+            );
+            Ok(Some(version))
         }
         Err(e) => {
             log::debug!("could not read `current` symlink: {}", e);
-            index
+            Ok(None)
+        }
+    }
+}
+
+pub fn status(index: &ArtefactIndex, current: &Path) -> Result<StatusOutput> {
+    let installed = installed_version(current)?;
+    let latest = index.builds().last().map(|build| build.version.clone());
+
+    let upgrade = match (&installed, &latest) {
+        (Some(installed), Some(latest)) if latest > installed => {
+            let (path, download_size) = index
+                .upgrade_plan(installed.clone(), latest.clone(), None)
+                .context("find upgrade path to latest build")?;
+            Some(upgrade_info(path, download_size))
+        }
+        _ => None,
+    };
+
+    Ok(StatusOutput {
+        installed,
+        latest,
+        upgrade,
+    })
+}
+
+/// Everything the index knows about one build, and what installing it would
+/// take
+///
+/// Only uses what's already in the index, so this doesn't download anything.
+pub fn info(index: &ArtefactIndex, version: Version, current: &Path) -> Result<InfoOutput> {
+    let version = index.resolve_version(version)?;
+    let builds = index.builds();
+    let build = match builds.iter().find(|build| build.version == version) {
+        Some(build) => build,
+        None => {
+            let similar = similar_versions(&version, builds.iter().map(|build| &build.version));
+            let unknown = None.with_context(|| format!("build `{}` unknown", version));
+            return if similar.is_empty() {
+                unknown
+            } else {
+                unknown.with_suggestion(|| format!("did you mean {}?", similar.join(", ")))
+            };
+        }
+    };
+
+    let patches = index.patches();
+    let patches_in = patches
+        .iter()
+        .filter(|patch| patch.to == version)
+        .map(|patch| patch_info(patch))
+        .collect();
+    let patches_out = patches
+        .iter()
+        .filter(|patch| patch.from == version)
+        .map(|patch| patch_info(patch))
+        .collect();
+
+    let installed = installed_version(current)?;
+    let upgrade = match &installed {
+        Some(installed) if installed != &version => {
+            let (path, download_size) = index
+                .upgrade_plan(installed.clone(), version.clone(), None)
+                .with_context(|| format!("find upgrade path to `{}`", version))?;
+            Some(upgrade_info(path, download_size))
+        }
+        _ => None,
+    };
+
+    Ok(InfoOutput {
+        version: version.clone(),
+        local_size: build.local.as_ref().map(|entry| entry.size),
+        remote_size: build.remote.as_ref().map(|entry| entry.size),
+        local_modified: build.local.as_ref().and_then(|entry| entry.modified),
+        remote_modified: build.remote.as_ref().and_then(|entry| entry.modified),
+        remote_alias_of: index.remote_alias_of(&version).cloned(),
+        uncompressed_size: build.uncompressed_size,
+        checksum: build.checksum,
+        meta: build.custom_meta.clone(),
+        patches_in,
+        patches_out,
+        installed,
+        upgrade,
+    })
+}
+
+/// What [`install`] would do to get to `version` from the installed build
+///
+/// Like [`info`], this only uses what's already in the index.
+pub fn plan(index: &ArtefactIndex, version: Version, current: &Path) -> Result<PlanOutput> {
+    let version = index.resolve_version(version)?;
+    let installed = installed_version(current)?;
+
+    let (path, download_size, fetch) = match &installed {
+        Some(installed) if installed == &version => {
+            return Ok(PlanOutput {
+                from: Some(version.clone()),
+                to: version,
+                method: UpgradeMethod::AlreadyInstalled,
+                patches: Vec::new(),
+                build_size: None,
+                download_size: 0,
+                fetch: Vec::new(),
+            });
+        }
+        Some(installed) => {
+            let plan = index
+                .plan_upgrade(installed.clone(), version.clone())
+                .with_context(|| format!("find upgrade path to `{}`", version))?;
+            (plan.path, plan.download_size, plan.files_to_fetch)
+        }
+        None => {
+            let build = index
+                .builds()
+                .into_iter()
+                .find(|build| build.version == version)
+                .with_context(|| format!("build `{}` unknown", version))?;
+            let download_size = if build.local.is_some() {
+                0
+            } else {
+                build.size()
+            };
+            let path = index::UpgradePath::InstallBuild(Box::new(build.clone()));
+            let fetch = index.files_to_fetch(&path)?;
+            (path, download_size, fetch)
+        }
+    };
+
+    let (method, patches, build_size) = match &path {
+        index::UpgradePath::ApplyPatches(patches) => (
+            UpgradeMethod::Patches,
+            patches.iter().map(patch_info).collect(),
+            None,
+        ),
+        index::UpgradePath::InstallBuild(build) => {
+            (UpgradeMethod::Build, Vec::new(), Some(build.size()))
+        }
+    };
+    Ok(PlanOutput {
+        from: installed,
+        to: version,
+        method,
+        patches,
+        build_size,
+        download_size,
+        fetch,
+    })
+}
+
+/// Known versions that look like `version`, e.g. to suggest them when it's
+/// mistyped, closest ones first
+fn similar_versions<'a>(
+    version: &Version,
+    known: impl Iterator<Item = &'a Version>,
+) -> Vec<String> {
+    const MAX_SUGGESTIONS: usize = 5;
+
+    let wanted = version.as_str().to_lowercase();
+    let mut similar = known
+        .filter_map(|candidate| {
+            let name = candidate.as_str().to_lowercase();
+            let distance = edit_distance(&wanted, &name);
+            let related = name.contains(&wanted) || wanted.contains(&name);
+            (related || distance <= 2).then(|| (distance, candidate.as_str()))
+        })
+        .collect::<Vec<_>>();
+    similar.sort();
+    similar
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, name)| format!("`{}`", name))
+        .collect()
+}
+
+/// Levenshtein distance between `a` and `b` (in chars)
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+fn upgrade_info(path: index::UpgradePath, download_size: u64) -> UpgradeInfo {
+    match path {
+        index::UpgradePath::ApplyPatches(patches) => UpgradeInfo {
+            method: UpgradeMethod::Patches,
+            patches: patches.iter().map(|p| p.file_name()).collect(),
+            download_size,
+        },
+        index::UpgradePath::InstallBuild(_) => UpgradeInfo {
+            method: UpgradeMethod::Build,
+            patches: Vec::new(),
+            download_size,
+        },
+    }
+}
+
+/// How to [`install`] a build
+#[derive(Debug, Clone, Default)]
+pub struct InstallOptions {
+    /// Download the full build instead of applying more patches than this
+    pub max_patch_hops: Option<usize>,
+    /// Number of local builds to keep, see [`ArtefactIndex::prune_local_builds`]
+    pub keep: Option<usize>,
+    /// Point `current` at an unpacked copy of the build instead of the archive
+    pub extract: bool,
+    /// Shell command to run afterwards
+    pub post_install: Option<String>,
+    /// Refuse builds without a valid signature, see
+    /// [`ArtefactIndex::verify_signature`]
+    pub require_signature: bool,
+    pub dry_run: bool,
+}
+
+/// Get build, upgrading from `previous` if possible
+async fn fetch_build(
+    index: &mut ArtefactIndex,
+    previous: Option<&Version>,
+    target_version: &Version,
+    max_patch_hops: Option<usize>,
+) -> Result<(storage::Entry, index::UpgradePath)> {
+    match previous {
+        Some(current_version) => index
+            .upgrade_to_build(
+                current_version.clone(),
+                target_version.clone(),
+                max_patch_hops,
+            )
+            .await
+            .context("get build"),
+        None => {
+            let build = index
                 .get_build(target_version.clone())
                 .await
-                .context("get build")?
+                .context("get build")?;
+            let path = index::UpgradePath::InstallBuild(Box::new(index::Build::new(
+                target_version.clone(),
+            )));
+            Ok((build, path))
         }
+    }
+}
+
+pub async fn install(
+    index: &mut ArtefactIndex,
+    target_version: Version,
+    current: &Path,
+    options: InstallOptions,
+) -> Result<InstallOutput> {
+    let InstallOptions {
+        max_patch_hops,
+        keep,
+        extract,
+        post_install,
+        require_signature,
+        dry_run,
+    } = options;
+
+    let target_version = index.resolve_version(target_version)?;
+    let downloaded_before = index.downloaded_bytes();
+    let previous = installed_version(current)?;
+    if previous.as_ref() == Some(&target_version) {
+        log::info!("version `{}` already installed", target_version);
+        return Ok(InstallOutput {
+            version: target_version,
+            previous,
+            method: UpgradeMethod::AlreadyInstalled,
+            patches: Vec::new(),
+            downloaded: 0,
+            dry_run,
+            fetch: Vec::new(),
+            removed: Vec::new(),
+        });
+    }
+
+    if dry_run {
+        let path = match &previous {
+            Some(current_version) => {
+                index
+                    .upgrade_plan(
+                        current_version.clone(),
+                        target_version.clone(),
+                        max_patch_hops,
+                    )?
+                    .0
+            }
+            None => {
+                let build = index
+                    .builds()
+                    .into_iter()
+                    .find(|build| build.version == target_version)
+                    .with_context(|| format!("build `{}` unknown", target_version))?;
+                index::UpgradePath::InstallBuild(Box::new(build.clone()))
+            }
+        };
+        let fetch = index.files_to_fetch(&path)?;
+        let UpgradeInfo {
+            method, patches, ..
+        } = upgrade_info(path, 0);
+        return Ok(InstallOutput {
+            version: target_version,
+            previous,
+            method,
+            patches,
+            downloaded: 0,
+            dry_run,
+            fetch,
+            removed: Vec::new(),
+        });
+    }
+
+    let (target_build, path) =
+        match fetch_build(index, previous.as_ref(), &target_version, max_patch_hops).await {
+            Err(e) if index.is_from_cache() => {
+                log::info!(
+                    "could not get build with cached listing ({}), listing it again",
+                    e
+                );
+                index.refresh().await.context("refresh index")?;
+                fetch_build(index, previous.as_ref(), &target_version, max_patch_hops).await?
+            }
+            res => res?,
+        };
+
+    index
+        .verify_build(target_version.clone(), index::Location::Local)
+        .await
+        .with_context(|| format!("build `{}` is corrupt, not installing it", target_version))
+        .note(format!(
+            "delete `{}` to download it again",
+            target_build.path
+        ))?;
+
+    if require_signature || index.has_verifying_key() {
+        let signed = index
+            .verify_signature(&target_version)
+            .await
+            .with_context(|| {
+                format!(
+                    "build `{}` is not trusted, not installing it",
+                    target_version
+                )
+            })?;
+        if !signed {
+            ensure!(
+                !require_signature,
+                "build `{}` is not signed",
+                target_version
+            );
+            log::warn!("build `{}` is not signed", target_version);
+        }
+    }
+
+    let link_target = if extract {
+        extract_build(Path::new(&target_build.path), &target_version)
+            .with_context(|| format!("extract build `{}`", target_version))?
+    } else {
+        PathBuf::from(&target_build.path)
     };
 
+    // Only touch the links once the new build is complete and verified, so
+    // any failure above leaves `current` at the previous build
+    if let Ok(old_target) = fs::read_link(current) {
+        replace_symlink(&old_target, &previous_link(current))
+            .context("remember previous build for rollback")?;
+    }
+    replace_symlink(&link_target, current).context("point `current` at new build")?;
+    log::info!(
+        "successfully installed `{}` as `{}`",
+        target_version,
+        current.display()
+    );
+
+    if let Some(command) = &post_install {
+        run_post_install(command, &target_version, previous.as_ref(), current)?;
+    }
+
+    let removed = match keep {
+        Some(keep) => {
+            let mut protected = linked_versions(current);
+            protected.push(target_version.clone());
+            index
+                .prune_local_builds(keep, &protected)
+                .await
+                .context("remove old local builds")?
+        }
+        None => Vec::new(),
+    };
+
+    let downloaded = index.downloaded_bytes() - downloaded_before;
+    let UpgradeInfo {
+        method, patches, ..
+    } = upgrade_info(path, downloaded);
+    Ok(InstallOutput {
+        version: target_version,
+        previous,
+        method,
+        patches,
+        downloaded,
+        dry_run,
+        fetch: Vec::new(),
+        removed,
+    })
+}
+
+/// Unpack build archive into a `<version>.extracted` directory next to it
+///
+/// Unpacks into a temporary directory first, and moves that into place when
+/// done, so the final directory is always complete.
+fn extract_build(archive: &Path, version: &Version) -> Result<PathBuf> {
+    let store = archive
+        .parent()
+        .with_context(|| format!("`{}` is not in a directory", archive.display()))?;
+    let dir_name = paths::extracted_path_from_version(version.clone())?;
+    let target = store.join(&dir_name);
+    if target.is_dir() {
+        log::debug!("build `{}` already extracted", version);
+        return Ok(target);
+    }
+
+    let tmp = store.join(format!(".{}.tmp-{}", dir_name, std::process::id()));
+    if tmp.exists() {
+        fs::remove_dir_all(&tmp).with_context(|| format!("remove stale `{}`", tmp.display()))?;
+    }
+    fs::create_dir(&tmp).with_context(|| format!("create `{}`", tmp.display()))?;
+
+    let file = fs::File::open(archive)
+        .with_context(|| format!("open build archive `{}`", archive.display()))?;
+    let content = build_reader(archive, io::BufReader::new(file)).context("read build archive")?;
+    if let Err(e) = packaging::unpack(content, &tmp) {
+        fs::remove_dir_all(&tmp).log_and_discard();
+        return Err(e);
+    }
+
+    fs::rename(&tmp, &target)
+        .with_context(|| format!("move extracted build to `{}`", target.display()))?;
+    log::info!("extracted `{}` to `{}`", version, target.display());
+    Ok(target)
+}
+
+/// Run shell command after installing a new build
+///
+/// The command gets the installed version and the path of the `current`
+/// symlink in its environment. When it fails, the new build stays installed.
+fn run_post_install(
+    command: &str,
+    version: &Version,
+    previous: Option<&Version>,
+    current: &Path,
+) -> Result<()> {
+    use std::process::Command;
+
+    #[cfg(unix)]
+    let mut cmd = {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(command);
+        cmd
+    };
+    #[cfg(windows)]
+    let mut cmd = {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C").arg(command);
+        cmd
+    };
+
+    cmd.env("ARTEFACTA_INSTALLED_VERSION", version.as_str())
+        .env("ARTEFACTA_CURRENT_PATH", current);
+    match previous {
+        Some(previous) => cmd.env("ARTEFACTA_PREVIOUS_VERSION", previous.as_str()),
+        None => cmd.env_remove("ARTEFACTA_PREVIOUS_VERSION"),
+    };
+
+    log::info!("running post-install command `{}`", command);
+    let status = cmd
+        .status()
+        .with_context(|| format!("run post-install command `{}`", command))
+        .note("the new build was installed anyway")?;
+    ensure!(
+        status.success(),
+        "post-install command `{}` failed ({})",
+        command,
+        status
+    );
+    Ok(())
+}
+
+/// Switch `current` back to the build that was installed before
+///
+/// The build we switch away from becomes the new "previous" one, so rolling
+/// back twice returns to where we started.
+pub fn rollback(current: &Path) -> Result<Version> {
+    let previous = previous_link(current);
+    let previous_target = fs::read_link(&previous)
+        .context("no previous version to roll back to")
+        .note("the previous version is recorded when `install` replaces an installed build")?;
+    let previous_version = paths::build_version_from_path(&previous_target)?;
+    ensure!(
+        previous_target.exists(),
+        "previous build `{}` does not exist anymore at `{}`",
+        previous_version,
+        previous_target.display()
+    );
+
+    let current_target = fs::read_link(current).ok();
+    replace_symlink(&previous_target, current).context("point `current` at previous build")?;
+    match current_target {
+        Some(target) => replace_symlink(&target, &previous)
+            .context("remember rolled back build as previous one")?,
+        None => fs::remove_file(&previous).context("clear `previous` symlink")?,
+    }
+
+    log::info!("rolled back to `{}`", previous_version);
+    Ok(previous_version)
+}
+
+/// Versions that `current` and all other symlinks next to it point at
+fn linked_versions(current: &Path) -> Vec<Version> {
+    let dir = match current.parent().map(fs::read_dir) {
+        Some(Ok(dir)) => dir,
+        _ => return Vec::new(),
+    };
+    dir.filter_map(|entry| fs::read_link(entry.ok()?.path()).ok())
+        .filter_map(|target| paths::build_version_from_path(&target).ok())
+        .collect()
+}
+
+/// Symlink next to `current` pointing at the build installed before
+///
+/// For other links (see [`cli::LinkName`]), it's `<name>.previous`.
+fn previous_link(current: &Path) -> PathBuf {
+    match current.file_name().and_then(|name| name.to_str()) {
+        Some("current") | None => current.with_file_name("previous"),
+        Some(name) => current.with_file_name(format!("{}.previous", name)),
+    }
+}
+
+/// Make `link` point at `target`, replacing the old link
+///
+/// The new link is created next to the old one and then renamed over it, so
+/// `link` is never missing, even if we die halfway through.
+fn replace_symlink(target: &Path, link: &Path) -> Result<()> {
     #[cfg(unix)]
     use std::os::unix::fs::symlink;
     #[cfg(windows)]
-    use std::os::windows::fs::symlink_file as symlink;
+    let symlink = |target: &Path, link: &Path| {
+        use std::os::windows::fs::{symlink_dir, symlink_file};
+        if target.is_dir() {
+            symlink_dir(target, link)
+        } else {
+            symlink_file(target, link)
+        }
+    };
 
-    if current.exists() {
-        fs::remove_file(&current).context("clear old `current` symlink")?;
+    let file_name = link
+        .file_name()
+        .with_context(|| format!("`{}` has no file name", link.display()))?;
+    let tmp_link = link.with_file_name(format!(
+        ".{}.tmp-{}",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+    // left over from an earlier run that died with the same PID
+    if tmp_link.symlink_metadata().is_ok() {
+        remove_symlink(&tmp_link)
+            .with_context(|| format!("remove stale `{}`", tmp_link.display()))?;
     }
 
-    symlink(&target_build.path, &current).with_context(|| {
+    symlink(target, &tmp_link).with_context(|| {
         format!(
-            "create symlink pointing at new build: {} to {}",
-            target_build.path,
-            current.display()
+            "create symlink `{}` pointing at `{}`",
+            tmp_link.display(),
+            target.display()
         )
     })?;
-    log::info!(
-        "successfully installed `{}` as `{}`",
-        target_version,
-        current.display()
-    );
+
+    let res = fs::rename(&tmp_link, link);
+    // Windows refuses to replace some links (e.g. ones to directories). Not
+    // atomic, but the best we can do there.
+    #[cfg(windows)]
+    let res = res.or_else(|e| {
+        log::debug!("can't replace `{}` in one go: {}", link.display(), e);
+        if link.symlink_metadata().is_ok() {
+            remove_symlink(link)?;
+        }
+        fs::rename(&tmp_link, link)
+    });
+    if let Err(e) = res {
+        remove_symlink(&tmp_link).log_and_discard();
+        return Err(e)
+            .with_context(|| format!("move new symlink into place at `{}`", link.display()));
+    }
     Ok(())
 }
 
+/// Remove the symlink at `link` (not what it points at)
+///
+/// On Windows, links to directories are directories themselves and have to
+/// be removed as such. Their file type doesn't say so (`is_dir` is false for
+/// all links), but their attributes do.
+fn remove_symlink(link: &Path) -> io::Result<()> {
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        const FILE_ATTRIBUTE_DIRECTORY: u32 = 0x10;
+
+        if link.symlink_metadata()?.file_attributes() & FILE_ATTRIBUTE_DIRECTORY != 0 {
+            return fs::remove_dir(link);
+        }
+    }
+    fs::remove_file(link)
+}
+
 pub async fn add(index: &mut ArtefactIndex, build: cli::AddBuild) -> Result<()> {
     build.add_to(index).await.context("could not add new build")
 }
 
-pub async fn add_package(
+/// Add a build archive read from `reader` (e.g. stdin) as `version`
+pub async fn add_from_reader(
     index: &mut ArtefactIndex,
     version: Version,
-    build: cli::AddBuild,
+    mut reader: impl io::Read,
+    options: cli::AddOptions,
 ) -> Result<()> {
-    use tempfile::tempdir;
+    let entry = if options.verify {
+        // it has to be complete before it's in the store, so keep it aside
+        let tmp = tempfile::tempdir().context("could not create temporary directory")?;
+        let archive_path = tmp
+            .path()
+            .join(paths::build_path_from_version(version.clone())?);
+        let mut file = fs::File::create(&archive_path)
+            .with_context(|| format!("create `{}`", archive_path.display()))?;
+        io::copy(&mut reader, &mut file).context("read build archive")?;
+        cli::verify_build_archive(&archive_path)?;
+        let file = fs::File::open(&archive_path)
+            .with_context(|| format!("open `{}`", archive_path.display()))?;
+        index.add_local_build_from_reader(&version, io::BufReader::new(file))
+    } else {
+        index.add_local_build_from_reader(&version, reader)
+    }
+    .with_context(|| format!("could not add new build `{}`", version))?;
+    log::info!("successfully added `{:?}` to local index", entry);
+    options
+        .finish(index, version)
+        .await
+        .context("could not add new build")
+}
 
-    let build_path = build
-        .path
-        .canonicalize()
-        .with_context(|| format!("cannot canonicalize path `{}`", build.path.display()))?;
+/// Download a build archive from `url` and add it as `version`
+///
+/// The download has to be zstd compressed data, anything else (like an error
+/// page served with a success status) is not added.
+pub async fn add_from_url(
+    index: &mut ArtefactIndex,
+    url: &url::Url,
+    version: Version,
+    options: cli::AddOptions,
+) -> Result<()> {
+    let tmp = tempfile::tempdir().context("could not create temporary directory")?;
+    let archive_path = tmp
+        .path()
+        .join(paths::build_path_from_version(version.clone())?);
 
-    let archive_name = format!("{}.tar.zst", version);
-    let tmp = tempdir()
-                .context("could not create temporary directory")
-                .note("that is really strange: are you running this as weird dynamic user in systemd or something?")?;
-    let archive_path = tmp.path().join(&archive_name);
+    let file = fs::File::create(&archive_path)
+        .with_context(|| format!("create `{}`", archive_path.display()))?;
+    let mut file = io::BufWriter::new(file);
+    http::download(url, &mut file)
+        .await
+        .with_context(|| format!("could not download build `{}`", version))?;
+    io::Write::flush(&mut file).context("write downloaded build")?;
 
-    log::info!(
-        "packaging `{}` into `{}`",
-        build_path.display(),
-        archive_path.display()
-    );
+    let archive = fs::File::open(&archive_path)
+        .with_context(|| format!("open `{}`", archive_path.display()))?;
+    zstd::stream::read::Decoder::new(archive)
+        .and_then(|mut decoder| io::copy(&mut decoder, &mut io::sink()))
+        .map_err(|e| {
+            ErrorKind::Data.wrap(
+                Report::new(e),
+                format!("`{}` is not a zstd compressed build", url),
+            )
+        })?;
+
+    let add = AddBuild {
+        path: archive_path,
+        options,
+    };
+    add.add_to(index).await.context("could not add new build")?;
+
+    tmp.close()
+        .context("could not clean up temporary directory")?;
+    Ok(())
+}
+
+const TEMP_DIR_SUGGESTION: &str = "if the temporary directory is short on space, \
+    use `--temp-dir` to package on another volume, e.g. next to the local store";
+
+pub async fn add_package(
+    index: &mut ArtefactIndex,
+    version: Version,
+    build: cli::AddBuild,
+    options: PackageOptions,
+) -> Result<output::PackageOutput> {
+    let tmp = match &options.temp_dir {
+        Some(dir) => tempfile::tempdir_in(dir).with_context(|| {
+            format!(
+                "could not create temporary directory in `{}`",
+                dir.display()
+            )
+        })?,
+        None => tempfile::tempdir()
+            .context("could not create temporary directory")
+            .suggestion(TEMP_DIR_SUGGESTION)?,
+    };
+    let keep_temp = options.keep_temp_on_failure();
+    let cleanup = if keep_temp {
+        None
+    } else {
+        let path = tmp.path().to_path_buf();
+        Some(interrupt::on_interrupt(async move {
+            let _ = fs::remove_dir_all(path);
+        }))
+    };
+    let result = package_in(tmp.path(), index, version, build, &options, keep_temp).await;
+    drop(cleanup);
+    match result {
+        Ok(result) => {
+            tmp.close()
+                .context("could not clean up temporary directory")?;
+            Ok(result)
+        }
+        Err(e) if keep_temp => {
+            let path = tmp.into_path();
+            log::warn!(
+                "packaging failed, keeping temporary directory `{}`",
+                path.display()
+            );
+            Err(e)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Package the build into `tmp` and add it from there
+async fn package_in(
+    tmp: &Path,
+    index: &mut ArtefactIndex,
+    version: Version,
+    build: cli::AddBuild,
+    options: &PackageOptions,
+    keep_temp: bool,
+) -> Result<output::PackageOutput> {
+    let sources = options.sources(&build.path)?;
+    let archive_path = tmp.join(paths::build_path_from_version(version.clone())?);
+
+    for source in &sources {
+        log::info!(
+            "packaging `{}` into `{}`",
+            source.path.display(),
+            archive_path.display()
+        );
+    }
 
     let mut archive_file = PartialFile::create(&archive_path)
-        .with_context(|| format!("cannot create file `{}`", archive_path.display()))?;
-    let mut archive = compress(&mut archive_file)
+        .with_context(|| format!("cannot create file `{}`", archive_path.display()))
+        .suggestion(TEMP_DIR_SUGGESTION)?;
+    if keep_temp {
+        archive_file.keep_if_unfinished();
+    }
+    let settings = options.packaging_settings();
+    let source_size = packaging::source_size(&sources, &settings).context("get size of build")?;
+    let threads = options
+        .compression_threads
+        .unwrap_or_else(|| compression::default_threads(source_size));
+    log::debug!("compressing using {} threads", threads);
+    let mut archive = compress_with_threads(&mut archive_file, threads)
         .with_context(|| format!("cannot create zstd file `{}`", archive_path.display()))?;
-    package(&build_path, &mut archive)
-        .with_context(|| format!("package archive `{}`", archive_path.display()))?;
+    let progress = progress::Progress::new(format!("packaging {}", version), source_size);
+    let compressing = timings::start(timings::Phase::Compress);
+    let uncompressed_size =
+        packaging::package_with(&sources, progress.writer(&mut archive), &settings)
+            .with_context(|| format!("package archive `{}`", archive_path.display()))
+            .suggestion(TEMP_DIR_SUGGESTION)?;
+    drop(progress);
     archive
         .finish()
-        .with_context(|| format!("write zstd archive `{}`", archive_path.display()))?;
+        .with_context(|| format!("write zstd archive `{}`", archive_path.display()))
+        .suggestion(TEMP_DIR_SUGGESTION)?;
+    drop(compressing);
     archive_file
         .finish()
         .context("faild to finish moving archive file into place")?;
+    let compressed_size = fs::metadata(&archive_path)
+        .with_context(|| format!("get size of `{}`", archive_path.display()))?
+        .len();
+    let result = output::PackageOutput::new(version.clone(), uncompressed_size, compressed_size);
+    log::info!(
+        "compressed `{}` from {} to {} (ratio {:.2})",
+        version,
+        output::file_size(uncompressed_size),
+        output::file_size(compressed_size),
+        result.ratio
+    );
+
+    let meta = BuildMeta {
+        uncompressed_size: Some(uncompressed_size),
+        ..BuildMeta::default()
+    };
+    meta.write(tmp.join(paths::build_meta_path_from_version(version)?))
+        .context("write build metadata")?;
 
     let add = AddBuild {
         path: archive_path,
         ..build
     };
     add.add_to(index).await.context("could not add new build")?;
-
-    tmp.close()
-        .context("could not clean up temporary directory")?;
-    Ok(())
+    Ok(result)
 }
 
-pub async fn create_patch(index: &mut ArtefactIndex, from: Version, to: Version) -> Result<()> {
+pub async fn create_patch(
+    index: &mut ArtefactIndex,
+    from: Version,
+    to: Version,
+) -> Result<PatchInfo> {
     ensure!(
         from != to,
         "Rejecting to create patch between same versions ({}->{})",
@@ -153,7 +1450,145 @@ pub async fn create_patch(index: &mut ArtefactIndex, from: Version, to: Version)
     index.get_build(from.clone()).await?;
     index.get_build(to.clone()).await?;
     index.calculate_patch(from.clone(), to.clone()).await?;
-    Ok(())
+
+    let patch = index
+        .patches()
+        .into_iter()
+        .find(|patch| patch.from == from && patch.to == to)
+        .context("newly created patch is not in index")?;
+    Ok(patch_info(patch))
+}
+
+/// Guess how large a patch from `from` to `to` would be, without calculating
+/// it (see [`estimate`])
+///
+/// Downloads the builds if they are not available locally.
+pub async fn estimate_patch(
+    index: &mut ArtefactIndex,
+    from: Version,
+    to: Version,
+) -> Result<PatchEstimate> {
+    ensure!(
+        from != to,
+        "Rejecting to estimate patch between same versions ({}->{})",
+        from,
+        to
+    );
+    let old_build = index
+        .get_build(from.clone())
+        .await
+        .context("get old build")?;
+    let new_build = index.get_build(to.clone()).await.context("get new build")?;
+    let comparison = estimate::compare(
+        index::open_build(&old_build).context("read old build")?,
+        index::open_build(&new_build).context("read new build")?,
+    )
+    .context("compare builds")?;
+
+    let estimated_size = comparison.patch_size(new_build.size);
+    Ok(PatchEstimate {
+        from,
+        to,
+        build_size: new_build.size,
+        uncompressed_size: comparison.new_size,
+        unmatched_size: comparison.unmatched,
+        estimated_size,
+        savings: new_build.size.saturating_sub(estimated_size),
+    })
+}
+
+/// Create patches between consecutive builds, in natural order, where there is
+/// no patch yet
+///
+/// Chains the given `versions`, or all builds in the index if there are none.
+/// With `dry_run`, only plans them. Returns the patches that were created.
+pub async fn create_patches_chain(
+    index: &mut ArtefactIndex,
+    versions: Vec<Version>,
+    dry_run: bool,
+) -> Result<CreatePatchOutput> {
+    let mut versions = if versions.is_empty() {
+        index
+            .builds()
+            .iter()
+            .map(|build| build.version.clone())
+            .collect()
+    } else {
+        let builds = index.builds();
+        for version in &versions {
+            ensure!(
+                builds.iter().any(|build| &build.version == version),
+                "build `{}` unknown",
+                version
+            );
+        }
+        versions
+    };
+    versions.sort();
+    versions.dedup();
+
+    let mut output = CreatePatchOutput::default();
+    for pair in versions.windows(2) {
+        let (from, to) = (pair[0].clone(), pair[1].clone());
+        if dry_run {
+            output.planned.push(plan_patch(index, from, to)?);
+            continue;
+        }
+        let exists = index
+            .patches()
+            .iter()
+            .any(|patch| patch.from == from && patch.to == to);
+        if exists {
+            log::info!("patch from `{}` to `{}` already exists", from, to);
+            continue;
+        }
+        let patch = create_patch(index, from.clone(), to.clone())
+            .await
+            .with_context(|| format!("create patch from `{}` to `{}`", from, to))?;
+        log::info!("created patch from `{}` to `{}`", patch.from, patch.to);
+        output.patches.push(patch);
+    }
+    Ok(output)
+}
+
+/// What [`create_patch`] would do, without doing it
+pub fn plan_patch(index: &ArtefactIndex, from: Version, to: Version) -> Result<PatchPlan> {
+    ensure!(
+        from != to,
+        "Rejecting to create patch between same versions ({}->{})",
+        from,
+        to
+    );
+    let builds = index.builds();
+    let find_build = |version: &Version| {
+        builds
+            .iter()
+            .find(|build| &build.version == version)
+            .with_context(|| format!("build `{}` unknown", version))
+    };
+    let old_build = find_build(&from)?;
+    let new_build = find_build(&to)?;
+
+    let mut fetch = Vec::new();
+    for build in [old_build, new_build] {
+        if build.local.is_none() {
+            fetch.push(paths::build_path_from_version(build.version.clone())?);
+        }
+    }
+    let exists = index
+        .patches()
+        .iter()
+        .any(|patch| patch.from == from && patch.to == to);
+
+    Ok(PatchPlan {
+        from,
+        to,
+        exists,
+        fetch,
+        diff_size: new_build
+            .uncompressed_size
+            .unwrap_or_else(|| new_build.size()),
+    })
 }
 
 pub async fn auto_patch(
@@ -161,7 +1596,17 @@ pub async fn auto_patch(
     repo_root: &Path,
     current: Version,
     prefix: &str,
+    tag_format: &git::TagFormat,
+    tag_order: git::TagOrder,
+    jobs: usize,
 ) -> Result<()> {
+    use futures::stream::{self, StreamExt};
+
+    ensure!(
+        jobs >= 1,
+        "need to run at least 1 job at a time, got {}",
+        jobs
+    );
     let current_build =
         Version::try_from(&format!("{}{}", prefix, current)).with_context(|| {
             format!(
@@ -172,29 +1617,52 @@ pub async fn auto_patch(
     log::debug!("current version incl. given prefix is {}", current_build);
     index.get_build(current_build.clone()).await?;
 
-    let repo = git2::Repository::discover(&repo_root)
+    let repo = git2::Repository::discover(repo_root)
         .with_context(|| format!("can't open repository at `{}`", repo_root.display()))
         .suggestion("If this path looks wrong, you can overwrite it with `--repo-root=<PATH>`")?;
     log::debug!("opened git repo {}", repo_root.display());
     let tags = git::get_tags(&repo).context("can't get tags from repo")?;
-    let tag_names = tags
-        .iter()
-        .map(|tag| tag.name.clone())
-        .collect::<Vec<String>>();
-    log::trace!("found these tags in repo: {:?}", tag_names);
+    log::trace!(
+        "found these tags in repo: {:?}",
+        tags.iter().map(|tag| &tag.name).collect::<Vec<_>>()
+    );
 
-    let to_patch = git::find_tags_to_patch(current.as_str(), &tag_names)
-        .context("can't find version to create patches for")?;
+    let to_patch =
+        git::find_tags_to_patch_with_format(current.as_str(), &tags, tag_format, tag_order)
+            .context("can't find version to create patches for")?;
     log::info!("will create patches from these versions: {:?}", to_patch);
 
     let mut failed = false;
+    let mut patch_jobs = Vec::new();
     for tag in &to_patch {
         let tag = format!("{}{}", prefix, tag);
-        if let Err(e) = get_and_patch(index, &tag, current_build.clone()).await {
-            log::error!("could not create patch from tag {}: {:?}", tag, e);
-            failed = true;
-        } else {
-            log::info!("patch `{}` -> `{}`", tag, current_build);
+        match prepare_patch_from_tag(index, &tag, current_build.clone()).await {
+            Ok(Some(job)) => patch_jobs.push((tag, job)),
+            Ok(None) => log::info!("patch `{}` -> `{}` exists", tag, current_build),
+            Err(e) => {
+                log::error!("could not create patch from tag {}: {:?}", tag, e);
+                failed = true;
+            }
+        }
+    }
+
+    // Only diffing runs in parallel, the index is updated one patch at a time
+    let mut calculated = stream::iter(patch_jobs)
+        .map(|(tag, job)| async move {
+            let patch = tokio::task::spawn_blocking(move || job.run())
+                .await
+                .context("patch calculation panicked")
+                .and_then(|patch| patch);
+            (tag, patch)
+        })
+        .buffer_unordered(jobs);
+    while let Some((tag, patch)) = calculated.next().await {
+        match patch.and_then(|patch| index.add_calculated_patch(patch)) {
+            Ok(()) => log::info!("patch `{}` -> `{}`", tag, current_build),
+            Err(e) => {
+                log::error!("could not create patch from tag {}: {:?}", tag, e);
+                failed = true;
+            }
         }
     }
     if failed {
@@ -204,10 +1672,64 @@ pub async fn auto_patch(
     Ok(())
 }
 
-async fn get_and_patch(index: &mut ArtefactIndex, tag: &str, to: Version) -> Result<()> {
+/// Get builds for a patch from the build of `tag` to `to`, see
+/// [`ArtefactIndex::prepare_patch`]
+async fn prepare_patch_from_tag(
+    index: &mut ArtefactIndex,
+    tag: &str,
+    to: Version,
+) -> Result<Option<index::PatchJob>> {
     let version = index.get_build_for_tag(tag)?;
     log::debug!("source version: picked {} from tag {}", version, tag);
-    index.get_build(version.clone()).await?;
-    index.calculate_patch(version.clone(), to.clone()).await?;
+    index.prepare_patch(version, to).await
+}
+
+async fn patch_from(index: &mut ArtefactIndex, from: Version, to: Version) -> Result<()> {
+    index.get_build(from.clone()).await?;
+    index.calculate_patch(from, to).await?;
+    Ok(())
+}
+
+/// Create patches to `to` from the `count` builds right before it, in natural
+/// version order
+///
+/// Like [`auto_patch`], but for versions that sort by themselves, so it doesn't
+/// need a git repository.
+pub async fn auto_patch_local(index: &mut ArtefactIndex, to: Version, count: usize) -> Result<()> {
+    ensure!(
+        count >= 1,
+        "need to patch from at least 1 build, got {}",
+        count
+    );
+    index.get_build(to.clone()).await?;
+
+    let earlier = index
+        .builds()
+        .into_iter()
+        .map(|build| build.version.clone())
+        .filter(|version| version < &to)
+        .collect::<Vec<_>>();
+    let from = &earlier[earlier.len().saturating_sub(count)..];
+    if from.is_empty() {
+        log::warn!("no builds before `{}`, nothing to patch from", to);
+        return Ok(());
+    }
+    log::info!("will create patches from these versions: {:?}", from);
+
+    let mut failed = 0;
+    for version in from {
+        if let Err(e) = patch_from(index, version.clone(), to.clone()).await {
+            log::error!("could not create patch from {}: {:?}", version, e);
+            failed += 1;
+        } else {
+            log::info!("patch `{}` -> `{}`", version, to);
+        }
+    }
+    ensure!(
+        failed == 0,
+        "failed to create {} of {} patches",
+        failed,
+        from.len()
+    );
     Ok(())
 }
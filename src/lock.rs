@@ -0,0 +1,98 @@
+//! Keeping two processes from changing the local store at the same time
+//!
+//! Commands that change the local store (e.g. `install` or `sync`) hold an
+//! advisory lock on [`FILE_NAME`] in it while they run, so a cron job can't
+//! race a manual `install` on the `current` symlink. Read-only commands like
+//! `list` don't take it. The lock is released when the process ends, however
+//! it ends, so a crashed run never leaves the store locked.
+
+use crate::ErrorKind;
+use erreur::{Context, Help, Result};
+use fs2::FileExt;
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+/// Name of the lock file in the local store
+pub const FILE_NAME: &str = ".artefacta.lock";
+
+/// Exclusive lock on a local store, released when dropped
+#[derive(Debug)]
+pub struct StoreLock {
+    path: PathBuf,
+    file: File,
+}
+
+impl StoreLock {
+    /// Lock the local store at `dir`, failing right away if another process
+    /// holds the lock
+    pub fn acquire(dir: &Path) -> Result<Self> {
+        let path = dir.join(FILE_NAME);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            // it might be locked (and tell by whom)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("open lock file `{}`", path.display()))?;
+
+        if let Err(e) = file.try_lock_exclusive() {
+            if e.kind() != fs2::lock_contended_error().kind() {
+                return Err(e).with_context(|| format!("lock `{}`", path.display()));
+            }
+            let mut holder = String::new();
+            let _ = file.read_to_string(&mut holder);
+            let holder = match holder.trim() {
+                "" => String::from("another artefacta process"),
+                pid => format!("another artefacta process (PID {})", pid),
+            };
+            return Err(ErrorKind::Busy.error(format!(
+                "local store `{}` is in use by {}",
+                dir.display(),
+                holder
+            )))
+            .suggestion("Wait for it to finish and try again");
+        }
+
+        // only for the error message above, the lock itself is what counts
+        file.set_len(0)
+            .and_then(|_| file.seek(SeekFrom::Start(0)))
+            .and_then(|_| writeln!(file, "{}", std::process::id()))
+            .with_context(|| format!("write lock file `{}`", path.display()))?;
+        log::debug!("locked `{}`", path.display());
+        Ok(StoreLock { path, file })
+    }
+}
+
+impl Drop for StoreLock {
+    fn drop(&mut self) {
+        // the file stays, as removing it would race with the next process
+        // locking it
+        let _ = self.file.set_len(0);
+        if let Err(e) = FileExt::unlock(&self.file) {
+            log::debug!("could not unlock `{}`: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[test]
+fn second_lock_is_rejected() {
+    let dir = tempfile::tempdir().unwrap();
+
+    let lock = StoreLock::acquire(dir.path()).unwrap();
+    let error = StoreLock::acquire(dir.path()).unwrap_err();
+    assert_eq!(ErrorKind::of(&error), Some(ErrorKind::Busy));
+    assert!(
+        error
+            .to_string()
+            .contains(&format!("(PID {})", std::process::id())),
+        "{}",
+        error
+    );
+
+    drop(lock);
+    StoreLock::acquire(dir.path()).unwrap();
+}
@@ -1,60 +1,379 @@
 use artefacta::{
-    cli::{Cli, Command},
-    ArtefactIndex,
+    cli::{self, Cli, Command, LogFormat},
+    config::Config,
+    lock::StoreLock,
+    metrics::Metrics,
+    output::CreatePatchOutput,
+    storage::EncryptionKey,
+    ArtefactIndex, ErrorKind, InstallOptions, SigningKey, VerifyingKey,
 };
 use erreur::{Context, Help, Result};
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 use structopt::StructOpt;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    erreur::install_panic_handler()?;
+    let no_color = cli::no_color(std::env::args_os());
+    if no_color {
+        erreur::install_panic_handler_without_colors()?;
+    } else {
+        erreur::install_panic_handler()?;
+    }
 
-    let args = Cli::from_args();
-    setup_logging(args.verbose);
+    if let Some(config) = Config::load(cli::config_path(std::env::args_os()).as_deref())? {
+        config.apply_as_defaults();
+    }
+
+    let args = match Cli::from_iter_safe(std::env::args_os()) {
+        Ok(args) => args,
+        Err(e) if e.use_stderr() => {
+            eprintln!("{}", e.message);
+            std::process::exit(ErrorKind::Usage.exit_code());
+        }
+        // `--help` and `--version`
+        Err(e) => e.exit(),
+    };
+    setup_logging(&args, no_color)?;
+    artefacta::progress::set_enabled(!args.quiet && atty::is(atty::Stream::Stderr));
+    artefacta::storage::set_max_bandwidth(args.max_bandwidth);
+    if let Err(e) = artefacta::paths::set_naming(args.naming()) {
+        return exit_with_kind(Err(ErrorKind::Usage.wrap(e, "invalid file naming")));
+    }
+    if let Some(Err(e)) = args.cmd.link_name().map(cli::LinkName::check) {
+        return exit_with_kind(Err(ErrorKind::Usage.error(e)));
+    }
+    artefacta::interrupt::handle_interrupts();
 
     log::debug!("{:?}", args);
-    let mut index = ArtefactIndex::new(&args.local_store, args.remote_store.clone())
-        .await
+    if args.dry_run
+        && matches!(
+            args.cmd,
+            Command::Add { .. }
+                | Command::AddPackage { .. }
+                | Command::AutoPatch { .. }
+                | Command::AutoPatchLocal { .. }
+                | Command::EstimatePatch { .. }
+                | Command::Rollback { .. }
+                | Command::GenerateSigningKey { .. }
+        )
+    {
+        return exit_with_kind(Err(
+            ErrorKind::Usage.error("`--dry-run` is not supported by this command")
+        ));
+    }
+
+    let metrics = args
+        .metrics_file
+        .as_ref()
+        .map(|_| Arc::new(Metrics::default()));
+    let metrics_file = args.metrics_file.clone();
+    let timings = args.timings;
+    let result = run(args, metrics.clone()).await;
+
+    if timings {
+        if let Some(summary) = artefacta::timings::summary() {
+            eprint!("timings:\n{}", summary);
+        }
+    }
+
+    if let (Some(path), Some(metrics)) = (metrics_file, metrics) {
+        let written = metrics
+            .write_textfile(&path, result.is_ok())
+            .context("write metrics");
+        if result.is_ok() {
+            written?;
+        } else if let Err(e) = written {
+            log::warn!("{:#}", e);
+        }
+    }
+    exit_with_kind(result)
+}
+
+/// Exit with the code for the kind of failure (see [`ErrorKind`]) if we know
+/// it, otherwise return the result (which exits with 1 on errors)
+fn exit_with_kind(result: Result<()>) -> Result<()> {
+    if let Err(e) = &result {
+        if let Some(kind) = ErrorKind::of(e) {
+            eprintln!("Error: {:?}", e);
+            std::process::exit(kind.exit_code());
+        }
+    }
+    result
+}
+
+/// Open the index and run the command
+async fn run(args: Cli, metrics: Option<Arc<Metrics>>) -> Result<()> {
+    // a missing local store is reported when opening the index
+    let _lock = if args.cmd.changes_local_store() && !args.dry_run && args.local_store.is_dir() {
+        Some(StoreLock::acquire(&args.local_store)?)
+    } else {
+        None
+    };
+    let remote_store = match &args.encryption_key_file {
+        Some(path) => args
+            .remote_store
+            .clone()
+            .encrypted(EncryptionKey::read(path).context("read encryption key")?),
+        None => args.remote_store.clone(),
+    };
+    let index = if args.cache_max_age > 0 {
+        let max_age = if args.refresh {
+            Duration::ZERO
+        } else {
+            Duration::from_secs(args.cache_max_age)
+        };
+        ArtefactIndex::new_cached(&args.local_store, remote_store, max_age).await
+    } else {
+        ArtefactIndex::new(&args.local_store, remote_store).await
+    };
+    let mut index = index
         .context("open artifact store")
         .note("Always use absolute paths. This is serious business, there is no room for doubt.")?;
+    index.set_checksum_algorithm(args.checksum);
+    index.set_diff_window_size(args.diff_window * 1024 * 1024);
+    index.set_diff_mmap(args.diff_mmap)?;
+    index
+        .set_upload_concurrency(args.concurrency)
+        .context("invalid `--concurrency`")?;
+    index.set_alias_duplicates(args.alias_duplicates);
+    index.set_prefer_remote(args.prefer_remote);
+    index.set_metrics(metrics);
+    if let Some(path) = &args.signing_key {
+        index.set_signing_key(Some(SigningKey::read(path).context("read signing key")?));
+    }
+    if let Some(path) = &args.verify_key {
+        index.set_verifying_key(Some(VerifyingKey::read(path).context("read verify key")?));
+    }
 
     match args.cmd {
         Command::Debug => {
             dbg!(index);
         }
-        Command::Sync => {
-            artefacta::sync(&index).await?;
+        Command::Status { name } => {
+            let current = name.path_in(&args.local_store);
+            args.output.print(&artefacta::status(&index, &current)?)?;
+        }
+        Command::Info { version, name } => {
+            let current = name.path_in(&args.local_store);
+            args.output
+                .print(&artefacta::info(&index, version, &current)?)?;
+        }
+        Command::Plan { version, name } => {
+            let current = name.path_in(&args.local_store);
+            args.output
+                .print(&artefacta::plan(&index, version, &current)?)?;
+        }
+        Command::Rollback { name } => {
+            let current = name.path_in(&args.local_store);
+            artefacta::rollback(&current)?;
+        }
+        Command::Verify {
+            version,
+            patches,
+            remote,
+        } => {
+            artefacta::verify(&index, version, patches, remote).await?;
+        }
+        Command::VerifyLocal { jobs } => {
+            let report = artefacta::verify_local(&index, jobs).await?;
+            args.output.print(&report)?;
+            if report.corrupt > 0 {
+                return Err(ErrorKind::Data.error(format!(
+                    "{} of {} local files are corrupt",
+                    report.corrupt,
+                    report.files.len()
+                )));
+            }
+        }
+        Command::List(options) => {
+            args.output
+                .print(&artefacta::list(&index, &options).await?)?;
+        }
+        Command::Doctor { repair } => {
+            artefacta::doctor(&mut index, repair && !args.dry_run).await?;
+        }
+        Command::GenerateSigningKey { path } => {
+            artefacta::generate_signing_key(&path)?;
         }
-        Command::Install { version } => {
-            let current = args.local_store.join("current");
-            artefacta::install(&mut index, version, &current).await?;
+        Command::Manifest { upload } => {
+            let manifest = artefacta::manifest(&index, upload && !args.dry_run).await?;
+            args.output.print(&manifest)?;
         }
-        Command::AddPackage { version, build } => {
-            artefacta::add_package(&mut index, version, build).await?;
+        Command::Sync(options) => {
+            args.output
+                .print(&artefacta::sync(&index, args.dry_run, &options).await?)?;
         }
-        Command::CreatePatch { from, to } => {
-            artefacta::create_patch(&mut index, from, to).await?;
+        Command::Pull { version, patches } => {
+            artefacta::pull(&mut index, version, patches, args.dry_run).await?;
+        }
+        Command::RemoveLocal { version, force } => {
+            let current = cli::LinkName::default().path_in(&args.local_store);
+            artefacta::remove_local(&mut index, version, &current, force).await?;
+        }
+        Command::Label {
+            version,
+            label,
+            remove,
+        } => {
+            artefacta::label(&mut index, version, &label, remove, args.dry_run).await?;
+        }
+        Command::Clean => {
+            artefacta::clean(&index, args.dry_run).await?;
+        }
+        Command::PrunePatches => {
+            artefacta::prune_patches(&mut index, args.dry_run).await?;
+        }
+        Command::Install {
+            version,
+            tag,
+            label,
+            prefix,
+            name,
+            max_patch_hops,
+            keep,
+            extract,
+            post_install,
+            require_signature,
+        } => {
+            let current = name.path_in(&args.local_store);
+            let version = match (version, tag, label) {
+                (Some(version), _, _) => version,
+                (None, Some(tag), _) => index.get_build_for_tag(&format!("{}{}", prefix, tag))?,
+                (None, None, Some(label)) => index.resolve_label(&label).await?,
+                (None, None, None) => {
+                    unreachable!("structopt requires a version, a tag, or a label")
+                }
+            };
+            let result = artefacta::install(
+                &mut index,
+                version,
+                &current,
+                InstallOptions {
+                    max_patch_hops,
+                    keep,
+                    extract,
+                    post_install,
+                    require_signature,
+                    dry_run: args.dry_run,
+                },
+            )
+            .await?;
+            args.output.print(&result)?;
+        }
+        Command::AddPackage {
+            version,
+            build,
+            options,
+        } => {
+            let result = artefacta::add_package(&mut index, version, build, options).await?;
+            args.output.print(&result)?;
+        }
+        Command::CreatePatch {
+            from,
+            to,
+            reverse,
+            diff,
+        } => {
+            diff.apply_to(&mut index)?;
+            let mut pairs = vec![(from.clone(), to.clone())];
+            if reverse {
+                pairs.push((to, from));
+            }
+
+            let mut output = CreatePatchOutput::default();
+            for (from, to) in pairs {
+                if args.dry_run {
+                    output
+                        .planned
+                        .push(artefacta::plan_patch(&index, from, to)?);
+                } else {
+                    output
+                        .patches
+                        .push(artefacta::create_patch(&mut index, from, to).await?);
+                }
+            }
+            args.output.print(&output)?;
+        }
+        Command::CreatePatchesChain { versions, diff } => {
+            diff.apply_to(&mut index)?;
+            let output =
+                artefacta::create_patches_chain(&mut index, versions, args.dry_run).await?;
+            args.output.print(&output)?;
+        }
+        Command::EstimatePatch { from, to } => {
+            args.output
+                .print(&artefacta::estimate_patch(&mut index, from, to).await?)?;
         }
         Command::AutoPatch {
             repo_root,
             current,
             prefix,
+            tag_pattern,
+            semver,
+            tag_order,
+            jobs,
         } => {
-            artefacta::auto_patch(&mut index, repo_root.as_ref(), current, &prefix).await?;
+            let format = match tag_pattern {
+                _ if semver => artefacta::git::TagFormat::Semver,
+                Some(pattern) => artefacta::git::TagFormat::Pattern(pattern),
+                None => artefacta::git::TagFormat::Heuristic,
+            };
+            artefacta::auto_patch(
+                &mut index,
+                repo_root.as_ref(),
+                current,
+                &prefix,
+                &format,
+                tag_order,
+                jobs,
+            )
+            .await?;
+        }
+        Command::AutoPatchLocal { to, count } => {
+            artefacta::auto_patch_local(&mut index, to, count).await?;
         }
-        Command::Add(build) => artefacta::add(&mut index, build).await?,
+        Command::Add {
+            path,
+            stdin,
+            from_url,
+            version,
+            options,
+        } => match (path, from_url, version) {
+            (Some(path), ..) => artefacta::add(&mut index, cli::AddBuild { path, options }).await?,
+            (None, Some(url), Some(version)) => {
+                artefacta::add_from_url(&mut index, &url, version, options).await?
+            }
+            (None, None, Some(version)) if stdin => {
+                artefacta::add_from_reader(&mut index, version, std::io::stdin().lock(), options)
+                    .await?
+            }
+            _ => unreachable!("structopt requires a path, or a version with --stdin or --from-url"),
+        },
     }
 
     Ok(())
 }
 
-fn setup_logging(verbose: bool) {
+fn setup_logging(args: &Cli, no_color: bool) -> Result<()> {
+    let format = args.log_format;
     let mut log = pretty_env_logger::formatted_timed_builder();
     log.target(env_logger::Target::Stderr);
+    if no_color {
+        log.write_style(env_logger::WriteStyle::Never);
+    }
+    if format == LogFormat::Json {
+        log.format(move |buf, record| writeln!(buf, "{}", log_line(format, record)));
+    }
 
-    if verbose {
+    if args.verbose {
         log.filter(None, log::LevelFilter::Info)
             .filter(Some("artefacta"), log::LevelFilter::Debug);
+    } else if args.quiet {
+        log.filter(None, log::LevelFilter::Warn);
     } else {
         log.filter(None, log::LevelFilter::Warn)
             .filter(Some("artefacta"), log::LevelFilter::Info);
@@ -64,5 +383,86 @@ fn setup_logging(verbose: bool) {
         log.parse_filters(&s);
     }
 
-    log.init();
+    let path = match &args.log_file {
+        Some(path) => path,
+        None => {
+            log.init();
+            return Ok(());
+        }
+    };
+    if args.rotate_log_file && path.exists() {
+        let mut rotated = path.clone().into_os_string();
+        rotated.push(".1");
+        std::fs::rename(path, &rotated)
+            .with_context(|| format!("rotate log file `{}`", path.display()))?;
+    }
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("open log file `{}`", path.display()))?;
+    let stderr = log.build();
+    log::set_max_level(stderr.filter());
+    log::set_boxed_logger(Box::new(TeeLogger {
+        stderr,
+        file: Mutex::new(file),
+        format,
+    }))
+    .context("set up logging")?;
+    Ok(())
+}
+
+/// Log line without colors, as written to log files (and to stderr with
+/// `--log-format json`)
+fn log_line(format: LogFormat, record: &log::Record) -> String {
+    let timestamp = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
+    match format {
+        LogFormat::Pretty => format!(
+            "{} {:<5} {} > {}",
+            timestamp,
+            record.level(),
+            record.target(),
+            record.args()
+        ),
+        LogFormat::Json => serde_json::json!({
+            "timestamp": timestamp,
+            "level": record.level().as_str(),
+            "target": record.target(),
+            "message": record.args().to_string(),
+        })
+        .to_string(),
+    }
+}
+
+/// Logs to stderr and, with the same filters, appends to a file
+struct TeeLogger {
+    stderr: env_logger::Logger,
+    file: Mutex<std::fs::File>,
+    format: LogFormat,
+}
+
+impl log::Log for TeeLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.stderr.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.stderr.matches(record) {
+            return;
+        }
+        self.stderr.log(record);
+        let mut file = self
+            .file
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        // nowhere to report this but stderr, which already got the line
+        let _ = writeln!(file, "{}", log_line(self.format, record));
+    }
+
+    fn flush(&self) {
+        self.stderr.flush();
+        if let Ok(mut file) = self.file.lock() {
+            let _ = file.flush();
+        }
+    }
 }
@@ -0,0 +1,222 @@
+//! Transferred bytes and timings, for Prometheus
+//!
+//! With `--metrics-file <path>`, the index records what a run transferred and
+//! how long its operations took. Afterwards, we write that to `<path>` in the
+//! text format the node exporter's textfile collector reads. Each run replaces
+//! the file, so all values describe the latest run.
+
+use crate::PartialFile;
+use erreur::{Context, Result};
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::Write,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Operations we keep timings of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Operation {
+    Push,
+    GetFile,
+    CalculatePatch,
+    UpgradeToBuild,
+}
+
+impl Operation {
+    fn label(self) -> &'static str {
+        match self {
+            Operation::Push => "push",
+            Operation::GetFile => "get_file",
+            Operation::CalculatePatch => "calculate_patch",
+            Operation::UpgradeToBuild => "upgrade_to_build",
+        }
+    }
+}
+
+/// Metrics of one run
+///
+/// Shared by the index and `main`, which writes them out at the end.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    values: Mutex<Values>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct Values {
+    uploaded_bytes: u64,
+    downloaded_bytes: u64,
+    patches_created: u64,
+    created_patch_bytes: u64,
+    patches_applied: u64,
+    /// Number and total duration of successful operations
+    operations: BTreeMap<Operation, (u64, Duration)>,
+}
+
+impl Metrics {
+    pub fn add_uploaded(&self, bytes: u64) {
+        self.update(|values| values.uploaded_bytes += bytes);
+    }
+
+    pub fn add_downloaded(&self, bytes: u64) {
+        self.update(|values| values.downloaded_bytes += bytes);
+    }
+
+    pub fn add_created_patch(&self, size: u64) {
+        self.update(|values| {
+            values.patches_created += 1;
+            values.created_patch_bytes += size;
+        });
+    }
+
+    pub fn add_applied_patches(&self, count: u64) {
+        self.update(|values| values.patches_applied += count);
+    }
+
+    pub fn add_operation(&self, operation: Operation, duration: Duration) {
+        self.update(|values| {
+            let (count, total) = values.operations.entry(operation).or_default();
+            *count += 1;
+            *total += duration;
+        });
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Values)) {
+        // values stay consistent even if another thread panicked
+        let mut values = self
+            .values
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        f(&mut values);
+    }
+
+    /// Metrics in Prometheus' text format, for a run that ended at `now`
+    pub fn render(&self, success: bool, now: SystemTime) -> String {
+        let values = self
+            .values
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone();
+        let mut out = String::new();
+        let mut gauge = |name: &str, help: &str, samples: &[(String, String)]| {
+            writeln!(out, "# HELP artefacta_{} {}", name, help).expect("writing to string");
+            writeln!(out, "# TYPE artefacta_{} gauge", name).expect("writing to string");
+            for (labels, value) in samples {
+                writeln!(out, "artefacta_{}{} {}", name, labels, value).expect("writing to string");
+            }
+        };
+        let single = |value: u64| [(String::new(), value.to_string())];
+
+        gauge(
+            "last_run_timestamp_seconds",
+            "When the last run ended.",
+            &[(
+                String::new(),
+                now.duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs()
+                    .to_string(),
+            )],
+        );
+        gauge(
+            "last_run_success",
+            "Whether the last run succeeded.",
+            &single(success as u64),
+        );
+        gauge(
+            "uploaded_bytes",
+            "Bytes uploaded to remote storage.",
+            &single(values.uploaded_bytes),
+        );
+        gauge(
+            "downloaded_bytes",
+            "Bytes downloaded from remote storage.",
+            &single(values.downloaded_bytes),
+        );
+        gauge(
+            "patches_created",
+            "Number of patches calculated.",
+            &single(values.patches_created),
+        );
+        gauge(
+            "created_patch_bytes",
+            "Total size of the calculated patches.",
+            &single(values.created_patch_bytes),
+        );
+        gauge(
+            "patches_applied",
+            "Number of patches applied to get to new builds.",
+            &single(values.patches_applied),
+        );
+
+        let label = |operation: &Operation| format!("{{operation=\"{}\"}}", operation.label());
+        let counts = values
+            .operations
+            .iter()
+            .map(|(operation, (count, _))| (label(operation), count.to_string()))
+            .collect::<Vec<_>>();
+        gauge("operations", "Number of successful operations.", &counts);
+        let durations = values
+            .operations
+            .iter()
+            .map(|(operation, (_, total))| (label(operation), total.as_secs_f64().to_string()))
+            .collect::<Vec<_>>();
+        gauge(
+            "operation_duration_seconds",
+            "Total duration of successful operations.",
+            &durations,
+        );
+
+        out
+    }
+
+    /// Replace the file at `path` with the metrics, see [`Metrics::render`]
+    pub fn write_textfile(&self, path: impl AsRef<Path>, success: bool) -> Result<()> {
+        let path = path.as_ref();
+        let mut file = PartialFile::create(path)
+            .with_context(|| format!("create metrics file `{}`", path.display()))?;
+        file.write_all(self.render(success, SystemTime::now()).as_bytes())
+            .with_context(|| format!("write metrics file `{}`", path.display()))?;
+        file.finish()
+            .with_context(|| format!("finish writing metrics file `{}`", path.display()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_textfile() {
+        let metrics = Metrics::default();
+        metrics.add_downloaded(100);
+        metrics.add_downloaded(20);
+        metrics.add_created_patch(7);
+        metrics.add_operation(Operation::GetFile, Duration::from_millis(500));
+        metrics.add_operation(Operation::GetFile, Duration::from_millis(250));
+        metrics.add_operation(Operation::Push, Duration::from_secs(2));
+
+        let text = metrics.render(true, UNIX_EPOCH + Duration::from_secs(1_600_000_000));
+        for line in [
+            "# TYPE artefacta_downloaded_bytes gauge",
+            "artefacta_last_run_timestamp_seconds 1600000000",
+            "artefacta_last_run_success 1",
+            "artefacta_downloaded_bytes 120",
+            "artefacta_uploaded_bytes 0",
+            "artefacta_created_patch_bytes 7",
+            "artefacta_operations{operation=\"get_file\"} 2",
+            "artefacta_operation_duration_seconds{operation=\"get_file\"} 0.75",
+            "artefacta_operation_duration_seconds{operation=\"push\"} 2",
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "missing `{}` in:\n{}",
+                line,
+                text
+            );
+        }
+    }
+}
@@ -0,0 +1,79 @@
+//! Read-only memory maps of files
+//!
+//! Only used with the `mmap` feature on unix, where we can call `mmap(2)`
+//! directly instead of pulling in another dependency.
+
+use std::{fs::File, io, ops::Deref, os::unix::io::AsRawFd, ptr, slice};
+
+/// Content of a file, paged in by the OS as needed
+///
+/// The file must not be changed while it is mapped, so only map files nobody
+/// else knows about, like anonymous temp files.
+pub(crate) struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+// The mapping is read-only, so it can be shared like a `&[u8]`.
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    pub(crate) fn map(file: &File) -> io::Result<Self> {
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            // can't map nothing
+            return Ok(Mmap {
+                ptr: ptr::null_mut(),
+                len,
+            });
+        }
+
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mmap { ptr, len })
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        if self.ptr.is_null() {
+            &[]
+        } else {
+            unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) }
+        }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        if !self.ptr.is_null() && unsafe { libc::munmap(self.ptr, self.len) } != 0 {
+            log::debug!("munmap failed: {}", io::Error::last_os_error());
+        }
+    }
+}
+
+#[test]
+fn map_file_content() {
+    use std::io::Write;
+
+    let mut file = tempfile::tempfile().unwrap();
+    file.write_all(b"hello mmap").unwrap();
+    assert_eq!(&*Mmap::map(&file).unwrap(), b"hello mmap");
+
+    let empty = tempfile::tempfile().unwrap();
+    assert!(Mmap::map(&empty).unwrap().is_empty());
+}
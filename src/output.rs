@@ -0,0 +1,768 @@
+//! Results of commands, printed for humans or as JSON
+//!
+//! Only results go to stdout, logs always go to stderr. This way, the JSON
+//! output can be consumed by other tools no matter how verbose we are.
+
+use crate::{Checksum, Version};
+use erreur::{Context, Result, StdResult};
+use serde::{Deserialize, Serialize, Serializer};
+use std::{collections::BTreeMap, fmt, str::FromStr, time::SystemTime};
+
+/// How to print results of commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Human,
+    Json,
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        OutputFormat::Human
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> StdResult<Self, Self::Err> {
+        match s {
+            "human" => Ok(OutputFormat::Human),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("unknown output format `{}`", s)),
+        }
+    }
+}
+
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OutputFormat::Human => write!(f, "human"),
+            OutputFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+impl OutputFormat {
+    /// Print result of a command to stdout
+    pub fn print(self, output: &impl CommandOutput) -> Result<()> {
+        match self {
+            OutputFormat::Human => output.print_human(),
+            OutputFormat::Json => {
+                let json = serde_json::to_string(output).context("serialize output")?;
+                println!("{}", json);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Result of a command
+pub trait CommandOutput: Serialize {
+    /// Print result for humans
+    ///
+    /// Prints nothing by default, for commands where the logs say it all.
+    fn print_human(&self) {}
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    pub version: Version,
+    pub local: bool,
+    pub remote: bool,
+    pub size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<Checksum>,
+    /// When the build's file was last changed, in remote storage if it's
+    /// there and locally otherwise
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_time"
+    )]
+    pub modified: Option<SystemTime>,
+    /// Key-value pairs from the build's metadata (see `add --meta`)
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub meta: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PatchInfo {
+    pub from: Version,
+    pub to: Version,
+    pub local: bool,
+    pub remote: bool,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ListOutput {
+    pub builds: Vec<BuildInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub patches: Option<Vec<PatchInfo>>,
+}
+
+impl CommandOutput for ListOutput {
+    fn print_human(&self) {
+        fn yes_no(x: bool) -> String {
+            if x { "yes" } else { "no" }.to_string()
+        }
+
+        let builds = self
+            .builds
+            .iter()
+            .map(|build| {
+                [
+                    build.version.to_string(),
+                    yes_no(build.local),
+                    yes_no(build.remote),
+                    file_size(build.size),
+                    build
+                        .checksum
+                        .map_or_else(|| "-".to_string(), short_checksum),
+                    build.modified.map_or_else(|| "-".to_string(), format_time),
+                ]
+            })
+            .collect::<Vec<_>>();
+        let header = ["BUILD", "LOCAL", "REMOTE", "SIZE", "CHECKSUM", "MODIFIED"];
+        // only builds added with `--meta` have something to show here
+        if self.builds.iter().any(|build| !build.meta.is_empty()) {
+            let builds = builds
+                .into_iter()
+                .zip(&self.builds)
+                .map(
+                    |([version, local, remote, size, checksum, modified], build)| {
+                        [
+                            version,
+                            local,
+                            remote,
+                            size,
+                            checksum,
+                            modified,
+                            format_meta(&build.meta, ", "),
+                        ]
+                    },
+                )
+                .collect::<Vec<_>>();
+            let [a, b, c, d, e, f] = header;
+            print_table([a, b, c, d, e, f, "META"], &builds);
+        } else {
+            print_table(header, &builds);
+        }
+
+        if let Some(patches) = &self.patches {
+            let patches = patches
+                .iter()
+                .map(|patch| {
+                    [
+                        format!("{} -> {}", patch.from, patch.to),
+                        yes_no(patch.local),
+                        yes_no(patch.remote),
+                        file_size(patch.size),
+                    ]
+                })
+                .collect::<Vec<_>>();
+            println!();
+            print_table(["PATCH", "LOCAL", "REMOTE", "SIZE"], &patches);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum UpgradeMethod {
+    /// Target version was already installed
+    AlreadyInstalled,
+    /// Full build (downloaded if necessary)
+    Build,
+    /// Applied patches
+    Patches,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UpgradeInfo {
+    pub method: UpgradeMethod,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub patches: Vec<String>,
+    pub download_size: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusOutput {
+    pub installed: Option<Version>,
+    pub latest: Option<Version>,
+    /// How to upgrade to the latest version, if installed one is older
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upgrade: Option<UpgradeInfo>,
+}
+
+impl CommandOutput for StatusOutput {
+    fn print_human(&self) {
+        let installed = match &self.installed {
+            Some(version) => version,
+            None => {
+                println!("nothing installed");
+                return;
+            }
+        };
+        println!("installed: {}", installed);
+
+        match (&self.latest, &self.upgrade) {
+            (None, _) => println!("no builds in store"),
+            (Some(latest), Some(upgrade)) => {
+                let how = match upgrade.method {
+                    UpgradeMethod::Patches => format!("{} patches", upgrade.patches.len()),
+                    _ => "full build".to_string(),
+                };
+                println!(
+                    "newer build available: {} (upgrade downloads {} using {})",
+                    latest,
+                    file_size(upgrade.download_size),
+                    how
+                );
+            }
+            (Some(_), None) => println!("up to date"),
+        }
+    }
+}
+
+/// Everything known about one build
+#[derive(Debug, Clone, Serialize)]
+pub struct InfoOutput {
+    pub version: Version,
+    /// Size of the local file, if there is one
+    pub local_size: Option<u64>,
+    /// Size of the file in remote storage, if there is one
+    pub remote_size: Option<u64>,
+    /// When the local file was last changed
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_time"
+    )]
+    pub local_modified: Option<SystemTime>,
+    /// When the file in remote storage was last changed
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_time"
+    )]
+    pub remote_modified: Option<SystemTime>,
+    /// Build whose file is used in remote storage, for aliases
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote_alias_of: Option<Version>,
+    pub uncompressed_size: Option<u64>,
+    pub checksum: Option<Checksum>,
+    /// Key-value pairs from the build's metadata (see `add --meta`)
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub meta: BTreeMap<String, String>,
+    /// Patches to this build
+    pub patches_in: Vec<PatchInfo>,
+    /// Patches from this build
+    pub patches_out: Vec<PatchInfo>,
+    pub installed: Option<Version>,
+    /// How to get from the installed version to this build
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upgrade: Option<UpgradeInfo>,
+}
+
+impl CommandOutput for InfoOutput {
+    fn print_human(&self) {
+        fn stored(size: Option<u64>, modified: Option<SystemTime>) -> String {
+            match (size, modified) {
+                (None, _) => "no".to_string(),
+                (Some(size), None) => format!("yes ({})", file_size(size)),
+                (Some(size), Some(modified)) => format!(
+                    "yes ({}, modified {})",
+                    file_size(size),
+                    format_time(modified)
+                ),
+            }
+        }
+        fn patches(patches: &[PatchInfo]) -> String {
+            if patches.is_empty() {
+                return "none".to_string();
+            }
+            patches
+                .iter()
+                .map(|patch| {
+                    let mut location = Vec::new();
+                    if patch.local {
+                        location.push("local");
+                    }
+                    if patch.remote {
+                        location.push("remote");
+                    }
+                    format!(
+                        "{} -> {} ({}, {})",
+                        patch.from,
+                        patch.to,
+                        file_size(patch.size),
+                        location.join(" and ")
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("\n              ")
+        }
+
+        println!("build:        {}", self.version);
+        println!(
+            "local:        {}",
+            stored(self.local_size, self.local_modified)
+        );
+        match &self.remote_alias_of {
+            Some(target) => println!(
+                "remote:       {} (as `{}`)",
+                stored(self.remote_size, self.remote_modified),
+                target
+            ),
+            None => println!(
+                "remote:       {}",
+                stored(self.remote_size, self.remote_modified)
+            ),
+        }
+        if let Some(size) = self.uncompressed_size {
+            println!("uncompressed: {}", file_size(size));
+        }
+        if let Some(checksum) = &self.checksum {
+            println!("checksum:     {}", checksum);
+        }
+        if !self.meta.is_empty() {
+            println!(
+                "meta:         {}",
+                format_meta(&self.meta, "\n              ")
+            );
+        }
+        println!("patches in:   {}", patches(&self.patches_in));
+        println!("patches out:  {}", patches(&self.patches_out));
+
+        match (&self.installed, &self.upgrade) {
+            (None, _) => println!("installed:    nothing"),
+            (Some(installed), None) => println!("installed:    {} (this build)", installed),
+            (Some(installed), Some(upgrade)) => {
+                println!("installed:    {}", installed);
+                let how = match upgrade.method {
+                    UpgradeMethod::Patches => upgrade.patches.join(", "),
+                    _ => "full build".to_string(),
+                };
+                println!(
+                    "install:      downloads {} using {}",
+                    file_size(upgrade.download_size),
+                    how
+                );
+            }
+        }
+    }
+}
+
+/// What `install` would do, see [`crate::plan`]
+#[derive(Debug, Clone, Serialize)]
+pub struct PlanOutput {
+    /// Installed build (if any)
+    pub from: Option<Version>,
+    pub to: Version,
+    pub method: UpgradeMethod,
+    /// Patches to apply, in order
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub patches: Vec<PatchInfo>,
+    /// Size of the full build, when not applying patches
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build_size: Option<u64>,
+    /// Bytes to download from remote storage
+    pub download_size: u64,
+    /// Files to download from remote storage
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fetch: Vec<String>,
+}
+
+impl CommandOutput for PlanOutput {
+    fn print_human(&self) {
+        match (&self.from, &self.method) {
+            (_, UpgradeMethod::AlreadyInstalled) => {
+                println!("{} is already installed", self.to);
+                return;
+            }
+            (Some(from), UpgradeMethod::Patches) => println!(
+                "upgrade {} -> {} applying {} patches:",
+                from,
+                self.to,
+                self.patches.len()
+            ),
+            (Some(from), UpgradeMethod::Build) => {
+                println!("upgrade {} -> {} using full build", from, self.to)
+            }
+            (None, _) => println!("install {} using full build", self.to),
+        }
+        for patch in &self.patches {
+            let location = if patch.local { " (local)" } else { "" };
+            println!(
+                "  {} -> {}: {}{}",
+                patch.from,
+                patch.to,
+                file_size(patch.size),
+                location
+            );
+        }
+        if let Some(size) = self.build_size {
+            println!("  {}: {}", self.to, file_size(size));
+        }
+        println!("downloads {} in total", file_size(self.download_size));
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InstallOutput {
+    pub version: Version,
+    pub previous: Option<Version>,
+    pub method: UpgradeMethod,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub patches: Vec<String>,
+    /// Bytes actually downloaded from remote storage
+    pub downloaded: u64,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub dry_run: bool,
+    /// Files that would be downloaded (only set for dry runs)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fetch: Vec<String>,
+    /// Old local builds deleted afterwards (see `--keep`)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub removed: Vec<Version>,
+}
+
+impl CommandOutput for InstallOutput {
+    fn print_human(&self) {
+        if !self.dry_run || self.method == UpgradeMethod::AlreadyInstalled {
+            return;
+        }
+
+        let how = match self.method {
+            UpgradeMethod::Patches => format!("applying {}", self.patches.join(", ")),
+            _ => "using full build".to_string(),
+        };
+        println!("would install {} {}", self.version, how);
+        print_fetch(&self.fetch);
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CreatePatchOutput {
+    pub patches: Vec<PatchInfo>,
+    /// Patches that would be created (only set for dry runs)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub planned: Vec<PatchPlan>,
+}
+
+/// Work needed to create a patch
+#[derive(Debug, Clone, Serialize)]
+pub struct PatchPlan {
+    pub from: Version,
+    pub to: Version,
+    /// Patch is already there, nothing to do
+    pub exists: bool,
+    /// Builds that need to be downloaded first
+    pub fetch: Vec<String>,
+    /// Bytes of the new build to diff (uncompressed, if known)
+    pub diff_size: u64,
+}
+
+impl CommandOutput for CreatePatchOutput {
+    fn print_human(&self) {
+        for plan in &self.planned {
+            if plan.exists {
+                println!("patch {} -> {} already exists", plan.from, plan.to);
+                continue;
+            }
+            println!(
+                "would create patch {} -> {} (diffing {})",
+                plan.from,
+                plan.to,
+                file_size(plan.diff_size)
+            );
+            print_fetch(&plan.fetch);
+        }
+    }
+}
+
+/// Name of the manifest in remote storage
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// Version of the manifest format, bumped on incompatible changes
+pub const MANIFEST_SCHEMA_VERSION: u32 = 1;
+
+/// Builds and patches in remote storage, for clients that can't list it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub schema_version: u32,
+    pub builds: Vec<ManifestBuild>,
+    pub patches: Vec<ManifestPatch>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestBuild {
+    pub version: Version,
+    /// Name of the file in remote storage (that of another build for aliases)
+    pub file: String,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uncompressed_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<Checksum>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestPatch {
+    pub from: Version,
+    pub to: Version,
+    pub file: String,
+    pub size: u64,
+}
+
+impl CommandOutput for Manifest {
+    /// The manifest is JSON either way, but pretty-printed for humans
+    fn print_human(&self) {
+        match serde_json::to_string_pretty(self) {
+            Ok(json) => println!("{}", json),
+            Err(e) => log::error!("could not serialize manifest: {}", e),
+        }
+    }
+}
+
+/// Files `sync` uploaded (or would upload, for dry runs)
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncOutput {
+    pub files: Vec<SyncedFile>,
+    /// Bytes of all files together
+    pub total_size: u64,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncedFile {
+    pub name: String,
+    pub size: u64,
+}
+
+impl SyncOutput {
+    pub fn new(files: Vec<(String, u64)>, dry_run: bool) -> Self {
+        SyncOutput {
+            total_size: files.iter().map(|(_, size)| size).sum(),
+            files: files
+                .into_iter()
+                .map(|(name, size)| SyncedFile { name, size })
+                .collect(),
+            dry_run,
+        }
+    }
+}
+
+/// Uploads are logged, so this only lists them for dry runs
+impl CommandOutput for SyncOutput {
+    fn print_human(&self) {
+        if !self.dry_run {
+            return;
+        }
+        if self.files.is_empty() {
+            println!("nothing to upload");
+            return;
+        }
+        for file in &self.files {
+            println!("{} ({})", file.name, file_size(file.size));
+        }
+        println!(
+            "would upload {} in {} files",
+            file_size(self.total_size),
+            self.files.len()
+        );
+    }
+}
+
+/// How well a new build compressed, see `add-package`
+#[derive(Debug, Clone, Serialize)]
+pub struct PackageOutput {
+    pub version: Version,
+    /// Size of the tar archive before compressing it
+    pub uncompressed_size: u64,
+    pub compressed_size: u64,
+    /// Uncompressed size divided by compressed size
+    pub ratio: f64,
+}
+
+impl PackageOutput {
+    pub fn new(version: Version, uncompressed_size: u64, compressed_size: u64) -> Self {
+        let ratio = if compressed_size == 0 {
+            0.0
+        } else {
+            uncompressed_size as f64 / compressed_size as f64
+        };
+        PackageOutput {
+            version,
+            uncompressed_size,
+            compressed_size,
+            ratio,
+        }
+    }
+}
+
+/// The ratio is logged, nothing else to say
+impl CommandOutput for PackageOutput {}
+
+/// Likely size of a patch, compared to downloading the new build
+#[derive(Debug, Clone, Serialize)]
+pub struct PatchEstimate {
+    pub from: Version,
+    pub to: Version,
+    /// Size of the new build (compressed)
+    pub build_size: u64,
+    /// Size of the new build (decompressed)
+    pub uncompressed_size: u64,
+    /// Bytes of the decompressed new build not found in the old one
+    pub unmatched_size: u64,
+    pub estimated_size: u64,
+    /// Bytes saved by downloading the patch instead of the build
+    pub savings: u64,
+}
+
+impl CommandOutput for PatchEstimate {
+    fn print_human(&self) {
+        let percent = |part: u64, whole: u64| {
+            if whole == 0 {
+                0.0
+            } else {
+                part as f64 / whole as f64 * 100.0
+            }
+        };
+        println!(
+            "patch {} -> {} would be about {} ({:.1}% of the new build's {}), saving {} per download",
+            self.from,
+            self.to,
+            file_size(self.estimated_size),
+            percent(self.estimated_size, self.build_size),
+            file_size(self.build_size),
+            file_size(self.savings)
+        );
+        println!(
+            "{:.1}% of the new build's content is also in the old one",
+            100.0 - percent(self.unmatched_size, self.uncompressed_size)
+        );
+    }
+}
+
+/// Local files `verify-local` checked
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifyLocalOutput {
+    pub files: Vec<VerifiedFile>,
+    pub ok: usize,
+    pub corrupt: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifiedFile {
+    pub name: String,
+    pub size: u64,
+    /// Size after decompressing (unknown for corrupt files)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uncompressed_size: Option<u64>,
+    /// Whether there was a checksum to compare with (otherwise, it was only
+    /// decompressed)
+    pub checksum_checked: bool,
+    /// What's wrong with the file, if it's corrupt
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl VerifyLocalOutput {
+    pub fn new(mut files: Vec<VerifiedFile>) -> Self {
+        files.sort_by(|a, b| a.name.cmp(&b.name));
+        let corrupt = files.iter().filter(|file| file.error.is_some()).count();
+        VerifyLocalOutput {
+            ok: files.len() - corrupt,
+            corrupt,
+            files,
+        }
+    }
+}
+
+impl CommandOutput for VerifyLocalOutput {
+    fn print_human(&self) {
+        for file in &self.files {
+            match (&file.error, file.checksum_checked) {
+                (Some(error), _) => println!("CORRUPT  {}: {}", file.name, error),
+                (None, true) => println!("ok       {}", file.name),
+                (None, false) => println!("ok       {} (no checksum)", file.name),
+            }
+        }
+        println!("{} ok, {} corrupt", self.ok, self.corrupt);
+    }
+}
+
+fn print_fetch(files: &[String]) {
+    if files.is_empty() {
+        println!("  nothing to download");
+    }
+    for file in files {
+        println!("  download {}", file);
+    }
+}
+
+pub(crate) fn file_size(size: u64) -> String {
+    use humansize::{file_size_opts as options, FileSize};
+    size.file_size(options::BINARY).expect("never negative")
+}
+
+/// `key=value` pairs of metadata, joined with `separator`
+fn format_meta(meta: &BTreeMap<String, String>, separator: &str) -> String {
+    if meta.is_empty() {
+        return "-".to_string();
+    }
+    meta.iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+/// Checksum shortened to the first 12 digits, like git does with commits
+fn short_checksum(checksum: Checksum) -> String {
+    let checksum = checksum.to_string();
+    let end = checksum.find(':').map_or(checksum.len(), |idx| idx + 13);
+    checksum[..end.min(checksum.len())].to_string()
+}
+
+/// Time in UTC, to the minute
+fn format_time(time: SystemTime) -> String {
+    chrono::DateTime::<chrono::Utc>::from(time)
+        .format("%Y-%m-%d %H:%M")
+        .to_string()
+}
+
+/// Times as RFC 3339 in JSON, e.g. `2022-05-30T12:00:00Z`
+fn serialize_time<S: Serializer>(
+    time: &Option<SystemTime>,
+    serializer: S,
+) -> StdResult<S::Ok, S::Error> {
+    match time {
+        Some(time) => serializer.serialize_str(
+            &chrono::DateTime::<chrono::Utc>::from(*time)
+                .to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        ),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn print_table<const N: usize>(header: [&str; N], rows: &[[String; N]]) {
+    let mut widths = header.map(str::len);
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let print_row = |cells: &mut dyn Iterator<Item = &str>| {
+        let line = cells
+            .zip(widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    };
+    print_row(&mut header.iter().copied());
+    for row in rows {
+        print_row(&mut row.iter().map(String::as_str));
+    }
+}
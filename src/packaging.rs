@@ -1,60 +1,397 @@
 //! Package build using `tar` in the most deterministic way possible.
 
-use erreur::{Context, Result};
+use erreur::{bail, ensure, Context, Help, Report, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
 use std::{
     fs,
-    io::{BufReader, Write},
-    path::Path,
+    io::{BufRead, BufReader, Read, Write},
+    path::{Component, Path, PathBuf},
 };
-use walkdir::WalkDir;
+use walkdir::{DirEntry, WalkDir};
+
+/// Name of the file (in the source directory) listing paths to exclude
+pub const IGNORE_FILE: &str = ".artefactaignore";
+
+/// How to handle symlinks in the source directory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Symlinks {
+    /// Add symlinks as symlinks
+    Preserve,
+    /// Add the files (and directories) symlinks point to
+    Follow,
+}
+
+impl Default for Symlinks {
+    fn default() -> Self {
+        Symlinks::Preserve
+    }
+}
+
+/// What to add to the archive
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    pub symlinks: Symlinks,
+    /// Patterns (in gitignore syntax) of paths to leave out, in addition to
+    /// the ones listed in the source's [`IGNORE_FILE`]
+    pub exclude: Vec<String>,
+    /// Add paths that are not valid UTF-8 (with a warning) instead of failing
+    pub allow_non_utf8_paths: bool,
+}
+
+/// File or directory to add to an archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Source {
+    pub path: PathBuf,
+    /// Directory in the archive to put the content in (instead of the root)
+    pub prefix: Option<PathBuf>,
+}
 
-pub fn package(source: &Path, target: impl Write) -> Result<()> {
-    let mut archive = tar::Builder::new(target);
+impl Source {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Source {
+            path: path.into(),
+            prefix: None,
+        }
+    }
+
+    pub fn with_prefix(path: impl Into<PathBuf>, prefix: impl Into<PathBuf>) -> Self {
+        Source {
+            path: path.into(),
+            prefix: Some(prefix.into()),
+        }
+    }
+}
+
+/// Write `source` as tar archive to `target`
+///
+/// Returns the size of the (uncompressed) tar archive in bytes.
+pub fn package(source: &Path, target: impl Write) -> Result<u64> {
+    package_with(&[Source::new(source)], target, &Settings::default())
+}
+
+/// Like [`package`] but combining multiple sources with explicit [`Settings`]
+///
+/// Entries from all sources are added in order of their path in the archive,
+/// so the result does not depend on the order of `sources`.
+pub fn package_with(sources: &[Source], target: impl Write, settings: &Settings) -> Result<u64> {
+    let mut archive = tar::Builder::new(CountingWriter::new(target));
     archive.mode(tar::HeaderMode::Deterministic);
-    log::debug!("writing files from `{}` to archive", source.display());
 
-    let root = if source.is_file() {
+    let mut entries = Vec::new();
+    for source in sources {
+        log::debug!("writing files from `{}` to archive", source.path.display());
+        entries.extend(
+            source_entries(source, settings)
+                .with_context(|| format!("read content of `{}`", source.path.display()))?,
+        );
+    }
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    if let Some(duplicate) = entries.windows(2).find(|x| x[0].0 == x[1].0) {
+        bail!(
+            "`{}` and `{}` would both end up as `{}` in the archive",
+            duplicate[0].1.path().display(),
+            duplicate[1].1.path().display(),
+            duplicate[0].0.display(),
+        );
+    }
+
+    for (path, file) in entries {
+        if file.file_type().is_dir() {
+            add_empty_dir(&mut archive, &file, &path)
+                .with_context(|| format!("add directory `{}` to archive", file.path().display()))?;
+        } else if file.file_type().is_file() {
+            add_file(&mut archive, &file, &path)
+                .with_context(|| format!("add `{}` to archive", file.path().display()))?;
+        } else if file.file_type().is_symlink() {
+            add_symlink(&mut archive, &file, &path)
+                .with_context(|| format!("add symlink `{}` to archive", file.path().display()))?;
+        } else {
+            log::warn!(
+                "skipping `{}` which is neither file, directory, nor symlink",
+                file.path().display()
+            );
+        }
+    }
+
+    let target = archive.into_inner().context("writing tar")?;
+
+    Ok(target.written)
+}
+
+/// Unpack an (uncompressed) archive created by [`package_with`] into `target`
+///
+/// Permissions are kept, but as archives don't have meaningful modification
+/// times, files get the current time instead.
+pub fn unpack(archive: impl Read, target: &Path) -> Result<()> {
+    let mut archive = tar::Archive::new(archive);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_mtime(false);
+    archive
+        .unpack(target)
+        .with_context(|| format!("unpack archive into `{}`", target.display()))
+}
+
+/// Make sure a compressed archive decompresses and is a complete tar archive,
+/// returning the number of entries
+///
+/// Reads all of it, as truncated archives only fail at the end. Plain tar
+/// archives are fine, too, if `name`d like one (see [`crate::build_reader`]).
+pub fn verify_archive(name: impl AsRef<Path>, compressed: impl BufRead) -> Result<usize> {
+    let content = crate::build_reader(name, compressed).context("start decompressing")?;
+    let mut archive = tar::Archive::new(content);
+    let mut count = 0;
+    for entry in archive.entries().context("read archive")? {
+        let mut entry = entry.with_context(|| format!("read entry {} of archive", count + 1))?;
+        let path = entry.path().map(|path| path.display().to_string());
+        std::io::copy(&mut entry, &mut std::io::sink()).with_context(|| {
+            format!(
+                "read `{}` in archive",
+                path.unwrap_or_else(|_| "?".to_string())
+            )
+        })?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Sum of the sizes of all files that [`package_with`] would add
+pub fn source_size(sources: &[Source], settings: &Settings) -> Result<u64> {
+    let mut size = 0;
+    for source in sources {
+        for (_, file) in source_entries(source, settings)? {
+            if file.file_type().is_file() {
+                size += file.metadata().context("read metadata")?.len();
+            }
+        }
+    }
+    Ok(size)
+}
+
+/// Entries of `source` that need to be added to the archive, together with
+/// their path in the archive
+fn source_entries(source: &Source, settings: &Settings) -> Result<Vec<(PathBuf, DirEntry)>> {
+    let root = if source.path.is_file() {
         source
+            .path
             .parent()
-            .with_context(|| format!("can't find parent of `{}`", source.display()))?
+            .with_context(|| format!("can't find parent of `{}`", source.path.display()))?
     } else {
-        source
+        &source.path
     };
+    if let Some(prefix) = &source.prefix {
+        ensure!(
+            prefix
+                .components()
+                .all(|c| matches!(c, Component::Normal(_))),
+            "prefix `{}` must be a relative path without `..`",
+            prefix.display()
+        );
+    }
+
+    let ignored = ignore_rules(&source.path, settings).context("read ignore rules")?;
+
+    let mut entries = Vec::new();
+    for file in walk(&source.path, settings, &ignored) {
+        let file = checked(file)?;
+        let relative = file.path().strip_prefix(root).context("root path prefix")?;
+        let path = match &source.prefix {
+            Some(prefix) => prefix.join(relative),
+            None => relative.to_path_buf(),
+        };
+        check_path(&path, settings)?;
+
+        // Directories with content are implicitly created when unpacking
+        // that content, only empty ones need their own entry
+        if file.file_type().is_dir()
+            && (path.as_os_str().is_empty() || !is_empty_dir(file.path(), &ignored)?)
+        {
+            log::trace!("skipping directory entry in tar");
+            continue;
+        }
+
+        entries.push((path, file));
+    }
+    Ok(entries)
+}
 
-    let entries = WalkDir::new(source)
+/// All entries below `source` that are not ignored, in a deterministic order
+fn walk<'a>(
+    source: &Path,
+    settings: &Settings,
+    ignored: &'a Gitignore,
+) -> impl Iterator<Item = walkdir::Result<DirEntry>> + 'a {
+    WalkDir::new(source)
+        .follow_links(settings.symlinks == Symlinks::Follow)
         .sort_by(|a, b| a.path().cmp(b.path()))
-        .into_iter();
+        .into_iter()
+        .filter_entry(move |entry| entry.depth() == 0 || !is_ignored(ignored, entry))
+}
 
-    for file in entries {
-        let file = file.context("read file")?;
-        if file.file_type().is_dir() {
-            log::trace!("skipping directory entry in tar");
-        } else if file.file_type().is_file() {
-            add_file(&mut archive, &file, root)
-                .with_context(|| format!("add `{}` to archive", file.path().display()))?;
+/// Unwrap walked entry, with a helpful message in case of symlink cycles
+///
+/// Those can only happen when following symlinks, and `WalkDir` detects them
+/// for us.
+fn checked(entry: walkdir::Result<DirEntry>) -> Result<DirEntry> {
+    match entry {
+        Err(e) if e.loop_ancestor().is_some() => {
+            let link = e
+                .path()
+                .unwrap_or_else(|| Path::new("?"))
+                .display()
+                .to_string();
+            Err(e)
+                .with_context(|| format!("symlink `{}` points at a directory containing it", link))
+                .suggestion("remove the symlink or don't follow symlinks")
         }
+        entry => entry.context("read file"),
     }
+}
+
+fn is_ignored(ignored: &Gitignore, entry: &DirEntry) -> bool {
+    let is_ignored = ignored
+        .matched(entry.path(), entry.file_type().is_dir())
+        .is_ignore();
+    if is_ignored {
+        log::trace!("ignoring `{}`", entry.path().display());
+    }
+    is_ignored
+}
 
-    archive.finish().context("writing tar")?;
+/// Rules from the [`IGNORE_FILE`] in `source` and the explicitly excluded
+/// patterns, relative to `source`
+///
+/// The ignore file itself is never added to the archive.
+fn ignore_rules(source: &Path, settings: &Settings) -> Result<Gitignore> {
+    let mut rules = GitignoreBuilder::new(source);
+    if source.is_dir() {
+        let ignore_file = source.join(IGNORE_FILE);
+        if ignore_file.is_file() {
+            log::debug!("using ignore rules from `{}`", ignore_file.display());
+            if let Some(e) = rules.add(&ignore_file) {
+                return Err(e)
+                    .with_context(|| format!("read ignore file `{}`", ignore_file.display()));
+            }
+            rules
+                .add_line(None, &format!("/{}", IGNORE_FILE))
+                .context("ignore the ignore file")?;
+        }
+    }
+    for pattern in &settings.exclude {
+        rules
+            .add_line(None, pattern)
+            .with_context(|| format!("invalid exclude pattern `{}`", pattern))?;
+    }
+    rules.build().context("build ignore rules")
+}
 
+fn check_path(path: &Path, settings: &Settings) -> Result<()> {
+    if path.to_str().is_some() {
+        return Ok(());
+    }
+    if !settings.allow_non_utf8_paths {
+        return Err(Report::msg(format!(
+            "path `{}` is not valid UTF-8",
+            path.display()
+        )))
+        .suggestion("rename it, or use `--allow-non-utf8-paths` if you really need it");
+    }
+    log::warn!(
+        "adding path `{}` to archive which is not UTF-8. \
+        This will most likely break somewhere down the line \
+        without us noticing until it's much too late.",
+        path.display()
+    );
     Ok(())
 }
 
+/// Whether a directory will end up without any content in the archive
+fn is_empty_dir(path: &Path, ignored: &Gitignore) -> Result<bool> {
+    for entry in WalkDir::new(path).min_depth(1).max_depth(1) {
+        let entry = entry.with_context(|| format!("read directory `{}`", path.display()))?;
+        if !is_ignored(ignored, &entry) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn add_empty_dir<W: Write>(
+    archive: &mut tar::Builder<W>,
+    dir: &walkdir::DirEntry,
+    path: &Path,
+) -> Result<()> {
+    let mut header = deterministic_header(tar::EntryType::Directory)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let metadata = dir.metadata().context("read metadata")?;
+        header.set_mode(metadata.permissions().mode())
+    }
+    #[cfg(not(unix))]
+    {
+        header.set_mode(0o40755)
+    }
+
+    archive
+        .append_data(&mut header, path, std::io::empty())
+        .context("append directory")?;
+    Ok(())
+}
+
+fn add_symlink<W: Write>(
+    archive: &mut tar::Builder<W>,
+    link: &walkdir::DirEntry,
+    path: &Path,
+) -> Result<()> {
+    let target = fs::read_link(link.path()).context("read symlink target")?;
+    log::trace!(
+        "adding symlink `{}` -> `{}`",
+        path.display(),
+        target.display()
+    );
+
+    let mut header = deterministic_header(tar::EntryType::Symlink)?;
+    header.set_mode(0o777);
+
+    // Note: This sets both path and link target (with GNU extensions for long
+    // names) and appends the entry to the archive.
+    archive
+        .append_link(&mut header, path, &target)
+        .context("append symlink")?;
+    Ok(())
+}
+
+/// Header with everything that depends on who packages the build when set to
+/// fixed values
+///
+/// `HeaderMode::Deterministic` only applies to headers filled from file
+/// metadata, and we build ours by hand, so we take care of this ourselves:
+/// Modification time is the Unix epoch, owner is user and group 0 without
+/// names, and device numbers are 0. Only size and mode are left to be set.
+fn deterministic_header(entry_type: tar::EntryType) -> Result<tar::Header> {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(entry_type);
+    header.set_size(0);
+    header.set_mtime(0);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_username("").context("set user name header")?;
+    header.set_groupname("").context("set group name header")?;
+    header
+        .set_device_major(0)
+        .context("set device major header")?;
+    header
+        .set_device_minor(0)
+        .context("set device minor header")?;
+    Ok(header)
+}
+
 fn add_file<W: Write>(
     archive: &mut tar::Builder<W>,
     file: &walkdir::DirEntry,
-    root: &Path,
+    path: &Path,
 ) -> Result<()> {
-    let path = file.path().strip_prefix(root).context("root path prefix")?;
-    let is_sane_path = path.to_str().is_some();
-    if !is_sane_path {
-        log::warn!(
-            "adding path `{}` to archive which is not UTF-8. \
-            This will most likely break somewhere down the line \
-            without us noticing until it's much too late.",
-            path.display()
-        );
-    }
     let metadata = file.metadata().context("read metadata")?;
 
     // Welcome to this new tar file entry.
@@ -62,7 +399,7 @@ fn add_file<W: Write>(
     // We set the size, POSIX permission flags, and some defaults ourselves but
     // the call to `append_data` all the way down there will set the path with
     // the nice GNU extensions to handle long paths.
-    let mut header = tar::Header::new_gnu();
+    let mut header = deterministic_header(tar::EntryType::Regular)?;
     header.set_size(metadata.len());
 
     #[cfg(unix)]
@@ -76,14 +413,6 @@ fn add_file<W: Write>(
         header.set_mode(0o100755)
     }
 
-    header.set_cksum();
-    header
-        .set_device_major(0)
-        .context("set device major header")?;
-    header
-        .set_device_minor(0)
-        .context("set device minor header")?;
-
     let file = BufReader::new(fs::File::open(file.path()).context("open file")?);
 
     // Note: This also sets the file path in the header, and then appends header
@@ -94,6 +423,30 @@ fn add_file<W: Write>(
     Ok(())
 }
 
+/// Keeps track of how many bytes went through it
+struct CountingWriter<W> {
+    inner: W,
+    written: u64,
+}
+
+impl<W: Write> CountingWriter<W> {
+    fn new(inner: W) -> Self {
+        CountingWriter { inner, written: 0 }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -110,7 +463,7 @@ mod tests {
         let binary = tmp.child("do-the-work.sh");
         binary.write_str("#! /bin/sh\necho 'Done!'").unwrap();
 
-        let mut output = compress(fs::File::create(&archive.path()).unwrap()).unwrap();
+        let mut output = compress(fs::File::create(archive.path()).unwrap()).unwrap();
         package(binary.path(), &mut output).expect("package");
         output.finish().unwrap();
 
@@ -125,6 +478,64 @@ mod tests {
             .assert(predicate::path::is_file());
     }
 
+    #[test]
+    fn verify_complete_archives_only() {
+        let tmp = tempdir().unwrap();
+        tmp.child("src/main.rs").write_str("fn main() {}").unwrap();
+        tmp.child("src/asset.bin")
+            .write_binary(&random_bytes(100_000).unwrap())
+            .unwrap();
+
+        let mut output = compress(Vec::new()).unwrap();
+        package(&tmp.path().join("src"), &mut output).expect("package");
+        let archive = output.finish().unwrap();
+        assert_eq!(verify_archive("build.tar.zst", &archive[..]).unwrap(), 2);
+
+        let truncated = &archive[..archive.len() / 2];
+        assert!(verify_archive("build.tar.zst", truncated).is_err());
+        let not_tar = zstd::stream::encode_all(&random_bytes(10_000).unwrap()[..], 3).unwrap();
+        assert!(verify_archive("build.tar.zst", &not_tar[..]).is_err());
+        assert!(verify_archive("build.tar.zst", &b"not even zstd"[..]).is_err());
+    }
+
+    #[test]
+    fn package_reports_tar_size() {
+        let tmp = tempdir().unwrap();
+        tmp.child("src/main.rs").write_str("fn main() {}").unwrap();
+
+        let mut output = Vec::new();
+        let size = package(&tmp.path().join("src"), &mut output).expect("package");
+        assert_eq!(size, output.len() as u64);
+    }
+
+    #[test]
+    fn archive_with_multiple_compression_threads() {
+        let tmp = tempdir().unwrap();
+        let src = tmp.child("src");
+        src.child("asset.bin")
+            .write_binary(&random_bytes(4 << 20).unwrap())
+            .unwrap();
+        src.child("main.rs").write_str("fn main() {}").unwrap();
+
+        let target = tempdir().unwrap();
+        let archive = target.child("archive.tar.zst");
+
+        let mut output =
+            crate::compress_with_threads(fs::File::create(archive.path()).unwrap(), 4).unwrap();
+        package(src.path(), &mut output).expect("package");
+        output.finish().unwrap();
+
+        let unarchive = tempdir().unwrap();
+        untar(archive.path(), unarchive.path());
+
+        unarchive
+            .child("asset.bin")
+            .assert(predicate::path::is_file());
+        unarchive
+            .child("main.rs")
+            .assert(predicate::path::is_file());
+    }
+
     #[test]
     fn archive_with_long_paths() {
         let tmp = tempdir().unwrap();
@@ -205,6 +616,318 @@ mod tests {
             .assert(predicate::path::is_file());
     }
 
+    #[test]
+    fn archive_keeps_empty_directories() {
+        let tmp = tempdir().unwrap();
+        let src = tmp.child("src");
+        src.child("main.rs").write_str("fn main() {}").unwrap();
+        src.child("logs").create_dir_all().unwrap();
+        src.child("data/cache").create_dir_all().unwrap();
+
+        let archive = tmp.child("archive.tar.zst");
+        let mut output = compress(fs::File::create(archive.path()).unwrap()).unwrap();
+        package(src.path(), &mut output).expect("package");
+        output.finish().unwrap();
+
+        let unarchive = tempdir().unwrap();
+        untar(archive.path(), unarchive.path());
+
+        unarchive
+            .child("main.rs")
+            .assert(predicate::path::is_file());
+        unarchive.child("logs").assert(predicate::path::is_dir());
+        unarchive
+            .child("data/cache")
+            .assert(predicate::path::is_dir());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn archive_keeps_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let tmp = tempdir().unwrap();
+        let src = tmp.child("src");
+        src.child("lib/libfoo.so.1")
+            .write_str("shared code")
+            .unwrap();
+        symlink("libfoo.so.1", src.child("lib/libfoo.so").path()).unwrap();
+
+        let archive = tmp.child("archive.tar.zst");
+        let mut output = compress(fs::File::create(archive.path()).unwrap()).unwrap();
+        package(src.path(), &mut output).expect("package");
+        output.finish().unwrap();
+
+        let unarchive = tempdir().unwrap();
+        untar(archive.path(), unarchive.path());
+
+        let link = unarchive.child("lib/libfoo.so");
+        assert!(fs::symlink_metadata(link.path())
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(
+            fs::read_link(link.path()).unwrap(),
+            Path::new("libfoo.so.1")
+        );
+        link.assert("shared code");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn archive_following_symlinks() {
+        use std::os::unix::fs::symlink;
+
+        let tmp = tempdir().unwrap();
+        let src = tmp.child("src");
+        src.child("lib/libfoo.so.1")
+            .write_str("shared code")
+            .unwrap();
+        symlink("libfoo.so.1", src.child("lib/libfoo.so").path()).unwrap();
+
+        let archive = tmp.child("archive.tar.zst");
+        let mut output = compress(fs::File::create(archive.path()).unwrap()).unwrap();
+        let settings = Settings {
+            symlinks: Symlinks::Follow,
+            ..Settings::default()
+        };
+        package_with(&[Source::new(src.path())], &mut output, &settings).expect("package");
+        output.finish().unwrap();
+
+        let unarchive = tempdir().unwrap();
+        untar(archive.path(), unarchive.path());
+
+        let file = unarchive.child("lib/libfoo.so");
+        assert!(fs::symlink_metadata(file.path())
+            .unwrap()
+            .file_type()
+            .is_file());
+        file.assert("shared code");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn symlink_cycles_are_reported() {
+        use std::os::unix::fs::symlink;
+
+        let tmp = tempdir().unwrap();
+        let src = tmp.child("src");
+        src.child("assets/logo.png").write_str("png").unwrap();
+        symlink("..", src.child("assets/parent").path()).unwrap();
+
+        let settings = Settings {
+            symlinks: Symlinks::Follow,
+            ..Settings::default()
+        };
+        let err = package_with(&[Source::new(src.path())], Vec::new(), &settings).unwrap_err();
+        assert!(
+            err.chain().any(|e| e
+                .to_string()
+                .contains("points at a directory containing it")),
+            "unexpected error: {:?}",
+            err
+        );
+        assert!(source_size(&[Source::new(src.path())], &settings).is_err());
+
+        // without following, the symlink is just stored
+        package(src.path(), Vec::new()).unwrap();
+    }
+
+    #[test]
+    fn archive_headers_do_not_depend_on_environment() {
+        let tmp = tempdir().unwrap();
+        let src = tmp.child("src");
+        src.child("main.rs").write_str("fn main() {}").unwrap();
+        src.child("logs").create_dir_all().unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("main.rs", src.child("lib.rs").path()).unwrap();
+
+        let mut output = Vec::new();
+        package(src.path(), &mut output).expect("package");
+
+        let mut archive = tar::Archive::new(&output[..]);
+        let mut entries = 0;
+        for entry in archive.entries().unwrap() {
+            let entry = entry.unwrap();
+            let header = entry.header();
+            let path = entry.path().unwrap().display().to_string();
+            assert_eq!(header.mtime().unwrap(), 0, "mtime of {}", path);
+            assert_eq!(header.uid().unwrap(), 0, "uid of {}", path);
+            assert_eq!(header.gid().unwrap(), 0, "gid of {}", path);
+            assert_eq!(header.username().unwrap(), Some(""), "user of {}", path);
+            assert_eq!(header.groupname().unwrap(), Some(""), "group of {}", path);
+            assert_eq!(
+                header.device_major().unwrap(),
+                Some(0),
+                "device of {}",
+                path
+            );
+            assert_eq!(
+                header.device_minor().unwrap(),
+                Some(0),
+                "device of {}",
+                path
+            );
+            entries += 1;
+        }
+        assert!(entries >= 2);
+    }
+
+    #[test]
+    fn archive_combining_multiple_sources() {
+        let tmp = tempdir().unwrap();
+        tmp.child("out/bin/game").write_str("ELF").unwrap();
+        tmp.child("out/bin/tool").write_str("ELF").unwrap();
+        tmp.child("art/logo.png").write_str("png").unwrap();
+        tmp.child("README").write_str("read me").unwrap();
+
+        let sources = [
+            Source::with_prefix(tmp.path().join("out/bin"), "bin"),
+            Source::with_prefix(tmp.path().join("art"), "assets"),
+            Source::new(tmp.path().join("README")),
+        ];
+        let archive = tmp.child("archive.tar.zst");
+        let mut output = compress(fs::File::create(archive.path()).unwrap()).unwrap();
+        package_with(&sources, &mut output, &Settings::default()).expect("package");
+        output.finish().unwrap();
+
+        let unarchive = tempdir().unwrap();
+        untar(archive.path(), unarchive.path());
+
+        unarchive.child("bin/game").assert("ELF");
+        unarchive.child("bin/tool").assert("ELF");
+        unarchive.child("assets/logo.png").assert("png");
+        unarchive.child("README").assert("read me");
+
+        // order of sources doesn't matter
+        let mut output1 = Vec::new();
+        package_with(&sources, &mut output1, &Settings::default()).unwrap();
+        let mut reversed = sources.to_vec();
+        reversed.reverse();
+        let mut output2 = Vec::new();
+        package_with(&reversed, &mut output2, &Settings::default()).unwrap();
+        assert_eq!(output1, output2);
+    }
+
+    #[test]
+    fn sources_must_not_overlap() {
+        let tmp = tempdir().unwrap();
+        tmp.child("a/main.rs").write_str("fn main() {}").unwrap();
+        tmp.child("b/main.rs").write_str("fn main() {}").unwrap();
+
+        let sources = [
+            Source::new(tmp.path().join("a")),
+            Source::new(tmp.path().join("b")),
+        ];
+        let err = package_with(&sources, Vec::new(), &Settings::default()).unwrap_err();
+        assert!(err.to_string().contains("would both end up as `main.rs`"));
+
+        let escaping = [Source::with_prefix(tmp.path().join("a"), "../a")];
+        assert!(package_with(&escaping, Vec::new(), &Settings::default()).is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn non_utf8_paths_are_rejected() {
+        use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+        let tmp = tempdir().unwrap();
+        let src = tmp.child("src");
+        src.child("main.rs").write_str("fn main() {}").unwrap();
+        let weird_name = OsStr::from_bytes(b"caf\xe9.txt");
+        fs::write(src.path().join(weird_name), "latin1").unwrap();
+
+        let sources = [Source::new(src.path())];
+        let err = package_with(&sources, Vec::new(), &Settings::default()).unwrap_err();
+        assert!(
+            err.chain()
+                .any(|e| e.to_string().contains("not valid UTF-8")),
+            "unexpected error: {:?}",
+            err
+        );
+
+        let settings = Settings {
+            allow_non_utf8_paths: true,
+            ..Settings::default()
+        };
+        let mut output = Vec::new();
+        package_with(&sources, &mut output, &settings).expect("package");
+
+        let mut archive = tar::Archive::new(&output[..]);
+        let paths = archive
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().into_owned())
+            .collect::<Vec<_>>();
+        assert!(paths.contains(&Path::new(weird_name).to_path_buf()));
+    }
+
+    #[test]
+    fn archive_without_ignored_paths() {
+        let tmp = tempdir().unwrap();
+        let src = tmp.child("src");
+        src.child("main.rs").write_str("fn main() {}").unwrap();
+        src.child("main.pdb").write_str("debug info").unwrap();
+        src.child(".git/HEAD").write_str("ref: main").unwrap();
+        src.child("tmp/scratch").write_str("whatever").unwrap();
+        src.child("assets/logo.png").write_str("png").unwrap();
+        src.child("assets/logo.psd").write_str("psd").unwrap();
+        src.child(IGNORE_FILE).write_str(".git\n*.pdb\n").unwrap();
+
+        let settings = Settings {
+            exclude: vec!["tmp/".into(), "assets/*.psd".into()],
+            ..Settings::default()
+        };
+        let archive = tmp.child("archive.tar.zst");
+        let mut output = compress(fs::File::create(archive.path()).unwrap()).unwrap();
+        package_with(&[Source::new(src.path())], &mut output, &settings).expect("package");
+        output.finish().unwrap();
+
+        let unarchive = tempdir().unwrap();
+        untar(archive.path(), unarchive.path());
+
+        unarchive
+            .child("main.rs")
+            .assert(predicate::path::is_file());
+        unarchive
+            .child("assets/logo.png")
+            .assert(predicate::path::is_file());
+        for ignored in [IGNORE_FILE, "main.pdb", ".git", "tmp", "assets/logo.psd"] {
+            unarchive.child(ignored).assert(predicate::path::missing());
+        }
+
+        let mut output1 = Vec::new();
+        package_with(&[Source::new(src.path())], &mut output1, &settings).unwrap();
+        let mut output2 = Vec::new();
+        package_with(&[Source::new(src.path())], &mut output2, &settings).unwrap();
+        assert_eq!(output1, output2);
+    }
+
+    #[test]
+    fn directories_with_only_ignored_content_are_kept() {
+        let tmp = tempdir().unwrap();
+        let src = tmp.child("src");
+        src.child("main.rs").write_str("fn main() {}").unwrap();
+        src.child("logs/old.log").write_str("old").unwrap();
+
+        let settings = Settings {
+            exclude: vec!["*.log".into()],
+            ..Settings::default()
+        };
+        let archive = tmp.child("archive.tar.zst");
+        let mut output = compress(fs::File::create(archive.path()).unwrap()).unwrap();
+        package_with(&[Source::new(src.path())], &mut output, &settings).expect("package");
+        output.finish().unwrap();
+
+        let unarchive = tempdir().unwrap();
+        untar(archive.path(), unarchive.path());
+
+        unarchive.child("logs").assert(predicate::path::is_dir());
+        unarchive
+            .child("logs/old.log")
+            .assert(predicate::path::missing());
+    }
+
     proptest! {
         #[test]
         fn determinsitic_tar(files in prop::collection::vec(r"[0-9A-Za-z][0-9A-Za-z/]+[0-9A-Za-z]", 1..10)) {
@@ -214,7 +937,7 @@ mod tests {
 
             // create some random files in random paths
             for f in &files {
-                random_zstd_file(&dir1.join(f)).expect("random_file");
+                random_zstd_file(dir1.join(f)).expect("random_file");
             }
 
             // package this dir
@@ -1,20 +1,29 @@
+use crate::interrupt::{on_interrupt, Registration};
 use erreur::{Context, Result};
 use std::{
     ffi::OsString,
     fs::{self, File},
-    io::{BufWriter, Write},
+    io::{self, BufWriter, Write},
     path::{Path, PathBuf},
     time::SystemTime,
 };
 
 /// Small helper struct to make writing files a bit safer by first writing to a
 /// hidden file and once finished renaming it to the requested name.
+///
+/// The hidden file is next to the target by default. When it's somewhere else
+/// (see [`PartialFile::create_in`]) and that is on another file system, it's
+/// copied next to the target first, so the target still appears all at once.
 #[derive(Debug)]
 pub struct PartialFile {
     target_path: PathBuf,
     partial_path: PathBuf,
     partial_file: BufWriter<File>,
     finished: bool,
+    /// Keep the partial file when dropped before it's finished
+    keep: bool,
+    /// Delete the partial file if we're interrupted before it's finished
+    cleanup: Option<Registration>,
 }
 
 impl PartialFile {
@@ -22,6 +31,23 @@ impl PartialFile {
         let target_path: PathBuf = target_path.into();
         let partial_path = generate_partial_file_name(&target_path)
             .context("could not generate name for partial/temporary file")?;
+        Self::create_at(target_path, partial_path)
+    }
+
+    /// Write to a hidden file in `temp_dir` instead of next to the target
+    pub fn create_in(target_path: impl Into<PathBuf>, temp_dir: impl AsRef<Path>) -> Result<Self> {
+        let target_path: PathBuf = target_path.into();
+        let partial_path = generate_partial_file_name(&target_path)
+            .context("could not generate name for partial/temporary file")?;
+        let partial_path = temp_dir.as_ref().join(
+            partial_path
+                .file_name()
+                .context("partial file without file name")?,
+        );
+        Self::create_at(target_path, partial_path)
+    }
+
+    fn create_at(target_path: PathBuf, partial_path: PathBuf) -> Result<Self> {
         let partial_file = File::create(&partial_path).with_context(|| {
             format!(
                 "could not create partial/temp file `{}`",
@@ -29,15 +55,30 @@ impl PartialFile {
             )
         })?;
         let partial_file = BufWriter::new(partial_file);
+        let cleanup = on_interrupt({
+            let partial_path = partial_path.clone();
+            async move {
+                let _ = fs::remove_file(partial_path);
+            }
+        });
 
         Ok(PartialFile {
             target_path,
             partial_path,
             partial_file,
             finished: false,
+            keep: false,
+            cleanup: Some(cleanup),
         })
     }
 
+    /// Don't delete the partial file when this is dropped without finishing
+    /// it, e.g. to look at what was written before something failed
+    pub fn keep_if_unfinished(&mut self) {
+        self.keep = true;
+        self.cleanup = None;
+    }
+
     pub fn finish(mut self) -> Result<File> {
         self.partial_file.flush().with_context(|| {
             format!(
@@ -45,14 +86,15 @@ impl PartialFile {
                 self.partial_path.display()
             )
         })?;
-        fs::rename(&self.partial_path, &self.target_path).with_context(|| {
+        move_into_place(&self.partial_path, &self.target_path).with_context(|| {
             format!(
-                "cannot finish partial file `{}`, renaming it to `{}` failed",
+                "cannot finish partial file `{}`, moving it to `{}` failed",
                 self.partial_path.display(),
                 self.target_path.display()
             )
         })?;
         self.finished = true;
+        self.cleanup = None;
         File::open(&self.target_path)
             .with_context(|| format!("cannot open finished file `{}`", self.target_path.display()))
     }
@@ -81,6 +123,10 @@ impl Drop for PartialFile {
         if self.finished {
             return;
         }
+        if self.keep {
+            log::info!("Keeping partial file `{}`.", self.partial_path.display());
+            return;
+        }
 
         log::info!("Deleting partial file `{}`.", self.partial_path.display());
         log::debug!(
@@ -98,6 +144,53 @@ impl Drop for PartialFile {
     }
 }
 
+/// Rename `from` to `to`, copying it if they are on different file systems
+fn move_into_place(from: &Path, to: &Path) -> Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if is_cross_device(&e) => {
+            log::debug!(
+                "`{}` is on another file system than `{}`, copying it",
+                from.display(),
+                to.display()
+            );
+            copy_into_place(from, to)
+        }
+        res => Ok(res?),
+    }
+}
+
+/// Copy `from` to a partial file next to `to`, sync it, rename it to `to`, and
+/// then remove `from`
+fn copy_into_place(from: &Path, to: &Path) -> Result<()> {
+    let mut copy = PartialFile::create(to)?;
+    let mut source = File::open(from).with_context(|| format!("open `{}`", from.display()))?;
+    io::copy(&mut source, &mut copy)
+        .with_context(|| format!("copy `{}` to `{}`", from.display(), to.display()))?;
+    copy.partial_file.flush().context("flush copy")?;
+    copy.partial_file
+        .get_ref()
+        .sync_all()
+        .with_context(|| format!("sync `{}`", copy.partial_path.display()))?;
+    copy.finish()?;
+
+    if let Err(e) = fs::remove_file(from) {
+        log::warn!("Could not delete copied file `{}`: {}", from.display(), e);
+    }
+    Ok(())
+}
+
+/// Whether renaming failed because source and target are on different file
+/// systems (`EXDEV` on unix, `ERROR_NOT_SAME_DEVICE` on Windows)
+fn is_cross_device(e: &io::Error) -> bool {
+    if cfg!(unix) {
+        e.raw_os_error() == Some(18)
+    } else if cfg!(windows) {
+        e.raw_os_error() == Some(17)
+    } else {
+        false
+    }
+}
+
 fn generate_partial_file_name(path: &Path) -> Result<PathBuf> {
     let target_file_name = path
         .file_name()
@@ -162,3 +255,115 @@ fn remove_temp_file_on_drop() {
     assert!(!temp_path.exists(), "temp file should no longer exists");
     assert!(!testfile.exists(), "target file should not exists");
 }
+
+#[test]
+fn remove_temp_file_when_writing_fails() {
+    use crate::test_helpers::*;
+    use std::io::{self, Read};
+
+    /// Fails after the first few bytes, like a corrupt or truncated input
+    struct Failing(usize);
+
+    impl Read for Failing {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0 == 0 {
+                return Err(io::Error::new(io::ErrorKind::Other, "input broke"));
+            }
+            let n = buf.len().min(self.0);
+            buf[..n].fill(42);
+            self.0 -= n;
+            Ok(n)
+        }
+    }
+
+    fn write_file(path: &Path) -> Result<()> {
+        let mut file = PartialFile::create(path)?;
+        io::copy(&mut Failing(10_000), &mut file).context("copy input")?;
+        file.finish()?;
+        Ok(())
+    }
+
+    let tmp = tempdir().unwrap();
+    assert!(write_file(&tmp.path().join("test1")).is_err());
+    assert_eq!(
+        fs::read_dir(tmp.path()).unwrap().count(),
+        0,
+        "partial file was left behind"
+    );
+}
+
+#[test]
+fn partial_file_in_other_directory() {
+    use crate::test_helpers::*;
+
+    let target_dir = tempdir().unwrap();
+    let temp_dir = tempdir().unwrap();
+    let testfile = target_dir.path().join("test1");
+
+    let mut x = PartialFile::create_in(&testfile, temp_dir.path()).unwrap();
+    assert!(x.partial_path.starts_with(temp_dir.path()));
+    write!(&mut x, "lorem ipsum dolor sit test").unwrap();
+    x.finish().unwrap();
+
+    assert_eq!(
+        fs::read_to_string(&testfile).unwrap(),
+        "lorem ipsum dolor sit test"
+    );
+    assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn copy_partial_file_into_place() {
+    use crate::test_helpers::*;
+
+    let target_dir = tempdir().unwrap();
+    let temp_dir = tempdir().unwrap();
+    let from = temp_dir.path().join("test1.part");
+    let to = target_dir.path().join("test1");
+    fs::write(&from, "lorem ipsum dolor sit test").unwrap();
+
+    copy_into_place(&from, &to).unwrap();
+    assert_eq!(
+        fs::read_to_string(&to).unwrap(),
+        "lorem ipsum dolor sit test"
+    );
+    assert!(!from.exists(), "copied file was removed");
+    assert_eq!(
+        fs::read_dir(target_dir.path()).unwrap().count(),
+        1,
+        "only the target is left"
+    );
+}
+
+/// Only does something useful when `/dev/shm` is a separate (tmpfs) mount
+#[test]
+#[cfg(unix)]
+fn partial_file_on_other_file_system() {
+    use crate::test_helpers::*;
+    use std::os::unix::fs::MetadataExt;
+
+    let target_dir = tempdir().unwrap();
+    let temp_dir = match tempfile::tempdir_in("/dev/shm") {
+        Ok(dir) => dir,
+        Err(e) => {
+            eprintln!("skipping, can't use /dev/shm: {}", e);
+            return;
+        }
+    };
+    let device = |path: &Path| fs::metadata(path).unwrap().dev();
+    if device(target_dir.path()) == device(temp_dir.path()) {
+        eprintln!("skipping, /dev/shm is on the same file system");
+        return;
+    }
+
+    let testfile = target_dir.path().join("test1");
+    let mut x = PartialFile::create_in(&testfile, temp_dir.path()).unwrap();
+    write!(&mut x, "lorem ipsum dolor sit test").unwrap();
+    x.finish().unwrap();
+
+    assert_eq!(
+        fs::read_to_string(&testfile).unwrap(),
+        "lorem ipsum dolor sit test"
+    );
+    assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+}
@@ -1,7 +1,8 @@
-use erreur::{Context, Result};
+use erreur::{bail, ensure, Context, Result};
+use once_cell::sync::{Lazy, OnceCell};
 use std::{convert::TryFrom, path::Path};
 
-use crate::index::Version;
+use crate::index::{ChecksumAlgorithm, Version};
 
 pub fn path_as_string(path: impl AsRef<Path>) -> Result<String> {
     let path = path.as_ref();
@@ -11,23 +12,293 @@ pub fn path_as_string(path: impl AsRef<Path>) -> Result<String> {
         .to_string())
 }
 
+/// Extension of compressed files, builds and patches alike (with the default
+/// [`Naming`])
+pub const COMPRESSED_EXTENSION: &str = ".zst";
+/// Extension of build archives (before [`COMPRESSED_EXTENSION`], or on its own
+/// for uncompressed archives, which we read, too)
+pub const ARCHIVE_EXTENSION: &str = ".tar";
+/// Extension some tools use for compressed tar archives, which we read, too
+pub const SHORT_BUILD_EXTENSION: &str = ".tzst";
+/// Extension of patches (before [`COMPRESSED_EXTENSION`], with the default
+/// [`Naming`])
+pub const PATCH_EXTENSION: &str = ".patch";
+/// Default separator of the versions in a patch file name,
+/// `<from>-<to>.patch.zst`
+pub const PATCH_SEPARATOR: &str = "-";
+/// Separator of versions when they contain [`PATCH_SEPARATOR`] themselves,
+/// and in alias file names
+///
+/// Versions can't contain this one.
+pub const LONG_SEPARATOR: &str = "---";
+pub const ALIAS_EXTENSION: &str = ".alias";
+pub const META_EXTENSION: &str = ".meta.json";
+pub const EXTRACTED_EXTENSION: &str = ".extracted";
+pub const SIGNATURE_EXTENSION: &str = ".sig";
+
+/// How build and patch files are named
+///
+/// Set for the whole process with [`set_naming`] (from `--build-suffix`,
+/// `--patch-suffix`, and `--patch-separator`). The functions in this module
+/// (like [`build_path_from_version`] and [`is_patch_path`]) use that.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Naming {
+    /// Suffix of build files, e.g. `.tar.zst` in `1.2.3.tar.zst`
+    ///
+    /// Builds ending in [`SHORT_BUILD_EXTENSION`] or [`ARCHIVE_EXTENSION`]
+    /// are read, too.
+    pub build_suffix: String,
+    /// Suffix of patch files, e.g. `.patch.zst` in `1-2.patch.zst`
+    pub patch_suffix: String,
+    /// Separator of the versions in patch file names, e.g. `-` in
+    /// `1-2.patch.zst`
+    ///
+    /// Patches between versions that contain it use [`LONG_SEPARATOR`].
+    pub patch_separator: String,
+}
+
+impl Default for Naming {
+    fn default() -> Self {
+        Naming {
+            build_suffix: format!("{}{}", ARCHIVE_EXTENSION, COMPRESSED_EXTENSION),
+            patch_suffix: format!("{}{}", PATCH_EXTENSION, COMPRESSED_EXTENSION),
+            patch_separator: PATCH_SEPARATOR.to_string(),
+        }
+    }
+}
+
+impl Naming {
+    /// Make sure builds and patches can be told apart by their names, and
+    /// versions read from them again
+    pub fn validate(&self) -> Result<()> {
+        ensure!(
+            !self.build_suffix.is_empty() && !self.patch_suffix.is_empty(),
+            "suffixes of builds and patches can't be empty"
+        );
+        for build_suffix in self.build_suffixes() {
+            ensure!(
+                !build_suffix.ends_with(&self.patch_suffix)
+                    && !self.patch_suffix.ends_with(build_suffix),
+                "patch suffix `{}` can't be told apart from build suffix `{}`",
+                self.patch_suffix,
+                build_suffix
+            );
+        }
+        ensure!(
+            !self.patch_separator.is_empty()
+                && !self.patch_separator.contains(LONG_SEPARATOR)
+                && !self.patch_separator.contains(['/', '\\']),
+            "patch separator `{}` must not be empty or contain `{}`, `/`, or `\\`",
+            self.patch_separator,
+            LONG_SEPARATOR
+        );
+        Ok(())
+    }
+
+    /// Suffixes of files we read as builds
+    fn build_suffixes(&self) -> [&str; 3] {
+        [&self.build_suffix, SHORT_BUILD_EXTENSION, ARCHIVE_EXTENSION]
+    }
+
+    fn is_build_path(&self, path: &str) -> bool {
+        self.build_suffixes()
+            .iter()
+            .any(|suffix| path.ends_with(suffix))
+    }
+
+    fn is_patch_path(&self, path: &str) -> bool {
+        path.ends_with(&self.patch_suffix)
+    }
+
+    fn build_path(&self, v: &Version) -> String {
+        format!("{}{}", v.as_str(), self.build_suffix)
+    }
+
+    fn patch_path(&self, from: &Version, to: &Version) -> String {
+        let separator = if from.as_str().contains(&self.patch_separator)
+            || to.as_str().contains(&self.patch_separator)
+        {
+            LONG_SEPARATOR
+        } else {
+            &self.patch_separator
+        };
+        format!(
+            "{}{}{}{}",
+            from.as_str(),
+            separator,
+            to.as_str(),
+            self.patch_suffix
+        )
+    }
+
+    fn file_name(&self, path: &Path) -> Result<String> {
+        let name = path
+            .file_name()
+            .with_context(|| format!("no file name for `{:?}`", path))?;
+        let name = path_as_string(name)?;
+        let [build, short, plain] = self.build_suffixes();
+        for suffix in [build, short, plain, &self.patch_suffix] {
+            if let Some(stem) = name.strip_suffix(suffix).filter(|stem| !stem.is_empty()) {
+                return Ok(stem.to_string());
+            }
+        }
+
+        // other files, like extracted builds
+        let file_name = path
+            .file_stem()
+            .with_context(|| format!("no file stem for `{:?}`", path))?;
+        let name = path_as_string(file_name)?;
+
+        // get rid of pesky .tar suffixes
+        let name = name.trim_end_matches(ARCHIVE_EXTENSION);
+        // get rid of pesky .patch suffixes
+        let name = name.trim_end_matches(PATCH_EXTENSION);
+
+        Ok(name.to_string())
+    }
+
+    fn patch_versions(&self, path: &Path) -> Result<(Version, Version)> {
+        let name = self
+            .file_name(path)
+            .with_context(|| format!("get name of `{:?}`", path))?;
+
+        // `<hash>-<hash>` or `<complex-name>---<complex-name>`
+        for separator in [self.patch_separator.as_str(), LONG_SEPARATOR] {
+            let parts: Vec<&str> = name.split(separator).collect();
+            if parts.len() == 2 {
+                return Version::try_from(parts[0])
+                    .into_iter()
+                    .zip(Version::try_from(parts[1]))
+                    .next()
+                    .with_context(|| {
+                        format!("parse name `{}` from path `{:?}` as version", name, path)
+                    });
+            }
+        }
+
+        bail!(
+            "path `{}` cannot be parsed as patch file name pattern with 2 version",
+            path.display()
+        );
+    }
+}
+
+static NAMING: OnceCell<Naming> = OnceCell::new();
+static DEFAULT_NAMING: Lazy<Naming> = Lazy::new(Naming::default);
+
+/// Name build and patch files like this from now on
+///
+/// Like the bandwidth limit (see [`crate::storage::set_max_bandwidth`]), this
+/// is for the whole process, as file names are needed in lots of places that
+/// don't have an index at hand (like [`crate::index::Patch::file_name`]). Can
+/// only be set once.
+pub fn set_naming(naming: Naming) -> Result<()> {
+    naming.validate()?;
+    NAMING
+        .set(naming)
+        .ok()
+        .context("naming of files is already set")
+}
+
+/// Naming used for build and patch files, see [`set_naming`]
+///
+/// Until it is set, this is the default naming. Command line arguments are
+/// parsed before that, so anything checked while parsing them (like
+/// [`crate::cli::LinkName`]) needs to be checked again afterwards.
+pub fn naming() -> &'static Naming {
+    NAMING.get().unwrap_or(&DEFAULT_NAMING)
+}
+
 pub fn file_name(path: impl AsRef<Path>) -> Result<String> {
-    let path = path.as_ref();
-    let file_name = path
-        .file_stem()
-        .with_context(|| format!("no file stem for `{:?}`", path))?;
-    let name = path_as_string(file_name)?;
+    naming().file_name(path.as_ref())
+}
 
-    // get rid of pesky .tar suffixes
-    let name = name.trim_end_matches(".tar");
-    // get rid of pesky .patch suffixes
-    let name = name.trim_end_matches(".patch");
+/// Whether `path` is named like a build file
+///
+/// Besides what [`build_path_from_version`] writes, that's
+/// [`SHORT_BUILD_EXTENSION`] and uncompressed archives (see
+/// [`is_plain_build_path`]). [`file_name`] strips all of them, so they give
+/// the same version.
+pub fn is_build_path(path: &str) -> bool {
+    naming().is_build_path(path)
+}
 
-    Ok(name.to_string())
+/// Whether `path` is named like an uncompressed build archive, e.g.
+/// `v1.2.3.tar`
+///
+/// We keep those uncompressed (under that name) when adding or downloading
+/// them, so their checksums stay the same.
+pub fn is_plain_build_path(path: &str) -> bool {
+    path.ends_with(ARCHIVE_EXTENSION)
 }
 
 pub fn build_path_from_version(v: Version) -> Result<String> {
-    Ok(format!("{}.tar.zst", v.as_str()))
+    Ok(naming().build_path(&v))
+}
+
+/// File an uncompressed build is stored in, e.g. `1.2.3.tar`
+pub fn plain_build_path_from_version(v: Version) -> Result<String> {
+    Ok(format!("{}{}", v.as_str(), ARCHIVE_EXTENSION))
+}
+
+/// Name of a patch without the compression extension, e.g. `1-2.patch`
+pub fn patch_name(from: &Version, to: &Version) -> String {
+    let path = patch_path(from, to);
+    match path.strip_suffix(COMPRESSED_EXTENSION) {
+        Some(name) => name.to_string(),
+        None => path,
+    }
+}
+
+/// File a patch is stored in, e.g. `1-2.patch.zst`
+pub fn patch_path(from: &Version, to: &Version) -> String {
+    naming().patch_path(from, to)
+}
+
+/// Whether `path` is named like a patch file
+pub fn is_patch_path(path: &str) -> bool {
+    naming().is_patch_path(path)
+}
+
+/// Versions a patch file goes from and to
+pub fn patch_versions_from_path(path: impl AsRef<Path>) -> Result<(Version, Version)> {
+    naming().patch_versions(path.as_ref())
+}
+
+/// Directory a build gets extracted to by `install --extract`
+///
+/// As [`file_name`] strips the extension, [`build_version_from_path`] works
+/// for this, too.
+pub fn extracted_path_from_version(v: Version) -> Result<String> {
+    Ok(format!("{}{}", v.as_str(), EXTRACTED_EXTENSION))
+}
+
+pub fn build_meta_path_from_version(v: Version) -> Result<String> {
+    Ok(format!("{}{}", v.as_str(), META_EXTENSION))
+}
+
+/// File containing the checksum of a build or patch file
+pub fn checksum_path(file_name: &str, algorithm: ChecksumAlgorithm) -> String {
+    format!("{}.{}", file_name, algorithm.extension())
+}
+
+/// File containing the signature of a build file
+pub fn signature_path(file_name: &str) -> String {
+    format!("{}{}", file_name, SIGNATURE_EXTENSION)
+}
+
+/// Version a `<version>.meta.json` file belongs to
+pub fn build_version_from_meta_path(path: impl AsRef<Path>) -> Result<Version> {
+    let path = path.as_ref();
+    let name = path
+        .file_name()
+        .with_context(|| format!("no file name for `{:?}`", path))?;
+    let name = path_as_string(name)?;
+    let name = name
+        .strip_suffix(META_EXTENSION)
+        .with_context(|| format!("`{:?}` is not a metadata file", path))?;
+    Version::try_from(name)
+        .with_context(|| format!("parse name `{}` from path `{:?}` as version", name, path))
 }
 
 pub fn build_version_from_path(path: impl AsRef<Path>) -> Result<Version> {
@@ -36,3 +307,124 @@ pub fn build_version_from_path(path: impl AsRef<Path>) -> Result<Version> {
     Version::try_from(&name)
         .with_context(|| format!("parse name `{}` from path `{:?}` as version", name, path))
 }
+
+/// File in remote storage saying that `alias` is the same build as `target`
+///
+/// Versions can't contain `---`, so that's what separates them.
+pub fn alias_path(alias: &Version, target: &Version) -> String {
+    format!(
+        "{}{}{}{}",
+        alias.as_str(),
+        LONG_SEPARATOR,
+        target.as_str(),
+        ALIAS_EXTENSION
+    )
+}
+
+/// Alias and target of a `<alias>---<target>.alias` file
+pub fn alias_versions_from_path(path: impl AsRef<Path>) -> Result<(Version, Version)> {
+    let path = path.as_ref();
+    let name = path
+        .file_name()
+        .with_context(|| format!("no file name for `{:?}`", path))?;
+    let name = path_as_string(name)?;
+    let (alias, target) = name
+        .strip_suffix(ALIAS_EXTENSION)
+        .and_then(|name| name.split_once(LONG_SEPARATOR))
+        .with_context(|| format!("`{:?}` is not an alias file", path))?;
+    let parse = |name| {
+        Version::try_from(name)
+            .with_context(|| format!("parse name `{}` from path `{:?}` as version", name, path))
+    };
+    Ok((parse(alias)?, parse(target)?))
+}
+
+#[test]
+fn both_build_extensions_give_the_same_version() {
+    let version: Version = "v1.2.3".parse().unwrap();
+    for path in [
+        "v1.2.3.tar.zst",
+        "v1.2.3.tzst",
+        "/builds/v1.2.3.tzst",
+        "v1.2.3.tar",
+    ] {
+        assert!(is_build_path(path), "{}", path);
+        assert_eq!(build_version_from_path(path).unwrap(), version);
+    }
+    let canonical = build_path_from_version(version.clone()).unwrap();
+    assert_eq!(canonical, "v1.2.3.tar.zst");
+    assert_eq!(build_version_from_path(canonical).unwrap(), version);
+    assert!(!is_build_path("v1-v2.patch.zst"));
+    assert!(is_plain_build_path("v1.2.3.tar"));
+    assert!(!is_plain_build_path("v1.2.3.tar.zst"));
+}
+
+#[test]
+fn patch_path_roundtrip() {
+    for (from, to, path) in [
+        ("build1", "build2", "build1-build2.patch.zst"),
+        ("v1.2.3-rc1", "v1.2.3", "v1.2.3-rc1---v1.2.3.patch.zst"),
+    ] {
+        let from: Version = from.parse().unwrap();
+        let to: Version = to.parse().unwrap();
+        assert_eq!(patch_path(&from, &to), path);
+        assert!(is_patch_path(path));
+        assert!(!is_build_path(path));
+        assert_eq!(
+            patch_versions_from_path(format!("builds/{}", path)).unwrap(),
+            (from, to)
+        );
+    }
+}
+
+#[test]
+fn configured_naming_roundtrip() {
+    let naming = Naming {
+        build_suffix: ".tar.zstd".into(),
+        patch_suffix: ".bsdiff.zstd".into(),
+        patch_separator: "_to_".into(),
+    };
+    naming.validate().unwrap();
+    let v1: Version = "v1.2.3".parse().unwrap();
+    let v2: Version = "v1.2.4".parse().unwrap();
+
+    let build = naming.build_path(&v1);
+    assert_eq!(build, "v1.2.3.tar.zstd");
+    assert!(naming.is_build_path(&build) && !naming.is_patch_path(&build));
+    assert_eq!(naming.file_name(Path::new(&build)).unwrap(), "v1.2.3");
+    assert!(naming.is_build_path("v1.2.3.tzst"));
+
+    let patch = naming.patch_path(&v1, &v2);
+    assert_eq!(patch, "v1.2.3_to_v1.2.4.bsdiff.zstd");
+    assert!(naming.is_patch_path(&patch) && !naming.is_build_path(&patch));
+    assert_eq!(naming.patch_versions(Path::new(&patch)).unwrap(), (v1, v2));
+}
+
+#[test]
+fn ambiguous_naming_is_rejected() {
+    let with = |build: &str, patch: &str, separator: &str| Naming {
+        build_suffix: build.into(),
+        patch_suffix: patch.into(),
+        patch_separator: separator.into(),
+    };
+    assert!(Naming::default().validate().is_ok());
+    assert!(with(".tar.zst", ".zst", "-").validate().is_err());
+    assert!(with(".zst", ".patch.zst", "-").validate().is_err());
+    assert!(with(".tar.zst", ".patch.tar", "-").validate().is_err());
+    assert!(with("", ".patch.zst", "-").validate().is_err());
+    assert!(with(".tar.zst", ".patch.zst", "").validate().is_err());
+    assert!(with(".tar.zst", ".patch.zst", "/").validate().is_err());
+}
+
+#[test]
+fn alias_path_roundtrip() {
+    let alias: Version = "v1.2.3-rc1".parse().unwrap();
+    let target: Version = "v1.2.3".parse().unwrap();
+    let path = alias_path(&alias, &target);
+    assert_eq!(path, "v1.2.3-rc1---v1.2.3.alias");
+    assert_eq!(
+        alias_versions_from_path(format!("builds/{}", path)).unwrap(),
+        (alias, target)
+    );
+    assert!(alias_versions_from_path("v1.2.3.alias").is_err());
+}
@@ -0,0 +1,170 @@
+//! Progress bars for long-running local operations
+//!
+//! Drawn on stderr while packaging or diffing large builds. Disabled unless
+//! [`set_enabled`] was called (which the CLI does when stderr is a terminal
+//! and `--quiet` is not set), so library users and non-interactive runs only
+//! get the usual log lines.
+
+use humansize::{file_size_opts as options, FileSize};
+use std::{
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+const BAR_WIDTH: usize = 30;
+
+/// Show progress bars from now on
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Progress of one operation, counted in bytes
+///
+/// Redrawn in the background so it keeps ticking even while the operation
+/// is busy and can't report anything. Cleared when dropped.
+pub(crate) struct Progress {
+    state: Arc<State>,
+    drawer: Option<thread::JoinHandle<()>>,
+}
+
+struct State {
+    label: String,
+    total: u64,
+    done: AtomicU64,
+    finished: AtomicBool,
+}
+
+impl Progress {
+    pub(crate) fn new(label: impl Into<String>, total: u64) -> Self {
+        let state = Arc::new(State {
+            label: label.into(),
+            total,
+            done: AtomicU64::new(0),
+            finished: AtomicBool::new(false),
+        });
+
+        let drawer = if ENABLED.load(Ordering::Relaxed) {
+            let state = state.clone();
+            Some(thread::spawn(move || state.draw_until_finished()))
+        } else {
+            None
+        };
+
+        Progress { state, drawer }
+    }
+
+    pub(crate) fn inc(&self, bytes: u64) {
+        self.state.done.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Set progress to `bytes` unless we're already further along
+    pub(crate) fn advance_to(&self, bytes: u64) {
+        self.state.done.fetch_max(bytes, Ordering::Relaxed);
+    }
+
+    /// Count all bytes written through `inner`
+    pub(crate) fn writer<W: Write>(&self, inner: W) -> ProgressWriter<'_, W> {
+        ProgressWriter {
+            inner,
+            progress: self,
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.state.finished.store(true, Ordering::Relaxed);
+        if let Some(drawer) = self.drawer.take() {
+            if drawer.join().is_err() {
+                log::debug!("progress bar for `{}` panicked", self.state.label);
+            }
+        }
+    }
+}
+
+impl State {
+    fn draw_until_finished(&self) {
+        let start = Instant::now();
+        while !self.finished.load(Ordering::Relaxed) {
+            self.draw(start.elapsed());
+            thread::sleep(REDRAW_INTERVAL);
+        }
+        // clear the line so following log output starts fresh
+        let _ = write!(io::stderr(), "\r\x1b[2K");
+    }
+
+    fn draw(&self, elapsed: Duration) {
+        let done = self.done.load(Ordering::Relaxed).min(self.total);
+        let line = format!(
+            "\r\x1b[2K{} [{}] {}/{} ({}s)",
+            self.label,
+            bar(done, self.total),
+            file_size(done),
+            file_size(self.total),
+            elapsed.as_secs(),
+        );
+        let mut stderr = io::stderr();
+        let _ = stderr.write_all(line.as_bytes());
+        let _ = stderr.flush();
+    }
+}
+
+fn bar(done: u64, total: u64) -> String {
+    let filled = if total == 0 {
+        BAR_WIDTH
+    } else {
+        (done as f64 / total as f64 * BAR_WIDTH as f64) as usize
+    };
+    format!("{:<width$}", "#".repeat(filled), width = BAR_WIDTH)
+}
+
+fn file_size(size: u64) -> String {
+    size.file_size(options::BINARY).expect("never negative")
+}
+
+pub(crate) struct ProgressWriter<'p, W> {
+    inner: W,
+    progress: &'p Progress,
+}
+
+impl<'p, W: Write> Write for ProgressWriter<'p, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.progress.inc(written as u64);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[test]
+fn bar_is_filled_proportionally() {
+    assert_eq!(bar(0, 100), " ".repeat(BAR_WIDTH));
+    assert_eq!(bar(50, 100).trim_end(), "#".repeat(BAR_WIDTH / 2));
+    assert_eq!(bar(100, 100), "#".repeat(BAR_WIDTH));
+    assert_eq!(bar(0, 0), "#".repeat(BAR_WIDTH));
+}
+
+#[test]
+fn writer_counts_bytes() {
+    let progress = Progress::new("test", 10);
+    let mut content = Vec::new();
+    progress.writer(&mut content).write_all(b"hello").unwrap();
+    assert_eq!(content, b"hello");
+    assert_eq!(progress.state.done.load(Ordering::Relaxed), 5);
+
+    progress.advance_to(3);
+    assert_eq!(progress.state.done.load(Ordering::Relaxed), 5);
+    progress.advance_to(8);
+    assert_eq!(progress.state.done.load(Ordering::Relaxed), 8);
+}
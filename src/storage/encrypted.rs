@@ -0,0 +1,582 @@
+//! Encryption of all files in a storage with a symmetric key
+//!
+//! Files are encrypted after compression, so encrypting doesn't make them
+//! larger than necessary. Each file starts with [`MAGIC`] and a salt, from
+//! which (and the key) we derive a key for only this file. The content
+//! follows in chunks of [`CHUNK_SIZE`] bytes, each sealed with
+//! ChaCha20-Poly1305. Nonces count the chunks and mark the last one, so
+//! reordered or truncated files don't decrypt. The file name is authenticated
+//! as well, so one file can't be swapped for another.
+//!
+//! The salt is a MAC (with the key) of the file name and content, so the same
+//! file always encrypts to the same bytes, and an interrupted upload of it can
+//! be resumed (see `multipart`). That only tells whoever can read the storage
+//! that a file is uploaded again unchanged.
+//!
+//! Next to every file, `<file>.enc.sha256` has the SHA-256 checksum of the
+//! encrypted bytes (in the format of `sha256sum`, and not encrypted itself).
+//! Downloads are checked against it before decrypting them, so a file that was
+//! corrupted in transfer is told apart from one that doesn't decrypt with the
+//! key. Listings leave these files out. The checksum files next to builds and
+//! patches are of their plain content (and encrypted like everything else),
+//! which checks that decrypting gave the file that was uploaded.
+//!
+//! Files are encrypted and decrypted one chunk at a time, through temporary
+//! files, so large builds aren't kept in memory.
+
+use super::{Entry, File, Storage, StorageBackend, TempFile};
+use crate::{
+    index::{Checksum, ChecksumAlgorithm},
+    paths::path_as_string,
+};
+use erreur::{ensure, Context, Help, Result};
+use futures::{
+    future,
+    stream::{BoxStream, StreamExt, TryStreamExt},
+};
+use ring::{
+    aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305},
+    hkdf, hmac, pbkdf2,
+};
+use std::{
+    fmt, fs,
+    io::{self, BufWriter, Read, Write},
+    num::NonZeroU32,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+/// Start of every encrypted file, including the version of the format
+const MAGIC: &[u8; 8] = b"ARTFENC1";
+const SALT_LEN: usize = 16;
+const HEADER_LEN: usize = MAGIC.len() + SALT_LEN;
+/// Bytes of content sealed at once
+const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+/// Added to the path of a file for the checksum of its encrypted bytes
+const CIPHERTEXT_CHECKSUM_EXTENSION: &str = ".enc.sha256";
+
+/// Key files are passphrases, so make guessing them expensive
+const PBKDF2_ITERATIONS: u32 = 100_000;
+const PBKDF2_SALT: &[u8] = b"artefacta encryption key";
+const HKDF_INFO: &[u8] = b"artefacta file";
+const SALT_INFO: &[u8] = b"artefacta salt";
+
+/// Symmetric key for [`Storage::encrypted`]
+#[derive(Clone)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+    pub fn from_passphrase(passphrase: &[u8]) -> Result<Self> {
+        ensure!(!passphrase.is_empty(), "encryption key is empty");
+        let mut key = [0; 32];
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            NonZeroU32::new(PBKDF2_ITERATIONS).expect("not zero"),
+            PBKDF2_SALT,
+            passphrase,
+            &mut key,
+        );
+        Ok(EncryptionKey(key))
+    }
+
+    /// Read a passphrase from a file (ignoring a trailing newline)
+    pub fn read(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content =
+            fs::read(path).with_context(|| format!("read key file `{}`", path.display()))?;
+        let passphrase = content
+            .strip_suffix(b"\n")
+            .map(|rest| rest.strip_suffix(b"\r").unwrap_or(rest))
+            .unwrap_or(&content);
+        EncryptionKey::from_passphrase(passphrase)
+            .with_context(|| format!("read key file `{}`", path.display()))
+    }
+
+    /// Salt for encrypting `content` as `name`
+    fn salt(&self, name: &str, mut content: impl Read) -> Result<[u8; SALT_LEN]> {
+        let key: hmac::Key = hkdf::Salt::new(hkdf::HKDF_SHA256, &[])
+            .extract(&self.0)
+            .expand(&[SALT_INFO], hmac::HMAC_SHA256)
+            .expect("valid length for HMAC key")
+            .into();
+        let mut mac = hmac::Context::with_key(&key);
+        mac.update(name.as_bytes());
+        mac.update(&[0]);
+        let mut buffer = vec![0; CHUNK_SIZE];
+        loop {
+            let read = content.read(&mut buffer).context("read file to encrypt")?;
+            if read == 0 {
+                break;
+            }
+            mac.update(&buffer[..read]);
+        }
+        let mut salt = [0; SALT_LEN];
+        salt.copy_from_slice(&mac.sign().as_ref()[..SALT_LEN]);
+        Ok(salt)
+    }
+
+    fn file_key(&self, salt: &[u8]) -> LessSafeKey {
+        let prk = hkdf::Salt::new(hkdf::HKDF_SHA256, salt).extract(&self.0);
+        let okm = prk
+            .expand(&[HKDF_INFO], &CHACHA20_POLY1305)
+            .expect("valid length for ChaCha20-Poly1305 key");
+        LessSafeKey::new(UnboundKey::from(okm))
+    }
+
+    /// Write `content` encrypted as `name` with `salt` to `out`, returning
+    /// the number of bytes written
+    fn encrypt(
+        &self,
+        name: &str,
+        salt: &[u8; SALT_LEN],
+        content: impl Read,
+        mut out: impl Write,
+    ) -> Result<u64> {
+        let key = self.file_key(salt);
+        out.write_all(MAGIC)
+            .and_then(|_| out.write_all(salt))
+            .context("write encrypted file")?;
+        let mut written = HEADER_LEN as u64;
+
+        // even empty files get one (empty) chunk, so we know they are complete
+        let mut chunks = Chunks::new(content, CHUNK_SIZE);
+        while let Some((index, chunk, last)) = chunks.next().context("read file to encrypt")? {
+            let tag = key
+                .seal_in_place_separate_tag(nonce(index, last), Aad::from(name.as_bytes()), chunk)
+                .ok()
+                .context("encrypt file")?;
+            out.write_all(chunk)
+                .and_then(|_| out.write_all(tag.as_ref()))
+                .context("write encrypted file")?;
+            written += (chunk.len() + TAG_LEN) as u64;
+        }
+        out.flush().context("write encrypted file")?;
+        Ok(written)
+    }
+
+    /// Write the content of `encrypted`, a file encrypted as `name`, to `out`,
+    /// returning the number of bytes written
+    fn decrypt(&self, name: &str, mut encrypted: impl Read, mut out: impl Write) -> Result<u64> {
+        let mut header = [0; HEADER_LEN];
+        let complete = read_full(&mut encrypted, &mut header)
+            .with_context(|| format!("read `{}`", name))?
+            == HEADER_LEN;
+        ensure!(
+            complete && header.starts_with(MAGIC),
+            "`{}` is not encrypted",
+            name
+        );
+        let key = self.file_key(&header[MAGIC.len()..]);
+
+        let mut written = 0;
+        let mut chunks = Chunks::new(encrypted, CHUNK_SIZE + TAG_LEN);
+        while let Some((index, chunk, last)) =
+            chunks.next().with_context(|| format!("read `{}`", name))?
+        {
+            let plain = key
+                .open_in_place(nonce(index, last), Aad::from(name.as_bytes()), chunk)
+                .ok()
+                .with_context(|| format!("decrypt `{}`", name))?;
+            out.write_all(plain).context("write decrypted file")?;
+            written += plain.len() as u64;
+        }
+        out.flush().context("write decrypted file")?;
+        Ok(written)
+    }
+}
+
+/// Chunks of a reader, read one ahead to tell which one is the last
+///
+/// There's always at least one chunk, which is empty for empty content.
+struct Chunks<R> {
+    reader: R,
+    current: Vec<u8>,
+    next: Vec<u8>,
+    /// How much of `next` was read ahead, if anything
+    next_len: Option<usize>,
+    index: usize,
+    done: bool,
+}
+
+impl<R: Read> Chunks<R> {
+    fn new(reader: R, size: usize) -> Self {
+        Chunks {
+            reader,
+            current: vec![0; size],
+            next: vec![0; size],
+            next_len: None,
+            index: 0,
+            done: false,
+        }
+    }
+
+    /// Index of the next chunk, the chunk itself, and whether it's the last
+    /// one
+    fn next(&mut self) -> io::Result<Option<(usize, &mut [u8], bool)>> {
+        if self.done {
+            return Ok(None);
+        }
+        let len = match self.next_len.take() {
+            Some(len) => {
+                std::mem::swap(&mut self.current, &mut self.next);
+                len
+            }
+            None => read_full(&mut self.reader, &mut self.current)?,
+        };
+        self.done = len < self.current.len() || {
+            let next_len = read_full(&mut self.reader, &mut self.next)?;
+            self.next_len = Some(next_len);
+            next_len == 0
+        };
+        let index = self.index;
+        self.index += 1;
+        Ok(Some((index, &mut self.current[..len], self.done)))
+    }
+}
+
+/// Read until `buffer` is full or `reader` has nothing more to give,
+/// returning how many bytes were read
+fn read_full(mut reader: impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("EncryptionKey([redacted])")
+    }
+}
+
+fn nonce(index: usize, last: bool) -> Nonce {
+    let mut nonce = [0; aead::NONCE_LEN];
+    nonce[..8].copy_from_slice(&(index as u64).to_be_bytes());
+    nonce[aead::NONCE_LEN - 1] = last as u8;
+    Nonce::assume_unique_for_key(nonce)
+}
+
+/// Size of the content of an encrypted file of `size` bytes
+fn decrypted_size(size: u64) -> Option<u64> {
+    let body = size.checked_sub(HEADER_LEN as u64)?;
+    let chunks = (body + (CHUNK_SIZE + TAG_LEN) as u64 - 1) / (CHUNK_SIZE + TAG_LEN) as u64;
+    body.checked_sub(chunks.max(1) * TAG_LEN as u64)
+}
+
+/// File name used to authenticate the content of `path`
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// File containing the checksum of the encrypted bytes of `path`
+fn ciphertext_checksum_path(path: &str) -> String {
+    format!("{}{}", path, CIPHERTEXT_CHECKSUM_EXTENSION)
+}
+
+/// Storage that encrypts files before passing them to another one
+pub(crate) struct Encrypted {
+    pub(crate) inner: Storage,
+    pub(crate) key: EncryptionKey,
+}
+
+impl fmt::Debug for Encrypted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Encrypted").field(&self.inner).finish()
+    }
+}
+
+impl fmt::Display for Encrypted {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (encrypted)", self.inner)
+    }
+}
+
+impl Encrypted {
+    /// Encrypt `file` (to be added as `target`) into a temporary file
+    fn encrypt(&self, file: &File, target: &Path) -> Result<File> {
+        let name = target
+            .file_name()
+            .and_then(|name| name.to_str())
+            .with_context(|| format!("no file name in `{}`", target.display()))?;
+        let salt = self.key.salt(name, file.reader()?)?;
+        let (out, temp) = TempFile::create()?;
+        let size = self
+            .key
+            .encrypt(name, &salt, file.reader()?, BufWriter::new(out))?;
+
+        let entry = match file {
+            File::InFilesystem(entry) | File::Inline(entry, _) | File::Temporary(entry, _) => {
+                Entry {
+                    size,
+                    ..entry.clone()
+                }
+            }
+        };
+        Ok(File::Temporary(entry, temp))
+    }
+
+    /// Upload `encrypted` to `target`, after the checksum of its bytes
+    ///
+    /// With the checksum uploaded first, there's never a file without one: If
+    /// uploading the file is interrupted, it encrypts to the same bytes when
+    /// it's uploaded again.
+    async fn add_encrypted(
+        &self,
+        encrypted: &File,
+        target: &Path,
+        checksum: Option<&Checksum>,
+    ) -> Result<()> {
+        let path = path_as_string(target)?;
+        let sidecar: Arc<[u8]> = Checksum::sha256(encrypted.reader()?)
+            .with_context(|| format!("calculate checksum of encrypted `{}`", path))?
+            .sidecar(file_name(&path))
+            .into_bytes()
+            .into();
+        let checksum_path = ciphertext_checksum_path(&path);
+        let entry = match encrypted {
+            File::InFilesystem(entry) | File::Inline(entry, _) | File::Temporary(entry, _) => {
+                Entry {
+                    path: checksum_path.clone(),
+                    size: sidecar.len() as u64,
+                    modified: None,
+                    ..entry.clone()
+                }
+            }
+        };
+        self.inner
+            .backend
+            .add_file(&File::Inline(entry, sidecar), Path::new(&checksum_path))
+            .await?;
+
+        match checksum {
+            Some(checksum) => {
+                self.inner
+                    .backend
+                    .add_file_with_checksum(encrypted, target, checksum)
+                    .await
+            }
+            None => self.inner.backend.add_file(encrypted, target).await,
+        }
+    }
+
+    /// Make sure `file`, as downloaded from `path`, has the checksum uploaded
+    /// along with it
+    async fn validate_ciphertext(&self, storage: &Storage, path: &str, file: &File) -> Result<()> {
+        let checksum_path = ciphertext_checksum_path(path);
+        let sidecar = self
+            .inner
+            .backend
+            .get_file(storage, &checksum_path)
+            .await
+            .with_context(|| format!("get checksum of encrypted `{}`", path))?;
+        let mut content = Vec::new();
+        sidecar
+            .reader()?
+            .read_to_end(&mut content)
+            .with_context(|| format!("read `{}`", checksum_path))?;
+        Checksum::from_sidecar(ChecksumAlgorithm::Sha256, &content)
+            .with_context(|| format!("invalid checksum file `{}`", checksum_path))?
+            .validate(file.reader()?)
+            .with_context(|| format!("encrypted `{}` is corrupt", path))
+    }
+}
+
+/// Whether `entry` is the checksum of an encrypted file, instead of a file
+/// itself
+fn is_ciphertext_checksum(entry: &Entry) -> bool {
+    entry.path.ends_with(CIPHERTEXT_CHECKSUM_EXTENSION)
+}
+
+/// Entries list the size of the content, so comparing them with local files
+/// works as before
+fn with_decrypted_size(mut entry: Entry) -> Entry {
+    if let Some(size) = decrypted_size(entry.size) {
+        entry.size = size;
+    }
+    entry
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for Encrypted {
+    async fn list_files(&self, storage: &Storage) -> Result<Vec<Entry>> {
+        let files = self.inner.backend.list_files(storage).await?;
+        Ok(files
+            .into_iter()
+            .filter(|entry| !is_ciphertext_checksum(entry))
+            .map(with_decrypted_size)
+            .collect())
+    }
+
+    fn list_files_stream<'a>(&'a self, storage: &'a Storage) -> BoxStream<'a, Result<Entry>> {
+        self.inner
+            .backend
+            .list_files_stream(storage)
+            .try_filter(|entry| future::ready(!is_ciphertext_checksum(entry)))
+            .map(|entry| entry.map(with_decrypted_size))
+            .boxed()
+    }
+
+    async fn get_file(&self, storage: &Storage, path: &str) -> Result<File> {
+        let file = self.inner.backend.get_file(storage, path).await?;
+        self.validate_ciphertext(storage, path, &file).await?;
+        let (out, temp) = TempFile::create()?;
+        let size = self
+            .key
+            .decrypt(file_name(path), file.reader()?, BufWriter::new(out))
+            .note("Is the encryption key the one files were uploaded with?")?;
+
+        let entry = match file {
+            File::InFilesystem(entry) | File::Inline(entry, _) | File::Temporary(entry, _) => {
+                Entry { size, ..entry }
+            }
+        };
+        Ok(File::Temporary(entry, temp))
+    }
+
+    async fn add_file(&self, file: &File, target: &Path) -> Result<()> {
+        let encrypted = self.encrypt(file, target)?;
+        self.add_encrypted(&encrypted, target, None).await
+    }
+
+    /// The checksum is of the unencrypted content, so it's the same for every
+    /// key and passed on as is
+    async fn add_file_with_checksum(
+        &self,
+        file: &File,
+        target: &Path,
+        checksum: &Checksum,
+    ) -> Result<()> {
+        let encrypted = self.encrypt(file, target)?;
+        self.add_encrypted(&encrypted, target, Some(checksum)).await
+    }
+
+    /// Removes the checksum of the encrypted bytes after the file, so
+    /// there's never a file without one
+    async fn remove_file(&self, path: &str) -> Result<()> {
+        self.inner.backend.remove_file(path).await?;
+        let checksum_path = ciphertext_checksum_path(path);
+        if let Err(e) = self.inner.backend.remove_file(&checksum_path).await {
+            log::debug!("no checksum file `{}` to remove: {}", checksum_path, e);
+        }
+        Ok(())
+    }
+
+    /// Files are never usable from disk directly
+    fn local_path(&self) -> Option<PathBuf> {
+        None
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        self.inner.modified()
+    }
+}
+
+#[cfg(test)]
+impl EncryptionKey {
+    /// Encrypt `content` as `name` in memory, like [`Encrypted`] would
+    pub(crate) fn encrypt_bytes(&self, name: &str, content: &[u8]) -> Result<Vec<u8>> {
+        let salt = self.salt(name, content)?;
+        let mut encrypted = Vec::new();
+        self.encrypt(name, &salt, content, &mut encrypted)?;
+        Ok(encrypted)
+    }
+
+    pub(crate) fn decrypt_bytes(&self, name: &str, encrypted: &[u8]) -> Result<Vec<u8>> {
+        let mut content = Vec::new();
+        self.decrypt(name, encrypted, &mut content)?;
+        Ok(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encrypted_size(size: u64) -> u64 {
+        let chunks = ((size + CHUNK_SIZE as u64 - 1) / CHUNK_SIZE as u64).max(1);
+        HEADER_LEN as u64 + size + chunks * TAG_LEN as u64
+    }
+
+    #[test]
+    fn encryption_roundtrip() {
+        let key = EncryptionKey::from_passphrase(b"hunter2").unwrap();
+        for size in [
+            0,
+            1,
+            CHUNK_SIZE - 1,
+            CHUNK_SIZE,
+            CHUNK_SIZE + 1,
+            3 * CHUNK_SIZE,
+        ] {
+            let content: Vec<u8> = (0..size).map(|i| i as u8).collect();
+            let encrypted = key.encrypt_bytes("1.0.0.tar.zst", &content).unwrap();
+            assert_eq!(encrypted.len() as u64, encrypted_size(size as u64));
+            assert_eq!(decrypted_size(encrypted.len() as u64), Some(size as u64));
+            assert_ne!(&encrypted[HEADER_LEN..], &content[..]);
+            assert_eq!(
+                key.decrypt_bytes("1.0.0.tar.zst", &encrypted).unwrap(),
+                content
+            );
+        }
+    }
+
+    #[test]
+    fn same_files_encrypt_the_same() {
+        let key = EncryptionKey::from_passphrase(b"hunter2").unwrap();
+        let content = vec![42; CHUNK_SIZE + 1];
+        let encrypted = key.encrypt_bytes("1.0.0.tar.zst", &content).unwrap();
+        assert_eq!(
+            key.encrypt_bytes("1.0.0.tar.zst", &content).unwrap(),
+            encrypted
+        );
+
+        let mut changed = content.clone();
+        changed[CHUNK_SIZE] = 0;
+        for other in [
+            key.encrypt_bytes("2.0.0.tar.zst", &content).unwrap(),
+            key.encrypt_bytes("1.0.0.tar.zst", &changed).unwrap(),
+            EncryptionKey::from_passphrase(b"hunter3")
+                .unwrap()
+                .encrypt_bytes("1.0.0.tar.zst", &content)
+                .unwrap(),
+        ] {
+            assert_ne!(
+                other[MAGIC.len()..HEADER_LEN],
+                encrypted[MAGIC.len()..HEADER_LEN]
+            );
+        }
+    }
+
+    #[test]
+    fn tampered_files_do_not_decrypt() {
+        let key = EncryptionKey::from_passphrase(b"hunter2").unwrap();
+        let content = vec![42; 2 * CHUNK_SIZE];
+        let encrypted = key.encrypt_bytes("1.0.0.tar.zst", &content).unwrap();
+
+        let other_key = EncryptionKey::from_passphrase(b"hunter3").unwrap();
+        assert!(other_key
+            .decrypt_bytes("1.0.0.tar.zst", &encrypted)
+            .is_err());
+        assert!(key.decrypt_bytes("2.0.0.tar.zst", &encrypted).is_err());
+
+        let truncated = &encrypted[..HEADER_LEN + CHUNK_SIZE + TAG_LEN];
+        assert!(key.decrypt_bytes("1.0.0.tar.zst", truncated).is_err());
+
+        let mut flipped = encrypted.clone();
+        flipped[HEADER_LEN + 3] ^= 1;
+        assert!(key.decrypt_bytes("1.0.0.tar.zst", &flipped).is_err());
+
+        assert!(key.decrypt_bytes("1.0.0.tar.zst", &content).is_err());
+        assert!(key
+            .decrypt_bytes("1.0.0.tar.zst", &encrypted[..HEADER_LEN])
+            .is_err());
+    }
+}
@@ -1,12 +1,14 @@
 use crate::{paths, Storage};
 use erreur::{Context, Result};
-use std::{fmt, path::Path};
+use std::{fmt, path::Path, time::SystemTime};
 
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Entry {
     pub storage: Storage,
     pub path: String,
     pub size: u64,
+    /// When the file was last changed, if the storage tells us
+    pub modified: Option<SystemTime>,
 }
 
 impl Entry {
@@ -16,20 +18,18 @@ impl Entry {
             .canonicalize()
             .with_context(|| format!("cannot canonicalize path `{}`", path.display()))?;
 
-        let size = path
-            .metadata()
-            .with_context(|| {
-                format!(
-                    "can't read metadata for new build file `{}`",
-                    path.display()
-                )
-            })?
-            .len();
+        let metadata = path.metadata().with_context(|| {
+            format!(
+                "can't read metadata for new build file `{}`",
+                path.display()
+            )
+        })?;
 
         Ok(Entry {
             storage,
             path: paths::path_as_string(path)?,
-            size,
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
         })
     }
 }
@@ -1,15 +1,129 @@
-use super::{InnerStorage, Storage};
-use std::path::PathBuf;
+use super::{Entry, File, Storage, StorageBackend};
+use crate::{paths::path_as_string, PartialFile};
+use erreur::{ensure, Context, Result};
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use std::{
+    fmt,
+    fs::{self, read_dir},
+    io::Write,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
 
-impl Storage {
-    pub fn is_local(&self) -> bool {
-        matches!(*self.inner, InnerStorage::Filesystem(_))
+/// Directory on disk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Filesystem(pub(super) PathBuf);
+
+impl fmt::Display for Filesystem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "filesystem (`{}`)", self.0.display())
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for Filesystem {
+    async fn list_files(&self, storage: &Storage) -> Result<Vec<Entry>> {
+        self.list_files_stream(storage).try_collect().await
+    }
+
+    fn list_files_stream<'a>(&'a self, storage: &'a Storage) -> BoxStream<'a, Result<Entry>> {
+        let path = &self.0;
+        let dir = match read_dir(path)
+            .with_context(|| format!("could not read directory `{}`", path.display()))
+        {
+            Ok(dir) => dir,
+            Err(e) => return stream::once(async { Err(e) }).boxed(),
+        };
+        let entries = dir.filter_map(move |entry| {
+            let file = || -> Result<_> {
+                let entry = entry.context("could not read file entry")?;
+                let path = entry.path();
+                // symlinks like `current` may point at builds that are gone
+                let metadata = entry
+                    .metadata()
+                    .with_context(|| format!("could not read metadata of `{}`", path.display()))?;
+                if metadata.file_type().is_symlink() {
+                    return Ok(None);
+                }
+                let path = path
+                    .canonicalize()
+                    .with_context(|| format!("cannot canonicalize path `{}`", path.display()))?;
+
+                Ok(Some(Entry {
+                    storage: storage.clone(),
+                    path: path_as_string(path)?,
+                    size: metadata.len(),
+                    modified: metadata.modified().ok(),
+                }))
+            };
+            file().transpose()
+        });
+        stream::iter(entries).boxed()
+    }
+
+    async fn get_file(&self, storage: &Storage, path: &str) -> Result<File> {
+        let path = self.0.join(path);
+        ensure!(path.exists(), "Path `{}` does not exist", path.display());
+        let metadata = path
+            .metadata()
+            .with_context(|| format!("read metadata of `{}`", path.display()))?;
+
+        Ok(File::InFilesystem(Entry {
+            storage: storage.clone(),
+            path: path_as_string(path)?,
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        }))
+    }
+
+    async fn add_file(&self, file: &File, target: &Path) -> Result<()> {
+        let root = &self.0;
+        let new_path = if target.is_absolute() {
+            ensure!(
+                target.starts_with(root),
+                "build target path is absolute but not in storage directory"
+            );
+
+            target.to_path_buf()
+        } else {
+            root.join(target)
+        };
+
+        // a failed copy must not leave half a build in the store
+        let mut new_file = PartialFile::create(&new_path)
+            .with_context(|| format!("create `{}`", new_path.display()))?;
+        match file {
+            File::InFilesystem(entry) | File::Temporary(entry, _) => {
+                std::io::copy(&mut file.reader()?, &mut new_file).with_context(|| {
+                    format!("copy `{}` to `{}`", entry.path, new_path.display())
+                })?;
+            }
+            File::Inline(_, content) => {
+                new_file
+                    .write_all(content)
+                    .context("write content of file")?;
+            }
+        };
+        new_file.finish().context("finish writing to new file")?;
+        Ok(())
+    }
+
+    async fn remove_file(&self, path: &str) -> Result<()> {
+        let root = &self.0;
+        let path = root.join(path);
+        ensure!(
+            path.starts_with(root),
+            "path `{}` is not in storage directory",
+            path.display()
+        );
+        fs::remove_file(&path).with_context(|| format!("could not remove `{}`", path.display()))
+    }
+
+    fn local_path(&self) -> Option<PathBuf> {
+        Some(self.0.clone())
     }
 
-    pub fn local_path(&self) -> Option<PathBuf> {
-        match *self.inner {
-            InnerStorage::Filesystem(ref p) => Some(p.clone()),
-            _ => None,
-        }
+    fn modified(&self) -> Option<SystemTime> {
+        fs::metadata(&self.0).and_then(|m| m.modified()).ok()
     }
 }
@@ -1,35 +1,47 @@
-use crate::{paths::path_as_string, PartialFile};
-use erreur::{bail, ensure, Context, Help, Report, Result, StdResult};
-pub use std::{
-    convert::{TryFrom, TryInto},
+use crate::index::Checksum;
+use erreur::{bail, ensure, Context, Report, Result};
+use futures::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use std::{
+    cmp::Ordering,
+    convert::TryFrom,
     fmt,
-    fs::{self, read_dir},
-    io::{BufWriter, Write},
+    hash::{Hash, Hasher},
     path::{Path, PathBuf},
     str::FromStr,
     sync::Arc,
-    time::Duration,
+    time::SystemTime,
 };
 use url::Url;
 
+mod encrypted;
 mod entry;
 mod local;
+mod multipart;
 mod s3;
+mod throttle;
 
+pub use encrypted::EncryptionKey;
 pub use entry::Entry;
+use local::Filesystem;
+pub use throttle::set_max_bandwidth;
 
 /// Storage abstraction
 ///
 /// Cheap to clone, but immutable.
 ///
-/// # Variants
+/// # Backends
 ///
 /// - Local file system: Some directory on disk
 /// - S3: An S3 bucket, identified by a URL
+/// - Encrypted: Any of these, with files encrypted by a key (see
+///   [`Storage::encrypted`])
 ///
 ///   NOTE: For connecting to S3, the necessary credentials are read from env
 ///   variables by default. See [this page][1] for more details.
 ///
+/// Other kinds of storage can be used by implementing [`StorageBackend`]
+/// and wrapping it with [`Storage::from_backend`].
+///
 /// [1]: https://github.com/rusoto/rusoto/blob/e7ed8eabbb758bda4a857436ca572114de2bf283/AWS-CREDENTIALS.md
 ///
 /// # Examples
@@ -45,52 +57,110 @@ pub use entry::Entry;
 /// assert!(local_dir.is_local());
 /// assert!(local_dir.local_path().is_some());
 /// ```
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Clone)]
 pub struct Storage {
-    inner: Arc<InnerStorage>,
+    backend: Arc<dyn StorageBackend>,
+    /// `Debug` representation of the backend, which storages are compared by
+    id: Arc<str>,
+}
+
+/// Where a [`Storage`] keeps its files
+///
+/// Paths are relative to the root of the storage. Entries and files returned
+/// by a backend belong to `storage`, the [`Storage`] it is wrapped in.
+///
+/// The `Debug` representation has to identify the storage: Storages are equal
+/// when it is, and listings of remote storage are only reused for the same
+/// one (see `--cache-max-age`).
+#[async_trait::async_trait]
+pub trait StorageBackend: fmt::Debug + fmt::Display + Send + Sync + 'static {
+    /// All files in the storage
+    async fn list_files(&self, storage: &Storage) -> Result<Vec<Entry>>;
+
+    /// All files in the storage, as they are listed
+    ///
+    /// By default, lists all files with [`StorageBackend::list_files`] first.
+    /// Backends that list files in parts (like pages of S3 objects) should
+    /// hand out each part as soon as they have it.
+    fn list_files_stream<'a>(&'a self, storage: &'a Storage) -> BoxStream<'a, Result<Entry>> {
+        stream::once(self.list_files(storage))
+            .map_ok(|files| stream::iter(files.into_iter().map(Ok)))
+            .try_flatten()
+            .boxed()
+    }
+
+    /// Get a file, either as a path on disk or with its content
+    async fn get_file(&self, storage: &Storage, path: &str) -> Result<File>;
+
+    /// Write `file` to `target`, replacing any file that's there
+    async fn add_file(&self, file: &File, target: &Path) -> Result<()>;
+
+    /// Add a file whose (unencrypted) content has `checksum`
+    ///
+    /// Backends that resume interrupted uploads use the checksum to find them
+    /// again. By default, this is [`StorageBackend::add_file`].
+    async fn add_file_with_checksum(
+        &self,
+        file: &File,
+        target: &Path,
+        _checksum: &Checksum,
+    ) -> Result<()> {
+        self.add_file(file, target).await
+    }
+
+    async fn remove_file(&self, path: &str) -> Result<()>;
+
+    /// Directory the files are in, if they are on this machine
+    fn local_path(&self) -> Option<PathBuf> {
+        None
+    }
+
+    /// When files were last added to or removed from the storage, if we can
+    /// tell without listing it
+    fn modified(&self) -> Option<SystemTime> {
+        None
+    }
 }
 
 impl fmt::Display for Storage {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self.inner.as_ref() {
-            InnerStorage::Filesystem(root) => write!(f, "filesystem (`{}`)", root.display()),
-            InnerStorage::S3(b) => write!(f, "S3 ({})", b.bucket),
-        }
+        fmt::Display::fmt(&self.backend, f)
     }
 }
 
 impl fmt::Debug for Storage {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self.inner.as_ref() {
-            InnerStorage::Filesystem(root) => {
-                f.debug_tuple("Filesystem").field(root).finish()?;
-            }
-            InnerStorage::S3(b) => {
-                f.debug_tuple("S3")
-                    .field(&b.endpoint)
-                    .field(&b.path)
-                    .finish()?;
-            }
-        }
-        Ok(())
+        f.write_str(&self.id)
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-enum InnerStorage {
-    Filesystem(PathBuf),
-    S3(s3::Bucket),
+impl PartialEq for Storage {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
 }
 
-impl From<InnerStorage> for Storage {
-    fn from(inner: InnerStorage) -> Self {
-        Storage {
-            inner: Arc::new(inner),
-        }
+impl Eq for Storage {}
+
+impl PartialOrd for Storage {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
-impl<'p> TryFrom<&'p Path> for Storage {
+impl Ord for Storage {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.id.cmp(&other.id)
+    }
+}
+
+impl Hash for Storage {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl TryFrom<&Path> for Storage {
     type Error = Report;
 
     fn try_from(path: &Path) -> Result<Self> {
@@ -98,7 +168,7 @@ impl<'p> TryFrom<&'p Path> for Storage {
         let path = path
             .canonicalize()
             .with_context(|| format!("cannot canonicalize path `{}`", path.display()))?;
-        Ok(InnerStorage::Filesystem(path).into())
+        Ok(Storage::from_backend(Filesystem(path)))
     }
 }
 
@@ -116,281 +186,203 @@ impl FromStr for Storage {
     fn from_str(s: &str) -> Result<Self> {
         let path = PathBuf::from(s);
         if path.exists() {
-            return Ok(InnerStorage::Filesystem(path).into());
+            return Ok(Storage::from_backend(Filesystem(path)));
         }
 
         let url = Url::from_str(s).with_context(|| format!("invalid URL `{}`", s))?;
         match url.scheme() {
-            "s3" => Ok(InnerStorage::S3(
+            "s3" => Ok(Storage::from_backend(
                 s3::Bucket::try_from(&url)
                     .with_context(|| format!("convert `{}` to S3 bucket", url))?,
-            )
-            .into()),
+            )),
             scheme => bail!("unsupported protocol `{}`", scheme),
         }
     }
 }
 
 impl Storage {
-    pub async fn list_files(&self) -> Result<Vec<Entry>> {
-        match self.inner.as_ref() {
-            InnerStorage::Filesystem(path) => Ok(read_dir(&path)
-                .with_context(|| format!("could not read directory `{}`", path.display()))?
-                .map(|entry| -> Result<_> {
-                    let entry = entry.context("could not read file entry")?;
-                    let path = entry.path();
-                    let path = path.canonicalize().with_context(|| {
-                        format!("cannot canonicalize path `{}`", path.display())
-                    })?;
-                    let metadata = entry.metadata().with_context(|| {
-                        format!("could not read metadata of `{}`", path.display())
-                    })?;
-
-                    Ok((metadata, path_as_string(path)?))
-                })
-                .collect::<Result<Vec<_>>>()?
-                .into_iter()
-                .filter(|(metadata, _)| !metadata.file_type().is_symlink())
-                .map(|(metadata, path)| Entry {
-                    storage: self.clone(),
-                    path,
-                    size: metadata.len(),
-                })
-                .collect::<Vec<_>>()),
-            InnerStorage::S3(bucket) => {
-                use rusoto_s3::{ListObjectsV2Request, S3Client, S3};
-
-                let client: S3Client = bucket.try_into().context("build S3 client")?;
-
-                let res = client
-                    .list_objects_v2(ListObjectsV2Request {
-                        bucket: bucket.bucket.to_owned(),
-                        prefix: Some(bucket.path.trim_start_matches('/').to_string()),
-                        ..Default::default()
-                    })
-                    .await
-                    .context("list files in bucket")?;
-                if res.is_truncated.unwrap_or_default() {
-                    log::debug!("didn't get all the files -- pagination not implemented!");
-                }
-
-                res.contents
-                    .unwrap_or_default()
-                    .iter()
-                    .map(|obj| {
-                        Ok(Entry {
-                            storage: self.clone(),
-                            path: obj.key.clone().context("got an object with no key")?,
-                            size: obj
-                                .size
-                                .map(|s| s as u64)
-                                .context("got an object with no size")?,
-                        })
-                    })
-                    .collect::<Result<Vec<_>>>()
-                    .context("parsing file list from S3")
-            }
+    /// Use a custom kind of storage
+    pub fn from_backend(backend: impl StorageBackend) -> Self {
+        let id = format!("{:?}", backend).into();
+        Storage {
+            backend: Arc::new(backend),
+            id,
         }
     }
 
+    /// Encrypt files before storing them, and decrypt them when getting them
+    ///
+    /// Sizes of listed files are those of their content. See the
+    /// `encrypted` module for how files are encrypted.
+    pub fn encrypted(self, key: EncryptionKey) -> Self {
+        Storage::from_backend(encrypted::Encrypted { inner: self, key })
+    }
+
+    pub fn is_local(&self) -> bool {
+        self.local_path().is_some()
+    }
+
+    pub fn local_path(&self) -> Option<PathBuf> {
+        self.backend.local_path()
+    }
+
+    /// When files were last added to or removed from the storage, if we can
+    /// tell without listing it
+    pub(crate) fn modified(&self) -> Option<SystemTime> {
+        self.backend.modified()
+    }
+
+    pub async fn list_files(&self) -> Result<Vec<Entry>> {
+        self.backend.list_files(self).await
+    }
+
+    /// Like [`Storage::list_files`], but without waiting for all files to be
+    /// listed
+    pub fn list_files_stream(&self) -> BoxStream<'_, Result<Entry>> {
+        self.backend.list_files_stream(self)
+    }
+
     pub async fn get_file(&self, path: &str) -> Result<File> {
-        match self.inner.as_ref() {
-            InnerStorage::Filesystem(root) => {
-                let path = root.join(path);
-                ensure!(path.exists(), "Path `{}` does not exist", path.display());
-                let size = path
-                    .metadata()
-                    .with_context(|| format!("read metadata of `{}`", path.display()))?
-                    .len();
-
-                Ok(File::InFilesystem(Entry {
-                    storage: self.clone(),
-                    path: path_as_string(path)?,
-                    size,
-                }))
-            }
-            InnerStorage::S3(bucket) => {
-                use async_read_progress::*;
-                use rusoto_s3::{GetObjectRequest, S3Client, S3};
-                use tokio::io::AsyncReadExt;
-
-                let key = bucket.key_for(path);
-                let client: S3Client = bucket.try_into().context("build S3 client")?;
-
-                let result = client
-                    .get_object(GetObjectRequest {
-                        bucket: bucket.bucket.to_owned(),
-                        key: key.clone(),
-                        ..Default::default()
-                    })
-                    .await
-                    .with_context(|| format!("Couldn't get object with path `{}`", key))?;
-
-                let checksum = result.e_tag.context("object has no checksum")?;
-
-                let size = result
-                    .content_length
-                    .map(|s| s as u64)
-                    .context("got an object with no size")?;
-
-                let mut stream = result
-                    .body
-                    .context("object without body")?
-                    .into_async_read()
-                    .report_progress(Duration::from_secs(2), |bytes_read| {
-                        use humansize::{file_size_opts as options, FileSize};
-
-                        log::info!(
-                            "reading `{}`… {}/{}",
-                            key,
-                            bytes_read
-                                .file_size(options::BINARY)
-                                .expect("never negative"),
-                            size.file_size(options::BINARY).expect("never negative")
-                        )
-                    });
-
-                log::debug!("fetching `{}` from S3", key);
-                let mut body = Vec::new();
-                stream
-                    .read_to_end(&mut body)
-                    .await
-                    .context("failed to read object content into buffer")
-                    .note("S3 has bad days just like the rest of us")?;
-
-                log::info!("downloaded `{}` from S3", key);
-                s3::validate_checksum(&key, &body, &checksum)
-                    .with_context(|| format!("checksum mismatch for file `{}`", key))?;
-
-                let entry = Entry {
-                    storage: self.clone(),
-                    path: key.to_owned(),
-                    size: result
-                        .content_length
-                        .map(|s| s as u64)
-                        .context("got an object with no size")
-                        .with_suggestion(|| {
-                            format!(
-                                "Best check whether the upload of `{}` \
-                                was successful using S3/DigitalOceans web interface",
-                                key
-                            )
-                        })?,
-                };
-
-                Ok(File::Inline(entry, body.into_boxed_slice().into()))
-            }
-        }
+        self.backend.get_file(self, path).await
     }
 
     pub async fn add_file(&self, file: &File, target: impl AsRef<Path>) -> Result<()> {
         log::debug!("adding file {:?} to `{}`", file, self);
-        let target = target.as_ref();
-
-        match self.inner.as_ref() {
-            InnerStorage::Filesystem(root) => {
-                let new_path = if target.is_absolute() {
-                    ensure!(
-                        target.starts_with(&root),
-                        "build target path is absolute but not in storage directory"
-                    );
-
-                    target.to_path_buf()
-                } else {
-                    root.join(target)
-                };
-
-                match file {
-                    File::InFilesystem(entry) => {
-                        fs::copy(&entry.path, &new_path).with_context(|| {
-                            format!("copy `{}` to `{}`", entry.path, new_path.display())
-                        })?;
-                    }
-                    File::Inline(_, content) => {
-                        let mut new_file = PartialFile::create(&new_path)
-                            .with_context(|| format!("create `{}`", new_path.display()))?;
-                        new_file
-                            .write_all(content)
-                            .context("write content of file")?;
-                        new_file.finish().context("finish writing to new file")?;
-                    }
-                };
-            }
+        self.backend.add_file(file, target.as_ref()).await
+    }
 
-            InnerStorage::S3(bucket) => {
-                use rusoto_core::{request::BufferedHttpResponse, RusotoError};
-                use rusoto_s3::{PutObjectError, PutObjectRequest, S3Client, S3};
-
-                fn try_parse_s3_error<T>(
-                    res: StdResult<T, RusotoError<PutObjectError>>,
-                ) -> Result<T> {
-                    match res {
-                        Ok(x) => Ok(x),
-                        Err(RusotoError::Unknown(BufferedHttpResponse {
-                            status,
-                            ref body,
-                            ..
-                        })) => {
-                            let pattern = b"<Code>BadDigest</Code>";
-                            if body
-                                .windows(pattern.len())
-                                .any(move |sub_slice| sub_slice == pattern)
-                            {
-                                res.context("S3 checksum failure")
-                                    .warning("Checksum failures can mean data is corrupted")
-                            } else {
-                                let msg = format!(
-                                    "S3 responded with status `{}` and body: `{}`",
-                                    status,
-                                    String::from_utf8_lossy(body),
-                                );
-                                res.context(msg)
-                            }
-                        }
-                        Err(e) => Err(Report::new(e)),
-                    }
-                }
-
-                let client: S3Client = bucket.try_into().context("build S3 client")?;
-
-                let content = match file {
-                    File::InFilesystem(entry) => fs::read(&entry.path)
-                        .with_context(|| format!("could not read `{}`", entry.path))?,
-                    File::Inline(_, content) => content.to_vec(),
-                };
-
-                let key = bucket.key_for(&path_as_string(target)?);
-                log::debug!("adding file as `{}`", key);
-                let checksum = md5::compute(&content);
-                let response = client
-                    .put_object(PutObjectRequest {
-                        bucket: bucket.bucket.to_owned(),
-                        key: key.clone(),
-                        content_md5: Some(base64::encode(&*checksum)),
-                        body: Some(content.into()),
-                        ..Default::default()
-                    })
-                    .await;
-                let response = try_parse_s3_error(response);
-                response
-                    .with_context(|| format!("Failed to upload object `{}` to S3", key))
-                    .note("S3 has bad days just like the rest of us")?;
+    /// Add a file, with the checksum of its content if the index has one
+    pub async fn add_file_with_checksum(
+        &self,
+        file: &File,
+        target: impl AsRef<Path>,
+        checksum: Option<&Checksum>,
+    ) -> Result<()> {
+        match checksum {
+            Some(checksum) => {
+                log::debug!("adding file {:?} ({}) to `{}`", file, checksum, self);
+                self.backend
+                    .add_file_with_checksum(file, target.as_ref(), checksum)
+                    .await
             }
+            None => self.add_file(file, target).await,
         }
-        Ok(())
+    }
+
+    pub async fn remove_file(&self, path: &str) -> Result<()> {
+        log::debug!("removing file `{}` from `{}`", path, self);
+        self.backend.remove_file(path).await
     }
 }
 
+/// File in a [`Storage`], either on disk or with its content
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum File {
     InFilesystem(Entry),
     Inline(Entry, Arc<[u8]>),
+    /// Content in a temporary file, e.g. decrypted from an encrypted storage
+    ///
+    /// The entry's path is the file's path in the storage, not the temporary
+    /// file's.
+    Temporary(Entry, TempFile),
+}
+
+/// Temporary file (in the system's temp dir, e.g. `$TMPDIR`) that's removed
+/// when the last clone of it is dropped, or when we're interrupted
+#[derive(Clone)]
+pub struct TempFile(Arc<TempFileInner>);
+
+struct TempFileInner {
+    path: tempfile::TempPath,
+    _cleanup: crate::interrupt::Registration,
+}
+
+impl TempFile {
+    /// Create an empty temporary file, returning it opened for writing
+    pub(crate) fn create() -> Result<(std::fs::File, Self)> {
+        let (file, path) = tempfile::NamedTempFile::new()
+            .context("create temporary file")?
+            .into_parts();
+        let cleanup = crate::interrupt::on_interrupt({
+            let path = path.to_path_buf();
+            async move {
+                let _ = std::fs::remove_file(path);
+            }
+        });
+        let inner = TempFileInner {
+            path,
+            _cleanup: cleanup,
+        };
+        Ok((file, TempFile(Arc::new(inner))))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0.path
+    }
+}
+
+impl PartialEq for TempFile {
+    fn eq(&self, other: &Self) -> bool {
+        self.path() == other.path()
+    }
+}
+
+impl Eq for TempFile {}
+
+impl PartialOrd for TempFile {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TempFile {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.path().cmp(other.path())
+    }
+}
+
+impl Hash for TempFile {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.path().hash(state);
+    }
+}
+
+impl fmt::Debug for TempFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("TempFile").field(&self.path()).finish()
+    }
 }
 
 impl File {
     pub fn copy_to_local(self, _storage: Storage) -> Result<Self> {
         todo!()
     }
+
+    /// Size of the file in bytes
+    pub fn size(&self) -> u64 {
+        match self {
+            File::InFilesystem(entry) | File::Inline(entry, _) | File::Temporary(entry, _) => {
+                entry.size
+            }
+        }
+    }
+
+    /// Read the file's content
+    pub fn reader(&self) -> Result<Box<dyn std::io::Read + Send>> {
+        match self {
+            File::InFilesystem(entry) => {
+                let file = std::fs::File::open(&entry.path)
+                    .with_context(|| format!("open file `{}`", entry.path))?;
+                Ok(Box::new(std::io::BufReader::new(file)))
+            }
+            File::Inline(_, content) => Ok(Box::new(std::io::Cursor::new(content.clone()))),
+            File::Temporary(entry, temp) => {
+                let file = std::fs::File::open(temp.path())
+                    .with_context(|| format!("open temporary file of `{}`", entry.path))?;
+                Ok(Box::new(std::io::BufReader::new(file)))
+            }
+        }
+    }
 }
 
 impl fmt::Debug for File {
@@ -402,6 +394,115 @@ impl fmt::Debug for File {
                 .field(e)
                 .field(&format_args!("[bytes]"))
                 .finish(),
+            File::Temporary(e, temp) => f.debug_tuple("TempFile").field(e).field(temp).finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_helpers::*, ArtefactIndex};
+    use std::{collections::BTreeMap, sync::Mutex};
+
+    /// Files in memory, like a storage service would keep them
+    #[derive(Default)]
+    struct Memory {
+        name: &'static str,
+        files: Mutex<BTreeMap<String, Arc<[u8]>>>,
+    }
+
+    impl fmt::Debug for Memory {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.debug_tuple("Memory").field(&self.name).finish()
+        }
+    }
+
+    impl fmt::Display for Memory {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "memory ({})", self.name)
         }
     }
+
+    #[async_trait::async_trait]
+    impl StorageBackend for Memory {
+        async fn list_files(&self, storage: &Storage) -> Result<Vec<Entry>> {
+            let files = self.files.lock().unwrap();
+            Ok(files
+                .iter()
+                .map(|(path, content)| Entry {
+                    storage: storage.clone(),
+                    path: path.clone(),
+                    size: content.len() as u64,
+                    modified: None,
+                })
+                .collect())
+        }
+
+        async fn get_file(&self, storage: &Storage, path: &str) -> Result<File> {
+            let files = self.files.lock().unwrap();
+            let content = files.get(path).context("no such file")?;
+            let entry = Entry {
+                storage: storage.clone(),
+                path: path.to_owned(),
+                size: content.len() as u64,
+                modified: None,
+            };
+            Ok(File::Inline(entry, content.clone()))
+        }
+
+        async fn add_file(&self, file: &File, target: &Path) -> Result<()> {
+            let mut content = Vec::new();
+            file.reader()?.read_to_end(&mut content)?;
+            let path = crate::paths::path_as_string(target)?;
+            self.files.lock().unwrap().insert(path, content.into());
+            Ok(())
+        }
+
+        async fn remove_file(&self, path: &str) -> Result<()> {
+            self.files
+                .lock()
+                .unwrap()
+                .remove(path)
+                .context("no such file")?;
+            Ok(())
+        }
+    }
+
+    fn memory(name: &'static str) -> Storage {
+        Storage::from_backend(Memory {
+            name,
+            ..Memory::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn custom_backend_as_remote() -> Result<()> {
+        let remote = memory("builds");
+        assert!(!remote.is_local());
+        assert_eq!(remote, memory("builds"));
+        assert_ne!(remote, memory("other"));
+
+        let local = tempdir()?;
+        let build = random_zstd_file(local.path().join("build1.tar.zst"))?;
+        let index = ArtefactIndex::new(local.path(), remote.clone()).await?;
+        index.push(false).await?;
+        let uploaded = remote.list_files().await?;
+        assert!(
+            uploaded.iter().any(|entry| entry.path == "build1.tar.zst"),
+            "{:?}",
+            uploaded
+        );
+        let streamed: Vec<Entry> = remote.list_files_stream().try_collect().await?;
+        assert_eq!(streamed, uploaded);
+
+        let other_local = tempdir()?;
+        let mut index = ArtefactIndex::new(other_local.path(), remote.clone()).await?;
+        let entry = index.get_build("build1".parse()?).await?;
+        assert_eq!(crate::decompress(std::fs::File::open(&entry.path)?)?, build);
+
+        remote.remove_file("build1.tar.zst").await?;
+        assert!(remote.get_file("build1.tar.zst").await.is_err());
+        Ok(())
+    }
 }
@@ -0,0 +1,541 @@
+//! Resumable multipart uploads
+//!
+//! Large files are uploaded to S3 in parts of [`PART_SIZE`] bytes. The parts
+//! that are done are recorded in a state file in the local store (in
+//! [`STATE_DIR`]), named after the bucket, the key, and the checksum of the
+//! file the index knows. That's the checksum of the unencrypted content, so an
+//! interrupted upload to an encrypted remote is found again even though the
+//! file is encrypted anew. When an upload is interrupted, running `sync` again
+//! only uploads the missing parts: a part is reused when its ETag (the MD5 of
+//! what was uploaded) matches the part we'd upload now, and uploaded again
+//! otherwise (e.g. when the encryption key changed in between). Uploads started more than [`STALE_AFTER`] ago are
+//! aborted instead of resumed, so S3 doesn't keep their parts forever.
+//!
+//! Uploads that fail can be resumed, but when the process is interrupted
+//! (Ctrl-C or SIGTERM), the upload is aborted and its state file removed, so
+//! nothing is left behind (see [`crate::interrupt`]).
+
+use crate::interrupt;
+use erreur::{Context, Result};
+use futures::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Bytes per part (S3 wants at least 5 MiB for all but the last one)
+pub(crate) const PART_SIZE: usize = 16 * 1024 * 1024;
+/// Files at least this large are uploaded in parts
+pub(crate) const THRESHOLD: usize = 4 * PART_SIZE;
+/// Directory in the local store with the state of unfinished uploads
+pub(crate) const STATE_DIR: &str = ".artefacta-uploads";
+/// Age after which unfinished uploads are given up
+const STALE_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Where parts are uploaded to
+#[async_trait::async_trait]
+pub(crate) trait Parts: Send + Sync {
+    /// Start an upload, returning its ID
+    async fn create(&self, key: &str) -> Result<String>;
+
+    /// Upload part `number` (counting from 1), returning its ETag
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        number: i64,
+        content: &[u8],
+    ) -> Result<String>;
+
+    /// Put the parts (numbers and ETags) together
+    async fn complete(&self, key: &str, upload_id: &str, parts: Vec<(i64, String)>) -> Result<()>;
+
+    async fn abort(&self, key: &str, upload_id: &str) -> Result<()> {
+        self.aborting(key, upload_id).await
+    }
+
+    /// Abort an upload, with a future that doesn't borrow `self` (to run
+    /// when interrupted)
+    fn aborting(&self, key: &str, upload_id: &str) -> BoxFuture<'static, Result<()>>;
+
+    /// Uploads that were started but neither completed nor aborted
+    async fn pending(&self) -> Result<Vec<Pending>>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Pending {
+    pub(crate) key: String,
+    pub(crate) upload_id: String,
+    pub(crate) started: Option<SystemTime>,
+}
+
+/// Content of a state file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct State {
+    key: String,
+    upload_id: String,
+    /// Seconds since the Unix epoch
+    started: u64,
+    part_size: usize,
+    /// ETags of the uploaded parts, by number
+    parts: BTreeMap<i64, String>,
+}
+
+impl State {
+    fn read(path: &Path) -> Option<State> {
+        let content = fs::read(path).ok()?;
+        match serde_json::from_slice(&content) {
+            Ok(state) => Some(state),
+            Err(e) => {
+                log::debug!("ignoring invalid upload state `{}`: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    fn write(&self, path: &Path) -> Result<()> {
+        let dir = path.parent().context("state file has no directory")?;
+        fs::create_dir_all(dir).with_context(|| format!("create directory `{}`", dir.display()))?;
+        let content = serde_json::to_vec(self).context("serialize upload state")?;
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, content).with_context(|| format!("write `{}`", temp.display()))?;
+        fs::rename(&temp, path).with_context(|| format!("write `{}`", path.display()))
+    }
+
+    fn is_stale(&self, now: SystemTime) -> bool {
+        let started = UNIX_EPOCH + Duration::from_secs(self.started);
+        now.duration_since(started)
+            .map_or(false, |age| age > STALE_AFTER)
+    }
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+/// State file of uploading content with ID `content_id` (e.g. its checksum) as
+/// `key` to `target` (e.g. the bucket)
+fn state_path(state_dir: &Path, target: &str, key: &str, content_id: &str) -> PathBuf {
+    let id = Sha256::new()
+        .chain(target)
+        .chain([0])
+        .chain(key)
+        .chain([0])
+        .chain(content_id)
+        .finalize();
+    state_dir.join(format!("{}.json", hex::encode(&id[..16])))
+}
+
+/// Upload `content` as `key` in parts, continuing an earlier upload of the
+/// same content if there is one
+///
+/// `target` identifies where the upload goes (e.g. the bucket), `content_id`
+/// what is uploaded (e.g. the checksum of the unencrypted file). Without a
+/// `state_dir`, uploads can't be resumed.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn upload(
+    parts: &impl Parts,
+    target: &str,
+    key: &str,
+    content: &[u8],
+    content_id: &str,
+    part_size: usize,
+    state_dir: Option<&Path>,
+) -> Result<()> {
+    let now = SystemTime::now();
+    abort_stale(parts, state_dir, now).await;
+
+    let path = state_dir.map(|dir| state_path(dir, target, key, content_id));
+    let resumed = path
+        .as_deref()
+        .and_then(State::read)
+        .filter(|state| state.part_size == part_size && !state.is_stale(now));
+    let mut state = match resumed {
+        Some(state) => {
+            log::info!(
+                "resuming upload of `{}` ({} parts already uploaded)",
+                key,
+                state.parts.len()
+            );
+            state
+        }
+        None => State {
+            key: key.to_string(),
+            upload_id: parts.create(key).await.context("start multipart upload")?,
+            started: unix_time(now),
+            part_size,
+            parts: BTreeMap::new(),
+        },
+    };
+    if let Some(path) = &path {
+        state.write(path)?;
+    }
+    let cleanup = interrupt::on_interrupt({
+        let abort = parts.aborting(key, &state.upload_id);
+        let (key, path) = (key.to_string(), path.clone());
+        async move {
+            if let Err(e) = abort.await {
+                log::warn!("could not abort upload of `{}`: {}", key, e);
+            }
+            if let Some(path) = path {
+                let _ = fs::remove_file(path);
+            }
+        }
+    });
+
+    let count = (content.len() + part_size - 1) / part_size;
+    for (index, part) in content.chunks(part_size).enumerate() {
+        let number = index as i64 + 1;
+        if let Some(etag) = state.parts.get(&number) {
+            if etag.trim_matches('"') == format!("{:x}", md5::compute(part)) {
+                continue;
+            }
+            log::debug!("part {} of `{}` changed, uploading it again", number, key);
+        }
+        log::debug!("uploading part {}/{} of `{}`", number, count, key);
+        let etag = parts
+            .upload_part(key, &state.upload_id, number, part)
+            .await
+            .with_context(|| format!("upload part {} of `{}`", number, key))?;
+        state.parts.insert(number, etag);
+        if let Some(path) = &path {
+            state.write(path)?;
+        }
+    }
+
+    parts
+        .complete(key, &state.upload_id, state.parts.into_iter().collect())
+        .await
+        .with_context(|| format!("complete multipart upload of `{}`", key))?;
+    drop(cleanup);
+    if let Some(path) = &path {
+        fs::remove_file(path).with_context(|| format!("remove `{}`", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Abort uploads started more than [`STALE_AFTER`] before `now`, by us
+/// (according to state files) or anyone else
+///
+/// Only logs errors, as failing to clean up shouldn't stop new uploads.
+async fn abort_stale(parts: &impl Parts, state_dir: Option<&Path>, now: SystemTime) {
+    let state_files = state_dir
+        .and_then(|dir| fs::read_dir(dir).ok())
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "json"));
+    for path in state_files {
+        let state = match State::read(&path) {
+            Some(state) if !state.is_stale(now) => continue,
+            state => state,
+        };
+        if let Some(state) = state {
+            log::info!("aborting stale upload of `{}`", state.key);
+            if let Err(e) = parts.abort(&state.key, &state.upload_id).await {
+                log::debug!("could not abort upload of `{}`: {}", state.key, e);
+            }
+        }
+        if let Err(e) = fs::remove_file(&path) {
+            log::debug!("could not remove `{}`: {}", path.display(), e);
+        }
+    }
+
+    let pending = match parts.pending().await {
+        Ok(pending) => pending,
+        Err(e) => {
+            log::debug!("could not list unfinished uploads: {}", e);
+            return;
+        }
+    };
+    for upload in pending {
+        let stale = upload
+            .started
+            .and_then(|started| now.duration_since(started).ok())
+            .map_or(false, |age| age > STALE_AFTER);
+        if stale {
+            log::info!("aborting stale upload of `{}`", upload.key);
+            if let Err(e) = parts.abort(&upload.key, &upload.upload_id).await {
+                log::warn!("could not abort upload of `{}`: {}", upload.key, e);
+            }
+        }
+    }
+}
+
+/// ETag S3 gives a file uploaded in parts of `part_size`
+pub(crate) fn etag(content: &[u8], part_size: usize) -> String {
+    let checksums: Vec<u8> = content
+        .chunks(part_size)
+        .flat_map(|part| md5::compute(part).0)
+        .collect();
+    let count = (content.len() + part_size - 1) / part_size;
+    format!("{:x}-{}", md5::compute(checksums), count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use erreur::ensure;
+    use std::sync::{Arc, Mutex};
+
+    /// Checksum of the content, as the index would pass it
+    const ID: &str = "sha256:0123456789abcdef";
+
+    /// Uploads parts to a directory, optionally failing after a number of
+    /// parts like a flaky network
+    struct DirParts {
+        dir: PathBuf,
+        fail_after: Mutex<Option<usize>>,
+        uploaded: Mutex<Vec<i64>>,
+        aborted: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl DirParts {
+        fn new(dir: &Path) -> Self {
+            DirParts {
+                dir: dir.to_path_buf(),
+                fail_after: Mutex::new(None),
+                uploaded: Mutex::new(Vec::new()),
+                aborted: Arc::new(Mutex::new(Vec::new())),
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Parts for DirParts {
+        async fn create(&self, _key: &str) -> Result<String> {
+            let id = format!("upload{}", fs::read_dir(&self.dir)?.count());
+            fs::create_dir(self.dir.join(&id))?;
+            Ok(id)
+        }
+
+        async fn upload_part(
+            &self,
+            _key: &str,
+            upload_id: &str,
+            number: i64,
+            content: &[u8],
+        ) -> Result<String> {
+            if let Some(left) = self.fail_after.lock().unwrap().as_mut() {
+                ensure!(*left > 0, "connection reset by peer");
+                *left -= 1;
+            }
+            fs::write(self.dir.join(upload_id).join(number.to_string()), content)?;
+            self.uploaded.lock().unwrap().push(number);
+            Ok(format!("{:x}", md5::compute(content)))
+        }
+
+        async fn complete(
+            &self,
+            key: &str,
+            upload_id: &str,
+            parts: Vec<(i64, String)>,
+        ) -> Result<()> {
+            let mut content = Vec::new();
+            for (number, etag) in parts {
+                let part = fs::read(self.dir.join(upload_id).join(number.to_string()))?;
+                ensure!(format!("{:x}", md5::compute(&part)) == etag, "wrong ETag");
+                content.extend(part);
+            }
+            fs::write(self.dir.join(key), content)?;
+            fs::remove_dir_all(self.dir.join(upload_id))?;
+            Ok(())
+        }
+
+        fn aborting(&self, _key: &str, upload_id: &str) -> BoxFuture<'static, Result<()>> {
+            let (aborted, upload_id) = (self.aborted.clone(), upload_id.to_string());
+            Box::pin(async move {
+                aborted.lock().unwrap().push(upload_id);
+                Ok(())
+            })
+        }
+
+        async fn pending(&self) -> Result<Vec<Pending>> {
+            Ok(Vec::new())
+        }
+    }
+
+    #[tokio::test]
+    async fn interrupted_uploads_are_resumed() {
+        let remote = tempfile::tempdir().unwrap();
+        let local = tempfile::tempdir().unwrap();
+        let state_dir = local.path().join(STATE_DIR);
+        let parts = DirParts::new(remote.path());
+        let content: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+
+        *parts.fail_after.lock().unwrap() = Some(2);
+        let interrupted = upload(
+            &parts,
+            "bucket",
+            "build",
+            &content,
+            ID,
+            300,
+            Some(&state_dir),
+        )
+        .await;
+        assert!(interrupted.is_err());
+        assert!(!remote.path().join("build").exists());
+        assert_eq!(fs::read_dir(&state_dir).unwrap().count(), 1);
+
+        *parts.fail_after.lock().unwrap() = None;
+        upload(
+            &parts,
+            "bucket",
+            "build",
+            &content,
+            ID,
+            300,
+            Some(&state_dir),
+        )
+        .await
+        .unwrap();
+        assert_eq!(fs::read(remote.path().join("build")).unwrap(), content);
+        assert_eq!(*parts.uploaded.lock().unwrap(), [1, 2, 3, 4]);
+        assert_eq!(fs::read_dir(&state_dir).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn interrupted_encrypted_uploads_are_resumed() {
+        let remote = tempfile::tempdir().unwrap();
+        let local = tempfile::tempdir().unwrap();
+        let state_dir = local.path().join(STATE_DIR);
+        let parts = DirParts::new(remote.path());
+        let key = crate::storage::EncryptionKey::from_passphrase(b"hunter2").unwrap();
+        let content: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+
+        // every `sync` encrypts the file again, and finds the upload by the
+        // checksum of the unencrypted file
+        let encrypted = key.encrypt_bytes("build", &content).unwrap();
+        *parts.fail_after.lock().unwrap() = Some(2);
+        let interrupted = upload(
+            &parts,
+            "bucket",
+            "build",
+            &encrypted,
+            ID,
+            300,
+            Some(&state_dir),
+        )
+        .await;
+        assert!(interrupted.is_err());
+        assert_eq!(fs::read_dir(&state_dir).unwrap().count(), 1);
+
+        let encrypted = key.encrypt_bytes("build", &content).unwrap();
+        *parts.fail_after.lock().unwrap() = None;
+        upload(
+            &parts,
+            "bucket",
+            "build",
+            &encrypted,
+            ID,
+            300,
+            Some(&state_dir),
+        )
+        .await
+        .unwrap();
+        assert_eq!(*parts.uploaded.lock().unwrap(), [1, 2, 3, 4]);
+        let uploaded = fs::read(remote.path().join("build")).unwrap();
+        assert_eq!(key.decrypt_bytes("build", &uploaded).unwrap(), content);
+        assert_eq!(fs::read_dir(&state_dir).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn resumed_uploads_encrypted_with_another_key_upload_all_parts() {
+        let remote = tempfile::tempdir().unwrap();
+        let local = tempfile::tempdir().unwrap();
+        let state_dir = local.path().join(STATE_DIR);
+        let parts = DirParts::new(remote.path());
+        let content: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+
+        let old_key = crate::storage::EncryptionKey::from_passphrase(b"hunter2").unwrap();
+        let encrypted = old_key.encrypt_bytes("build", &content).unwrap();
+        *parts.fail_after.lock().unwrap() = Some(2);
+        let interrupted = upload(
+            &parts,
+            "bucket",
+            "build",
+            &encrypted,
+            ID,
+            300,
+            Some(&state_dir),
+        )
+        .await;
+        assert!(interrupted.is_err());
+
+        let new_key = crate::storage::EncryptionKey::from_passphrase(b"hunter3").unwrap();
+        let encrypted = new_key.encrypt_bytes("build", &content).unwrap();
+        *parts.fail_after.lock().unwrap() = None;
+        upload(
+            &parts,
+            "bucket",
+            "build",
+            &encrypted,
+            ID,
+            300,
+            Some(&state_dir),
+        )
+        .await
+        .unwrap();
+        assert_eq!(*parts.uploaded.lock().unwrap(), [1, 2, 1, 2, 3, 4]);
+        let uploaded = fs::read(remote.path().join("build")).unwrap();
+        assert_eq!(new_key.decrypt_bytes("build", &uploaded).unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn stale_uploads_are_aborted() {
+        let remote = tempfile::tempdir().unwrap();
+        let local = tempfile::tempdir().unwrap();
+        let state_dir = local.path().join(STATE_DIR);
+        let parts = DirParts::new(remote.path());
+        let content = vec![42; 1000];
+
+        *parts.fail_after.lock().unwrap() = Some(1);
+        assert!(upload(
+            &parts,
+            "bucket",
+            "build",
+            &content,
+            ID,
+            300,
+            Some(&state_dir)
+        )
+        .await
+        .is_err());
+        let path = state_path(&state_dir, "bucket", "build", ID);
+        let mut state = State::read(&path).unwrap();
+        state.started -= STALE_AFTER.as_secs() + 1;
+        state.write(&path).unwrap();
+
+        *parts.fail_after.lock().unwrap() = None;
+        upload(
+            &parts,
+            "bucket",
+            "build",
+            &content,
+            ID,
+            300,
+            Some(&state_dir),
+        )
+        .await
+        .unwrap();
+        assert_eq!(*parts.aborted.lock().unwrap(), ["upload0"]);
+        assert_eq!(*parts.uploaded.lock().unwrap(), [1, 1, 2, 3, 4]);
+        assert_eq!(fs::read(remote.path().join("build")).unwrap(), content);
+    }
+
+    #[test]
+    fn multipart_etags() {
+        let content = vec![7; 1000];
+        let whole = md5::compute(&content).0;
+        assert_eq!(etag(&content, 1000), format!("{:x}-1", md5::compute(whole)));
+        assert!(etag(&content, 300).ends_with("-4"));
+        assert_ne!(etag(&content, 300), etag(&content, 500));
+    }
+}
@@ -1,7 +1,18 @@
-use erreur::{ensure, Context, Report, Result};
-use rusoto_core::Region;
+use super::{multipart, throttle, Entry, File, Storage, StorageBackend};
+use crate::{index::Checksum, paths::path_as_string};
+use erreur::{ensure, Context, Help, Report, Result, StdResult};
+use futures::{
+    future::BoxFuture,
+    stream::{self, BoxStream, StreamExt, TryStreamExt},
+};
+use rusoto_core::{ByteStream, Region};
 use rusoto_s3::S3Client;
-use std::convert::TryFrom;
+use std::{
+    convert::{TryFrom, TryInto},
+    fmt, fs,
+    path::Path,
+    time::{Duration, Instant, SystemTime},
+};
 use url::Url;
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -26,9 +37,111 @@ impl Bucket {
         root.push_str(path);
         root
     }
+
+    /// Upload a file, finding interrupted uploads of it by `checksum` (or the
+    /// MD5 of what is uploaded, without one)
+    async fn put(&self, file: &File, target: &Path, checksum: Option<&Checksum>) -> Result<()> {
+        use rusoto_core::{request::BufferedHttpResponse, RusotoError};
+        use rusoto_s3::{PutObjectError, PutObjectRequest, S3};
+
+        fn try_parse_s3_error<T>(res: StdResult<T, RusotoError<PutObjectError>>) -> Result<T> {
+            match res {
+                Ok(x) => Ok(x),
+                Err(RusotoError::Unknown(BufferedHttpResponse {
+                    status, ref body, ..
+                })) => {
+                    let pattern = b"<Code>BadDigest</Code>";
+                    if body
+                        .windows(pattern.len())
+                        .any(move |sub_slice| sub_slice == pattern)
+                    {
+                        res.context("S3 checksum failure")
+                            .warning("Checksum failures can mean data is corrupted")
+                    } else {
+                        let msg = format!(
+                            "S3 responded with status `{}` and body: `{}`",
+                            status,
+                            String::from_utf8_lossy(body),
+                        );
+                        res.context(msg)
+                    }
+                }
+                Err(e) => Err(Report::new(e)),
+            }
+        }
+
+        let client: S3Client = self.try_into().context("build S3 client")?;
+
+        let content = match file {
+            File::InFilesystem(entry) => {
+                fs::read(&entry.path).with_context(|| format!("could not read `{}`", entry.path))?
+            }
+            File::Inline(_, content) => content.to_vec(),
+            File::Temporary(entry, temp) => fs::read(temp.path())
+                .with_context(|| format!("could not read temporary file of `{}`", entry.path))?,
+        };
+
+        let key = self.key_for(&path_as_string(target)?);
+        log::debug!("adding file as `{}`", key);
+        if content.len() >= multipart::THRESHOLD {
+            let state_dir = match file {
+                File::InFilesystem(entry) | File::Inline(entry, _) | File::Temporary(entry, _) => {
+                    entry
+                        .storage
+                        .local_path()
+                        .map(|path| path.join(multipart::STATE_DIR))
+                }
+            };
+            let content_id = match checksum {
+                Some(checksum) => checksum.to_string(),
+                None => format!("md5:{:x}", md5::compute(&content)),
+            };
+            let parts = S3Parts {
+                bucket: self,
+                client,
+            };
+            return multipart::upload(
+                &parts,
+                &format!("{}.{}", self.bucket, self.endpoint),
+                &key,
+                &content,
+                &content_id,
+                multipart::PART_SIZE,
+                state_dir.as_deref(),
+            )
+            .await
+            .with_context(|| format!("Failed to upload object `{}` to S3", key))
+            .note("Run `sync` again to continue where the upload stopped");
+        }
+
+        let md5 = md5::compute(&content);
+        let size = content.len();
+        let body = body(&key, content);
+        let response = client
+            .put_object(PutObjectRequest {
+                bucket: self.bucket.to_owned(),
+                key: key.clone(),
+                content_length: Some(size as i64),
+                content_md5: Some(base64::encode(*md5)),
+                body: Some(body),
+                ..Default::default()
+            })
+            .await;
+        let response = try_parse_s3_error(response);
+        response
+            .with_context(|| format!("Failed to upload object `{}` to S3", key))
+            .note("S3 has bad days just like the rest of us")?;
+        Ok(())
+    }
 }
 
-impl<'a> TryFrom<&'a Url> for Bucket {
+impl fmt::Display for Bucket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "S3 ({})", self.bucket)
+    }
+}
+
+impl TryFrom<&Url> for Bucket {
     type Error = Report;
 
     fn try_from(url: &Url) -> Result<Bucket> {
@@ -79,18 +192,337 @@ impl<'a> TryFrom<&'a Bucket> for S3Client {
     }
 }
 
-pub fn validate_checksum(key: &str, body: &[u8], received: &str) -> Result<()> {
-    if received.contains('-') {
-        log::warn!(
-            "S3 checksum for file `{}` is in multipart format, which artefacta doesn't support yet",
-            key
-        );
-        return Ok(());
+#[async_trait::async_trait]
+impl StorageBackend for Bucket {
+    async fn list_files(&self, storage: &Storage) -> Result<Vec<Entry>> {
+        self.list_files_stream(storage).try_collect().await
+    }
+
+    /// Objects page by page, as S3 returns at most 1000 at a time
+    fn list_files_stream<'a>(&'a self, storage: &'a Storage) -> BoxStream<'a, Result<Entry>> {
+        use rusoto_s3::{ListObjectsV2Request, S3};
+
+        let client: S3Client = match self.try_into().context("build S3 client") {
+            Ok(client) => client,
+            Err(e) => return stream::once(async { Err(e) }).boxed(),
+        };
+
+        // `None` once the last page was listed, otherwise where the next
+        // page starts (`Some(None)` for the first one)
+        let pages = stream::try_unfold(Some(None), move |next| {
+            let client = client.clone();
+            async move {
+                let continuation_token = match next {
+                    Some(token) => token,
+                    None => return Ok::<_, Report>(None),
+                };
+                let res = client
+                    .list_objects_v2(ListObjectsV2Request {
+                        bucket: self.bucket.to_owned(),
+                        prefix: Some(self.path.trim_start_matches('/').to_string()),
+                        continuation_token,
+                        ..Default::default()
+                    })
+                    .await
+                    .context("list files in bucket")?;
+
+                let next = match (res.is_truncated, res.next_continuation_token) {
+                    (Some(true), Some(token)) => Some(Some(token)),
+                    (Some(true), None) => {
+                        log::warn!("S3 listing is truncated but has no continuation token");
+                        None
+                    }
+                    _ => None,
+                };
+                let entries = res
+                    .contents
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|obj| {
+                        Ok(Entry {
+                            storage: storage.clone(),
+                            path: obj.key.context("got an object with no key")?,
+                            size: obj
+                                .size
+                                .map(|s| s as u64)
+                                .context("got an object with no size")?,
+                            modified: obj.last_modified.as_deref().and_then(parse_time),
+                        })
+                    })
+                    .collect::<Vec<Result<Entry>>>();
+                log::debug!("listed {} objects in bucket", entries.len());
+                Ok(Some((stream::iter(entries), next)))
+            }
+        });
+        pages.try_flatten().boxed()
+    }
+
+    async fn get_file(&self, storage: &Storage, path: &str) -> Result<File> {
+        use async_read_progress::*;
+        use rusoto_s3::{GetObjectRequest, S3};
+        use tokio::io::AsyncReadExt;
+
+        let key = self.key_for(path);
+        let client: S3Client = self.try_into().context("build S3 client")?;
+
+        let result = client
+            .get_object(GetObjectRequest {
+                bucket: self.bucket.to_owned(),
+                key: key.clone(),
+                ..Default::default()
+            })
+            .await
+            .with_context(|| format!("Couldn't get object with path `{}`", key))?;
+
+        let checksum = result.e_tag.context("object has no checksum")?;
+
+        let size = result
+            .content_length
+            .map(|s| s as u64)
+            .context("got an object with no size")?;
+
+        let stream = result
+            .body
+            .context("object without body")?
+            .into_async_read()
+            .report_progress(Duration::from_secs(2), |bytes_read| {
+                use humansize::{file_size_opts as options, FileSize};
+
+                log::info!(
+                    "reading `{}`… {}/{}",
+                    key,
+                    bytes_read
+                        .file_size(options::BINARY)
+                        .expect("never negative"),
+                    size.file_size(options::BINARY).expect("never negative")
+                )
+            });
+        // progress is reported for the bytes the throttled reader let through
+        let mut stream = throttle::reader(stream);
+
+        log::debug!("fetching `{}` from S3", key);
+        let mut body = Vec::new();
+        stream
+            .read_to_end(&mut body)
+            .await
+            .context("failed to read object content into buffer")
+            .note("S3 has bad days just like the rest of us")?;
+
+        log::info!("downloaded `{}` from S3", key);
+        validate_checksum(&key, &body, &checksum)
+            .with_context(|| format!("checksum mismatch for file `{}`", key))?;
+
+        let entry = Entry {
+            storage: storage.clone(),
+            path: key.to_owned(),
+            size: result
+                .content_length
+                .map(|s| s as u64)
+                .context("got an object with no size")
+                .with_suggestion(|| {
+                    format!(
+                        "Best check whether the upload of `{}` \
+                        was successful using S3/DigitalOceans web interface",
+                        key
+                    )
+                })?,
+            modified: result.last_modified.as_deref().and_then(parse_time),
+        };
+
+        Ok(File::Inline(entry, body.into_boxed_slice().into()))
+    }
+
+    async fn add_file(&self, file: &File, target: &Path) -> Result<()> {
+        self.put(file, target, None).await
+    }
+
+    async fn add_file_with_checksum(
+        &self,
+        file: &File,
+        target: &Path,
+        checksum: &Checksum,
+    ) -> Result<()> {
+        self.put(file, target, Some(checksum)).await
+    }
+
+    async fn remove_file(&self, path: &str) -> Result<()> {
+        use rusoto_s3::{DeleteObjectRequest, S3};
+
+        let client: S3Client = self.try_into().context("build S3 client")?;
+        let key = self.key_for(path);
+        client
+            .delete_object(DeleteObjectRequest {
+                bucket: self.bucket.to_owned(),
+                key: key.clone(),
+                ..Default::default()
+            })
+            .await
+            .with_context(|| format!("Failed to delete object `{}` from S3", key))?;
+        Ok(())
     }
+}
+
+/// Body of an upload, sent as fast as `--max-bandwidth` allows
+fn body(key: &str, content: Vec<u8>) -> ByteStream {
+    let limiter = match throttle::limiter() {
+        Some(limiter) => limiter,
+        None => return content.into(),
+    };
+
+    let key = key.to_string();
+    let size = content.len();
+    let mut sent = 0;
+    let mut last_report = Instant::now();
+    let chunks = throttle::chunks(content, limiter).inspect(move |chunk| {
+        use humansize::{file_size_opts as options, FileSize};
+
+        sent += chunk.as_ref().map_or(0, |chunk| chunk.len());
+        if last_report.elapsed() >= Duration::from_secs(2) {
+            last_report = Instant::now();
+            log::info!(
+                "uploading `{}`… {}/{}",
+                key,
+                sent.file_size(options::BINARY).expect("never negative"),
+                size.file_size(options::BINARY).expect("never negative")
+            );
+        }
+    });
+    ByteStream::new_with_size(chunks, size)
+}
+
+/// Multipart uploads to a bucket
+struct S3Parts<'a> {
+    bucket: &'a Bucket,
+    client: S3Client,
+}
+
+#[async_trait::async_trait]
+impl multipart::Parts for S3Parts<'_> {
+    async fn create(&self, key: &str) -> Result<String> {
+        use rusoto_s3::{CreateMultipartUploadRequest, S3};
+
+        let response = self
+            .client
+            .create_multipart_upload(CreateMultipartUploadRequest {
+                bucket: self.bucket.bucket.to_owned(),
+                key: key.to_owned(),
+                ..Default::default()
+            })
+            .await
+            .with_context(|| format!("Couldn't start upload of `{}`", key))?;
+        response.upload_id.context("S3 returned no upload ID")
+    }
+
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        number: i64,
+        content: &[u8],
+    ) -> Result<String> {
+        use rusoto_s3::{UploadPartRequest, S3};
+
+        let checksum = md5::compute(content);
+        let response = self
+            .client
+            .upload_part(UploadPartRequest {
+                bucket: self.bucket.bucket.to_owned(),
+                key: key.to_owned(),
+                upload_id: upload_id.to_owned(),
+                part_number: number,
+                content_length: Some(content.len() as i64),
+                content_md5: Some(base64::encode(*checksum)),
+                body: Some(body(
+                    &format!("{} (part {})", key, number),
+                    content.to_vec(),
+                )),
+                ..Default::default()
+            })
+            .await?;
+        response.e_tag.context("S3 returned no ETag for part")
+    }
+
+    async fn complete(&self, key: &str, upload_id: &str, parts: Vec<(i64, String)>) -> Result<()> {
+        use rusoto_s3::{
+            CompleteMultipartUploadRequest, CompletedMultipartUpload, CompletedPart, S3,
+        };
+
+        let parts = parts
+            .into_iter()
+            .map(|(number, etag)| CompletedPart {
+                e_tag: Some(etag),
+                part_number: Some(number),
+            })
+            .collect();
+        self.client
+            .complete_multipart_upload(CompleteMultipartUploadRequest {
+                bucket: self.bucket.bucket.to_owned(),
+                key: key.to_owned(),
+                upload_id: upload_id.to_owned(),
+                multipart_upload: Some(CompletedMultipartUpload { parts: Some(parts) }),
+                ..Default::default()
+            })
+            .await?;
+        Ok(())
+    }
+
+    fn aborting(&self, key: &str, upload_id: &str) -> BoxFuture<'static, Result<()>> {
+        use rusoto_s3::{AbortMultipartUploadRequest, S3};
 
+        let client = self.client.clone();
+        let request = AbortMultipartUploadRequest {
+            bucket: self.bucket.bucket.to_owned(),
+            key: key.to_owned(),
+            upload_id: upload_id.to_owned(),
+            ..Default::default()
+        };
+        Box::pin(async move {
+            client.abort_multipart_upload(request).await?;
+            Ok(())
+        })
+    }
+
+    async fn pending(&self) -> Result<Vec<multipart::Pending>> {
+        use rusoto_s3::{ListMultipartUploadsRequest, S3};
+
+        let response = self
+            .client
+            .list_multipart_uploads(ListMultipartUploadsRequest {
+                bucket: self.bucket.bucket.to_owned(),
+                prefix: Some(self.bucket.key_for("")),
+                ..Default::default()
+            })
+            .await?;
+        Ok(response
+            .uploads
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|upload| {
+                Some(multipart::Pending {
+                    key: upload.key?,
+                    upload_id: upload.upload_id?,
+                    started: upload.initiated.as_deref().and_then(parse_time),
+                })
+            })
+            .collect())
+    }
+}
+
+pub fn validate_checksum(key: &str, body: &[u8], received: &str) -> Result<()> {
     // strip quotes
     let received = received.trim_start_matches('"').trim_end_matches('"');
 
+    if received.contains('-') {
+        if received != multipart::etag(body, multipart::PART_SIZE) {
+            log::warn!(
+                "S3 checksum for file `{}` is in multipart format, \
+                but not from parts of the size artefacta uploads, not checking it",
+                key
+            );
+        }
+        return Ok(());
+    }
+
     log::trace!("S3's checksum for file `{}`: {}", key, received);
     let checksum = md5::compute(body);
     let checksum = format!("{:x}", checksum);
@@ -104,3 +536,25 @@ pub fn validate_checksum(key: &str, body: &[u8], received: &str) -> Result<()> {
 
     Ok(())
 }
+
+/// Time of an object's last change, as S3 reports it
+///
+/// Listings use RFC 3339 (`2022-05-30T12:00:00.000Z`), the `Last-Modified`
+/// header of downloads uses HTTP dates (`Mon, 30 May 2022 12:00:00 GMT`).
+fn parse_time(time: &str) -> Option<SystemTime> {
+    chrono::DateTime::parse_from_rfc3339(time)
+        .or_else(|_| chrono::DateTime::parse_from_rfc2822(time))
+        .map(SystemTime::from)
+        .map_err(|e| log::debug!("can't parse S3 time `{}`: {}", time, e))
+        .ok()
+}
+
+#[test]
+fn parse_s3_times() {
+    use std::time::UNIX_EPOCH;
+
+    let expected = UNIX_EPOCH + Duration::from_secs(1_653_912_000);
+    assert_eq!(parse_time("2022-05-30T12:00:00.000Z"), Some(expected));
+    assert_eq!(parse_time("Mon, 30 May 2022 12:00:00 GMT"), Some(expected));
+    assert_eq!(parse_time("yesterday"), None);
+}
@@ -0,0 +1,174 @@
+//! Limit on the bandwidth of transfers to and from S3
+//!
+//! The limit is shared by all transfers of the process, so uploading several
+//! files at the same time (see `--concurrency`) doesn't multiply it. Each
+//! transfer books the time its bytes take at the limit, after the bytes booked
+//! before, and waits until then before moving more.
+
+use bytes::Bytes;
+use futures::{ready, stream::Stream};
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    io::{AsyncRead, ReadBuf},
+    time::Sleep,
+};
+
+/// Bytes sent per step of throttled uploads
+const CHUNK_SIZE: usize = 64 * 1024;
+
+static LIMITER: Limiter = Limiter::new();
+
+/// Transfer at most `bytes_per_second` to and from S3 from now on (`None`
+/// for no limit, the default)
+pub fn set_max_bandwidth(bytes_per_second: Option<u64>) {
+    LIMITER
+        .rate
+        .store(bytes_per_second.unwrap_or(0), Ordering::Relaxed);
+}
+
+pub(crate) fn limiter() -> Option<&'static Limiter> {
+    if LIMITER.rate.load(Ordering::Relaxed) == 0 {
+        None
+    } else {
+        Some(&LIMITER)
+    }
+}
+
+pub(crate) struct Limiter {
+    /// Bytes per second, 0 for no limit
+    rate: AtomicU64,
+    /// Nanoseconds since the Unix epoch until which the bandwidth is booked
+    booked_until: AtomicU64,
+}
+
+impl Limiter {
+    const fn new() -> Self {
+        Limiter {
+            rate: AtomicU64::new(0),
+            booked_until: AtomicU64::new(0),
+        }
+    }
+
+    /// Book the time `bytes` take, returning how long to wait until they are
+    /// through
+    fn book(&self, bytes: usize) -> Duration {
+        let rate = self.rate.load(Ordering::Relaxed);
+        if rate == 0 || bytes == 0 {
+            return Duration::ZERO;
+        }
+        let cost = (bytes as u128 * 1_000_000_000 / rate as u128) as u64;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos() as u64);
+        let booked = self
+            .booked_until
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |until| {
+                Some(until.max(now) + cost)
+            })
+            .expect("always updated");
+        Duration::from_nanos(booked.max(now) + cost - now)
+    }
+}
+
+/// Reader that waits after each read until the bytes it got are through
+pub(crate) struct Throttled<R> {
+    inner: Pin<Box<R>>,
+    limiter: &'static Limiter,
+    wait: Option<Pin<Box<Sleep>>>,
+}
+
+/// Throttle `reader` if there is a limit, otherwise pass it on as it is
+pub(crate) fn reader<'a, R>(reader: R) -> Pin<Box<dyn AsyncRead + Send + 'a>>
+where
+    R: AsyncRead + Send + 'a,
+{
+    match limiter() {
+        Some(limiter) => Box::pin(Throttled {
+            inner: Box::pin(reader),
+            limiter,
+            wait: None,
+        }),
+        None => Box::pin(reader),
+    }
+}
+
+impl<R: AsyncRead> AsyncRead for Throttled<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if let Some(wait) = self.wait.as_mut() {
+            ready!(wait.as_mut().poll(cx));
+            self.wait = None;
+        }
+
+        let before = buf.filled().len();
+        ready!(self.inner.as_mut().poll_read(cx, buf))?;
+        let delay = self.limiter.book(buf.filled().len() - before);
+        if !delay.is_zero() {
+            self.wait = Some(Box::pin(tokio::time::sleep(delay)));
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Hand out `content` in chunks, each as soon as the limit allows it
+pub(crate) fn chunks(
+    content: Vec<u8>,
+    limiter: &'static Limiter,
+) -> impl Stream<Item = io::Result<Bytes>> + Send + 'static {
+    let content = Bytes::from(content);
+    futures::stream::unfold(0, move |offset| {
+        let content = content.clone();
+        async move {
+            let end = (offset + CHUNK_SIZE).min(content.len());
+            if offset >= end {
+                return None;
+            }
+            tokio::time::sleep(limiter.book(end - offset)).await;
+            Some((Ok(content.slice(offset..end)), end))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream::TryStreamExt;
+    use std::time::Instant;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn transfers_are_throttled() {
+        static LIMITER: Limiter = Limiter::new();
+        LIMITER.rate.store(512 * 1024, Ordering::Relaxed);
+        let content = vec![1u8; 256 * 1024];
+
+        let started = Instant::now();
+        let mut reader = Throttled {
+            inner: Box::pin(&content[..]),
+            limiter: &LIMITER,
+            wait: None,
+        };
+        let mut read = Vec::new();
+        reader.read_to_end(&mut read).await.unwrap();
+        assert_eq!(read, content);
+        assert!(started.elapsed() >= Duration::from_millis(450));
+
+        let started = Instant::now();
+        let sent: Vec<Bytes> = chunks(content.clone(), &LIMITER)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(sent.concat(), content);
+        assert!(started.elapsed() >= Duration::from_millis(450));
+    }
+}
@@ -33,6 +33,11 @@ pub fn random_zstd_file(path: impl AsRef<Path>) -> Result<Vec<u8>> {
     Ok(raw_content)
 }
 
+/// Write `size` zero bytes to `path`, for tests that only care about sizes
+pub fn file_of_size(path: impl AsRef<Path>, size: usize) {
+    fs::write(path, vec![0u8; size]).unwrap();
+}
+
 pub fn zstd_file(path: impl AsRef<Path>, content: &[u8]) -> Result<()> {
     let path = path.as_ref();
     let content = zstd::stream::encode_all(Cursor::new(content), 1)?;
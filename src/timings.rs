@@ -0,0 +1,149 @@
+//! How long the phases of a run took
+//!
+//! The index and the packaging code time their phases, e.g. listing storage or
+//! diffing builds. Each phase is logged at debug level when it ends (with the
+//! `artefacta::timings` target), and `--timings` prints the totals of the run
+//! at the end, for finding out where a slow run spends its time.
+
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+static TIMINGS: Timings = Timings::new();
+
+/// Phases we keep timings of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Phase {
+    ListStorage,
+    BuildGraph,
+    Download,
+    Decompress,
+    ApplyPatch,
+    Diff,
+    Compress,
+    Upload,
+}
+
+impl Phase {
+    const ALL: [Phase; 8] = [
+        Phase::ListStorage,
+        Phase::BuildGraph,
+        Phase::Download,
+        Phase::Decompress,
+        Phase::ApplyPatch,
+        Phase::Diff,
+        Phase::Compress,
+        Phase::Upload,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Phase::ListStorage => "list storage",
+            Phase::BuildGraph => "build graph",
+            Phase::Download => "download",
+            Phase::Decompress => "decompress",
+            Phase::ApplyPatch => "apply patches",
+            Phase::Diff => "diff",
+            Phase::Compress => "compress",
+            Phase::Upload => "upload",
+        }
+    }
+}
+
+/// Start timing `phase`, until the returned timer is dropped
+pub(crate) fn start(phase: Phase) -> Timer {
+    Timer {
+        phase,
+        started: Instant::now(),
+    }
+}
+
+/// Add `duration` spent in `phase`
+pub(crate) fn record(phase: Phase, duration: Duration) {
+    log::debug!("{} took {:.2?}", phase.label(), duration);
+    TIMINGS.add(phase, duration);
+}
+
+/// Totals of the phases that happened so far, one per line, or `None` if
+/// nothing was timed
+pub fn summary() -> Option<String> {
+    TIMINGS.summary()
+}
+
+#[must_use = "the phase ends when the timer is dropped"]
+pub(crate) struct Timer {
+    phase: Phase,
+    started: Instant,
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        record(self.phase, self.started.elapsed());
+    }
+}
+
+struct Timings {
+    /// Number of times and total nanoseconds, by phase
+    totals: [(AtomicU64, AtomicU64); 8],
+}
+
+impl Timings {
+    const fn new() -> Self {
+        Timings {
+            totals: [
+                (AtomicU64::new(0), AtomicU64::new(0)),
+                (AtomicU64::new(0), AtomicU64::new(0)),
+                (AtomicU64::new(0), AtomicU64::new(0)),
+                (AtomicU64::new(0), AtomicU64::new(0)),
+                (AtomicU64::new(0), AtomicU64::new(0)),
+                (AtomicU64::new(0), AtomicU64::new(0)),
+                (AtomicU64::new(0), AtomicU64::new(0)),
+                (AtomicU64::new(0), AtomicU64::new(0)),
+            ],
+        }
+    }
+
+    fn add(&self, phase: Phase, duration: Duration) {
+        let (count, nanos) = &self.totals[phase as usize];
+        count.fetch_add(1, Ordering::Relaxed);
+        nanos.fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn summary(&self) -> Option<String> {
+        let mut out = String::new();
+        for phase in Phase::ALL {
+            let (count, nanos) = &self.totals[phase as usize];
+            let count = count.load(Ordering::Relaxed);
+            if count == 0 {
+                continue;
+            }
+            let total = Duration::from_nanos(nanos.load(Ordering::Relaxed));
+            write!(out, "{:>14}: {:.2?}", phase.label(), total).expect("writing to string");
+            if count > 1 {
+                write!(out, " ({} times)", count).expect("writing to string");
+            }
+            out.push('\n');
+        }
+        if out.is_empty() {
+            None
+        } else {
+            Some(out)
+        }
+    }
+}
+
+#[test]
+fn summarize_phases() {
+    let timings = Timings::new();
+    assert_eq!(timings.summary(), None);
+
+    timings.add(Phase::Upload, Duration::from_millis(1500));
+    timings.add(Phase::ListStorage, Duration::from_millis(20));
+    timings.add(Phase::Upload, Duration::from_millis(500));
+    assert_eq!(
+        timings.summary().unwrap(),
+        "  list storage: 20.00ms\n        upload: 2.00s (2 times)\n"
+    );
+}
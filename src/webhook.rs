@@ -0,0 +1,119 @@
+//! Telling other services about new builds after syncing
+//!
+//! After `sync --webhook <url>` uploaded something, we POST a summary of it as
+//! JSON to the URL, e.g. to notify a chat or trigger a deployment.
+
+use crate::{
+    http::{self, TIMEOUT},
+    index::Patch,
+    paths, Version,
+};
+use erreur::{ensure, Context, Result};
+use serde::Serialize;
+
+/// What `sync` uploaded
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SyncSummary {
+    /// Versions of new builds (including aliases of existing ones)
+    pub builds: Vec<Version>,
+    pub patches: Vec<SyncedPatch>,
+    /// Number of uploaded files, including checksums and metadata
+    pub files: usize,
+    /// Bytes uploaded, in total
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SyncedPatch {
+    pub from: Version,
+    pub to: Version,
+    pub size: u64,
+}
+
+impl SyncSummary {
+    /// Summarize uploaded files, given their names and sizes
+    pub fn new(files: &[(String, u64)]) -> Self {
+        let mut summary = SyncSummary::default();
+        for (name, size) in files {
+            summary.files += 1;
+            summary.bytes += size;
+            if paths::is_build_path(name) {
+                match paths::build_version_from_path(name) {
+                    Ok(version) => summary.builds.push(version),
+                    Err(e) => log::debug!("no version in `{}`: {}", name, e),
+                }
+            } else if name.ends_with(paths::ALIAS_EXTENSION) {
+                match paths::alias_versions_from_path(name) {
+                    Ok((alias, _)) => summary.builds.push(alias),
+                    Err(e) => log::debug!("no versions in `{}`: {}", name, e),
+                }
+            } else if paths::is_patch_path(name) {
+                match Patch::from_path(name) {
+                    Ok(Patch { from, to, .. }) => summary.patches.push(SyncedPatch {
+                        from,
+                        to,
+                        size: *size,
+                    }),
+                    Err(e) => log::debug!("no versions in `{}`: {}", name, e),
+                }
+            }
+        }
+        summary.builds.sort();
+        summary
+    }
+}
+
+/// POST `summary` as JSON to `url`, which has to respond with a success status
+pub async fn notify(url: &url::Url, summary: &SyncSummary) -> Result<()> {
+    let uri = http::uri(url).context("invalid webhook URL")?;
+    let body = serde_json::to_vec(summary).context("serialize sync summary")?;
+    let request = hyper::Request::post(uri)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .header(hyper::header::USER_AGENT, "artefacta")
+        .body(hyper::Body::from(body))
+        .context("build webhook request")?;
+
+    let response = tokio::time::timeout(TIMEOUT, http::client().request(request))
+        .await
+        .with_context(|| format!("no response within {}s", TIMEOUT.as_secs()))?
+        .context("send webhook request")?;
+
+    let status = response.status();
+    ensure!(status.is_success(), "webhook responded with `{}`", status);
+    log::info!("notified webhook at `{}`", url);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarize_uploaded_files() {
+        let files = [
+            ("2.tar.zst".to_string(), 100),
+            ("2.tar.zst.sha256".to_string(), 75),
+            ("1-2.patch.zst".to_string(), 10),
+            ("3---2.alias".to_string(), 2),
+            ("1.tar.zst".to_string(), 90),
+        ];
+        let summary = SyncSummary::new(&files);
+        assert_eq!(
+            summary.builds,
+            ["1", "2", "3"]
+                .iter()
+                .map(|v| v.parse().unwrap())
+                .collect::<Vec<Version>>()
+        );
+        assert_eq!(
+            summary.patches,
+            [SyncedPatch {
+                from: "1".parse().unwrap(),
+                to: "2".parse().unwrap(),
+                size: 10,
+            }]
+        );
+        assert_eq!(summary.files, 5);
+        assert_eq!(summary.bytes, 277);
+    }
+}
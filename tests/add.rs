@@ -1,6 +1,42 @@
 mod test_helpers;
 use test_helpers::*;
 
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    thread,
+};
+
+/// Answer one HTTP request on a local port with `status` and `body`
+fn file_server(status: &'static str, body: Vec<u8>) -> (String, thread::JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!(
+        "http://{}/builds/build.tar.zst",
+        listener.local_addr().unwrap()
+    );
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line.trim().is_empty() {
+                break;
+            }
+        }
+        let stream = reader.get_mut();
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+            status,
+            body.len()
+        )
+        .unwrap();
+        stream.write_all(&body).unwrap();
+    });
+    (url, server)
+}
+
 #[test]
 fn add_existing_tar_zst_file_by_copying_it() {
     let (local, remote) = init();
@@ -78,6 +114,137 @@ fn add_directory_by_packaging_it_as_a_tar_zst() {
     );
 }
 
+#[test]
+fn add_package_records_uncompressed_size() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    let build_dir = tempdir().unwrap();
+    let build_dir = build_dir.path();
+    fs::write(build_dir.join("lib.rs"), b"fn main() { /* code here */ }").unwrap();
+
+    artefacta(local, remote)
+        .arg("add-package")
+        .arg("build1")
+        .arg(build_dir)
+        .arg("--upload")
+        .succeeds();
+
+    assert!(local.join("build1.meta.json").exists());
+    assert!(remote.join("build1.meta.json").exists());
+
+    artefacta(local, remote)
+        .arg("debug")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("uncompressed_size: Some("));
+}
+
+#[test]
+fn add_package_reports_compression_ratio() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    let build_dir = tempdir().unwrap();
+    let build_dir = build_dir.path();
+    fs::write(build_dir.join("zeros.bin"), vec![0; 100_000]).unwrap();
+
+    let output = artefacta(local, remote)
+        .args(["--output", "json", "add-package", "build1"])
+        .arg(build_dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("compressed `build1` from"));
+
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["version"], "build1");
+    assert_eq!(
+        result["compressed_size"],
+        fs::metadata(local.join("build1.tar.zst")).unwrap().len()
+    );
+    let uncompressed = result["uncompressed_size"].as_u64().unwrap();
+    assert!(uncompressed > 100_000, "tar archive has all of the file");
+    assert!(result["ratio"].as_f64().unwrap() > 10.0);
+}
+
+#[test]
+#[cfg(unix)]
+fn add_package_following_symlinks() {
+    use std::os::unix::fs::symlink;
+
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    let shared = tempdir().unwrap();
+    shared
+        .child("textures/grass.png")
+        .write_str("green")
+        .unwrap();
+
+    let build_dir = tempdir().unwrap();
+    build_dir.child("game.exe").write_str("MZ").unwrap();
+    symlink(shared.path(), build_dir.child("assets").path()).unwrap();
+
+    artefacta(local, remote)
+        .arg("add-package")
+        .arg("build1")
+        .arg(build_dir.path())
+        .arg("--follow-symlinks")
+        .succeeds();
+
+    let unarchive = tempdir().unwrap();
+    untar(local.join("build1.tar.zst"), unarchive.path());
+
+    unarchive.child("assets/textures/grass.png").assert("green");
+}
+
+#[test]
+fn add_package_from_multiple_directories() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    let out = tempdir().unwrap();
+    out.child("bin/game").write_str("ELF").unwrap();
+    out.child("assets/logo.png").write_str("png").unwrap();
+
+    artefacta(local, remote)
+        .arg("add-package")
+        .arg("build1")
+        .arg(out.child("bin").path())
+        .arg(out.child("assets").path())
+        .args(["--prefix-with", "bin", "--prefix-with", "data"])
+        .succeeds();
+
+    let unarchive = tempdir().unwrap();
+    untar(local.join("build1.tar.zst"), unarchive.path());
+
+    unarchive.child("bin/game").assert("ELF");
+    unarchive.child("data/logo.png").assert("png");
+}
+
+#[test]
+fn add_package_needs_prefix_for_every_directory() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    let out = tempdir().unwrap();
+    out.child("bin/game").write_str("ELF").unwrap();
+    out.child("assets/logo.png").write_str("png").unwrap();
+
+    artefacta(local, remote)
+        .arg("add-package")
+        .arg("build1")
+        .arg(out.child("bin").path())
+        .arg(out.child("assets").path())
+        .args(["--prefix-with", "bin"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "need one `--prefix-with` per path",
+        ));
+}
+
 #[test]
 fn add_package_with_invalid_version() {
     let (local, remote) = init();
@@ -176,8 +343,395 @@ fn adding_file_that_does_not_exist() {
         .arg(scratch.join("wrong-name.tar.zst"))
         .assert()
         .failure()
+        .code(exitcode::NOINPUT)
         .stderr(
             predicate::str::is_match("Tried to add `(.*?)` as new build, but file does not exist")
                 .unwrap(),
         );
 }
+
+#[test]
+fn exit_codes_tell_failures_apart() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    let scratch = tempdir().unwrap();
+    let scratch = scratch.path();
+    fs::write(scratch.join("build1.tar.zst"), b"not zstd").unwrap();
+
+    artefacta(local, remote)
+        .args(["add", "--no-such-option"])
+        .assert()
+        .code(exitcode::USAGE);
+    artefacta(local, remote)
+        .args(["--dry-run", "add"])
+        .arg(scratch.join("build1.tar.zst"))
+        .assert()
+        .code(exitcode::USAGE)
+        .stderr(predicate::str::contains("`--dry-run` is not supported"));
+    artefacta(local, remote)
+        .args(["add", "--verify"])
+        .arg(scratch.join("build1.tar.zst"))
+        .assert()
+        .code(exitcode::DATAERR)
+        .stderr(predicate::str::contains("is not a valid build archive"));
+    artefacta(local, remote)
+        .args(["install", "build1"])
+        .assert()
+        .code(1);
+}
+
+#[test]
+fn added_builds_get_a_checksum_file_that_is_uploaded() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    let scratch = tempdir().unwrap();
+    let scratch = scratch.path();
+    random_zstd_file(scratch.join("build1.tar.zst")).unwrap();
+
+    artefacta(local, remote)
+        .arg("add")
+        .arg(scratch.join("build1.tar.zst"))
+        .arg("--upload")
+        .succeeds();
+
+    let checksum = fs::read_to_string(local.join("build1.tar.zst.sha256")).unwrap();
+    assert!(checksum.ends_with("  build1.tar.zst\n"), "{:?}", checksum);
+    assert_eq!(
+        fs::read_to_string(remote.join("build1.tar.zst.sha256")).unwrap(),
+        checksum,
+        "checksum was uploaded along with the build"
+    );
+}
+
+#[test]
+fn blake3_checksums_are_written_and_checked() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    let scratch = tempdir().unwrap();
+    let scratch = scratch.path();
+    random_zstd_file(scratch.join("build1.tar.zst")).unwrap();
+
+    artefacta(local, remote)
+        .arg("--checksum=blake3")
+        .arg("add")
+        .arg(scratch.join("build1.tar.zst"))
+        .arg("--upload")
+        .succeeds();
+
+    assert!(!local.join("build1.tar.zst.sha256").exists());
+    let checksum = fs::read_to_string(remote.join("build1.tar.zst.b3")).unwrap();
+    assert!(checksum.ends_with("  build1.tar.zst\n"), "{:?}", checksum);
+
+    // a fresh local store verifies the download with the blake3 checksum
+    let other_local = tempdir().unwrap();
+    let other_local = other_local.path();
+    artefacta(other_local, remote)
+        .arg("install")
+        .arg("build1")
+        .succeeds();
+    assert_eq!(
+        fs::read_to_string(other_local.join("build1.tar.zst.b3")).unwrap(),
+        checksum
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn identical_builds_are_stored_once() {
+    use std::os::unix::fs::MetadataExt;
+
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    let scratch = tempdir().unwrap();
+    let scratch = scratch.path();
+    random_zstd_file(scratch.join("build1.tar.zst")).unwrap();
+    fs::copy(
+        scratch.join("build1.tar.zst"),
+        scratch.join("build2.tar.zst"),
+    )
+    .unwrap();
+
+    for build in ["build1.tar.zst", "build2.tar.zst"] {
+        artefacta(local, remote)
+            .arg("add")
+            .arg(scratch.join(build))
+            .succeeds();
+    }
+
+    let build1 = fs::metadata(local.join("build1.tar.zst")).unwrap();
+    let build2 = fs::metadata(local.join("build2.tar.zst")).unwrap();
+    assert_eq!(build1.ino(), build2.ino(), "builds are the same file");
+    assert_eq!(build1.nlink(), 2);
+}
+
+#[test]
+fn add_build_from_stdin() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    let scratch = tempdir().unwrap();
+    let build = random_zstd_file(scratch.path().join("build.tar.zst")).unwrap();
+    let archive = fs::read(scratch.path().join("build.tar.zst")).unwrap();
+
+    artefacta(local, remote)
+        .args(["add", "--stdin", "--version", "build1"])
+        .write_stdin(archive.clone())
+        .succeeds();
+    assert_eq!(fs::read(local.join("build1.tar.zst")).unwrap(), archive);
+    assert!(local.join("build1.tar.zst.sha256").exists());
+
+    artefacta(local, remote)
+        .args(["install", "build1"])
+        .succeeds();
+    assert_eq!(
+        artefacta::decompress(fs::File::open(local.join("current")).unwrap()).unwrap(),
+        build
+    );
+
+    artefacta(local, remote)
+        .args(["add", "--stdin", "--version", "build2"])
+        .write_stdin(Vec::new())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("empty (size 0)"));
+    assert!(
+        fs::read_dir(local).unwrap().all(|entry| !entry
+            .unwrap()
+            .file_name()
+            .to_string_lossy()
+            .contains("build2")),
+        "nothing left of empty build"
+    );
+
+    artefacta(local, remote)
+        .args(["add", "--stdin"])
+        .write_stdin(archive)
+        .assert()
+        .failure();
+}
+
+#[test]
+fn add_build_from_url() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    let scratch = tempdir().unwrap();
+    random_zstd_file(scratch.path().join("build.tar.zst")).unwrap();
+    let archive = fs::read(scratch.path().join("build.tar.zst")).unwrap();
+
+    let (url, server) = file_server("200 OK", archive.clone());
+    artefacta(local, remote)
+        .args(["add", "--from-url", &url, "--version", "build1"])
+        .succeeds();
+    server.join().unwrap();
+    assert_eq!(fs::read(local.join("build1.tar.zst")).unwrap(), archive);
+    assert!(local.join("build1.tar.zst.sha256").exists());
+
+    let (url, server) = file_server("404 Not Found", Vec::new());
+    artefacta(local, remote)
+        .args(["add", "--from-url", &url, "--version", "build2"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("not found (404)"));
+    server.join().unwrap();
+
+    let (url, server) = file_server("200 OK", b"<html>Sign in</html>".to_vec());
+    artefacta(local, remote)
+        .args(["add", "--from-url", &url, "--version", "build2"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("not a zstd compressed build"));
+    server.join().unwrap();
+    assert!(!local.join("build2.tar.zst").exists());
+}
+
+#[test]
+fn add_with_verify_rejects_broken_archives() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    let scratch = tempdir().unwrap();
+    let scratch = scratch.path();
+    let src = scratch.join("src");
+    fs::create_dir(&src).unwrap();
+    fs::write(src.join("main.rs"), b"fn main() {}").unwrap();
+    let mut archive = artefacta::compress(Vec::new()).unwrap();
+    artefacta::package(&src, &mut archive).unwrap();
+    let archive = archive.finish().unwrap();
+    fs::write(scratch.join("build1.tar.zst"), &archive).unwrap();
+    fs::write(
+        scratch.join("build2.tar.zst"),
+        &archive[..archive.len() / 2],
+    )
+    .unwrap();
+    random_zstd_file(scratch.join("build3.tar.zst")).unwrap();
+
+    artefacta(local, remote)
+        .args(["add", "--verify"])
+        .arg(scratch.join("build1.tar.zst"))
+        .succeeds();
+    assert!(local.join("build1.tar.zst").exists());
+
+    for name in ["build2.tar.zst", "build3.tar.zst"] {
+        artefacta(local, remote)
+            .args(["add", "--verify"])
+            .arg(scratch.join(name))
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("is not a valid build archive"));
+        assert!(!local.join(name).exists(), "`{}` was added", name);
+    }
+
+    artefacta(local, remote)
+        .args(["add", "--verify", "--stdin", "--version", "build4"])
+        .write_stdin(&archive[..archive.len() / 2])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("is not a valid build archive"));
+    assert!(!local.join("build4.tar.zst").exists());
+}
+
+#[test]
+fn add_package_uses_given_temp_dir() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    let build_dir = tempdir().unwrap();
+    let build_dir = build_dir.path();
+    fs::write(build_dir.join("lib.rs"), b"fn main() { /* code here */ }").unwrap();
+    let temp_dir = tempdir().unwrap();
+    let temp_dir = temp_dir.path();
+
+    artefacta(local, remote)
+        .arg("add-package")
+        .arg("build1")
+        .arg(build_dir)
+        .arg("--temp-dir")
+        .arg(temp_dir)
+        .succeeds();
+    assert!(local.join("build1.tar.zst").exists());
+    assert_eq!(
+        fs::read_dir(temp_dir).unwrap().count(),
+        0,
+        "temporary directory was cleaned up"
+    );
+
+    artefacta(local, remote)
+        .arg("add-package")
+        .arg("build2")
+        .arg(build_dir)
+        .arg("--temp-dir")
+        .arg(temp_dir.join("missing"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "could not create temporary directory in",
+        ));
+    assert!(!local.join("build2.tar.zst").exists());
+}
+
+#[test]
+#[cfg(unix)]
+fn add_package_keeps_temp_dir_on_failure() {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    let build_dir = tempdir().unwrap();
+    let build_dir = build_dir.path();
+    fs::write(build_dir.join("lib.rs"), b"fn main() { /* code here */ }").unwrap();
+    fs::write(build_dir.join(OsStr::from_bytes(b"invalid-\xff")), b"oops").unwrap();
+    let temp_dir = tempdir().unwrap();
+    let temp_dir = temp_dir.path();
+
+    artefacta(local, remote)
+        .args(["add-package", "build1"])
+        .arg(build_dir)
+        .arg("--temp-dir")
+        .arg(temp_dir)
+        .assert()
+        .failure();
+    assert_eq!(fs::read_dir(temp_dir).unwrap().count(), 0);
+
+    artefacta(local, remote)
+        .args(["add-package", "build1"])
+        .arg(build_dir)
+        .arg("--temp-dir")
+        .arg(temp_dir)
+        .arg("--keep-temp")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("keeping temporary directory"));
+    let kept = fs::read_dir(temp_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    assert_eq!(kept.len(), 1);
+    assert_eq!(
+        fs::read_dir(&kept[0]).unwrap().count(),
+        1,
+        "partial archive is kept"
+    );
+    assert!(!local.join("build1.tar.zst").exists());
+}
+
+#[test]
+fn add_plain_tar_builds() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    let scratch = tempdir().unwrap();
+    let scratch = scratch.path();
+    for (version, content) in [("build1", "one"), ("build2", "two")] {
+        let src = scratch.join(version);
+        fs::create_dir(&src).unwrap();
+        fs::write(src.join("run.sh"), content).unwrap();
+        let mut archive = Vec::new();
+        artefacta::package(&src, &mut archive).unwrap();
+        fs::write(scratch.join(format!("{}.tar", version)), &archive).unwrap();
+    }
+
+    artefacta(local, remote)
+        .args(["add", "--verify"])
+        .arg(scratch.join("build1.tar"))
+        .succeeds();
+    artefacta(local, remote)
+        .args(["add", "--verify", "--calc-patch-from=build1", "--upload"])
+        .arg(scratch.join("build2.tar"))
+        .succeeds();
+    assert!(local.join("build1.tar").exists(), "kept uncompressed");
+    assert!(!local.join("build1.tar.zst").exists());
+    assert!(remote.join("build2.tar").exists());
+    assert!(remote.join("build1-build2.patch.zst").exists());
+
+    let (machine2, _) = init();
+    let machine2 = machine2.path();
+    artefacta(machine2, remote)
+        .args(["install", "build1", "--extract"])
+        .succeeds();
+    artefacta(machine2, remote)
+        .args(["install", "build2", "--extract"])
+        .succeeds();
+    assert!(machine2.join("build1-build2.patch.zst").exists());
+    assert_eq!(
+        fs::read(machine2.join("build2.tar")).unwrap(),
+        fs::read(scratch.join("build2.tar")).unwrap(),
+        "patched build is written uncompressed like its original"
+    );
+    assert_eq!(
+        fs::read_to_string(machine2.join("current").join("run.sh")).unwrap(),
+        "two"
+    );
+
+    artefacta(machine2, remote).arg("verify-local").succeeds();
+    artefacta(machine2, remote)
+        .arg("doctor")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("suspect").not());
+}
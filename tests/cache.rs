@@ -0,0 +1,82 @@
+mod test_helpers;
+use test_helpers::*;
+
+const CACHE_FILE: &str = ".artefacta-remote-cache.json";
+
+/// Make it look like nothing changed in `dir` for a while, so cached listings
+/// of it are used
+fn backdate(dir: &Path) {
+    run(&format!("touch -d '1 minute ago' '{}'", dir.display()), dir);
+}
+
+#[test]
+fn cached_remote_listing_is_used_until_refresh() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(remote.join("build1.tar.zst")).unwrap();
+    random_zstd_file(remote.join("build2.tar.zst")).unwrap();
+
+    artefacta(local, remote)
+        .args(["--cache-max-age=600", "list"])
+        .succeeds();
+    assert!(local.join(CACHE_FILE).exists(), "listing was cached");
+
+    fs::remove_file(remote.join("build2.tar.zst")).unwrap();
+    backdate(remote);
+    artefacta(local, remote)
+        .args(["--cache-max-age=600", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("build2"));
+
+    artefacta(local, remote)
+        .args(["--cache-max-age=600", "--refresh", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("build2").not());
+}
+
+#[test]
+fn cache_is_not_used_by_default() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(remote.join("build1.tar.zst")).unwrap();
+    artefacta(local, remote).arg("list").succeeds();
+    assert!(!local.join(CACHE_FILE).exists());
+}
+
+#[test]
+fn install_with_stale_cache_lists_remote_again() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(remote.join("build1.tar.zst")).unwrap();
+    random_zstd_file(remote.join("build3.tar.zst")).unwrap();
+    artefacta(local, remote)
+        .args(["--cache-max-age=600", "install", "build1"])
+        .succeeds();
+
+    // cache still lists build3
+    fs::remove_file(remote.join("build3.tar.zst")).unwrap();
+    backdate(remote);
+    artefacta(local, remote)
+        .args(["--cache-max-age=600", "install", "build3"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("listing it again"))
+        .stderr(predicate::str::contains("unknown"));
+    assert!(!local.join("build3.tar.zst").exists());
+
+    // cache doesn't know about build2 yet
+    let build2 = random_zstd_file(remote.join("build2.tar.zst")).unwrap();
+    backdate(remote);
+    artefacta(local, remote)
+        .args(["--cache-max-age=600", "install", "build2"])
+        .succeeds();
+    assert_eq!(
+        artefacta::decompress(fs::File::open(local.join("current")).unwrap()).unwrap(),
+        build2
+    );
+}
@@ -0,0 +1,48 @@
+mod test_helpers;
+use test_helpers::*;
+
+#[test]
+fn clean_removes_patches_without_builds() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(local.join("build1.tar.zst")).unwrap();
+    random_zstd_file(local.join("build2.tar.zst")).unwrap();
+    random_zstd_file(remote.join("build3.tar.zst")).unwrap();
+    artefacta(local, remote)
+        .args(["create-patch", "build1", "build2"])
+        .succeeds();
+    artefacta(local, remote)
+        .args(["create-patch", "build2", "build3"])
+        .succeeds();
+    fs::remove_file(local.join("build1.tar.zst")).unwrap();
+
+    artefacta(local, remote)
+        .args(["--dry-run", "clean"])
+        .assert()
+        .success()
+        .stdout("build1-build2.patch.zst\n");
+    assert!(local.join("build1-build2.patch.zst").exists());
+
+    artefacta(local, remote)
+        .arg("clean")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "removed orphaned patch `build1-build2.patch`",
+        ));
+    assert!(!local.join("build1-build2.patch.zst").exists());
+    assert!(!local.join("build1-build2.patch.zst.sha256").exists());
+    assert!(
+        local.join("build2-build3.patch.zst").exists(),
+        "build3 is still in remote storage"
+    );
+
+    // nothing left to clean
+    artefacta(local, remote)
+        .args(["--dry-run", "clean"])
+        .assert()
+        .success()
+        .stdout("");
+    artefacta(local, remote).arg("clean").succeeds();
+}
@@ -0,0 +1,115 @@
+mod test_helpers;
+use test_helpers::*;
+
+/// Run artefacta in `dir` without any of the usual environment variables
+fn artefacta_in(dir: &Path) -> Command {
+    let mut cmd = Command::cargo_bin("artefacta").unwrap();
+    cmd.current_dir(dir);
+    cmd.env_remove("ARTEFACTA_LOCAL_STORE");
+    cmd.env_remove("ARTEFACTA_REMOTE_STORE");
+    cmd.env_remove("ARTEFACTA_CONFIG");
+    cmd.env("XDG_CONFIG_HOME", dir.join("no-config-here"));
+    cmd.timeout(std::time::Duration::from_secs(10));
+    cmd
+}
+
+#[test]
+fn stores_are_read_from_config_in_working_dir() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+    let workdir = tempdir().unwrap();
+
+    fs::write(remote.join("build1.tar.zst"), b"build one").unwrap();
+    fs::write(
+        workdir.path().join("artefacta.toml"),
+        format!(
+            "local_store = {:?}\nremote_store = {:?}\n",
+            local.display().to_string(),
+            remote.display().to_string()
+        ),
+    )
+    .unwrap();
+
+    artefacta_in(workdir.path())
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("build1"));
+}
+
+#[test]
+fn flags_override_config() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+    let other_remote = tempdir().unwrap();
+    let workdir = tempdir().unwrap();
+
+    fs::write(remote.join("build1.tar.zst"), b"build one").unwrap();
+    fs::write(other_remote.path().join("build2.tar.zst"), b"build two").unwrap();
+    fs::write(
+        workdir.path().join("artefacta.toml"),
+        format!(
+            "local_store = {:?}\nremote_store = {:?}\n",
+            local.display().to_string(),
+            remote.display().to_string()
+        ),
+    )
+    .unwrap();
+
+    artefacta_in(workdir.path())
+        .arg("--remote")
+        .arg(other_remote.path())
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("build2").and(predicate::str::contains("build1").not()));
+}
+
+#[test]
+fn explicit_config_path() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+    let workdir = tempdir().unwrap();
+    let config = workdir.path().join("elsewhere.toml");
+
+    fs::write(remote.join("build1.tar.zst"), b"build one").unwrap();
+    fs::write(
+        &config,
+        format!(
+            "local_store = {:?}\nremote_store = {:?}\n",
+            local.display().to_string(),
+            remote.display().to_string()
+        ),
+    )
+    .unwrap();
+
+    artefacta_in(workdir.path())
+        .arg("--config")
+        .arg(&config)
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("build1"));
+
+    artefacta_in(workdir.path())
+        .args(["--config", "does-not-exist.toml", "list"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("does-not-exist.toml"));
+}
+
+#[test]
+fn invalid_config_is_an_error() {
+    let workdir = tempdir().unwrap();
+    fs::write(
+        workdir.path().join("artefacta.toml"),
+        "local-store = \"/tmp\"",
+    )
+    .unwrap();
+
+    artefacta_in(workdir.path())
+        .arg("list")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("unknown field `local-store`"));
+}
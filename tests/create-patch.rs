@@ -1,6 +1,8 @@
 mod test_helpers;
 use test_helpers::*;
 
+use std::io::Read;
+
 #[test]
 fn create_a_patch_from_remote_builds() {
     let (local, remote) = init();
@@ -32,3 +34,253 @@ fn patches_cant_have_same_to_and_from() {
             "Rejecting to create patch between same versions",
         ));
 }
+
+#[test]
+fn failed_patch_leaves_no_partial_file() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    // old build is cut off, so diffing fails halfway through
+    random_zstd_file(local.join("build1.tar.zst")).unwrap();
+    let old = fs::read(local.join("build1.tar.zst")).unwrap();
+    fs::write(local.join("build1.tar.zst"), &old[..old.len() / 2]).unwrap();
+    random_zstd_file(local.join("build2.tar.zst")).unwrap();
+
+    artefacta(local, remote)
+        .args(["create-patch", "build1", "build2"])
+        .assert()
+        .failure();
+
+    let leftovers: Vec<_> = fs::read_dir(local)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .filter(|name| name.to_string_lossy().contains("patch"))
+        .collect();
+    assert!(leftovers.is_empty(), "left behind {:?}", leftovers);
+}
+
+#[test]
+fn create_a_patch_with_custom_diff_params() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    let mut content = random_bytes(64 * 1024).unwrap();
+    zstd_file(remote.join("build1.tar.zst"), &content).unwrap();
+    content[1234] ^= 0xff;
+    zstd_file(remote.join("build2.tar.zst"), &content).unwrap();
+
+    artefacta(local, remote)
+        .args([
+            "create-patch",
+            "build1",
+            "build2",
+            "--diff-threads=2",
+            "--diff-chunk-size=4096",
+        ])
+        .succeeds();
+    artefacta(local, remote)
+        .env("ARTEFACTA_DIFF_PARALLEL_THRESHOLD", "0")
+        .args(["create-patch", "build2", "build1"])
+        .succeeds();
+
+    let mut patched = Vec::new();
+    artefacta::apply_patch(
+        local.join("build1.tar.zst"),
+        local.join("build1-build2.patch.zst"),
+    )
+    .unwrap()
+    .read_to_end(&mut patched)
+    .unwrap();
+    assert!(patched == content, "patch does not produce new build");
+}
+
+#[test]
+fn invalid_diff_params_are_rejected() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(remote.join("build1.tar.zst")).unwrap();
+    random_zstd_file(remote.join("build2.tar.zst")).unwrap();
+
+    for arg in ["--diff-threads=0", "--diff-chunk-size=0"] {
+        artefacta(local, remote)
+            .args(["create-patch", "build1", "build2", arg])
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("invalid diff parameters"))
+            .stderr(predicate::str::contains("cannot be less than 1"))
+            .stderr(predicate::str::contains("programming error").not());
+    }
+    assert!(!local.join("build1-build2.patch.zst").exists());
+}
+
+#[test]
+fn estimate_patch_size() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    let old = random_bytes(1_000_000).unwrap();
+    let mut new = old.clone();
+    new.splice(500_000..500_000, random_bytes(20_000).unwrap());
+    zstd_file(local.join("build1.tar.zst"), &old).unwrap();
+    zstd_file(local.join("build2.tar.zst"), &new).unwrap();
+    let build_size = fs::metadata(local.join("build2.tar.zst")).unwrap().len();
+
+    let output = artefacta(local, remote)
+        .args(["--output", "json", "estimate-patch", "build1", "build2"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let estimate: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(estimate["build_size"], build_size);
+    assert_eq!(estimate["uncompressed_size"], new.len() as u64);
+    let estimated = estimate["estimated_size"].as_u64().unwrap();
+    assert!(!local.join("build1-build2.patch.zst").exists());
+
+    artefacta(local, remote)
+        .args(["create-patch", "build1", "build2"])
+        .succeeds();
+    let actual = fs::metadata(local.join("build1-build2.patch.zst"))
+        .unwrap()
+        .len();
+    assert!(
+        estimated >= actual && estimated < build_size / 5,
+        "estimated {} bytes, patch has {}",
+        estimated,
+        actual
+    );
+}
+
+#[test]
+fn create_patches_between_consecutive_builds() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    for version in ["1.9", "1.10", "1.11"] {
+        random_zstd_file(local.join(format!("{}.tar.zst", version))).unwrap();
+    }
+    random_zstd_file(remote.join("2.0.tar.zst")).unwrap();
+    artefacta(local, remote)
+        .args(["create-patch", "1.10", "1.11"])
+        .succeeds();
+    let existing = fs::read(local.join("1.10-1.11.patch.zst")).unwrap();
+
+    artefacta(local, remote)
+        .args(["--dry-run", "create-patches-chain"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("would create patch 1.9 -> 1.10"))
+        .stdout(predicate::str::contains(
+            "patch 1.10 -> 1.11 already exists",
+        ))
+        .stdout(predicate::str::contains("would create patch 1.11 -> 2.0"));
+    assert!(!local.join("1.9-1.10.patch.zst").exists());
+
+    let output = artefacta(local, remote)
+        .args(["--output", "json", "create-patches-chain"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let output: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let created = output["patches"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|patch| format!("{}-{}", patch["from"], patch["to"]).replace('"', ""))
+        .collect::<Vec<_>>();
+    assert_eq!(created, ["1.9-1.10", "1.11-2.0"]);
+    assert!(local.join("1.9-1.10.patch.zst").exists());
+    assert!(local.join("1.11-2.0.patch.zst").exists());
+    assert_eq!(
+        fs::read(local.join("1.10-1.11.patch.zst")).unwrap(),
+        existing,
+        "existing patch was created again"
+    );
+
+    artefacta(local, remote)
+        .args(["create-patches-chain", "1.9", "1.11"])
+        .succeeds();
+    assert!(local.join("1.9-1.11.patch.zst").exists());
+    artefacta(local, remote)
+        .args(["create-patches-chain", "1.9", "3.0"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("build `3.0` unknown"));
+}
+
+#[test]
+fn auto_patch_from_nearest_earlier_builds() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    for build in 1..=5 {
+        random_zstd_file(local.join(format!("{}.tar.zst", build))).unwrap();
+    }
+
+    artefacta(local, remote)
+        .args(["auto-patch-local", "4", "--count", "2"])
+        .succeeds();
+    assert!(local.join("2-4.patch.zst").exists());
+    assert!(local.join("3-4.patch.zst").exists());
+    assert!(!local.join("1-4.patch.zst").exists());
+    assert!(!local.join("4-5.patch.zst").exists());
+
+    // patches that exist are kept, fewer earlier builds are fine
+    artefacta(local, remote)
+        .args(["auto-patch-local", "4", "--count", "10"])
+        .succeeds();
+    assert!(local.join("1-4.patch.zst").exists());
+
+    artefacta(local, remote)
+        .args(["auto-patch-local", "1"])
+        .succeeds();
+    artefacta(local, remote)
+        .args(["auto-patch-local", "6"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn builds_and_patches_use_configured_naming() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+    let naming = [
+        "--build-suffix=.tar.zstd",
+        "--patch-suffix=.bsdiff.zstd",
+        "--patch-separator=_to_",
+    ];
+
+    random_zstd_file(remote.join("build1.tar.zstd")).unwrap();
+    random_zstd_file(remote.join("build2.tar.zstd")).unwrap();
+
+    artefacta(local, remote)
+        .args(naming)
+        .args(["create-patch", "build1", "build2"])
+        .succeeds();
+    assert!(local.join("build1.tar.zstd").exists());
+    assert!(local.join("build1_to_build2.bsdiff.zstd").exists());
+
+    artefacta(local, remote).args(naming).arg("sync").succeeds();
+    assert!(remote.join("build1_to_build2.bsdiff.zstd").exists());
+
+    artefacta(local, remote)
+        .args(naming)
+        .args(["install", "build2", "--as", "prod.tar.zstd"])
+        .assert()
+        .code(64)
+        .stderr(predicate::str::contains(
+            "clash with files artefacta manages",
+        ));
+}
+
+#[test]
+fn patch_suffix_has_to_differ_from_build_suffix() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    artefacta(local, remote)
+        .args(["--patch-suffix=.zst", "list"])
+        .assert()
+        .code(64)
+        .stderr(predicate::str::contains("can't be told apart"));
+}
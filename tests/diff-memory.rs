@@ -0,0 +1,106 @@
+//! Calculating patches between large builds should not need memory in
+//! proportion to the size of the builds
+//!
+//! This is its own test binary as it counts all allocations.
+
+mod test_helpers;
+use test_helpers::*;
+
+use artefacta::ArtefactIndex;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    convert::TryInto,
+    io::Read,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let now = ALLOCATED.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+            PEAK.fetch_max(now, Ordering::SeqCst);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Peak of allocated memory while running `f`, on top of what was allocated
+/// before
+fn peak_memory<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATED.load(Ordering::SeqCst);
+    PEAK.store(before, Ordering::SeqCst);
+    let res = f();
+    (res, PEAK.load(Ordering::SeqCst) - before)
+}
+
+const BUILD_SIZE: usize = 8 * 1024 * 1024;
+
+#[test]
+fn large_diff_stays_within_memory_budget() {
+    let local = tempdir().unwrap();
+    let remote = tempdir().unwrap();
+
+    let old = random_bytes(BUILD_SIZE).unwrap();
+    let mut new = old.clone();
+    for offset in (0..BUILD_SIZE).step_by(BUILD_SIZE / 16) {
+        new[offset] ^= 0xff;
+    }
+    new.extend(random_bytes(1024).unwrap());
+    zstd_file(local.path().join("1.tar.zst"), &old).unwrap();
+    zstd_file(local.path().join("2.tar.zst"), &new).unwrap();
+    drop((old, new));
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let window_size = BUILD_SIZE / 16;
+    let (res, peak) = peak_memory(|| {
+        runtime.block_on(async {
+            let mut index =
+                ArtefactIndex::new(local.path(), remote.path().try_into().unwrap()).await?;
+            index.set_diff_window_size(window_size);
+            index.calculate_patch("1".parse()?, "2".parse()?).await
+        })
+    });
+    res.unwrap();
+
+    let budget = BUILD_SIZE;
+    assert!(
+        peak < budget,
+        "diffing two builds of {} bytes in windows of {} bytes took {} bytes of memory",
+        BUILD_SIZE,
+        window_size,
+        peak
+    );
+
+    let mut patched = Vec::new();
+    artefacta::apply_patch(
+        local.path().join("1.tar.zst"),
+        local.path().join("1-2.patch.zst"),
+    )
+    .unwrap()
+    .read_to_end(&mut patched)
+    .unwrap();
+    let new =
+        artefacta::decompress(fs::File::open(local.path().join("2.tar.zst")).unwrap()).unwrap();
+    assert!(patched == new, "patch does not produce new build");
+    assert!(
+        fs::metadata(local.path().join("1-2.patch.zst"))
+            .unwrap()
+            .len()
+            < 64 * 1024,
+        "patch should be small"
+    );
+}
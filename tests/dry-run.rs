@@ -0,0 +1,119 @@
+mod test_helpers;
+use test_helpers::*;
+
+fn files_in(dir: &Path) -> Vec<String> {
+    let mut files = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+#[test]
+fn sync_dry_run_lists_files_without_uploading() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    file_of_size(local.join("build1.tar.zst"), 100);
+    file_of_size(local.join("build2.tar.zst"), 100);
+    file_of_size(local.join("build1-build2.patch.zst"), 10);
+    file_of_size(remote.join("build1.tar.zst"), 100);
+
+    artefacta(local, remote)
+        .args(["--dry-run", "sync"])
+        .assert()
+        .success()
+        .stdout(
+            "build2.tar.zst (100 B)\n\
+             build1-build2.patch.zst (10 B)\n\
+             would upload 110 B in 2 files\n",
+        );
+
+    assert_eq!(files_in(remote), vec!["build1.tar.zst"]);
+
+    let output = artefacta(local, remote)
+        .args(["sync", "--dry-run", "--output", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let pending: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        pending,
+        serde_json::json!({
+            "files": [
+                { "name": "build2.tar.zst", "size": 100 },
+                { "name": "build1-build2.patch.zst", "size": 10 },
+            ],
+            "total_size": 110,
+            "dry_run": true,
+        })
+    );
+    assert_eq!(files_in(remote), vec!["build1.tar.zst"]);
+}
+
+#[test]
+#[cfg(unix)]
+fn install_dry_run_shows_plan_without_installing() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    file_of_size(remote.join("build1.tar.zst"), 1000);
+    file_of_size(remote.join("build2.tar.zst"), 1000);
+    file_of_size(remote.join("build1-build2.patch.zst"), 10);
+
+    file_of_size(local.join("build1.tar.zst"), 1000);
+    std::os::unix::fs::symlink(local.join("build1.tar.zst"), local.join("current")).unwrap();
+
+    artefacta(local, remote)
+        .args(["install", "build2", "--dry-run"])
+        .assert()
+        .success()
+        .stdout(
+            "would install build2 applying build1-build2.patch.zst\n  \
+             download build1-build2.patch.zst\n",
+        );
+
+    assert_eq!(files_in(local), vec!["build1.tar.zst", "current"]);
+    assert_eq!(
+        fs::read_link(local.join("current")).unwrap(),
+        local.join("build1.tar.zst")
+    );
+}
+
+#[test]
+fn create_patch_dry_run_reports_work() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    file_of_size(remote.join("build1.tar.zst"), 1000);
+    file_of_size(remote.join("build2.tar.zst"), 2048);
+    file_of_size(local.join("build1.tar.zst"), 1000);
+
+    artefacta(local, remote)
+        .args(["--dry-run", "create-patch", "build1", "build2"])
+        .assert()
+        .success()
+        .stdout(
+            "would create patch build1 -> build2 (diffing 2 KiB)\n  \
+             download build2.tar.zst\n",
+        );
+
+    assert_eq!(files_in(local), vec!["build1.tar.zst"]);
+}
+
+#[test]
+fn dry_run_is_rejected_for_commands_that_cannot_do_it() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    let build = local.join("build1.tar.zst");
+    file_of_size(&build, 100);
+
+    artefacta(local, remote)
+        .args(["--dry-run", "add"])
+        .arg(&build)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("not supported"));
+}
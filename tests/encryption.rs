@@ -0,0 +1,105 @@
+mod test_helpers;
+use test_helpers::*;
+
+#[test]
+fn encrypted_remote_round_trip() {
+    let (machine1, remote) = init();
+    let (machine1, remote) = (machine1.path(), remote.path());
+    let (machine2, keys) = init();
+    let (machine2, keys) = (machine2.path(), keys.path());
+    let (machine3, _) = init();
+    let machine3 = machine3.path();
+
+    let key_file = keys.join("key");
+    fs::write(&key_file, "correct horse battery staple\n").unwrap();
+    let wrong_key_file = keys.join("wrong-key");
+    fs::write(&wrong_key_file, "incorrect horse battery staple\n").unwrap();
+
+    let mut content = random_bytes(1024).unwrap();
+    zstd_file(machine1.join("build1.tar.zst"), &content).unwrap();
+    content.extend(random_bytes(32).unwrap());
+    zstd_file(machine1.join("build2.tar.zst"), &content).unwrap();
+
+    artefacta(machine1, remote)
+        .args(["create-patch", "build1", "build2"])
+        .succeeds();
+    artefacta(machine1, remote)
+        .arg("--encryption-key-file")
+        .arg(&key_file)
+        .arg("sync")
+        .succeeds();
+
+    for name in [
+        "build1.tar.zst",
+        "build2.tar.zst",
+        "build1-build2.patch.zst",
+    ] {
+        let uploaded = fs::read(remote.join(name)).unwrap();
+        assert!(uploaded.starts_with(b"ARTFENC1"), "`{}` is encrypted", name);
+        assert_ne!(uploaded, fs::read(machine1.join(name)).unwrap());
+        assert!(remote.join(format!("{}.enc.sha256", name)).exists());
+    }
+
+    artefacta(machine2, remote)
+        .arg("--encryption-key-file")
+        .arg(&wrong_key_file)
+        .args(["install", "build1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("decrypt `build1.tar.zst`"));
+    assert!(!machine2.join("build1.tar.zst").exists());
+
+    artefacta(machine2, remote)
+        .env("ARTEFACTA_ENCRYPTION_KEY_FILE", &key_file)
+        .args(["install", "build1"])
+        .succeeds();
+    artefacta(machine2, remote)
+        .env("ARTEFACTA_ENCRYPTION_KEY_FILE", &key_file)
+        .args(["install", "build2"])
+        .succeeds();
+    assert!(machine2.join("build1-build2.patch.zst").exists());
+    assert_eq!(
+        artefacta::decompress(fs::File::open(machine2.join("current")).unwrap()).unwrap(),
+        content
+    );
+
+    artefacta(machine3, remote)
+        .args(["install", "build1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("build `build1` is corrupt"));
+}
+
+#[test]
+fn corrupted_encrypted_files_fail_their_checksum() {
+    let (machine1, remote) = init();
+    let (machine1, remote) = (machine1.path(), remote.path());
+    let (machine2, keys) = init();
+    let (machine2, keys) = (machine2.path(), keys.path());
+
+    let key_file = keys.join("key");
+    fs::write(&key_file, "correct horse battery staple\n").unwrap();
+    random_zstd_file(machine1.join("build1.tar.zst")).unwrap();
+    artefacta(machine1, remote)
+        .arg("--encryption-key-file")
+        .arg(&key_file)
+        .arg("sync")
+        .succeeds();
+
+    let uploaded = remote.join("build1.tar.zst");
+    let mut content = fs::read(&uploaded).unwrap();
+    let last = content.len() - 1;
+    content[last] ^= 1;
+    fs::write(&uploaded, content).unwrap();
+
+    artefacta(machine2, remote)
+        .arg("--encryption-key-file")
+        .arg(&key_file)
+        .args(["install", "build1"])
+        .assert()
+        .code(65)
+        .stderr(predicate::str::contains(
+            "encrypted `build1.tar.zst` is corrupt",
+        ));
+    assert!(!machine2.join("build1.tar.zst").exists());
+}
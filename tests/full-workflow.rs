@@ -48,3 +48,50 @@ fn upgrade_to_new_build_with_patches() {
     assert!(machine2.join("build1-build2.patch.zst").exists());
     assert!(machine2.join("build2-build3.patch.zst").exists());
 }
+
+#[test]
+fn downgrade_to_old_build_with_reverse_patches() {
+    let (machine1, remote) = init();
+    let (machine1, remote) = (machine1.path(), remote.path());
+
+    let mut content = random_bytes(1024).unwrap();
+    zstd_file(remote.join("build1.tar.zst"), &content).unwrap();
+    content.extend(random_bytes(32).unwrap());
+    zstd_file(remote.join("build2.tar.zst"), &content).unwrap();
+    content.extend(random_bytes(32).unwrap());
+    zstd_file(remote.join("build3.tar.zst"), &content).unwrap();
+
+    artefacta(machine1, remote)
+        .args(["create-patch", "build1", "build2", "--reverse"])
+        .succeeds();
+    artefacta(machine1, remote)
+        .args(["create-patch", "build2", "build3", "--reverse"])
+        .succeeds();
+    artefacta(machine1, remote).args(["sync"]).succeeds();
+    assert!(remote.join("build3-build2.patch.zst").exists());
+    assert!(remote.join("build2-build1.patch.zst").exists());
+
+    let (machine2, _) = init();
+    let machine2 = machine2.path();
+
+    artefacta(machine2, remote)
+        .args(["install", "build3"])
+        .succeeds();
+    artefacta(machine2, remote)
+        .args(["install", "build1"])
+        .succeeds();
+    assert!(machine2.join("build3-build2.patch.zst").exists());
+    assert!(machine2.join("build2-build1.patch.zst").exists());
+    let decompress = |path: PathBuf| zstd::stream::decode_all(fs::File::open(path).unwrap());
+    assert_eq!(
+        decompress(machine2.join("build1.tar.zst")).unwrap(),
+        decompress(remote.join("build1.tar.zst")).unwrap(),
+    );
+
+    let current = machine2.join("current");
+    assert_eq!(
+        machine2.join("build1.tar.zst").canonicalize().unwrap(),
+        fs::read_link(&current).unwrap(),
+        "symlink points to old build"
+    );
+}
@@ -126,3 +126,62 @@ fn auto_patch_from_git_repo_with_prefix() {
     assert!(local.join("wtf-0.1.1.tar.zst").exists());
     assert!(local.join("wtf-0.1.0---wtf-0.1.1.patch.zst").exists());
 }
+
+#[test]
+fn auto_patch_calculates_patches_concurrently() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+    let repo = tempdir().unwrap();
+    let repo = repo.path();
+
+    run("git init .", repo);
+    run("git config user.email 'git-test@example.com'", repo);
+    run("git config user.name 'Author Name'", repo);
+    run("mkdir src", repo);
+
+    for (content, tag) in [("foo", "1.2.0"), ("bar", "2.0.0"), ("baz", "2.1.1")] {
+        run(&format!("echo {} > src/wtf", content), repo);
+        run("git add .", repo);
+        run(&format!("git commit -m 'bump {}'", tag), repo);
+        run(&format!("git tag {}", tag), repo);
+        artefacta(local, remote)
+            .arg("add-package")
+            .arg(tag)
+            .arg(repo.join("src"))
+            .succeeds();
+    }
+    run("echo lorem > src/wtf", repo);
+    run("git add .", repo);
+    run("git commit -m 'bump 2.1.2'", repo);
+    run("git tag 2.1.2", repo);
+    artefacta(local, remote)
+        .arg("add-package")
+        .arg("2.1.2")
+        .arg(repo.join("src"))
+        .succeeds();
+
+    artefacta(local, remote)
+        .arg("auto-patch")
+        .arg("--repo-root")
+        .arg(repo)
+        .args(["--jobs", "3", "2.1.2"])
+        .succeeds();
+    ls(local);
+
+    for from in ["1.2.0", "2.0.0", "2.1.1"] {
+        assert!(
+            local.join(format!("{}-2.1.2.patch.zst", from)).exists(),
+            "no patch from {}",
+            from
+        );
+    }
+
+    artefacta(local, remote)
+        .arg("auto-patch")
+        .arg("--repo-root")
+        .arg(repo)
+        .args(["--jobs", "0", "2.1.2"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("at least 1 job"));
+}
@@ -0,0 +1,154 @@
+mod test_helpers;
+use test_helpers::*;
+
+#[test]
+fn info_shows_patches_and_install_cost() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    let build1 = random_bytes(100_000).unwrap();
+    let mut build2 = build1.clone();
+    build2.extend(random_bytes(1_000).unwrap());
+    zstd_file(local.join("build1.tar.zst"), &build1).unwrap();
+    zstd_file(local.join("build2.tar.zst"), &build2).unwrap();
+    artefacta(local, remote)
+        .args(["create-patch", "build1", "build2"])
+        .succeeds();
+    artefacta(local, remote).arg("sync").succeeds();
+    run("touch -d '2022-05-30 12:00 UTC' build2.tar.zst", remote);
+
+    let other_local = tempdir().unwrap();
+    let other_local = other_local.path();
+    fs::copy(
+        local.join("build1.tar.zst"),
+        other_local.join("build1.tar.zst"),
+    )
+    .unwrap();
+    artefacta(other_local, remote)
+        .args(["install", "build1"])
+        .succeeds();
+
+    let output = artefacta(other_local, remote)
+        .args(["--output", "json", "info", "build2"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let patch_size = fs::metadata(remote.join("build1-build2.patch.zst"))
+        .unwrap()
+        .len();
+    assert_eq!(info["version"], "build2");
+    assert_eq!(info["local_size"], serde_json::Value::Null);
+    assert_eq!(
+        info["remote_size"],
+        fs::metadata(remote.join("build2.tar.zst")).unwrap().len()
+    );
+    assert_eq!(info["remote_modified"], "2022-05-30T12:00:00Z");
+    assert_eq!(info.get("local_modified"), None);
+    assert_eq!(info["patches_in"][0]["from"], "build1");
+    assert_eq!(info["patches_out"], serde_json::json!([]));
+    assert_eq!(info["installed"], "build1");
+    assert_eq!(info["upgrade"]["method"], "patches");
+    assert_eq!(info["upgrade"]["download_size"], patch_size);
+
+    artefacta(other_local, remote)
+        .args(["info", "build1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("build1 -> build2"))
+        .stdout(predicate::str::contains("(this build)"));
+    assert!(
+        !other_local.join("build2.tar.zst").exists(),
+        "info downloaded something"
+    );
+}
+
+#[test]
+fn info_on_unknown_version_suggests_similar_ones() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(local.join("1.2.3.tar.zst")).unwrap();
+    random_zstd_file(local.join("1.2.4.tar.zst")).unwrap();
+    random_zstd_file(local.join("5.0.0.tar.zst")).unwrap();
+
+    artefacta(local, remote)
+        .args(["info", "1.2.5"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("build `1.2.5` unknown"))
+        .stderr(predicate::str::contains("did you mean `1.2.3`, `1.2.4`?"));
+
+    artefacta(local, remote)
+        .args(["info", "foo"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("did you mean").not());
+}
+
+#[test]
+fn builds_keep_metadata_from_add() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+    let scratch = tempdir().unwrap();
+    let scratch = scratch.path();
+
+    random_zstd_file(scratch.join("build1.tar.zst")).unwrap();
+    random_zstd_file(scratch.join("build2.tar.zst")).unwrap();
+    artefacta(local, remote)
+        .arg("add")
+        .arg(scratch.join("build1.tar.zst"))
+        .args([
+            "--meta",
+            "git_sha=abc123",
+            "--meta",
+            "ci_job=https://ci.example.com/42",
+        ])
+        .arg("--upload")
+        .succeeds();
+    artefacta(local, remote)
+        .arg("add")
+        .arg(scratch.join("build2.tar.zst"))
+        .succeeds();
+    assert!(remote.join("build1.meta.json").exists());
+    assert!(!local.join("build2.meta.json").exists());
+
+    artefacta(local, remote)
+        .arg("add")
+        .arg(scratch.join("build1.tar.zst"))
+        .args(["--meta", "no-value"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("is not a `key=value` pair"));
+
+    artefacta(local, remote)
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("META\nbuild1"))
+        .stdout(predicate::str::contains(
+            "ci_job=https://ci.example.com/42, git_sha=abc123\n",
+        ));
+
+    // metadata comes along when installing the build elsewhere
+    let other_local = tempdir().unwrap();
+    let other_local = other_local.path();
+    artefacta(other_local, remote)
+        .args(["install", "build1"])
+        .succeeds();
+    let output = artefacta(other_local, remote)
+        .args(["--output", "json", "info", "build1"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(info["meta"]["git_sha"], "abc123");
+    assert_eq!(info["meta"]["ci_job"], "https://ci.example.com/42");
+
+    let output = artefacta(local, remote)
+        .args(["--output", "json", "info", "build2"])
+        .output()
+        .unwrap();
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(info.get("meta").is_none(), "no metadata, no field");
+}
@@ -49,3 +49,76 @@ fn local_has_patches_with_no_builds() {
         .success()
         .stderr(predicate::str::contains("failed to add patch").not());
 }
+
+#[test]
+fn doctor_reports_build_islands() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(remote.join("build1.tar.zst")).unwrap();
+    random_zstd_file(remote.join("build2.tar.zst")).unwrap();
+    random_zstd_file(remote.join("other1.tar.zst")).unwrap();
+
+    artefacta(local, remote)
+        .args(["create-patch", "build1", "build2"])
+        .succeeds();
+
+    artefacta(local, remote)
+        .args(["doctor"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("island 1: build1, build2"))
+        .stdout(predicate::str::contains("island 2: other1"));
+}
+
+#[test]
+fn doctor_repairs_corrupt_local_builds() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(remote.join("build1.tar.zst")).unwrap();
+    random_zstd_file(remote.join("build2.tar.zst")).unwrap();
+    random_zstd_file(local.join("build3.tar.zst")).unwrap();
+    // like downloads that were killed
+    fs::write(local.join("build1.tar.zst"), b"").unwrap();
+    fs::write(local.join("build2.tar.zst"), b"<html>").unwrap();
+
+    artefacta(local, remote)
+        .args(["list"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "local build `build1` looks corrupt (it's empty)",
+        ))
+        .stderr(predicate::str::contains(
+            "local build `build2` looks corrupt (it isn't zstd compressed)",
+        ))
+        .stderr(predicate::str::contains("`build3` looks corrupt").not());
+
+    artefacta(local, remote)
+        .args(["doctor"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "suspect local build build1: it's empty",
+        ));
+    assert!(
+        local.join("build1.tar.zst").exists(),
+        "only repairs when asked"
+    );
+
+    artefacta(local, remote)
+        .args(["doctor", "--repair"])
+        .succeeds();
+    assert!(!local.join("build1.tar.zst").exists());
+    assert!(!local.join("build2.tar.zst").exists());
+    assert!(local.join("build3.tar.zst").exists());
+
+    artefacta(local, remote)
+        .args(["install", "build2"])
+        .succeeds();
+    assert_eq!(
+        fs::read(local.join("build2.tar.zst")).unwrap(),
+        fs::read(remote.join("build2.tar.zst")).unwrap()
+    );
+}
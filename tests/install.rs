@@ -84,9 +84,9 @@ fn size_is_different_between_remote_and_local() {
     let (local, remote) = init();
     let (local, remote) = (local.path(), remote.path());
 
-    // two textfiles, both alike in dignity
-    fs::write(local.join("build1.tar.zst"), b"lorem ipsum").unwrap();
-    fs::write(remote.join("build1.tar.zst"), b"dolor sit amet").unwrap();
+    // two builds, both alike in dignity
+    zstd_file(local.join("build1.tar.zst"), b"lorem ipsum").unwrap();
+    zstd_file(remote.join("build1.tar.zst"), b"dolor sit amet").unwrap();
 
     artefacta(local, remote)
         .args(&["install", "build1"])
@@ -98,6 +98,26 @@ fn size_is_different_between_remote_and_local() {
             )
             .unwrap(),
         );
+    assert_eq!(
+        artefacta::decompress(fs::File::open(local.join("current")).unwrap()).unwrap(),
+        b"lorem ipsum"
+    );
+
+    // same again, but now we want what's on remote
+    let other_local = tempdir().unwrap();
+    let other_local = other_local.path();
+    zstd_file(other_local.join("build1.tar.zst"), b"lorem ipsum").unwrap();
+    artefacta(other_local, remote)
+        .args(["--prefer-remote", "install", "build1"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "Locally cached file for `build1` is not what's on remote (size on remote differs)",
+        ));
+    assert_eq!(
+        artefacta::decompress(fs::File::open(other_local.join("current")).unwrap()).unwrap(),
+        b"dolor sit amet"
+    );
 }
 
 #[test]
@@ -163,3 +183,592 @@ fn upgrade_to_new_build_despite_broken_patches() {
         "symlink points to new build"
     );
 }
+
+#[test]
+fn upgrade_to_new_build_despite_patch_producing_wrong_build() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+    let (packager, _) = init();
+    let packager = packager.path();
+    let scratch = tempdir().unwrap();
+    let scratch = scratch.path();
+
+    let content1 = random_bytes(1024).unwrap();
+    let mut content2 = content1.clone();
+    content2.extend(random_bytes(32).unwrap());
+    zstd_file(scratch.join("build1.tar.zst"), &content1).unwrap();
+    zstd_file(scratch.join("build2.tar.zst"), &content2).unwrap();
+    for build in ["build1.tar.zst", "build2.tar.zst"] {
+        artefacta(packager, remote)
+            .arg("add")
+            .arg(scratch.join(build))
+            .arg("--upload")
+            .succeeds();
+    }
+
+    // a valid patch, but to a different build2
+    let (patcher, patcher_remote) = init();
+    let (patcher, patcher_remote) = (patcher.path(), patcher_remote.path());
+    let mut wrong_content2 = content1.clone();
+    wrong_content2.extend(random_bytes(32).unwrap());
+    zstd_file(patcher.join("build1.tar.zst"), &content1).unwrap();
+    zstd_file(patcher.join("build2.tar.zst"), &wrong_content2).unwrap();
+    artefacta(patcher, patcher_remote)
+        .args(["create-patch", "build1", "build2"])
+        .succeeds();
+    fs::copy(
+        patcher.join("build1-build2.patch.zst"),
+        remote.join("build1-build2.patch.zst"),
+    )
+    .unwrap();
+
+    artefacta(local, remote)
+        .args(["install", "build1"])
+        .succeeds();
+    artefacta(local, remote)
+        .args(["install", "build2"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "failed to get build using patches",
+        ));
+
+    let installed =
+        zstd::stream::decode_all(fs::File::open(local.join("build2.tar.zst")).unwrap()).unwrap();
+    assert_eq!(installed, content2, "installed the real build2");
+}
+
+#[test]
+fn install_reports_result_as_json() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(remote.join("build1.tar.zst")).unwrap();
+    let size = fs::metadata(remote.join("build1.tar.zst")).unwrap().len();
+
+    let output = artefacta(local, remote)
+        .args(["--output", "json", "install", "build1"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        result,
+        serde_json::json!({
+            "version": "build1",
+            "previous": null,
+            "method": "build",
+            "downloaded": size,
+        })
+    );
+
+    let output = artefacta(local, remote)
+        .args(["--output", "json", "install", "build1"])
+        .output()
+        .unwrap();
+    let result: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(result["method"], "already-installed");
+    assert_eq!(result["downloaded"], 0);
+}
+
+#[test]
+#[cfg(unix)]
+fn keep_only_newest_local_builds() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    for build in ["build1", "build2", "build9", "build10"] {
+        random_zstd_file(remote.join(format!("{}.tar.zst", build))).unwrap();
+    }
+    // only exists locally, so must not be deleted
+    random_zstd_file(local.join("build0.tar.zst")).unwrap();
+
+    for build in ["build1", "build2", "build9", "build10"] {
+        artefacta(local, remote)
+            .args(["install", build, "--keep", "2"])
+            .succeeds();
+    }
+
+    let mut local_builds = fs::read_dir(local)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".tar.zst"))
+        .collect::<Vec<_>>();
+    local_builds.sort();
+    assert_eq!(
+        local_builds,
+        vec!["build0.tar.zst", "build10.tar.zst", "build9.tar.zst"]
+    );
+    assert!(remote.join("build1.tar.zst").exists(), "remote untouched");
+    assert_eq!(
+        fs::read_link(local.join("current")).unwrap(),
+        local.join("build10.tar.zst").canonicalize().unwrap()
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn post_install_command_gets_installed_version() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(remote.join("build1.tar.zst")).unwrap();
+    random_zstd_file(remote.join("build2.tar.zst")).unwrap();
+    let log = local.join("hook.log");
+
+    let hook = format!(
+        "echo \"$ARTEFACTA_PREVIOUS_VERSION -> $ARTEFACTA_INSTALLED_VERSION at $ARTEFACTA_CURRENT_PATH\" >> {}",
+        log.display()
+    );
+    artefacta(local, remote)
+        .args(["install", "build1", "--post-install", &hook])
+        .succeeds();
+    artefacta(local, remote)
+        .args(["install", "build2", "--post-install", &hook])
+        .succeeds();
+
+    let current = local.join("current");
+    assert_eq!(
+        fs::read_to_string(&log).unwrap(),
+        format!(
+            " -> build1 at {0}\nbuild1 -> build2 at {0}\n",
+            current.display()
+        )
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn failing_post_install_command_keeps_new_build() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(remote.join("build1.tar.zst")).unwrap();
+
+    artefacta(local, remote)
+        .args(["install", "build1", "--post-install", "exit 3"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "post-install command `exit 3` failed",
+        ));
+
+    assert_eq!(
+        fs::read_link(local.join("current")).unwrap(),
+        local.join("build1.tar.zst").canonicalize().unwrap()
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn current_symlink_is_never_missing_during_upgrades() {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread,
+    };
+
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(remote.join("build1.tar.zst")).unwrap();
+    random_zstd_file(remote.join("build2.tar.zst")).unwrap();
+    artefacta(local, remote)
+        .args(["install", "build1"])
+        .succeeds();
+
+    let current = local.join("current");
+    let done = Arc::new(AtomicBool::new(false));
+    let watcher = {
+        let (current, done) = (current.clone(), done.clone());
+        thread::spawn(move || {
+            let mut checks = 0_u64;
+            while !done.load(Ordering::SeqCst) {
+                assert!(
+                    current.symlink_metadata().is_ok(),
+                    "`current` went missing after {} checks",
+                    checks
+                );
+                checks += 1;
+            }
+        })
+    };
+
+    for build in ["build2", "build1", "build2", "build1"] {
+        artefacta(local, remote).args(["install", build]).succeeds();
+    }
+    done.store(true, Ordering::SeqCst);
+    watcher.join().expect("`current` symlink was missing");
+
+    let leftovers = fs::read_dir(local)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name.contains(".tmp-"))
+        .collect::<Vec<_>>();
+    assert!(
+        leftovers.is_empty(),
+        "temporary links left: {:?}",
+        leftovers
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn failed_fetch_keeps_current_build() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(remote.join("build1.tar.zst")).unwrap();
+    artefacta(local, remote)
+        .args(["install", "build1"])
+        .succeeds();
+    let current = fs::read_link(local.join("current")).unwrap();
+
+    // listed like a build, but reading it fails halfway through the download
+    fs::create_dir(remote.join("build2.tar.zst")).unwrap();
+
+    artefacta(local, remote)
+        .args(["install", "build2"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("get build"));
+
+    assert_eq!(
+        fs::read_link(local.join("current")).unwrap(),
+        current,
+        "still on old build"
+    );
+    assert!(
+        local.join("previous").symlink_metadata().is_err(),
+        "`previous` was not touched"
+    );
+    assert!(!local.join("build2.tar.zst").exists());
+}
+
+#[test]
+#[cfg(unix)]
+fn install_under_different_names() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(remote.join("build1.tar.zst")).unwrap();
+    random_zstd_file(remote.join("build2.tar.zst")).unwrap();
+
+    artefacta(local, remote)
+        .args(["install", "build1", "--as", "prod"])
+        .succeeds();
+    artefacta(local, remote)
+        .args(["install", "build2", "--as", "staging"])
+        .succeeds();
+
+    assert_eq!(
+        fs::read_link(local.join("prod")).unwrap(),
+        local.join("build1.tar.zst").canonicalize().unwrap()
+    );
+    assert_eq!(
+        fs::read_link(local.join("staging")).unwrap(),
+        local.join("build2.tar.zst").canonicalize().unwrap()
+    );
+    assert!(!local.join("current").exists());
+
+    artefacta(local, remote)
+        .args(["status", "--as", "staging"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("installed: build2"));
+
+    artefacta(local, remote)
+        .args(["install", "build1", "--as", "../elsewhere"])
+        .assert()
+        .failure();
+}
+
+#[test]
+#[cfg(unix)]
+fn corrupt_cached_build_is_not_installed() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(remote.join("build1.tar.zst")).unwrap();
+    random_zstd_file(remote.join("build2.tar.zst")).unwrap();
+    artefacta(local, remote)
+        .args(["install", "build1"])
+        .succeeds();
+
+    // truncated download
+    let build2 = fs::read(remote.join("build2.tar.zst")).unwrap();
+    fs::write(local.join("build2.tar.zst"), &build2[..build2.len() / 2]).unwrap();
+
+    artefacta(local, remote)
+        .args(["install", "build2"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("build `build2` is corrupt"));
+
+    assert_eq!(
+        fs::read_link(local.join("current")).unwrap(),
+        local.join("build1.tar.zst").canonicalize().unwrap(),
+        "still on old build"
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn build_with_wrong_checksum_is_not_installed() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(remote.join("build1.tar.zst")).unwrap();
+    random_zstd_file(local.join("build1.tar.zst")).unwrap();
+    fs::write(
+        local.join("build1.meta.json"),
+        r#"{ "checksum": "sha256:0000000000000000000000000000000000000000000000000000000000000000" }"#,
+    )
+    .unwrap();
+
+    artefacta(local, remote)
+        .args(["install", "build1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("checksum mismatch"));
+
+    assert!(local.join("current").symlink_metadata().is_err());
+}
+
+#[test]
+fn download_not_matching_its_checksum_file_is_rejected() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(remote.join("build1.tar.zst")).unwrap();
+    fs::write(
+        remote.join("build1.tar.zst.sha256"),
+        "0000000000000000000000000000000000000000000000000000000000000000  build1.tar.zst\n",
+    )
+    .unwrap();
+
+    artefacta(local, remote)
+        .args(["install", "build1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("checksum mismatch"));
+
+    assert!(
+        !local.join("build1.tar.zst").exists(),
+        "corrupt download was removed"
+    );
+}
+
+#[test]
+#[cfg(unix)]
+fn install_extracted_build() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+    let (packager, _) = init();
+    let packager = packager.path();
+
+    for (version, content) in [("build1", "one"), ("build2", "two"), ("build3", "three")] {
+        let build_dir = tempdir().unwrap();
+        let build_dir = build_dir.path();
+        fs::create_dir(build_dir.join("bin")).unwrap();
+        fs::write(build_dir.join("bin").join("run.sh"), content).unwrap();
+        fs::set_permissions(
+            build_dir.join("bin").join("run.sh"),
+            fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+
+        artefacta(packager, remote)
+            .args(["add-package", version])
+            .arg(build_dir)
+            .arg("--upload")
+            .succeeds();
+    }
+
+    artefacta(local, remote)
+        .args(["install", "build1", "--extract"])
+        .succeeds();
+
+    let current = local.join("current");
+    assert_eq!(
+        fs::read_link(&current).unwrap(),
+        local.join("build1.extracted").canonicalize().unwrap()
+    );
+    let script = current.join("bin").join("run.sh");
+    assert_eq!(fs::read_to_string(&script).unwrap(), "one");
+    assert_eq!(
+        fs::metadata(&script).unwrap().permissions().mode() & 0o777,
+        0o755
+    );
+
+    artefacta(local, remote)
+        .args(["install", "build2", "--extract", "--keep", "1"])
+        .succeeds();
+    assert_eq!(
+        fs::read_to_string(current.join("bin").join("run.sh")).unwrap(),
+        "two"
+    );
+    // `previous` still points at build1, so it's kept
+    assert!(local.join("build1.extracted").is_dir());
+
+    artefacta(local, remote)
+        .args(["install", "build3", "--extract", "--keep", "1"])
+        .succeeds();
+    assert!(!local.join("build1.tar.zst").exists());
+    assert!(!local.join("build1.extracted").exists());
+
+    artefacta(local, remote).arg("rollback").succeeds();
+    assert_eq!(
+        fs::read_to_string(current.join("bin").join("run.sh")).unwrap(),
+        "two"
+    );
+}
+
+#[test]
+fn install_latest_build_in_natural_order() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    artefacta(local, remote)
+        .args(["install", "latest"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no builds in the store"));
+
+    random_zstd_file(remote.join("build2.tar.zst")).unwrap();
+    random_zstd_file(remote.join("build10.tar.zst")).unwrap();
+    random_zstd_file(remote.join("build9.tar.zst")).unwrap();
+
+    artefacta(local, remote)
+        .args(["install", "latest"])
+        .succeeds();
+    assert_eq!(
+        local.join("build10.tar.zst").canonicalize().unwrap(),
+        fs::read_link(local.join("current")).unwrap(),
+        "symlink points to build10"
+    );
+
+    let scratch = tempdir().unwrap();
+    random_zstd_file(scratch.path().join("latest.tar.zst")).unwrap();
+    artefacta(local, remote)
+        .arg("add")
+        .arg(scratch.path().join("latest.tar.zst"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("reserved"));
+}
+
+#[test]
+fn install_latest_build_matching_pattern() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    for version in ["wtf-0.1.1", "wtf-0.1.10", "wtf-0.1.9", "zzz-1.0.0"] {
+        random_zstd_file(remote.join(format!("{}.tar.zst", version))).unwrap();
+    }
+
+    artefacta(local, remote)
+        .args(["install", "wtf-*"])
+        .succeeds();
+    assert_eq!(
+        local.join("wtf-0.1.10.tar.zst").canonicalize().unwrap(),
+        fs::read_link(local.join("current")).unwrap(),
+        "symlink points to wtf-0.1.10"
+    );
+
+    let output = artefacta(local, remote)
+        .args(["--output", "json", "info", "wtf-0.1.?"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(info["version"], "wtf-0.1.9");
+
+    artefacta(local, remote)
+        .args(["install", "foo-*"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no build matches `foo-*`"))
+        .stderr(predicate::str::contains("`wtf-0.1.9`"));
+    artefacta(local, remote)
+        .args(["install", "{wtf,zzz}-*"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("only one is supported"));
+}
+
+#[test]
+fn install_build_for_git_tag() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(remote.join("wtf-0.1.0.tar.zst")).unwrap();
+    random_zstd_file(remote.join("wtf-0.1.1.tar.zst")).unwrap();
+
+    artefacta(local, remote)
+        .args(["install", "--tag", "0.1.1", "--prefix", "wtf-"])
+        .succeeds();
+    assert_eq!(
+        local.join("wtf-0.1.1.tar.zst").canonicalize().unwrap(),
+        fs::read_link(local.join("current")).unwrap(),
+        "symlink points to build for tag"
+    );
+
+    artefacta(local, remote)
+        .args(["install", "--tag", "WTF-0.1.0"])
+        .succeeds();
+    assert_eq!(
+        local.join("wtf-0.1.0.tar.zst").canonicalize().unwrap(),
+        fs::read_link(local.join("current")).unwrap(),
+        "tags are matched case-insensitively"
+    );
+
+    artefacta(local, remote)
+        .args(["install", "--tag", "0.2.0", "--prefix", "wtf-"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "no build found matching tag `wtf-0.2.0`",
+        ));
+    artefacta(local, remote)
+        .args(["install", "--tag", "0.1.1", "wtf-0.1.1"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn install_build_stored_as_tzst() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    let build1 = random_zstd_file(remote.join("build1.tzst")).unwrap();
+    run("sha256sum build1.tzst > build1.tzst.sha256", remote);
+    random_zstd_file(remote.join("build2.tar.zst")).unwrap();
+
+    artefacta(local, remote)
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("build1 "))
+        .stdout(predicate::str::contains("build2 "));
+
+    artefacta(local, remote)
+        .args(["install", "build1"])
+        .succeeds();
+    assert!(
+        local.join("build1.tar.zst").exists(),
+        "stored locally with the usual extension"
+    );
+    assert_eq!(
+        artefacta::decompress(fs::File::open(local.join("current")).unwrap()).unwrap(),
+        build1
+    );
+    artefacta(local, remote)
+        .args(["verify", "build1", "--remote"])
+        .succeeds();
+}
@@ -0,0 +1,66 @@
+mod test_helpers;
+use test_helpers::*;
+
+#[test]
+fn install_newest_build_with_label() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    for build in ["1.0.0", "1.1.0", "1.2.0-beta"] {
+        random_zstd_file(remote.join(format!("{}.tar.zst", build))).unwrap();
+    }
+
+    artefacta(local, remote)
+        .args(["install", "--label", "stable"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no build is labeled `stable`"));
+
+    for (version, label) in [
+        ("1.0.0", "stable"),
+        ("1.1.0", "stable"),
+        ("1.2.0-beta", "beta"),
+    ] {
+        artefacta(local, remote)
+            .args(["label", version, label])
+            .succeeds();
+    }
+    assert!(remote.join("labels.json").exists());
+    artefacta(local, remote)
+        .args(["label", "2.0.0", "stable"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("build `2.0.0` unknown"));
+
+    artefacta(local, remote)
+        .args(["list", "--label", "stable"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1.0.0"))
+        .stdout(predicate::str::contains("1.1.0"))
+        .stdout(predicate::str::contains("beta").not());
+
+    artefacta(local, remote)
+        .args(["install", "--label", "stable"])
+        .succeeds();
+    assert_eq!(
+        fs::read_link(local.join("current")).unwrap(),
+        local.join("1.1.0.tar.zst").canonicalize().unwrap()
+    );
+
+    artefacta(local, remote)
+        .args(["label", "--remove", "1.1.0", "stable"])
+        .succeeds();
+    artefacta(local, remote)
+        .args(["--dry-run", "label", "1.2.0-beta", "stable"])
+        .assert()
+        .success()
+        .stdout("would label `1.2.0-beta` as `stable`\n");
+    artefacta(local, remote)
+        .args(["install", "--label", "stable"])
+        .succeeds();
+    assert_eq!(
+        fs::read_link(local.join("current")).unwrap(),
+        local.join("1.0.0.tar.zst").canonicalize().unwrap()
+    );
+}
@@ -0,0 +1,143 @@
+mod test_helpers;
+use test_helpers::*;
+
+#[test]
+fn list_builds_and_patches() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    fs::write(remote.join("build1.tar.zst"), b"build one").unwrap();
+    fs::write(local.join("build1.tar.zst"), b"build one").unwrap();
+    fs::write(remote.join("build10.tar.zst"), b"build ten").unwrap();
+    fs::write(local.join("build2.tar.zst"), b"build two").unwrap();
+    fs::write(remote.join("build1-build10.patch.zst"), b"patch").unwrap();
+    run("touch -d '2022-05-30 12:00 UTC' *.tar.zst", remote);
+    run("touch -d '2022-06-01 08:30 UTC' *.tar.zst", local);
+
+    artefacta(local, remote)
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(
+            "BUILD    LOCAL  REMOTE  SIZE  CHECKSUM  MODIFIED\n\
+             build1   yes    yes     9 B   -         2022-05-30 12:00\n\
+             build2   yes    no      9 B   -         2022-06-01 08:30\n\
+             build10  no     yes     9 B   -         2022-05-30 12:00\n",
+        );
+
+    artefacta(local, remote)
+        .args(["list", "--patches"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "PATCH              LOCAL  REMOTE  SIZE\n\
+             build1 -> build10  no     yes     5 B\n",
+        ));
+}
+
+#[test]
+fn list_only_local_or_remote_builds() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    fs::write(remote.join("build1.tar.zst"), b"build one").unwrap();
+    fs::write(local.join("build1.tar.zst"), b"build one").unwrap();
+    fs::write(remote.join("build10.tar.zst"), b"build ten").unwrap();
+    fs::write(local.join("build2.tar.zst"), b"build two").unwrap();
+
+    artefacta(local, remote)
+        .args(["list", "--local-only"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("build2").and(predicate::str::contains("build1 ").not()))
+        .stdout(predicate::str::contains("build10").not());
+
+    artefacta(local, remote)
+        .args(["list", "--remote-only"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("build10").and(predicate::str::contains("build2").not()));
+
+    artefacta(local, remote)
+        .args(["list", "--remote-only", "--local-only"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn list_as_json() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    fs::write(remote.join("build1.tar.zst"), b"build one").unwrap();
+    fs::write(remote.join("build2.tar.zst"), b"build two").unwrap();
+    fs::write(local.join("build2.tar.zst"), b"build two").unwrap();
+    fs::write(remote.join("build1-build2.patch.zst"), b"patch").unwrap();
+    run("touch -d '2022-05-30 12:00 UTC' *.tar.zst", remote);
+
+    let output = artefacta(local, remote)
+        .args(["list", "--patches", "--output", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let list: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        list,
+        serde_json::json!({
+            "builds": [
+                {
+                    "version": "build1", "local": false, "remote": true, "size": 9,
+                    "modified": "2022-05-30T12:00:00Z",
+                },
+                {
+                    "version": "build2", "local": true, "remote": true, "size": 9,
+                    "modified": "2022-05-30T12:00:00Z",
+                },
+            ],
+            "patches": [
+                { "from": "build1", "to": "build2", "local": false, "remote": true, "size": 5 },
+            ],
+        })
+    );
+}
+
+#[test]
+fn list_shows_checksums_of_added_builds() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    let scratch = tempdir().unwrap();
+    let scratch = scratch.path();
+    fs::write(scratch.join("build1.tar.zst"), b"build one").unwrap();
+    fs::write(remote.join("build2.tar.zst"), b"build two").unwrap();
+
+    artefacta(local, remote)
+        .arg("add")
+        .arg(scratch.join("build1.tar.zst"))
+        .succeeds();
+
+    // sha256 of "build one"
+    let digest = "5fddb9545db6e7a5d11471e66b874985c65d63e7292279c5db9eb2917bae2711";
+
+    let output = artefacta(local, remote)
+        .args(["list", "--output", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let list: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        list["builds"][0]["checksum"],
+        serde_json::json!(format!("sha256:{}", digest))
+    );
+    assert_eq!(list["builds"][1].get("checksum"), None);
+
+    artefacta(local, remote)
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "sha256:{}",
+            &digest[..12]
+        )));
+}
@@ -0,0 +1,56 @@
+mod test_helpers;
+use test_helpers::*;
+
+use std::{
+    process,
+    time::{Duration, Instant},
+};
+
+#[test]
+#[cfg(unix)]
+fn concurrent_mutating_runs_are_rejected() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+    let build_dir = tempdir().unwrap();
+
+    random_zstd_file(remote.join("build1.tar.zst")).unwrap();
+    random_zstd_file(build_dir.path().join("build2.tar.zst")).unwrap();
+
+    // keep the first run busy in its post-install hook
+    let started = build_dir.path().join("hook-started");
+    let hook = format!("touch {} && sleep 3", started.display());
+    let mut install = process::Command::new(assert_cmd::cargo::cargo_bin("artefacta"))
+        .env("ARTEFACTA_LOCAL_STORE", local)
+        .env("ARTEFACTA_REMOTE_STORE", remote)
+        .args(["install", "build1", "--post-install", &hook])
+        .spawn()
+        .unwrap();
+    let waiting = Instant::now();
+    while !started.exists() {
+        assert!(
+            waiting.elapsed() < Duration::from_secs(10),
+            "install never ran its hook"
+        );
+        std::thread::sleep(Duration::from_millis(50));
+    }
+
+    artefacta(local, remote)
+        .args(["add"])
+        .arg(build_dir.path().join("build2.tar.zst"))
+        .assert()
+        .failure()
+        .code(exitcode::TEMPFAIL)
+        .stderr(predicate::str::contains(format!(
+            "is in use by another artefacta process (PID {})",
+            install.id()
+        )));
+    // read-only commands don't need the lock
+    artefacta(local, remote).args(["list"]).succeeds();
+
+    assert!(install.wait().unwrap().success());
+    artefacta(local, remote)
+        .args(["add"])
+        .arg(build_dir.path().join("build2.tar.zst"))
+        .succeeds();
+    assert!(local.join("build2.tar.zst").exists());
+}
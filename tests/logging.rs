@@ -0,0 +1,125 @@
+mod test_helpers;
+use test_helpers::*;
+
+#[test]
+fn logs_as_json_lines() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+    random_zstd_file(local.join("build1.tar.zst")).unwrap();
+
+    let output = artefacta(local, remote)
+        .args(["--log-format", "json", "sync"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let lines = String::from_utf8(output.stderr).unwrap();
+    let logs = lines
+        .lines()
+        .map(|line| {
+            serde_json::from_str::<serde_json::Value>(line)
+                .unwrap_or_else(|e| panic!("`{}` is not JSON: {}", line, e))
+        })
+        .collect::<Vec<_>>();
+    assert!(logs.iter().all(|log| {
+        ["timestamp", "level", "target", "message"]
+            .iter()
+            .all(|key| log[key].is_string())
+    }));
+    assert!(logs.iter().any(|log| log["level"] == "INFO"
+        && log["target"] == "artefacta::index"
+        && log["message"] == "uploaded `build1.tar.zst`"));
+
+    artefacta(local, remote)
+        .args(["--log-format", "yaml", "list"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("unknown log format `yaml`"));
+}
+
+#[test]
+fn logs_to_file_as_well() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+    let logs = tempdir().unwrap();
+    let log_file = logs.path().join("artefacta.log");
+    random_zstd_file(local.join("build1.tar.zst")).unwrap();
+
+    artefacta(local, remote)
+        .arg("--log-file")
+        .arg(&log_file)
+        .arg("sync")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("uploaded `build1.tar.zst`"));
+    let first = fs::read_to_string(&log_file).unwrap();
+    assert!(first.contains("INFO  artefacta::index > uploaded `build1.tar.zst`"));
+    assert!(!first.contains('\x1b'), "no colors in:\n{}", first);
+
+    artefacta(local, remote)
+        .arg("--log-file")
+        .arg(&log_file)
+        .arg("list")
+        .succeeds();
+    let appended = fs::read_to_string(&log_file).unwrap();
+    assert!(appended.starts_with(&first) && appended.len() > first.len());
+
+    artefacta(local, remote)
+        .arg("--log-file")
+        .arg(&log_file)
+        .args(["--rotate-log-file", "--log-format", "json", "list"])
+        .succeeds();
+    assert_eq!(
+        fs::read_to_string(logs.path().join("artefacta.log.1")).unwrap(),
+        appended
+    );
+    let rotated = fs::read_to_string(&log_file).unwrap();
+    assert!(!rotated.is_empty());
+    for line in rotated.lines() {
+        serde_json::from_str::<serde_json::Value>(line)
+            .unwrap_or_else(|e| panic!("`{}` is not JSON: {}", line, e));
+    }
+}
+
+#[test]
+fn quiet_only_logs_warnings() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+    random_zstd_file(local.join("build1.tar.zst")).unwrap();
+
+    let mut quiet = Command::cargo_bin("artefacta").unwrap();
+    quiet
+        .env("ARTEFACTA_LOCAL_STORE", local)
+        .env("ARTEFACTA_REMOTE_STORE", remote)
+        .env_remove("RUST_LOG")
+        .args(["sync", "--quiet"])
+        .assert()
+        .success()
+        .stderr("");
+    assert!(remote.join("build1.tar.zst").exists());
+
+    artefacta(local, remote)
+        .args(["--quiet", "list"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+}
+
+#[test]
+fn no_colors_when_asked_not_to() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    let no_colors =
+        || predicate::str::contains("build1").and(predicate::str::contains("\x1b").not());
+    artefacta(local, remote)
+        .args(["--no-color", "install", "build1"])
+        .assert()
+        .failure()
+        .stderr(no_colors());
+    artefacta(local, remote)
+        .env("NO_COLOR", "1")
+        .args(["install", "build1"])
+        .assert()
+        .failure()
+        .stderr(no_colors());
+}
@@ -0,0 +1,83 @@
+mod test_helpers;
+use test_helpers::*;
+
+use artefacta::output::Manifest;
+
+#[test]
+fn manifest_lists_remote_builds_and_patches() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    let scratch = tempdir().unwrap();
+    let scratch = scratch.path();
+    random_zstd_file(scratch.join("build1.tar.zst")).unwrap();
+    random_zstd_file(scratch.join("build2.tar.zst")).unwrap();
+    artefacta(local, remote)
+        .arg("add")
+        .arg(scratch.join("build1.tar.zst"))
+        .succeeds();
+    artefacta(local, remote)
+        .arg("add")
+        .arg(scratch.join("build2.tar.zst"))
+        .arg("--calc-patch-from=build1")
+        .succeeds();
+    artefacta(local, remote).arg("sync").succeeds();
+    // only on remote, without a checksum
+    random_zstd_file(remote.join("build3.tar.zst")).unwrap();
+    // only local, so not in the manifest
+    random_zstd_file(local.join("build4.tar.zst")).unwrap();
+
+    artefacta(local, remote)
+        .args(["--dry-run", "manifest", "--upload"])
+        .succeeds();
+    assert!(
+        !remote.join("manifest.json").exists(),
+        "uploaded on dry run"
+    );
+
+    artefacta(local, remote)
+        .args(["manifest", "--upload"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"schema_version\": 1"));
+    let manifest: Manifest =
+        serde_json::from_slice(&fs::read(remote.join("manifest.json")).unwrap()).unwrap();
+    assert_eq!(manifest.schema_version, 1);
+
+    let builds = manifest
+        .builds
+        .iter()
+        .map(|build| (build.version.as_str(), build.file.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        builds,
+        [
+            ("build1", "build1.tar.zst"),
+            ("build2", "build2.tar.zst"),
+            ("build3", "build3.tar.zst")
+        ]
+    );
+    for build in &manifest.builds[..2] {
+        assert_eq!(
+            build.size,
+            fs::metadata(remote.join(&build.file)).unwrap().len()
+        );
+        build
+            .checksum
+            .expect("checksum of added build")
+            .validate(fs::File::open(remote.join(&build.file)).unwrap())
+            .unwrap();
+    }
+    assert!(manifest.builds[2].checksum.is_none());
+
+    assert_eq!(manifest.patches.len(), 1);
+    let patch = &manifest.patches[0];
+    assert_eq!(
+        (patch.from.as_str(), patch.to.as_str()),
+        ("build1", "build2")
+    );
+    assert_eq!(patch.file, "build1-build2.patch.zst");
+
+    // the manifest itself isn't mistaken for a build
+    artefacta(local, remote).arg("doctor").succeeds();
+}
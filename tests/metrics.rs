@@ -0,0 +1,103 @@
+mod test_helpers;
+use test_helpers::*;
+
+fn metric<'a>(text: &'a str, name: &str) -> &'a str {
+    text.lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+        .unwrap_or_else(|| panic!("no `{}` in:\n{}", name, text))
+}
+
+#[test]
+fn install_writes_metrics() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+    let metrics_dir = tempdir().unwrap();
+    let metrics_file = metrics_dir.path().join("artefacta.prom");
+
+    let build1 = random_bytes(100_000).unwrap();
+    let mut build2 = build1.clone();
+    build2.extend(random_bytes(1_000).unwrap());
+    zstd_file(local.join("build1.tar.zst"), &build1).unwrap();
+    zstd_file(local.join("build2.tar.zst"), &build2).unwrap();
+    artefacta(local, remote)
+        .args(["create-patch", "build1", "build2"])
+        .succeeds();
+    artefacta(local, remote).arg("sync").succeeds();
+
+    let other_local = tempdir().unwrap();
+    let other_local = other_local.path();
+    fs::copy(
+        local.join("build1.tar.zst"),
+        other_local.join("build1.tar.zst"),
+    )
+    .unwrap();
+    artefacta(other_local, remote)
+        .args(["install", "build1"])
+        .succeeds();
+
+    artefacta(other_local, remote)
+        .arg("--metrics-file")
+        .arg(&metrics_file)
+        .args(["install", "build2"])
+        .succeeds();
+    let text = fs::read_to_string(&metrics_file).unwrap();
+    assert_eq!(metric(&text, "artefacta_last_run_success"), "1");
+    assert_eq!(metric(&text, "artefacta_patches_applied"), "1");
+    let patch_size = fs::metadata(remote.join("build1-build2.patch.zst"))
+        .unwrap()
+        .len();
+    assert_eq!(
+        metric(&text, "artefacta_downloaded_bytes"),
+        patch_size.to_string()
+    );
+    assert_eq!(
+        metric(
+            &text,
+            "artefacta_operations{operation=\"upgrade_to_build\"}"
+        ),
+        "1"
+    );
+
+    artefacta(other_local, remote)
+        .arg("--metrics-file")
+        .arg(&metrics_file)
+        .args(["install", "build3"])
+        .assert()
+        .failure();
+    let text = fs::read_to_string(&metrics_file).unwrap();
+    assert_eq!(metric(&text, "artefacta_last_run_success"), "0");
+    assert_eq!(metric(&text, "artefacta_patches_applied"), "0");
+}
+
+#[test]
+fn timings_are_printed_when_asked_for() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    let build1 = random_bytes(100_000).unwrap();
+    let mut build2 = build1.clone();
+    build2.extend(random_bytes(1_000).unwrap());
+    zstd_file(local.join("build1.tar.zst"), &build1).unwrap();
+    zstd_file(local.join("build2.tar.zst"), &build2).unwrap();
+
+    artefacta(local, remote)
+        .args(["create-patch", "build1", "build2"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("timings:\n").not());
+
+    let output = artefacta(local, remote)
+        .args(["--timings", "sync"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let summary = stderr
+        .split("timings:\n")
+        .nth(1)
+        .unwrap_or_else(|| panic!("no timings in:\n{}", stderr));
+    for phase in ["list storage:", "build graph:", "upload:"] {
+        assert!(summary.contains(phase), "no `{}` in:\n{}", phase, summary);
+    }
+    assert!(!summary.contains("diff:"));
+}
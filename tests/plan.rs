@@ -0,0 +1,59 @@
+mod test_helpers;
+use test_helpers::*;
+
+#[test]
+#[cfg(unix)]
+fn plan_lists_patches_to_apply() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    file_of_size(remote.join("build1.tar.zst"), 1000);
+    file_of_size(remote.join("build2.tar.zst"), 1000);
+    file_of_size(remote.join("build10.tar.zst"), 1000);
+    file_of_size(remote.join("build1-build2.patch.zst"), 10);
+    file_of_size(remote.join("build2-build10.patch.zst"), 20);
+
+    file_of_size(local.join("build1.tar.zst"), 1000);
+    file_of_size(local.join("build1-build2.patch.zst"), 10);
+    std::os::unix::fs::symlink(local.join("build1.tar.zst"), local.join("current")).unwrap();
+
+    artefacta(local, remote)
+        .args(["plan", "latest"])
+        .assert()
+        .success()
+        .stdout(
+            "upgrade build1 -> build10 applying 2 patches:\n  \
+             build1 -> build2: 10 B (local)\n  \
+             build2 -> build10: 20 B\n\
+             downloads 20 B in total\n",
+        );
+    assert!(!local.join("build10.tar.zst").exists());
+    assert!(!local.join("build2-build10.patch.zst").exists());
+}
+
+#[test]
+fn plan_without_installed_build() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    file_of_size(remote.join("build1.tar.zst"), 1000);
+
+    let output = artefacta(local, remote)
+        .args(["plan", "build1", "--output", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let plan: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        plan,
+        serde_json::json!({
+            "from": null,
+            "to": "build1",
+            "method": "build",
+            "build_size": 1000,
+            "download_size": 1000,
+            "fetch": ["build1.tar.zst"],
+        })
+    );
+}
@@ -0,0 +1,27 @@
+mod test_helpers;
+use test_helpers::*;
+
+#[test]
+fn prune_patches_not_on_cheapest_path() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    file_of_size(remote.join("build1.tar.zst"), 1000);
+    file_of_size(remote.join("build2.tar.zst"), 1000);
+    file_of_size(remote.join("build3.tar.zst"), 1000);
+    file_of_size(remote.join("build1-build2.patch.zst"), 10);
+    file_of_size(remote.join("build2-build3.patch.zst"), 10);
+    file_of_size(remote.join("build1-build3.patch.zst"), 500);
+
+    artefacta(local, remote)
+        .args(["prune-patches", "--dry-run"])
+        .assert()
+        .success()
+        .stdout("build1-build3.patch.zst\n");
+    assert!(remote.join("build1-build3.patch.zst").exists());
+
+    artefacta(local, remote).args(["prune-patches"]).succeeds();
+    assert!(!remote.join("build1-build3.patch.zst").exists());
+    assert!(remote.join("build1-build2.patch.zst").exists());
+    assert!(remote.join("build2-build3.patch.zst").exists());
+}
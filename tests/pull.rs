@@ -0,0 +1,68 @@
+mod test_helpers;
+use test_helpers::*;
+
+#[test]
+fn pull_mirrors_remote_into_empty_local_store() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(local.join("build1.tar.zst")).unwrap();
+    random_zstd_file(local.join("build2.tar.zst")).unwrap();
+    artefacta(local, remote)
+        .args(["create-patch", "build1", "build2"])
+        .succeeds();
+    artefacta(local, remote).arg("sync").succeeds();
+
+    let other_local = tempdir().unwrap();
+    let other_local = other_local.path();
+    artefacta(other_local, remote)
+        .args(["--dry-run", "pull", "--patches"])
+        .assert()
+        .success()
+        .stdout("build1.tar.zst\nbuild2.tar.zst\nbuild1-build2.patch.zst\n");
+    assert!(!other_local.join("build1.tar.zst").exists());
+
+    artefacta(other_local, remote)
+        .args(["pull", "--patches"])
+        .succeeds();
+    for name in [
+        "build1.tar.zst",
+        "build2.tar.zst",
+        "build1-build2.patch.zst",
+    ] {
+        assert_eq!(
+            fs::read(other_local.join(name)).unwrap(),
+            fs::read(remote.join(name)).unwrap(),
+            "`{}` was pulled",
+            name
+        );
+    }
+
+    // nothing left to pull
+    artefacta(other_local, remote)
+        .args(["--dry-run", "pull", "--patches"])
+        .assert()
+        .success()
+        .stdout("");
+}
+
+#[test]
+fn pull_only_one_build() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(remote.join("build1.tar.zst")).unwrap();
+    random_zstd_file(remote.join("build2.tar.zst")).unwrap();
+    fs::write(remote.join("build1-build2.patch.zst"), b"patch").unwrap();
+
+    artefacta(local, remote).args(["pull", "build1"]).succeeds();
+    assert!(local.join("build1.tar.zst").exists());
+    assert!(!local.join("build2.tar.zst").exists());
+    assert!(!local.join("build1-build2.patch.zst").exists());
+
+    artefacta(local, remote)
+        .args(["pull", "nope"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("build `nope` unknown"));
+}
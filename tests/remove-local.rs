@@ -0,0 +1,74 @@
+mod test_helpers;
+use test_helpers::*;
+
+#[test]
+fn remove_cached_build_and_install_it_again() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    let build1 = random_zstd_file(local.join("build1.tar.zst")).unwrap();
+    random_zstd_file(local.join("build2.tar.zst")).unwrap();
+    artefacta(local, remote)
+        .args(["create-patch", "build1", "build2"])
+        .succeeds();
+    artefacta(local, remote).arg("sync").succeeds();
+    artefacta(local, remote)
+        .args(["install", "build2"])
+        .succeeds();
+
+    artefacta(local, remote)
+        .args(["remove-local", "build1"])
+        .succeeds();
+    assert!(!local.join("build1.tar.zst").exists());
+    assert!(!local.join("build1-build2.patch.zst").exists());
+    assert!(local.join("build2.tar.zst").exists());
+    assert!(remote.join("build1.tar.zst").exists());
+    assert!(remote.join("build1-build2.patch.zst").exists());
+
+    artefacta(local, remote)
+        .args(["install", "build1"])
+        .succeeds();
+    assert_eq!(
+        artefacta::decompress(fs::File::open(local.join("current")).unwrap()).unwrap(),
+        build1
+    );
+}
+
+#[test]
+fn keep_installed_and_unsynced_builds_unless_forced() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(local.join("build1.tar.zst")).unwrap();
+    random_zstd_file(local.join("build2.tar.zst")).unwrap();
+    artefacta(local, remote).arg("sync").succeeds();
+    artefacta(local, remote)
+        .args(["install", "build1"])
+        .succeeds();
+    random_zstd_file(local.join("build3.tar.zst")).unwrap();
+
+    artefacta(local, remote)
+        .args(["remove-local", "build1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("build `build1` is installed"));
+    artefacta(local, remote)
+        .args(["remove-local", "build3"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("not in remote storage"));
+    assert!(local.join("build1.tar.zst").exists());
+    assert!(local.join("build3.tar.zst").exists());
+
+    artefacta(local, remote)
+        .args(["remove-local", "build1", "--force"])
+        .succeeds();
+    assert!(!local.join("build1.tar.zst").exists());
+    assert!(remote.join("build1.tar.zst").exists());
+
+    artefacta(local, remote)
+        .args(["remove-local", "build1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("not stored locally"));
+}
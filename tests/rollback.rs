@@ -0,0 +1,54 @@
+#![cfg(unix)]
+
+mod test_helpers;
+use test_helpers::*;
+
+#[test]
+fn rollback_to_previous_install() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(remote.join("build1.tar.zst")).unwrap();
+    random_zstd_file(remote.join("build2.tar.zst")).unwrap();
+
+    artefacta(local, remote)
+        .args(["install", "build1"])
+        .succeeds();
+    artefacta(local, remote)
+        .args(["install", "build2"])
+        .succeeds();
+
+    let current = local.join("current");
+    let build1 = local.join("build1.tar.zst").canonicalize().unwrap();
+    let build2 = local.join("build2.tar.zst").canonicalize().unwrap();
+    assert_eq!(fs::read_link(&current).unwrap(), build2);
+
+    artefacta(local, remote).arg("rollback").succeeds();
+    assert_eq!(fs::read_link(&current).unwrap(), build1);
+
+    // rolling back again undoes the rollback
+    artefacta(local, remote).arg("rollback").succeeds();
+    assert_eq!(fs::read_link(&current).unwrap(), build2);
+}
+
+#[test]
+fn rollback_without_previous_version_fails() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(remote.join("build1.tar.zst")).unwrap();
+
+    artefacta(local, remote)
+        .args(["install", "build1"])
+        .succeeds();
+
+    artefacta(local, remote)
+        .arg("rollback")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no previous version"));
+    assert_eq!(
+        fs::read_link(local.join("current")).unwrap(),
+        local.join("build1.tar.zst").canonicalize().unwrap()
+    );
+}
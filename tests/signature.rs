@@ -0,0 +1,142 @@
+mod test_helpers;
+use test_helpers::*;
+
+#[test]
+fn install_checks_signatures() {
+    let (machine1, remote) = init();
+    let (machine1, remote) = (machine1.path(), remote.path());
+    let (machine2, keys) = init();
+    let (machine2, keys) = (machine2.path(), keys.path());
+    let scratch = tempdir().unwrap();
+    let scratch = scratch.path();
+
+    let key = keys.join("key");
+    let other_key = keys.join("other-key");
+    artefacta(machine1, remote)
+        .arg("generate-signing-key")
+        .arg(&key)
+        .succeeds();
+    artefacta(machine1, remote)
+        .arg("generate-signing-key")
+        .arg(&other_key)
+        .succeeds();
+    assert!(keys.join("key.pub").exists());
+
+    // build1 is signed with our key, build2 with another one, build3 not at all
+    for (build, signing_key) in [
+        ("build1", Some(&key)),
+        ("build2", Some(&other_key)),
+        ("build3", None),
+    ] {
+        let path = scratch.join(format!("{}.tar.zst", build));
+        random_zstd_file(&path).unwrap();
+        let mut cmd = artefacta(machine1, remote);
+        if let Some(signing_key) = signing_key {
+            cmd.arg("--signing-key").arg(signing_key);
+        }
+        cmd.arg("add").arg(&path).succeeds();
+    }
+    assert!(machine1.join("build1.tar.zst.sig").exists());
+    assert!(!machine1.join("build3.tar.zst.sig").exists());
+    artefacta(machine1, remote).arg("sync").succeeds();
+    assert!(remote.join("build1.tar.zst.sig").exists());
+
+    let verify = |cmd: &mut Command| {
+        cmd.arg("--verify-key").arg(keys.join("key.pub"));
+    };
+
+    let mut install = artefacta(machine2, remote);
+    verify(&mut install);
+    install
+        .args(["install", "--require-signature", "build1"])
+        .succeeds();
+
+    let mut install = artefacta(machine2, remote);
+    verify(&mut install);
+    install
+        .args(["install", "build2"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid signature for `build2`"));
+
+    let mut install = artefacta(machine2, remote);
+    verify(&mut install);
+    install
+        .args(["install", "--require-signature", "build3"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("build `build3` is not signed"));
+    assert_eq!(
+        fs::read_link(machine2.join("current")).unwrap(),
+        machine2.join("build1.tar.zst").canonicalize().unwrap(),
+        "still at the last trusted build"
+    );
+
+    let mut install = artefacta(machine2, remote);
+    verify(&mut install);
+    install
+        .args(["install", "build3"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("build `build3` is not signed"));
+}
+
+#[test]
+fn sync_signs_unsigned_builds() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+    let (machine2, keys) = init();
+    let (machine2, keys) = (machine2.path(), keys.path());
+
+    let key = keys.join("key");
+    artefacta(local, remote)
+        .arg("generate-signing-key")
+        .arg(&key)
+        .succeeds();
+    random_zstd_file(local.join("build1.tar.zst")).unwrap();
+
+    artefacta(local, remote)
+        .arg("--signing-key")
+        .arg(&key)
+        .arg("sync")
+        .succeeds();
+    assert!(remote.join("build1.tar.zst.sig").exists());
+
+    artefacta(machine2, remote)
+        .arg("--verify-key")
+        .arg(keys.join("key.pub"))
+        .args(["install", "--require-signature", "build1"])
+        .succeeds();
+}
+
+#[test]
+fn signed_builds_need_a_key_to_check() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(remote.join("build1.tar.zst")).unwrap();
+    artefacta(local, remote)
+        .args(["install", "--require-signature", "build1"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "no public key to verify signatures with",
+        ));
+}
+
+#[test]
+#[cfg(unix)]
+fn signing_keys_with_non_utf8_names() {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+    let keys = tempdir().unwrap();
+    let keys = keys.path();
+
+    artefacta(local, remote)
+        .arg("generate-signing-key")
+        .arg(keys.join(OsStr::from_bytes(b"key-\xff")))
+        .succeeds();
+    assert!(keys.join(OsStr::from_bytes(b"key-\xff.pub")).exists());
+}
@@ -0,0 +1,92 @@
+mod test_helpers;
+use test_helpers::*;
+
+#[test]
+fn status_without_installed_build() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    file_of_size(remote.join("build1.tar.zst"), 1000);
+
+    artefacta(local, remote)
+        .arg("status")
+        .assert()
+        .success()
+        .stdout("nothing installed\n");
+}
+
+#[test]
+#[cfg(unix)]
+fn status_shows_available_upgrade() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    file_of_size(remote.join("build1.tar.zst"), 1000);
+    file_of_size(remote.join("build2.tar.zst"), 1000);
+    file_of_size(remote.join("build10.tar.zst"), 1000);
+    file_of_size(remote.join("build1-build2.patch.zst"), 10);
+    file_of_size(remote.join("build2-build10.patch.zst"), 20);
+
+    file_of_size(local.join("build1.tar.zst"), 1000);
+    std::os::unix::fs::symlink(local.join("build1.tar.zst"), local.join("current")).unwrap();
+
+    artefacta(local, remote)
+        .arg("status")
+        .assert()
+        .success()
+        .stdout(
+            "installed: build1\n\
+             newer build available: build10 (upgrade downloads 30 B using 2 patches)\n",
+        );
+}
+
+#[test]
+#[cfg(unix)]
+fn status_when_up_to_date() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    file_of_size(remote.join("build1.tar.zst"), 1000);
+    file_of_size(local.join("build2.tar.zst"), 1000);
+    std::os::unix::fs::symlink(local.join("build2.tar.zst"), local.join("current")).unwrap();
+
+    artefacta(local, remote)
+        .arg("status")
+        .assert()
+        .success()
+        .stdout("installed: build2\nup to date\n");
+}
+
+#[test]
+#[cfg(unix)]
+fn status_as_json() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    file_of_size(remote.join("build1.tar.zst"), 1000);
+    file_of_size(remote.join("build2.tar.zst"), 1000);
+    file_of_size(remote.join("build1-build2.patch.zst"), 10);
+
+    file_of_size(local.join("build1.tar.zst"), 1000);
+    std::os::unix::fs::symlink(local.join("build1.tar.zst"), local.join("current")).unwrap();
+
+    let output = artefacta(local, remote)
+        .args(["status", "--output", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let status: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        status,
+        serde_json::json!({
+            "installed": "build1",
+            "latest": "build2",
+            "upgrade": {
+                "method": "patches",
+                "patches": ["build1-build2.patch.zst"],
+                "download_size": 10,
+            },
+        })
+    );
+}
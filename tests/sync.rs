@@ -0,0 +1,209 @@
+mod test_helpers;
+use test_helpers::*;
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    thread,
+};
+
+/// Accept one HTTP request on a local port, answer it with `status`, and
+/// return its first line and body
+fn webhook_server(status: &'static str) -> (String, thread::JoinHandle<(String, String)>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let server = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            if header.trim().is_empty() {
+                break;
+            }
+            let (name, value) = header.split_once(':').unwrap();
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap();
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+        write!(
+            reader.get_mut(),
+            "HTTP/1.1 {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+            status
+        )
+        .unwrap();
+        (
+            request_line.trim().to_string(),
+            String::from_utf8(body).unwrap(),
+        )
+    });
+    (url, server)
+}
+
+#[test]
+fn sync_uploads_everything_with_any_concurrency() {
+    for concurrency in ["1", "8"] {
+        let (local, remote) = init();
+        let (local, remote) = (local.path(), remote.path());
+
+        let mut builds = Vec::new();
+        for build in 1..=5 {
+            let name = format!("build{}.tar.zst", build);
+            random_zstd_file(local.join(&name)).unwrap();
+            builds.push(name);
+        }
+
+        artefacta(local, remote)
+            .args(["--concurrency", concurrency, "sync"])
+            .succeeds();
+
+        for name in &builds {
+            assert_eq!(
+                fs::read(remote.join(name)).unwrap(),
+                fs::read(local.join(name)).unwrap(),
+                "`{}` was uploaded with concurrency {}",
+                name,
+                concurrency
+            );
+        }
+    }
+}
+
+#[test]
+fn sync_needs_a_concurrency_of_at_least_one() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(local.join("build1.tar.zst")).unwrap();
+
+    artefacta(local, remote)
+        .args(["--concurrency", "0", "sync"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("at least 1 file at a time"));
+    assert!(!remote.join("build1.tar.zst").exists());
+}
+
+#[test]
+fn sync_can_alias_identical_builds() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    let scratch = tempdir().unwrap();
+    let scratch = scratch.path();
+    let build = random_zstd_file(scratch.join("build1.tar.zst")).unwrap();
+    fs::copy(
+        scratch.join("build1.tar.zst"),
+        scratch.join("build2.tar.zst"),
+    )
+    .unwrap();
+    for name in ["build1.tar.zst", "build2.tar.zst"] {
+        artefacta(local, remote)
+            .arg("add")
+            .arg(scratch.join(name))
+            .succeeds();
+    }
+
+    artefacta(local, remote)
+        .args(["--alias-duplicates", "sync"])
+        .succeeds();
+    assert!(remote.join("build1.tar.zst").exists());
+    assert!(
+        !remote.join("build2.tar.zst").exists(),
+        "duplicate uploaded"
+    );
+    assert!(remote.join("build2---build1.alias").exists());
+
+    // nothing left to upload
+    artefacta(local, remote)
+        .args(["--alias-duplicates", "--dry-run", "sync"])
+        .assert()
+        .success()
+        .stdout("nothing to upload\n");
+
+    let other_local = tempdir().unwrap();
+    let other_local = other_local.path();
+    artefacta(other_local, remote)
+        .args(["install", "build2"])
+        .succeeds();
+    assert_eq!(
+        artefacta::decompress(fs::File::open(other_local.join("current")).unwrap()).unwrap(),
+        build
+    );
+    artefacta(other_local, remote)
+        .args(["verify", "build2", "--remote"])
+        .succeeds();
+}
+
+#[test]
+fn sync_notifies_webhook() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(local.join("build1.tar.zst")).unwrap();
+    random_zstd_file(local.join("build2.tar.zst")).unwrap();
+    artefacta(local, remote)
+        .args(["create-patch", "build1", "build2"])
+        .succeeds();
+    let patch_size = fs::metadata(local.join("build1-build2.patch.zst"))
+        .unwrap()
+        .len();
+
+    let (url, server) = webhook_server("200 OK");
+    artefacta(local, remote)
+        .args(["sync", "--webhook", &url])
+        .succeeds();
+    let (request_line, body) = server.join().unwrap();
+    assert_eq!(request_line, "POST /hook HTTP/1.1");
+
+    let summary: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(summary["builds"], serde_json::json!(["build1", "build2"]));
+    assert_eq!(
+        summary["patches"],
+        serde_json::json!([{"from": "build1", "to": "build2", "size": patch_size}])
+    );
+    let uploaded: u64 = fs::read_dir(remote)
+        .unwrap()
+        .map(|entry| entry.unwrap().metadata().unwrap().len())
+        .sum();
+    assert_eq!(summary["bytes"], uploaded);
+
+    // nothing new, so nobody to notify (and nothing listening)
+    artefacta(local, remote)
+        .args(["sync", "--webhook", &url, "--webhook-required"])
+        .succeeds();
+}
+
+#[test]
+fn webhook_failures_only_fail_sync_when_required() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(local.join("build1.tar.zst")).unwrap();
+    let (url, server) = webhook_server("500 Internal Server Error");
+    artefacta(local, remote)
+        .args(["sync", "--webhook", &url])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("500"));
+    server.join().unwrap();
+    assert!(remote.join("build1.tar.zst").exists());
+
+    random_zstd_file(local.join("build2.tar.zst")).unwrap();
+    let (url, server) = webhook_server("500 Internal Server Error");
+    artefacta(local, remote)
+        .args(["sync", "--webhook", &url, "--webhook-required"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("notify webhook"));
+    server.join().unwrap();
+    assert!(
+        remote.join("build2.tar.zst").exists(),
+        "uploaded before notifying"
+    );
+}
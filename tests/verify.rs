@@ -0,0 +1,130 @@
+mod test_helpers;
+use test_helpers::*;
+
+#[test]
+fn verify_local_builds() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(local.join("build1.tar.zst")).unwrap();
+    random_zstd_file(local.join("build2.tar.zst")).unwrap();
+
+    artefacta(local, remote)
+        .arg("verify")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2 ok, 0 corrupt"));
+
+    fs::write(local.join("build2.tar.zst"), b"definitely not zstd").unwrap();
+
+    artefacta(local, remote)
+        .arg("verify")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("CORRUPT  build2 (local)"))
+        .stdout(predicate::str::contains("1 ok, 1 corrupt"));
+
+    artefacta(local, remote)
+        .args(["verify", "build1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1 ok, 0 corrupt"));
+}
+
+#[test]
+fn verify_build_against_stored_checksum() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    random_zstd_file(local.join("build1.tar.zst")).unwrap();
+    fs::write(
+        local.join("build1.meta.json"),
+        r#"{ "checksum": "sha256:b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9" }"#,
+    )
+    .unwrap();
+
+    artefacta(local, remote)
+        .arg("verify")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("CORRUPT  build1 (local)"))
+        .stderr(predicate::str::contains("checksum mismatch"));
+}
+
+#[test]
+fn verify_remote_builds_and_patches() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+
+    let mut content = random_bytes(1024).unwrap();
+    zstd_file(remote.join("build1.tar.zst"), &content).unwrap();
+    content.extend(random_bytes(32).unwrap());
+    zstd_file(remote.join("build2.tar.zst"), &content).unwrap();
+
+    artefacta(local, remote)
+        .args(["create-patch", "build1", "build2"])
+        .succeeds();
+
+    artefacta(local, remote)
+        .args(["verify", "--patches", "--remote"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "ok       build1-build2.patch.zst (local)",
+        ))
+        .stdout(predicate::str::contains("ok       build2 (remote)"));
+
+    fs::write(local.join("build1-build2.patch.zst"), b"broken").unwrap();
+
+    artefacta(local, remote)
+        .args(["verify", "--patches"])
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "CORRUPT  build1-build2.patch.zst (local)",
+        ));
+}
+
+#[test]
+fn verify_local_reads_all_local_files() {
+    let (local, remote) = init();
+    let (local, remote) = (local.path(), remote.path());
+    let build_dir = tempdir().unwrap();
+
+    for build in ["build1", "build2"] {
+        let path = build_dir.path().join(format!("{}.tar.zst", build));
+        random_zstd_file(&path).unwrap();
+        artefacta(local, remote).arg("add").arg(&path).succeeds();
+    }
+    artefacta(local, remote)
+        .args(["create-patch", "build1", "build2"])
+        .succeeds();
+
+    artefacta(local, remote)
+        .arg("verify-local")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("ok       build1.tar.zst\n"))
+        .stdout(predicate::str::contains(
+            "ok       build1-build2.patch.zst\n",
+        ))
+        .stdout(predicate::str::contains("3 ok, 0 corrupt"));
+
+    // truncated, but still starting like zstd
+    let build1 = fs::read(local.join("build1.tar.zst")).unwrap();
+    fs::write(local.join("build1.tar.zst"), &build1[..build1.len() / 2]).unwrap();
+    // not the content it had when it was added
+    random_zstd_file(local.join("build2.tar.zst")).unwrap();
+
+    artefacta(local, remote)
+        .args(["verify-local", "--jobs", "3"])
+        .assert()
+        .failure()
+        .code(exitcode::DATAERR)
+        .stdout(predicate::str::contains("CORRUPT  build1.tar.zst: "))
+        .stdout(predicate::str::contains(
+            "CORRUPT  build2.tar.zst: checksum mismatch",
+        ))
+        .stdout(predicate::str::contains("1 ok, 2 corrupt"))
+        .stderr(predicate::str::contains("2 of 3 local files are corrupt"));
+}